@@ -0,0 +1,506 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::assembler::{expression, symbol_table::SymbolTable};
+
+/// The pipeline lag: reading the PC yields the address of the
+/// current instruction plus 8 bytes (aka 2 instructions)
+pub const PIPELINE_OFFSET: i64 = 8;
+
+/// Size in bytes of one ARM instruction
+pub const INSTRUCTION_SIZE: u32 = 4;
+
+/// Bit positions of the fields shared by most instruction encodings
+pub const COND_SHIFT: u32 = 28;
+pub const RN_SHIFT: u32 = 16;
+pub const RD_SHIFT: u32 = 12;
+
+/// Where a line of source comes from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: Rc<str>,
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// An error found while assembling, tagged with the source line it came from
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub location: Location,
+    /// Column (from 1) the caret points at, 0 if the error has no source line
+    pub column: usize,
+    /// The source line, shown under the message
+    pub text: String,
+    pub message: String,
+}
+
+impl AsmError {
+    /// An error about a whole file or line
+    pub fn new(location: &Location, message: String) -> Self {
+        Self {
+            location: location.clone(),
+            column: 0,
+            text: String::new(),
+            message,
+        }
+    }
+
+    /// Attaches the source line the error was found on,
+    /// unless the error already points into another one
+    pub fn with_source(mut self, text: &str) -> Self {
+        if self.text.is_empty() {
+            self.column = find_column(text, &self.message);
+            self.text = text.to_string();
+        }
+        self
+    }
+
+    /// Attaches the source line the error was found on, pointing the caret at `part` of it
+    pub fn with_source_at(mut self, text: &str, part: &str) -> Self {
+        match text.find(part) {
+            Some(start) => {
+                self.column = text[..start].chars().count() + 1;
+                self.text = text.to_string();
+                self
+            }
+            None => self.with_source(text),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.column == 0 {
+            return write!(f, "{}: error: {}", self.location, self.message);
+        }
+        writeln!(
+            f,
+            "{}:{}: error: {}",
+            self.location, self.column, self.message
+        )?;
+        writeln!(f, "{}", self.text.trim_end())?;
+        // Keep the tabs so the caret lines up with the source
+        let padding: String = self
+            .text
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(f, "{}^", padding)
+    }
+}
+
+/// Renders every error, followed by how many there are
+pub fn render_errors(errors: &[AsmError]) -> String {
+    let mut rendered: Vec<String> = errors.iter().map(AsmError::to_string).collect();
+    let plural = if errors.len() == 1 { "" } else { "s" };
+    rendered.push(format!("{} error{} found", errors.len(), plural));
+    rendered.join("\n")
+}
+
+/// Whether `c` can be part of a label, register or number
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'
+}
+
+/// Finds the column of the source the message is about: the first part of the
+/// message in backticks, or else the first word with a digit, `#` or `=` in it,
+/// that appears in the line as a whole word.
+/// Falls back to the start of the instruction, after the labels
+fn find_column(text: &str, message: &str) -> usize {
+    let quoted = message.split('`').skip(1).step_by(2);
+    let words = message
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| matches!(c, ',' | ':' | '`' | '(' | ')')))
+        .filter(|word| word.contains(|c: char| c.is_ascii_digit() || c == '#' || c == '='));
+
+    let found = quoted
+        .chain(words)
+        .filter(|candidate| !candidate.is_empty())
+        .find_map(|candidate| {
+            text.match_indices(candidate)
+                .map(|(start, _)| start)
+                .find(|&start| {
+                    let before = text[..start].chars().next_back();
+                    let after = text[start + candidate.len()..].chars().next();
+                    // Don't match part of a longer word
+                    let joins_before =
+                        candidate.starts_with(is_word_char) && before.is_some_and(is_word_char);
+                    let joins_after =
+                        candidate.ends_with(is_word_char) && after.is_some_and(is_word_char);
+                    !joins_before && !joins_after
+                })
+        });
+
+    let start = found.unwrap_or_else(|| {
+        let mut start = text.len() - text.trim_start().len();
+        // Skip the labels
+        while let Some(colon) = text[start..].find(':') {
+            let label = text[start..start + colon].trim();
+            if label.is_empty() || !label.chars().all(is_word_char) {
+                break;
+            }
+            start += colon + 1;
+            start += text[start..].len() - text[start..].trim_start().len();
+        }
+        start
+    });
+    text[..start].chars().count() + 1
+}
+
+/// The result of encoding a single instruction.
+/// Encoders only know about the operands, the line is attached by the caller
+pub type EncodeResult = Result<u32, String>;
+
+/// Condition suffixes and their byte code (hs/lo are aliases of cs/cc)
+const CONDITIONS: [(&str, u32); 17] = [
+    ("eq", 0),
+    ("ne", 1),
+    ("cs", 2),
+    ("hs", 2),
+    ("cc", 3),
+    ("lo", 3),
+    ("mi", 4),
+    ("pl", 5),
+    ("vs", 6),
+    ("vc", 7),
+    ("hi", 8),
+    ("ls", 9),
+    ("ge", 10),
+    ("lt", 11),
+    ("gt", 12),
+    ("le", 13),
+    ("al", 14),
+];
+
+/// The byte code of the `al` (always) condition
+pub const COND_ALWAYS: u32 = 14;
+
+/// Base mnemonics the assembler knows about, together with the
+/// extra suffixes each of them accepts besides a condition code
+const MNEMONICS: [(&str, &[&str]); 91] = [
+    ("and", &["s"]),
+    ("eor", &["s"]),
+    ("sub", &["s"]),
+    ("rsb", &["s"]),
+    ("add", &["s"]),
+    ("adc", &["s"]),
+    ("sbc", &["s"]),
+    ("rsc", &["s"]),
+    ("tst", &[]),
+    ("teq", &[]),
+    ("cmp", &[]),
+    ("cmn", &[]),
+    ("orr", &["s"]),
+    ("mov", &["s"]),
+    ("bic", &["s"]),
+    ("mvn", &["s"]),
+    ("lsl", &["s"]),
+    ("lsr", &["s"]),
+    ("asr", &["s"]),
+    ("ror", &["s"]),
+    ("mul", &["s"]),
+    ("mla", &["s"]),
+    ("umull", &["s"]),
+    ("umlal", &["s"]),
+    ("smull", &["s"]),
+    ("smlal", &["s", "bb", "bt", "tb", "tt"]),
+    ("smul", &["bb", "bt", "tb", "tt", "wb", "wt"]),
+    ("smla", &["bb", "bt", "tb", "tt", "wb", "wt"]),
+    ("clz", &[]),
+    ("qadd", &["16", "8", "subx"]),
+    ("qsub", &["16", "8", "addx"]),
+    ("qdadd", &[]),
+    ("qdsub", &[]),
+    ("qasx", &[]),
+    ("qsax", &[]),
+    ("sadd", &["16", "8", "subx"]),
+    ("ssub", &["16", "8", "addx"]),
+    ("sasx", &[]),
+    ("ssax", &[]),
+    ("shadd", &["16", "8", "subx"]),
+    ("shsub", &["16", "8", "addx"]),
+    ("shasx", &[]),
+    ("shsax", &[]),
+    ("uadd", &["16", "8", "subx"]),
+    ("usub", &["16", "8", "addx"]),
+    ("uasx", &[]),
+    ("usax", &[]),
+    ("uqadd", &["16", "8", "subx"]),
+    ("uqsub", &["16", "8", "addx"]),
+    ("uqasx", &[]),
+    ("uqsax", &[]),
+    ("uhadd", &["16", "8", "subx"]),
+    ("uhsub", &["16", "8", "addx"]),
+    ("uhasx", &[]),
+    ("uhsax", &[]),
+    ("sel", &[]),
+    ("rev", &["16", "sh"]),
+    ("sxtb", &["16"]),
+    ("sxth", &[]),
+    ("uxtb", &["16"]),
+    ("uxth", &[]),
+    ("sxtab", &["16"]),
+    ("sxtah", &[]),
+    ("uxtab", &["16"]),
+    ("uxtah", &[]),
+    ("ssat", &["16"]),
+    ("usat", &["16"]),
+    ("ldr", &["b", "h", "sb", "sh", "d"]),
+    ("str", &["b", "h", "d"]),
+    ("ldrex", &[]),
+    ("strex", &[]),
+    ("swp", &["b"]),
+    ("ldm", &["ia", "ib", "da", "db", "fd", "fa", "ed", "ea"]),
+    ("stm", &["ia", "ib", "da", "db", "fd", "fa", "ed", "ea"]),
+    ("push", &[]),
+    ("pop", &[]),
+    ("b", &[]),
+    ("bl", &[]),
+    ("bx", &[]),
+    ("blx", &[]),
+    ("mrs", &[]),
+    ("msr", &[]),
+    ("swi", &[]),
+    ("svc", &[]),
+    ("cdp", &[]),
+    ("mcr", &[]),
+    ("mrc", &[]),
+    ("mcrr", &[]),
+    ("mrrc", &[]),
+    ("ldc", &["l"]),
+    ("stc", &["l"]),
+];
+
+/// A mnemonic split into its base instruction, condition and suffix
+/// e.g. `addeqs` is `add` + `eq` + `s`
+#[derive(Debug, PartialEq)]
+pub struct Mnemonic {
+    pub base: &'static str,
+    pub cond: u32,
+    pub suffix: &'static str,
+}
+
+impl Mnemonic {
+    /// Whether the `s` suffix (set condition codes) was given
+    pub fn sets_flags(&self) -> bool {
+        self.suffix == "s"
+    }
+}
+
+/// Returns the byte code of the given condition suffix
+pub fn condition_code(cond: &str) -> Option<u32> {
+    CONDITIONS
+        .iter()
+        .find(|(name, _)| *name == cond)
+        .map(|(_, code)| *code)
+}
+
+/// Tries to read `rest` as a condition and one of the allowed suffixes,
+/// in either order (`ldreqb` and `ldrbeq` are both accepted)
+fn split_suffixes(rest: &str, allowed: &[&'static str]) -> Option<(u32, &'static str)> {
+    if rest.is_empty() {
+        return Some((COND_ALWAYS, ""));
+    }
+    if let Some(code) = condition_code(rest) {
+        return Some((code, ""));
+    }
+    for suffix in allowed {
+        if rest == *suffix {
+            return Some((COND_ALWAYS, suffix));
+        }
+        if let Some(cond) = rest.strip_suffix(suffix) {
+            if let Some(code) = condition_code(cond) {
+                return Some((code, suffix));
+            }
+        }
+        if let Some(cond) = rest.strip_prefix(suffix) {
+            if let Some(code) = condition_code(cond) {
+                return Some((code, suffix));
+            }
+        }
+    }
+    None
+}
+
+/// Splits a mnemonic into base instruction, condition and suffix.
+/// Longer bases are tried first, so `bls` is `b` + `ls` but `bleq` is `bl` + `eq`
+pub fn split_mnemonic(mnemonic: &str) -> Result<Mnemonic, String> {
+    let lower = mnemonic.to_lowercase();
+    let mut candidates: Vec<&(&'static str, &[&'static str])> = MNEMONICS
+        .iter()
+        .filter(|(base, _)| lower.starts_with(base))
+        .collect();
+    candidates.sort_by_key(|(base, _)| std::cmp::Reverse(base.len()));
+
+    for (base, allowed) in candidates {
+        if let Some((cond, suffix)) = split_suffixes(&lower[base.len()..], allowed) {
+            return Ok(Mnemonic { base, cond, suffix });
+        }
+    }
+    Err(format!("unknown instruction `{}`", mnemonic))
+}
+
+/// Parses a register name (r0-r15, sp, lr, pc) into its index
+pub fn parse_register(operand: &str) -> Result<u32, String> {
+    let lower = operand.trim().to_lowercase();
+    match lower.as_str() {
+        "sp" => return Ok(13),
+        "lr" => return Ok(14),
+        "pc" => return Ok(15),
+        _ => {}
+    }
+    if let Some(index) = lower.strip_prefix('r') {
+        if let Ok(index) = index.parse::<u32>() {
+            if index < 16 {
+                return Ok(index);
+            }
+            return Err(format!("register {} does not exist", operand.trim()));
+        }
+    }
+    Err(format!("expected a register, found `{}`", operand.trim()))
+}
+
+/// Parses an integer literal, either decimal, hex (0x) or binary (0b),
+/// optionally preceded by a sign
+pub fn parse_number(literal: &str) -> Option<i64> {
+    let literal = literal.trim();
+    let (negative, digits) = match literal.strip_prefix('-') {
+        Some(rest) => (true, rest.trim_start()),
+        None => (
+            false,
+            literal.strip_prefix('+').unwrap_or(literal).trim_start(),
+        ),
+    };
+    let lower = digits.to_lowercase();
+    let value = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        lower.parse::<i64>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Evaluates an operand value: a constant expression of numbers and symbols
+pub fn evaluate(operand: &str, symbols: &SymbolTable) -> Result<i64, String> {
+    expression::evaluate_number(operand, symbols)
+}
+
+/// Evaluates an operand value that must fit in 32 bits (signed or unsigned).
+/// Range errors quote the operand, so the caret points at it
+pub fn evaluate_word(operand: &str, symbols: &SymbolTable) -> Result<u32, String> {
+    let value = evaluate(operand, symbols)?;
+    to_word(value).map_err(|_| {
+        format!(
+            "`{}` is {}, which does not fit in 32 bits",
+            operand.trim(),
+            value
+        )
+    })
+}
+
+/// The expression of an immediate operand of the form `#value`
+fn immediate_expression(operand: &str) -> Result<&str, String> {
+    operand.trim().strip_prefix('#').ok_or(format!(
+        "expected an immediate value, found `{}`",
+        operand.trim()
+    ))
+}
+
+/// Evaluates an immediate operand of the form `#value`
+pub fn parse_immediate(operand: &str, symbols: &SymbolTable) -> Result<i64, String> {
+    evaluate(immediate_expression(operand)?, symbols)
+}
+
+/// Evaluates an immediate operand of the form `#value` that must fit in 32 bits
+pub fn parse_word_immediate(operand: &str, symbols: &SymbolTable) -> Result<u32, String> {
+    evaluate_word(immediate_expression(operand)?, symbols)
+}
+
+/// Checks that a value fits in 32 bits (signed or unsigned) and truncates it
+pub fn to_word(value: i64) -> Result<u32, String> {
+    if value < i32::MIN as i64 || value > u32::MAX as i64 {
+        return Err(format!("value {} does not fit in 32 bits", value));
+    }
+    Ok(value as u32)
+}
+
+/// Encodes a value as an 8-bit immediate rotated right by an even amount.
+/// Returns the 12-bit operand (rotate << 8 | imm8) using the smallest rotation
+pub fn encode_rotated_immediate(value: u32) -> Option<u32> {
+    (0..16).find_map(|rotate: u32| {
+        let imm = value.rotate_left(rotate * 2);
+        if imm <= 0xFF {
+            Some((rotate << 8) | imm)
+        } else {
+            None
+        }
+    })
+}
+
+/// Splits the operand list of an instruction on the commas that are not
+/// nested inside brackets, braces or quotes
+pub fn split_operands(operands: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for c in operands.chars() {
+        if in_quotes {
+            current.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_quotes = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_quotes = true;
+                current.push(c);
+            }
+            '[' | '{' | '(' => {
+                depth += 1;
+                current.push(c);
+            }
+            ']' | '}' | ')' => {
+                depth -= 1;
+                current.push(c);
+            }
+            ',' if depth == 0 => {
+                result.push(current.trim().to_string());
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.trim().is_empty() || !result.is_empty() {
+        result.push(current.trim().to_string());
+    }
+    result
+}
+
+/// Checks that an instruction was given exactly `count` operands
+pub fn expect_operands(operands: &[String], count: usize, usage: &str) -> Result<(), String> {
+    if operands.len() != count {
+        return Err(format!(
+            "expected {} operands (`{}`), found {}",
+            count,
+            usage,
+            operands.len()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use crate::assembler::asm_utilities::*;
+
+/// Bits 25-27 of every block data transfer instruction
+const BLOCK_PATTERN: u32 = 0b100 << 25;
+/// Bit 24, set when the address is incremented or decremented before each transfer
+const PRE_INDEX_BIT: u32 = 1 << 24;
+/// Bit 23, set when the addresses go up from the base
+const UP_BIT: u32 = 1 << 23;
+/// Bit 22, set by `^` to transfer the user mode registers or restore the CPSR
+const USER_BANK_BIT: u32 = 1 << 22;
+/// Bit 21, set when the final address is written back into the base
+const WRITE_BACK_BIT: u32 = 1 << 21;
+/// Bit 20, set for loads
+const LOAD_BIT: u32 = 1 << 20;
+/// The stack pointer, r13
+const SP: u32 = 13;
+
+/// Returns the pre-index and up bits of an addressing mode suffix.
+/// The stack suffixes (full/empty, descending/ascending) mean
+/// different modes for loads and stores
+fn addressing_mode(suffix: &str, load: bool) -> u32 {
+    let mode = match (suffix, load) {
+        ("ib", _) | ("ed", true) | ("fa", false) => "ib",
+        ("da", _) | ("fa", true) | ("ed", false) => "da",
+        ("db", _) | ("ea", true) | ("fd", false) => "db",
+        _ => "ia",
+    };
+    match mode {
+        "ib" => PRE_INDEX_BIT | UP_BIT,
+        "da" => 0,
+        "db" => PRE_INDEX_BIT,
+        _ => UP_BIT,
+    }
+}
+
+/// Parses a register list such as `{r0-r3, r5, lr}` into a bit per register
+pub fn parse_register_list(operand: &str) -> EncodeResult {
+    let inner = operand
+        .trim()
+        .strip_prefix('{')
+        .and_then(|inner| inner.strip_suffix('}'))
+        .ok_or(format!(
+            "expected a register list in braces, found `{}`",
+            operand.trim()
+        ))?;
+
+    let mut list = 0;
+    for item in inner.split(',') {
+        let item = item.trim();
+        if item.is_empty() {
+            return Err(String::from("empty register in register list"));
+        }
+        list |= match item.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse_register(first)?, parse_register(last)?);
+                if first > last {
+                    return Err(format!("register range `{}` goes backwards", item));
+                }
+                (first..=last).fold(0, |list, reg| list | 1 << reg)
+            }
+            None => 1 << parse_register(item)?,
+        };
+    }
+    Ok(list)
+}
+
+/// Encodes `ldm/stm{mode} Rn{!}, {<registers>}{^}`,
+/// or `push/pop {<registers>}` which use the full descending stack at sp
+pub fn encode(mnemonic: &Mnemonic, operands: &[String]) -> EncodeResult {
+    let cond = mnemonic.cond << COND_SHIFT;
+    if mnemonic.base == "push" || mnemonic.base == "pop" {
+        expect_operands(operands, 1, &format!("{} {{<registers>}}", mnemonic.base))?;
+        let list = parse_register_list(&operands[0])?;
+        let bits = if mnemonic.base == "push" {
+            PRE_INDEX_BIT
+        } else {
+            UP_BIT | LOAD_BIT
+        };
+        return Ok(cond | BLOCK_PATTERN | bits | WRITE_BACK_BIT | (SP << RN_SHIFT) | list);
+    }
+
+    let usage = format!(
+        "{}{} Rn{{!}}, {{<registers>}}",
+        mnemonic.base, mnemonic.suffix
+    );
+    expect_operands(operands, 2, &usage)?;
+    let load = mnemonic.base == "ldm";
+    let (base, write_back) = match operands[0].trim().strip_suffix('!') {
+        Some(base) => (base, WRITE_BACK_BIT),
+        None => (operands[0].trim(), 0),
+    };
+    let rn = parse_register(base)?;
+    let (list, user_bank) = match operands[1].trim().strip_suffix('^') {
+        Some(list) => (list, USER_BANK_BIT),
+        None => (operands[1].trim(), 0),
+    };
+    let list = parse_register_list(list)?;
+
+    let mut bits = cond | BLOCK_PATTERN | addressing_mode(mnemonic.suffix, load);
+    bits |= user_bank | write_back | (rn << RN_SHIFT) | list;
+    if load {
+        bits |= LOAD_BIT;
+    }
+    Ok(bits)
+}
@@ -0,0 +1,92 @@
+use crate::assembler::{asm_utilities as util, symbol_table::SymbolTable};
+use util::*;
+
+/// Bits 25-27 of every branch instruction
+const BRANCH_PATTERN: u32 = 0b101 << 25;
+/// Bit 24, set for branch with link
+const LINK_BIT: u32 = 1 << 24;
+/// Bits 4-27 of BX, BLX with a register sets bit 5 on top
+const EXCHANGE_PATTERN: u32 = 0x12_fff1 << 4;
+/// Bit 5 of BX, set for BLX
+const EXCHANGE_LINK_BIT: u32 = 1 << 5;
+/// The condition field of BLX with an immediate offset, which can't be conditional
+const UNCONDITIONAL: u32 = 0b1111 << COND_SHIFT;
+/// Bit 24 of BLX with an immediate offset, adding a halfword to the target
+const HALFWORD_BIT: u32 = 1 << 24;
+/// Largest forward distance reachable with a signed 24-bit word offset
+const MAX_OFFSET: i64 = (1 << 25) - 4;
+const MIN_OFFSET: i64 = -(1 << 25);
+
+/// Encodes `b <target>` or `bl <target>`, where the target is a label or an address.
+/// The offset is relative to `address` + 8 because of the pipeline
+pub fn encode(
+    mnemonic: &Mnemonic,
+    operands: &[String],
+    address: u32,
+    symbols: &SymbolTable,
+) -> EncodeResult {
+    expect_operands(operands, 1, "b <label>")?;
+    let target = evaluate(&operands[0], symbols)?;
+    let offset = branch_offset(target, address)?;
+    if offset % 4 != 0 {
+        return Err(format!("branch target 0x{:x} is not word aligned", target));
+    }
+
+    let mut bits =
+        (mnemonic.cond << COND_SHIFT) | BRANCH_PATTERN | ((offset >> 2) as u32 & 0xFF_FFFF);
+    if mnemonic.base == "bl" {
+        bits |= LINK_BIT;
+    }
+    Ok(bits)
+}
+
+/// The offset of a branch target from the PC, checked to be in range
+fn branch_offset(target: i64, address: u32) -> Result<i64, String> {
+    let offset = target - (address as i64 + PIPELINE_OFFSET);
+    if !(MIN_OFFSET..=MAX_OFFSET).contains(&offset) {
+        return Err(format!("branch target 0x{:x} out of ±32MB range", target));
+    }
+    Ok(offset)
+}
+
+/// Encodes `bx Rm`, `blx Rm` or `blx <target>`. The register forms switch
+/// to Thumb state if bit 0 of the register is set, the immediate form always does
+pub fn encode_exchange(
+    mnemonic: &Mnemonic,
+    operands: &[String],
+    address: u32,
+    symbols: &SymbolTable,
+) -> EncodeResult {
+    let usage = format!("{} Rm", mnemonic.base);
+    expect_operands(operands, 1, &usage)?;
+    if let Ok(rm) = parse_register(&operands[0]) {
+        let mut bits = (mnemonic.cond << COND_SHIFT) | EXCHANGE_PATTERN | rm;
+        if mnemonic.base == "blx" {
+            bits |= EXCHANGE_LINK_BIT;
+        }
+        return Ok(bits);
+    }
+    if mnemonic.base == "bx" {
+        return Err(format!(
+            "expected a register, found `{}`",
+            operands[0].trim()
+        ));
+    }
+    if mnemonic.cond != COND_ALWAYS {
+        return Err(String::from("blx with a label can't be conditional"));
+    }
+
+    let target = evaluate(&operands[0], symbols)?;
+    let offset = branch_offset(target, address)?;
+    if offset % 2 != 0 {
+        return Err(format!(
+            "branch target 0x{:x} is not halfword aligned",
+            target
+        ));
+    }
+    let mut bits = UNCONDITIONAL | BRANCH_PATTERN | ((offset >> 2) as u32 & 0xFF_FFFF);
+    if offset & 2 != 0 {
+        bits |= HALFWORD_BIT;
+    }
+    Ok(bits)
+}
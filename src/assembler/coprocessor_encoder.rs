@@ -0,0 +1,233 @@
+use crate::assembler::{asm_utilities as util, symbol_table::SymbolTable};
+use util::*;
+
+/// Bits 24-27 of CDP, MCR and MRC
+const REGISTER_PATTERN: u32 = 0b1110 << 24;
+/// Bits 21-27 of MCRR and MRRC
+const DOUBLE_REGISTER_PATTERN: u32 = 0b110_0010 << 21;
+/// Bits 25-27 of LDC and STC
+const DATA_TRANSFER_PATTERN: u32 = 0b110 << 25;
+/// Bit 4, set for MCR and MRC
+const REGISTER_TRANSFER_BIT: u32 = 1 << 4;
+/// Bit 24, set when the offset is added before the transfer
+const PRE_INDEX_BIT: u32 = 1 << 24;
+/// Bit 23, set when the offset is added to the base
+const UP_BIT: u32 = 1 << 23;
+/// Bit 22, set for long transfers
+const LONG_BIT: u32 = 1 << 22;
+/// Bit 21, set when the address is written back into the base
+const WRITE_BACK_BIT: u32 = 1 << 21;
+/// Bit 20, set for transfers from the coprocessor
+const LOAD_BIT: u32 = 1 << 20;
+const COPROCESSOR_SHIFT: u32 = 8;
+const CRN_SHIFT: u32 = 16;
+const CRD_SHIFT: u32 = 12;
+const OPCODE2_SHIFT: u32 = 5;
+/// Largest word offset of LDC and STC, in bytes
+const MAX_OFFSET: i64 = 0xFF << 2;
+
+/// Parses a coprocessor name, `p0` to `p15`
+fn parse_coprocessor(operand: &str) -> Result<u32, String> {
+    let lower = operand.trim().to_lowercase();
+    match lower
+        .strip_prefix('p')
+        .and_then(|number| number.parse::<u32>().ok())
+    {
+        Some(number) if number < 16 => Ok(number),
+        _ => Err(format!(
+            "expected a coprocessor (p0-p15), found `{}`",
+            operand.trim()
+        )),
+    }
+}
+
+/// Parses a coprocessor register name, `c0` to `c15`
+fn parse_coprocessor_register(operand: &str) -> Result<u32, String> {
+    let lower = operand.trim().to_lowercase();
+    match lower
+        .strip_prefix('c')
+        .and_then(|number| number.parse::<u32>().ok())
+    {
+        Some(number) if number < 16 => Ok(number),
+        _ => Err(format!(
+            "expected a coprocessor register (c0-c15), found `{}`",
+            operand.trim()
+        )),
+    }
+}
+
+/// Parses an opcode operand, which has to fit in `max` and may start with `#`
+fn parse_opcode(operand: &str, max: i64, symbols: &SymbolTable) -> Result<u32, String> {
+    let operand = operand.trim();
+    let opcode = evaluate(operand.strip_prefix('#').unwrap_or(operand), symbols)?;
+    if !(0..=max).contains(&opcode) {
+        return Err(format!(
+            "coprocessor opcode {} out of range 0-{}",
+            opcode, max
+        ));
+    }
+    Ok(opcode as u32)
+}
+
+/// The optional opcode2 operand, 0 when left out
+fn parse_opcode2(operands: &[String], symbols: &SymbolTable) -> Result<u32, String> {
+    match operands.get(5) {
+        Some(operand) => parse_opcode(operand, 7, symbols),
+        None => Ok(0),
+    }
+}
+
+/// Encodes `cdp p<n>, <op1>, CRd, CRn, CRm{, <op2>}`,
+/// `mcr|mrc p<n>, <op1>, Rd, CRn, CRm{, <op2>}`
+/// and `mcrr|mrrc p<n>, <op1>, Rd, Rn, CRm`
+pub fn encode_register(
+    mnemonic: &Mnemonic,
+    operands: &[String],
+    symbols: &SymbolTable,
+) -> EncodeResult {
+    let cond = mnemonic.cond << COND_SHIFT;
+    let usage = match mnemonic.base {
+        "cdp" => "p<n>, <op1>, CRd, CRn, CRm{, <op2>}",
+        "mcrr" | "mrrc" => "p<n>, <op1>, Rd, Rn, CRm",
+        _ => "p<n>, <op1>, Rd, CRn, CRm{, <op2>}",
+    };
+    if operands.len() != 5 && (operands.len() != 6 || matches!(mnemonic.base, "mcrr" | "mrrc")) {
+        return Err(format!("`{}` expects `{}`", mnemonic.base, usage));
+    }
+    let coprocessor = parse_coprocessor(&operands[0])? << COPROCESSOR_SHIFT;
+    let crm = parse_coprocessor_register(&operands[4])?;
+
+    match mnemonic.base {
+        "cdp" => {
+            let opcode1 = parse_opcode(&operands[1], 15, symbols)?;
+            let crd = parse_coprocessor_register(&operands[2])?;
+            let crn = parse_coprocessor_register(&operands[3])?;
+            let opcode2 = parse_opcode2(operands, symbols)?;
+            Ok(cond
+                | REGISTER_PATTERN
+                | opcode1 << 20
+                | crn << CRN_SHIFT
+                | crd << CRD_SHIFT
+                | coprocessor
+                | opcode2 << OPCODE2_SHIFT
+                | crm)
+        }
+        "mcrr" | "mrrc" => {
+            let opcode = parse_opcode(&operands[1], 15, symbols)?;
+            let rd = parse_register(&operands[2])?;
+            let rn = parse_register(&operands[3])?;
+            let load = if mnemonic.base == "mrrc" { LOAD_BIT } else { 0 };
+            Ok(cond
+                | DOUBLE_REGISTER_PATTERN
+                | load
+                | rn << RN_SHIFT
+                | rd << RD_SHIFT
+                | coprocessor
+                | opcode << 4
+                | crm)
+        }
+        _ => {
+            let opcode1 = parse_opcode(&operands[1], 7, symbols)?;
+            let rd = parse_register(&operands[2])?;
+            let crn = parse_coprocessor_register(&operands[3])?;
+            let opcode2 = parse_opcode2(operands, symbols)?;
+            let load = if mnemonic.base == "mrc" { LOAD_BIT } else { 0 };
+            Ok(cond
+                | REGISTER_PATTERN
+                | opcode1 << 21
+                | load
+                | crn << CRN_SHIFT
+                | rd << RD_SHIFT
+                | coprocessor
+                | opcode2 << OPCODE2_SHIFT
+                | REGISTER_TRANSFER_BIT
+                | crm)
+        }
+    }
+}
+
+/// Encodes a `#±imm` word offset, returning the up bit and the offset in words
+fn encode_offset(operand: &str, symbols: &SymbolTable) -> EncodeResult {
+    let offset = parse_immediate(operand, symbols)?;
+    if offset.abs() > MAX_OFFSET || offset % 4 != 0 {
+        return Err(format!(
+            "offset #{} isn't a multiple of 4 up to {}",
+            offset, MAX_OFFSET
+        ));
+    }
+    let up = if offset >= 0 { UP_BIT } else { 0 };
+    Ok(up | (offset.unsigned_abs() as u32) >> 2)
+}
+
+/// Encodes `ldc|stc{l} p<n>, CRd, <address>`, where the address is one of `[Rn]`,
+/// `[Rn, #±imm]{!}`, `[Rn], #±imm` or `[Rn], {<option>}`
+pub fn encode_data_transfer(
+    mnemonic: &Mnemonic,
+    operands: &[String],
+    symbols: &SymbolTable,
+) -> EncodeResult {
+    if operands.len() != 3 && operands.len() != 4 {
+        return Err(format!(
+            "`{}` expects `p<n>, CRd, <address>`",
+            mnemonic.base
+        ));
+    }
+    let coprocessor = parse_coprocessor(&operands[0])? << COPROCESSOR_SHIFT;
+    let crd = parse_coprocessor_register(&operands[1])?;
+    let address = operands[2].trim();
+    let (inner, write_back) = match address.strip_suffix('!') {
+        Some(inner) => (inner.trim(), WRITE_BACK_BIT),
+        None => (address, 0),
+    };
+    let parts = inner
+        .strip_prefix('[')
+        .and_then(|inner| inner.strip_suffix(']'))
+        .map(split_operands)
+        .ok_or(format!(
+            "expected an address in brackets, found `{}`",
+            address
+        ))?;
+    if parts.is_empty() || parts.len() > 2 {
+        return Err(format!(
+            "expected `[Rn]` or `[Rn, #offset]`, found `{}`",
+            address
+        ));
+    }
+    let rn = parse_register(&parts[0])?;
+
+    let addressing = match operands.get(3) {
+        None => {
+            let offset = match parts.get(1) {
+                Some(offset) => encode_offset(offset, symbols)?,
+                None => UP_BIT,
+            };
+            PRE_INDEX_BIT | write_back | offset
+        }
+        Some(_) if parts.len() > 1 || write_back != 0 => {
+            return Err(String::from(
+                "post-indexed transfers take the offset after the brackets",
+            ));
+        }
+        Some(offset) => match offset.trim().strip_prefix('{') {
+            // Unindexed: the option is left to the coprocessor
+            Some(option) => {
+                let option = option
+                    .strip_suffix('}')
+                    .ok_or("expected `}` after the option")?;
+                UP_BIT | parse_opcode(option, 0xFF, symbols)?
+            }
+            None => WRITE_BACK_BIT | encode_offset(offset, symbols)?,
+        },
+    };
+
+    let load = if mnemonic.base == "ldc" { LOAD_BIT } else { 0 };
+    let long = if mnemonic.suffix == "l" { LONG_BIT } else { 0 };
+    Ok((mnemonic.cond << COND_SHIFT)
+        | DATA_TRANSFER_PATTERN
+        | addressing
+        | long
+        | load
+        | rn << RN_SHIFT
+        | crd << CRD_SHIFT
+        | coprocessor)
+}
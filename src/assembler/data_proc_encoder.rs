@@ -0,0 +1,257 @@
+use crate::assembler::{asm_utilities as util, symbol_table::SymbolTable};
+use util::*;
+
+/// Bit 25, set when operand2 is a rotated immediate
+const IMMEDIATE_BIT: u32 = 1 << 25;
+/// Bit 20, set when the instruction updates the CPSR flags
+const SET_FLAGS_BIT: u32 = 1 << 20;
+/// Bit 4 of a shifted register operand, set when the shift amount is in a register
+const SHIFT_BY_REGISTER_BIT: u32 = 1 << 4;
+
+/// The 4-bit opcode of a data processing mnemonic
+fn opcode(base: &str) -> Option<u32> {
+    let opcode = match base {
+        "and" => 0,
+        "eor" => 1,
+        "sub" => 2,
+        "rsb" => 3,
+        "add" => 4,
+        "adc" => 5,
+        "sbc" => 6,
+        "rsc" => 7,
+        "tst" => 8,
+        "teq" => 9,
+        "cmp" => 10,
+        "cmn" => 11,
+        "orr" => 12,
+        "mov" => 13,
+        "bic" => 14,
+        "mvn" => 15,
+        _ => return None,
+    };
+    Some(opcode)
+}
+
+/// The 2-bit shift type of a shift name
+fn shift_type(name: &str) -> Option<u32> {
+    match name {
+        "lsl" | "asl" => Some(0),
+        "lsr" => Some(1),
+        "asr" => Some(2),
+        "ror" => Some(3),
+        _ => None,
+    }
+}
+
+/// Encodes a shift applied to register `rm`, given as e.g. `lsr #2`, `asr r3` or `rrx`.
+/// Returns the low 12 bits of the operand
+fn encode_shift(rm: u32, shift: &str, symbols: &SymbolTable) -> EncodeResult {
+    let shift = shift.trim();
+    let lower = shift.to_lowercase();
+    if lower == "rrx" {
+        // rrx is encoded as ror #0
+        return Ok((3 << 5) | rm);
+    }
+
+    let (name, amount) = match shift.find(char::is_whitespace) {
+        Some(space) => (&lower[..space], shift[space..].trim()),
+        None => return Err(format!("expected a shift amount in `{}`", shift)),
+    };
+    let kind = shift_type(name).ok_or(format!("unknown shift `{}`", name))?;
+
+    if amount.starts_with('#') {
+        let amount = parse_immediate(amount, symbols)?;
+        let encoded = match (kind, amount) {
+            // A shift by 0 is the plain register, whatever the shift type
+            (_, 0) => return Ok(rm),
+            (0, 1..=31) | (3, 1..=31) => amount,
+            // lsr #32 and asr #32 are encoded as a shift by 0
+            (1, 1..=32) | (2, 1..=32) => amount % 32,
+            _ => {
+                return Err(format!(
+                    "shift amount #{} out of range for {}",
+                    amount, name
+                ))
+            }
+        } as u32;
+        Ok((encoded << 7) | (kind << 5) | rm)
+    } else {
+        let rs = parse_register(amount)?;
+        Ok((rs << 8) | (kind << 5) | SHIFT_BY_REGISTER_BIT | rm)
+    }
+}
+
+/// Encodes a register operand with an optional shift, given as the
+/// register followed by the shift operand (if any).
+/// Shared with single data transfer register offsets
+pub fn encode_shifted_register(operands: &[String], symbols: &SymbolTable) -> EncodeResult {
+    let rm = parse_register(&operands[0])?;
+    match operands.len() {
+        1 => Ok(rm),
+        2 => encode_shift(rm, &operands[1], symbols),
+        _ => Err(format!("unexpected operand `{}`", operands[2])),
+    }
+}
+
+/// For an immediate that cannot be encoded, the opcode that computes the same
+/// result using the complement or negation of the value
+fn alternative_opcode(opcode: u32, value: u32) -> Option<(u32, u32)> {
+    match opcode {
+        // and <-> bic, mov <-> mvn, adc <-> sbc use the bitwise complement
+        0 => Some((14, !value)),
+        14 => Some((0, !value)),
+        13 => Some((15, !value)),
+        15 => Some((13, !value)),
+        5 => Some((6, !value)),
+        6 => Some((5, !value)),
+        // add <-> sub, cmp <-> cmn use the negation
+        4 => Some((2, value.wrapping_neg())),
+        2 => Some((4, value.wrapping_neg())),
+        10 => Some((11, value.wrapping_neg())),
+        11 => Some((10, value.wrapping_neg())),
+        _ => None,
+    }
+}
+
+/// Encodes operand2, returning the final opcode (which might have been swapped
+/// to fit the immediate) and the operand bits, including the immediate bit
+fn encode_operand2(
+    opcode: u32,
+    operands: &[String],
+    symbols: &SymbolTable,
+) -> Result<(u32, u32), String> {
+    if operands[0].starts_with('#') {
+        if operands.len() > 1 {
+            return Err(format!("unexpected operand `{}`", operands[1]));
+        }
+        let value = parse_word_immediate(&operands[0], symbols)?;
+        if let Some(encoded) = encode_rotated_immediate(value) {
+            return Ok((opcode, IMMEDIATE_BIT | encoded));
+        }
+        if let Some((alternative, alt_value)) = alternative_opcode(opcode, value) {
+            if let Some(encoded) = encode_rotated_immediate(alt_value) {
+                return Ok((alternative, IMMEDIATE_BIT | encoded));
+            }
+        }
+        return Err(format!(
+            "immediate #0x{:x} cannot be encoded as rotated 8-bit, use ldr =",
+            value
+        ));
+    }
+    Ok((opcode, encode_shifted_register(operands, symbols)?))
+}
+
+/// Builds the final instruction word
+fn assemble_word(
+    mnemonic: &Mnemonic,
+    opcode: u32,
+    set_flags: bool,
+    rn: u32,
+    rd: u32,
+    operand2: u32,
+) -> u32 {
+    let s = if set_flags { SET_FLAGS_BIT } else { 0 };
+    (mnemonic.cond << COND_SHIFT)
+        | (opcode << 21)
+        | s
+        | (rn << RN_SHIFT)
+        | (rd << RD_SHIFT)
+        | operand2
+}
+
+/// Encodes a data processing instruction:
+/// `mov/mvn Rd, <op2>`, `tst/teq/cmp/cmn Rn, <op2>` or `<op> Rd, Rn, <op2>`
+pub fn encode(mnemonic: &Mnemonic, operands: &[String], symbols: &SymbolTable) -> EncodeResult {
+    let opcode = opcode(mnemonic.base).ok_or(format!(
+        "`{}` is not a data processing instruction",
+        mnemonic.base
+    ))?;
+
+    match opcode {
+        // mov, mvn: single operand
+        13 | 15 => {
+            if operands.len() < 2 {
+                return Err(format!("`{}` expects `Rd, <operand2>`", mnemonic.base));
+            }
+            let rd = parse_register(&operands[0])?;
+            let (opcode, operand2) = encode_operand2(opcode, &operands[1..], symbols)?;
+            Ok(assemble_word(
+                mnemonic,
+                opcode,
+                mnemonic.sets_flags(),
+                0,
+                rd,
+                operand2,
+            ))
+        }
+        // tst, teq, cmp, cmn: only set the flags
+        8..=11 => {
+            if operands.len() < 2 {
+                return Err(format!("`{}` expects `Rn, <operand2>`", mnemonic.base));
+            }
+            let rn = parse_register(&operands[0])?;
+            let (opcode, operand2) = encode_operand2(opcode, &operands[1..], symbols)?;
+            Ok(assemble_word(mnemonic, opcode, true, rn, 0, operand2))
+        }
+        _ => {
+            if operands.len() < 2 {
+                return Err(format!("`{}` expects `Rd, Rn, <operand2>`", mnemonic.base));
+            }
+            let rd = parse_register(&operands[0])?;
+            // `add r1, #3` is shorthand for `add r1, r1, #3`
+            let (rn, rest) = match parse_register(&operands[1]) {
+                Ok(rn) if operands.len() > 2 && !is_shift(&operands[2]) => (rn, &operands[2..]),
+                _ => (rd, &operands[1..]),
+            };
+            let (opcode, operand2) = encode_operand2(opcode, rest, symbols)?;
+            Ok(assemble_word(
+                mnemonic,
+                opcode,
+                mnemonic.sets_flags(),
+                rn,
+                rd,
+                operand2,
+            ))
+        }
+    }
+}
+
+/// Whether the operand is a shift (`lsl #2`, `rrx`) rather than a register or immediate
+fn is_shift(operand: &str) -> bool {
+    let lower = operand.trim().to_lowercase();
+    lower == "rrx"
+        || lower
+            .split_whitespace()
+            .next()
+            .is_some_and(|name| shift_type(name).is_some())
+}
+
+/// Encodes the shift-as-instruction form, e.g. `lsl Rd, #n` which is
+/// `mov Rd, Rd, lsl #n`, or `lsl Rd, Rm, Rs` which is `mov Rd, Rm, lsl Rs`
+pub fn encode_shift_instr(
+    mnemonic: &Mnemonic,
+    operands: &[String],
+    symbols: &SymbolTable,
+) -> EncodeResult {
+    let (rd, rm, amount) = match operands.len() {
+        2 => (&operands[0], &operands[0], &operands[1]),
+        3 => (&operands[0], &operands[1], &operands[2]),
+        _ => {
+            return Err(format!(
+                "`{}` expects `Rd, <amount>` or `Rd, Rm, <amount>`",
+                mnemonic.base
+            ))
+        }
+    };
+    let rd = parse_register(rd)?;
+    let rm = parse_register(rm)?;
+    let operand2 = encode_shift(rm, &format!("{} {}", mnemonic.base, amount), symbols)?;
+    Ok(assemble_word(
+        mnemonic,
+        13,
+        mnemonic.sets_flags(),
+        0,
+        rd,
+        operand2,
+    ))
+}
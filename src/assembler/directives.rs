@@ -0,0 +1,298 @@
+use crate::assembler::expression::evaluate_value;
+use crate::assembler::symbol_table::{Section, SymbolTable};
+use crate::assembler::{asm_utilities as util, parser::is_valid_label};
+use util::*;
+
+/// Largest amount of bytes a single directive may reserve
+const MAX_RESERVED: i64 = 1 << 24;
+
+/// Whether the directive defines a symbol (`.equ`, `.set`) instead of placing bytes
+pub fn is_symbol_directive(name: &str) -> bool {
+    matches!(name, ".equ" | ".set")
+}
+
+/// Whether the directive switches to another section
+pub fn is_section_directive(name: &str) -> bool {
+    matches!(name, ".text" | ".data" | ".section")
+}
+
+/// The section a `.text`, `.data` or `.section <name>` switches to
+pub fn section(name: &str, operands: &[String]) -> Result<Section, String> {
+    let name = match name {
+        ".section" => {
+            expect_operands(operands, 1, ".section <name>")?;
+            operands[0].trim()
+        }
+        _ => {
+            expect_operands(operands, 0, name)?;
+            name
+        }
+    };
+    Section::ALL
+        .iter()
+        .copied()
+        .find(|section| section.name() == name)
+        .ok_or(format!(
+            "unknown section `{}`, use `.text` or `.data`",
+            name
+        ))
+}
+
+/// Defines the symbol of an `.equ NAME, value` or `.set NAME, value`.
+/// `.set` may redefine a symbol, `.equ` may not.
+/// Symbols defined from a label are addresses in the label's section
+pub fn define_symbol(
+    name: &str,
+    operands: &[String],
+    symbols: &mut SymbolTable,
+) -> Result<(), String> {
+    expect_operands(operands, 2, &format!("{} NAME, <value>", name))?;
+    let symbol = operands[0].trim();
+    if !is_valid_label(symbol) {
+        return Err(format!("`{}` is not a valid symbol name", symbol));
+    }
+    let value = evaluate_value(&operands[1], symbols)?;
+    to_word(value.value)?;
+    if name == ".set" {
+        symbols.set(symbol, value);
+        Ok(())
+    } else {
+        symbols.define(symbol, value)
+    }
+}
+
+/// Evaluates an operand that must be known in the first pass,
+/// such as a size or an alignment, and checks it is in `0..=max`
+fn layout_value(operand: &str, max: i64, symbols: &SymbolTable) -> Result<u32, String> {
+    let value = evaluate(operand, symbols)?;
+    if !(0..=max).contains(&value) {
+        return Err(format!("value {} out of range (0 to {})", value, max));
+    }
+    Ok(value as u32)
+}
+
+/// The padding needed to bring `address` to a multiple of `alignment`
+fn padding(address: u32, alignment: u32) -> u32 {
+    (alignment - address % alignment) % alignment
+}
+
+/// The alignment in bytes an `.align` or `.balign` asks for
+pub fn alignment(name: &str, operands: &[String], symbols: &SymbolTable) -> Result<u32, String> {
+    if operands.is_empty() || operands.len() > 2 {
+        return Err(format!("`{}` expects `<alignment>{{, <fill>}}`", name));
+    }
+    if name == ".align" {
+        // On ARM, `.align n` aligns to 2^n bytes
+        return Ok(1 << layout_value(&operands[0], 16, symbols)?);
+    }
+    let alignment = layout_value(&operands[0], 1 << 16, symbols)?;
+    if !alignment.is_power_of_two() {
+        return Err(format!("alignment {} is not a power of 2", alignment));
+    }
+    Ok(alignment)
+}
+
+/// Size in bytes of each value of a data directive
+fn data_size(name: &str) -> Option<u32> {
+    match name {
+        ".word" | ".long" => Some(4),
+        ".hword" | ".short" => Some(2),
+        ".byte" => Some(1),
+        _ => None,
+    }
+}
+
+/// Computes how many bytes the directive at `address` places in the binary.
+/// Called by the first pass, so sizes must only depend on symbols defined earlier
+pub fn size(
+    name: &str,
+    operands: &[String],
+    address: u32,
+    symbols: &SymbolTable,
+) -> Result<u32, String> {
+    if let Some(size) = data_size(name) {
+        return Ok(size * operands.len() as u32);
+    }
+    match name {
+        ".ascii" | ".asciz" | ".string" => {
+            let mut size = 0;
+            for operand in operands {
+                size += parse_string(operand)?.len() as u32;
+                if name != ".ascii" {
+                    size += 1;
+                }
+            }
+            Ok(size)
+        }
+        ".space" | ".skip" => {
+            if operands.is_empty() || operands.len() > 2 {
+                return Err(format!("`{}` expects `<size>{{, <fill>}}`", name));
+            }
+            layout_value(&operands[0], MAX_RESERVED, symbols)
+        }
+        ".fill" => {
+            if operands.is_empty() || operands.len() > 3 {
+                return Err(String::from(
+                    "`.fill` expects `<repeat>{, <size>{, <value>}}`",
+                ));
+            }
+            let repeat = layout_value(&operands[0], MAX_RESERVED, symbols)?;
+            let size = match operands.get(1) {
+                Some(size) => layout_value(size, 8, symbols)?,
+                None => 1,
+            };
+            Ok(repeat * size)
+        }
+        ".align" | ".balign" => Ok(padding(address, alignment(name, operands, symbols)?)),
+        ".org" => {
+            expect_operands(operands, 1, ".org <address>")?;
+            let target = layout_value(&operands[0], u32::MAX as i64, symbols)?;
+            if target < address {
+                return Err(format!(
+                    "`.org 0x{:x}` would move the location counter backwards from 0x{:x}",
+                    target, address
+                ));
+            }
+            Ok(target - address)
+        }
+        _ => Err(format!("unknown directive `{}`", name)),
+    }
+}
+
+/// Checks that a value fits in `size` bytes, either signed or unsigned
+fn check_fits(value: i64, size: u32) -> Result<(), String> {
+    let bits = size * 8;
+    if bits < 64 && (value < -(1 << (bits - 1)) || value >= (1 << bits)) {
+        return Err(format!("value {} does not fit in {} byte(s)", value, size));
+    }
+    Ok(())
+}
+
+/// Appends the little endian bytes of a value
+fn push_value(bytes: &mut Vec<u8>, value: i64, size: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes()[..size as usize]);
+}
+
+/// Produces the `size` bytes (as computed by the first pass) the directive places
+pub fn emit(
+    name: &str,
+    operands: &[String],
+    size: u32,
+    symbols: &SymbolTable,
+) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(size as usize);
+    if let Some(value_size) = data_size(name) {
+        for operand in operands {
+            let value = evaluate(operand, symbols)?;
+            check_fits(value, value_size)?;
+            push_value(&mut bytes, value, value_size);
+        }
+        return Ok(bytes);
+    }
+
+    match name {
+        ".ascii" | ".asciz" | ".string" => {
+            for operand in operands {
+                bytes.extend(parse_string(operand)?);
+                if name != ".ascii" {
+                    bytes.push(0);
+                }
+            }
+        }
+        ".space" | ".skip" | ".align" | ".balign" => {
+            let fill = match operands.get(1) {
+                Some(fill) => {
+                    let fill = evaluate(fill, symbols)?;
+                    check_fits(fill, 1)?;
+                    fill as u8
+                }
+                None => 0,
+            };
+            bytes.resize(size as usize, fill);
+        }
+        ".fill" => {
+            let value_size = match operands.get(1) {
+                Some(value_size) => layout_value(value_size, 8, symbols)?,
+                None => 1,
+            };
+            let value = match operands.get(2) {
+                Some(value) => evaluate(value, symbols)?,
+                None => 0,
+            };
+            for _ in 0..size.checked_div(value_size).unwrap_or(0) {
+                push_value(&mut bytes, value, value_size);
+            }
+        }
+        _ => bytes.resize(size as usize, 0),
+    }
+    Ok(bytes)
+}
+
+/// Parses a double quoted string literal, resolving the escape sequences
+/// `\n`, `\t`, `\r`, `\0`, `\\`, `\"`, `\xHH` and octal `\NNN`
+pub fn parse_string(literal: &str) -> Result<Vec<u8>, String> {
+    let inner = literal
+        .trim()
+        .strip_prefix('"')
+        .and_then(|inner| inner.strip_suffix('"'))
+        .ok_or(format!(
+            "expected a string in double quotes, found `{}`",
+            literal.trim()
+        ))?;
+
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        let escaped = chars
+            .next()
+            .ok_or("unfinished escape sequence at the end of the string")?;
+        match escaped {
+            'n' => bytes.push(b'\n'),
+            't' => bytes.push(b'\t'),
+            'r' => bytes.push(b'\r'),
+            'a' => bytes.push(7),
+            'b' => bytes.push(8),
+            'f' => bytes.push(12),
+            'v' => bytes.push(11),
+            '\\' => bytes.push(b'\\'),
+            '"' => bytes.push(b'"'),
+            '\'' => bytes.push(b'\''),
+            'x' => {
+                let mut value: u32 = 0;
+                let mut digits = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) {
+                    value = (value << 4) | digit;
+                    digits += 1;
+                    chars.next();
+                }
+                if digits == 0 || value > 0xFF {
+                    return Err(String::from("invalid `\\x` escape sequence"));
+                }
+                bytes.push(value as u8);
+            }
+            '0'..='7' => {
+                let mut value = escaped.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = (value << 3) | digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                if value > 0xFF {
+                    return Err(String::from("octal escape sequence out of range"));
+                }
+                bytes.push(value as u8);
+            }
+            other => return Err(format!("unknown escape sequence `\\{}`", other)),
+        }
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,267 @@
+use crate::assembler::asm_utilities::parse_number;
+use crate::assembler::symbol_table::{SymbolTable, Value};
+
+/// Binary operators, longest first so that `<<` isn't read as `<`
+const OPERATORS: [&str; 18] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "&", "|", "^", "<",
+    ">",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    /// A label or constant, or `.` for the location counter
+    Symbol(String),
+    Operator(&'static str),
+    /// One of the unary `~` and `!`
+    Unary(char),
+    Open,
+    Close,
+}
+
+/// Precedence of a binary operator, as in C. Higher binds tighter
+fn precedence(operator: &str) -> u8 {
+    match operator {
+        "*" | "/" | "%" => 10,
+        "+" | "-" => 9,
+        "<<" | ">>" => 8,
+        "<" | "<=" | ">" | ">=" => 7,
+        "==" | "!=" => 6,
+        "&" => 5,
+        "^" => 4,
+        "|" => 3,
+        "&&" => 2,
+        _ => 1,
+    }
+}
+
+/// Whether `c` can be part of a label or a number
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'
+}
+
+/// Splits an expression into tokens
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let length = if c.is_ascii_digit() {
+            let length = rest.find(|c| !is_symbol_char(c)).unwrap_or(rest.len());
+            let value = parse_number(&rest[..length])
+                .ok_or(format!("invalid number `{}`", &rest[..length]))?;
+            tokens.push(Token::Number(value));
+            length
+        } else if is_symbol_char(c) {
+            let length = rest.find(|c| !is_symbol_char(c)).unwrap_or(rest.len());
+            tokens.push(Token::Symbol(rest[..length].to_string()));
+            length
+        } else if c == '\'' {
+            // A character constant such as 'a'
+            let mut chars = rest.chars().skip(1);
+            match (chars.next(), chars.next()) {
+                (Some(value), Some('\'')) => tokens.push(Token::Number(value as i64)),
+                _ => {
+                    let constant: String = rest.chars().take(3).collect();
+                    return Err(format!("invalid character constant `{}`", constant));
+                }
+            }
+            2 + rest[1..].chars().next().unwrap().len_utf8()
+        } else if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            1
+        } else if let Some(operator) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Operator(operator));
+            operator.len()
+        } else if c == '~' || c == '!' {
+            tokens.push(Token::Unary(c));
+            1
+        } else {
+            return Err(format!("unexpected `{}` in expression", c));
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// The number a value stands for. Addresses are only known
+/// once their section has been placed
+fn resolve(value: Value, symbols: &SymbolTable) -> Result<i64, String> {
+    match value.section {
+        Some(section) if !symbols.is_placed(section) => Err(format!(
+            "addresses in `{}` are only known after the first pass",
+            section.name()
+        )),
+        _ => Ok(value.value),
+    }
+}
+
+/// Precedence climbing parser that evaluates as it goes
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    symbols: &'a SymbolTable,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Parses binary operators binding at least as tight as `min_precedence`
+    fn binary(&mut self, min_precedence: u8) -> Result<Value, String> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Operator(operator)) = self.peek() {
+            let operator = *operator;
+            let precedence = precedence(operator);
+            if precedence < min_precedence {
+                break;
+            }
+            self.position += 1;
+            let rhs = self.binary(precedence + 1)?;
+            lhs = self.apply(operator, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Value::absolute(value)),
+            Some(Token::Symbol(name)) if name == "." => self
+                .symbols
+                .location()
+                .ok_or_else(|| String::from("the location counter `.` can't be used here")),
+            Some(Token::Symbol(name)) => self
+                .symbols
+                .get(&name)
+                .ok_or(format!("undefined label `{}`", name)),
+            Some(Token::Open) => {
+                let value = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(value),
+                    _ => Err(String::from("missing `)` in expression")),
+                }
+            }
+            Some(Token::Operator("+")) => self.unary(),
+            Some(Token::Operator("-")) => {
+                let value = self.unary()?;
+                Ok(Value::absolute(
+                    resolve(value, self.symbols)?.wrapping_neg(),
+                ))
+            }
+            Some(Token::Unary(operator)) => {
+                let value = self.unary()?;
+                let value = resolve(value, self.symbols)?;
+                Ok(Value::absolute(if operator == '~' {
+                    !value
+                } else {
+                    (value == 0) as i64
+                }))
+            }
+            Some(Token::Close) => Err(String::from("unexpected `)` in expression")),
+            Some(Token::Operator(operator)) => {
+                Err(format!("expected a value before `{}`", operator))
+            }
+            None => Err(String::from("expected an expression")),
+        }
+    }
+
+    /// Applies a binary operator. Adding a number to an address
+    /// or subtracting two addresses of the same section work with any layout,
+    /// the other operators need the final addresses
+    fn apply(&self, operator: &str, lhs: Value, rhs: Value) -> Result<Value, String> {
+        let relative = |value: i64, section| Value {
+            value,
+            section: Some(section),
+        };
+        match (operator, lhs.section, rhs.section) {
+            ("+", Some(section), None) | ("-", Some(section), None) => {
+                let offset = if operator == "+" {
+                    rhs.value
+                } else {
+                    -rhs.value
+                };
+                return Ok(relative(lhs.value.wrapping_add(offset), section));
+            }
+            ("+", None, Some(section)) => {
+                return Ok(relative(lhs.value.wrapping_add(rhs.value), section));
+            }
+            ("-", Some(left), Some(right)) if left == right => {
+                return Ok(Value::absolute(lhs.value - rhs.value));
+            }
+            ("-", Some(left), Some(right)) => {
+                return Err(format!(
+                    "cannot subtract an address in `{}` from one in `{}`",
+                    right.name(),
+                    left.name()
+                ));
+            }
+            _ => {}
+        }
+
+        let (lhs, rhs) = (resolve(lhs, self.symbols)?, resolve(rhs, self.symbols)?);
+        let shift = |amount: i64| {
+            if (0..64).contains(&amount) {
+                Ok(amount as u32)
+            } else {
+                Err(format!("shift amount {} out of range", amount))
+            }
+        };
+        let value = match operator {
+            "*" => lhs.wrapping_mul(rhs),
+            "/" => lhs.checked_div(rhs).ok_or("division by zero")?,
+            "%" => lhs.checked_rem(rhs).ok_or("division by zero")?,
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "<<" => lhs.wrapping_shl(shift(rhs)?),
+            ">>" => lhs >> shift(rhs)?,
+            "<" => (lhs < rhs) as i64,
+            "<=" => (lhs <= rhs) as i64,
+            ">" => (lhs > rhs) as i64,
+            ">=" => (lhs >= rhs) as i64,
+            "==" => (lhs == rhs) as i64,
+            "!=" => (lhs != rhs) as i64,
+            "&" => lhs & rhs,
+            "^" => lhs ^ rhs,
+            "|" => lhs | rhs,
+            "&&" => (lhs != 0 && rhs != 0) as i64,
+            _ => (lhs != 0 || rhs != 0) as i64,
+        };
+        Ok(Value::absolute(value))
+    }
+}
+
+/// Whether two expressions are made of the same tokens, however they are spaced
+pub fn same_tokens(expression: &str, other: &str) -> bool {
+    match (tokenize(expression), tokenize(other)) {
+        (Ok(tokens), Ok(other_tokens)) => tokens == other_tokens,
+        _ => expression == other,
+    }
+}
+
+/// Evaluates a constant expression with C operators and precedence.
+/// The result is relative to a section if it is an address
+pub fn evaluate_value(expression: &str, symbols: &SymbolTable) -> Result<Value, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        position: 0,
+        symbols,
+    };
+    let value = parser.binary(0)?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(Token::Close) => Err(String::from("unexpected `)` in expression")),
+        Some(_) => Err(format!("unexpected operand in `{}`", expression.trim())),
+    }
+}
+
+/// Evaluates a constant expression to a number,
+/// failing for addresses that aren't known yet
+pub fn evaluate_number(expression: &str, symbols: &SymbolTable) -> Result<i64, String> {
+    resolve(evaluate_value(expression, symbols)?, symbols)
+}
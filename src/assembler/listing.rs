@@ -0,0 +1,79 @@
+use crate::assembler::asm_utilities::*;
+
+/// Directives such as `.space` may place a lot of bytes,
+/// only the first ones are shown in the listing
+const MAX_LISTED_BYTES: usize = 32;
+
+/// Marks the lines that come from a macro expansion
+const MACRO_MARKER: char = '+';
+
+/// Formats a listing line: address, encoding, macro marker, location and source
+fn line(address: u32, encoding: &str, from_macro: bool, location: &Location, text: &str) -> String {
+    let marker = if from_macro { MACRO_MARKER } else { ' ' };
+    let location = location.to_string();
+    format!(
+        "{:0>8x}  {:<8} {} {:<16} {}",
+        address,
+        encoding,
+        marker,
+        location,
+        text.trim_end()
+    )
+}
+
+/// Lists an instruction or a literal, showing the encoded word
+pub fn word_line(
+    address: u32,
+    word: u32,
+    from_macro: bool,
+    location: &Location,
+    text: &str,
+) -> String {
+    line(
+        address,
+        &format!("{:0>8x}", word),
+        from_macro,
+        location,
+        text,
+    )
+}
+
+/// Lists a statement placing raw bytes, four per line in memory order.
+/// Statements that place no bytes, such as labels, only show their address
+pub fn data_lines(
+    address: u32,
+    bytes: &[u8],
+    from_macro: bool,
+    location: &Location,
+    text: &str,
+) -> Vec<String> {
+    let hex = |chunk: &[u8]| {
+        chunk
+            .iter()
+            .map(|byte| format!("{:0>2x}", byte))
+            .collect::<String>()
+    };
+    let mut chunks = bytes.chunks(4);
+    let first = chunks.next().map(hex).unwrap_or_default();
+    let mut lines = vec![line(address, &first, from_macro, location, text)];
+
+    for (ind, chunk) in chunks.enumerate() {
+        let chunk_address = address + 4 * (ind as u32 + 1);
+        if (chunk_address - address) as usize >= MAX_LISTED_BYTES {
+            lines.push(format!("{:0>8x}  ...", chunk_address));
+            break;
+        }
+        lines.push(format!("{:0>8x}  {}", chunk_address, hex(chunk)));
+    }
+    lines
+}
+
+/// Renders the `.sym` map: one `<address> <label>` line per label, by address
+pub fn symbol_map(labels: &[(String, u32)]) -> String {
+    let mut labels: Vec<&(String, u32)> = labels.iter().collect();
+    labels.sort_by_key(|(_, address)| *address);
+    labels
+        .iter()
+        .map(|(label, address)| format!("{:0>8x} {}\n", address, label))
+        .collect()
+}
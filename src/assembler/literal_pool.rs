@@ -0,0 +1,116 @@
+use crate::assembler::expression::same_tokens;
+use crate::assembler::{asm_utilities as util, symbol_table::SymbolTable};
+use util::*;
+
+/// The value of an `ldr Rd, =<value>` constant if it can be loaded
+/// with a `mov` instead of going through a literal pool.
+/// In the first pass only constants and labels defined earlier are known,
+/// anything else goes to the pool
+pub fn immediate_value(expression: &str, symbols: &SymbolTable) -> Option<u32> {
+    let value = evaluate_word(expression, symbols).ok()?;
+    encode_rotated_immediate(value).map(|_| value)
+}
+
+/// A constant waiting to be placed in a literal pool
+#[derive(Debug)]
+pub struct Literal {
+    /// The expression after the `=`, evaluated in the second pass
+    pub expression: String,
+    /// Where the first load that uses this constant is
+    pub location: Location,
+    /// The source line of that load
+    pub text: String,
+    /// Value of the expression if it is a plain number, used for deduplication
+    value: Option<i64>,
+}
+
+/// A pool that has been given its place in the binary
+#[derive(Debug)]
+pub struct PlacedPool {
+    pub address: u32,
+    pub literals: Vec<Literal>,
+}
+
+impl PlacedPool {
+    /// Address of the literal at the given index
+    pub fn literal_address(&self, index: usize) -> u32 {
+        self.address + (index as u32) * INSTRUCTION_SIZE
+    }
+
+    /// Size in bytes of the pool
+    pub fn size(&self) -> u32 {
+        (self.literals.len() as u32) * INSTRUCTION_SIZE
+    }
+}
+
+/// Collects the constants of `ldr Rd, =<value>` loads until an `.ltorg`
+/// (or the end of the program) places them after the code
+#[derive(Debug, Default)]
+pub struct LiteralPool {
+    pending: Vec<Literal>,
+    placed: Vec<PlacedPool>,
+}
+
+impl LiteralPool {
+    pub fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            placed: Vec::new(),
+        }
+    }
+
+    /// Adds a constant to the pending pool, reusing an equal one if present.
+    /// Returns the pool it will end up in and its index inside that pool
+    pub fn add(&mut self, expression: &str, location: &Location, text: &str) -> (usize, usize) {
+        let expression = expression.trim().to_string();
+        let value = parse_number(&expression);
+        let existing = self
+            .pending
+            .iter()
+            .position(|literal| match (value, literal.value) {
+                (Some(value), Some(other)) => value as u32 == other as u32,
+                _ => same_tokens(&literal.expression, &expression),
+            });
+
+        let index = existing.unwrap_or_else(|| {
+            self.pending.push(Literal {
+                expression,
+                location: location.clone(),
+                text: text.to_string(),
+                value,
+            });
+            self.pending.len() - 1
+        });
+        (self.placed.len(), index)
+    }
+
+    /// Places the pending constants at the given (word aligned) address.
+    /// Returns the size of the pool in bytes
+    pub fn flush(&mut self, address: u32) -> u32 {
+        if self.pending.is_empty() {
+            return 0;
+        }
+        let pool = PlacedPool {
+            address,
+            literals: std::mem::take(&mut self.pending),
+        };
+        let size = pool.size();
+        self.placed.push(pool);
+        size
+    }
+
+    /// Moves a placed pool by `offset` bytes, once its section has been placed
+    pub fn relocate(&mut self, id: usize, offset: u32) {
+        self.placed[id].address += offset;
+    }
+
+    /// Gets a placed pool by its id
+    pub fn pool(&self, id: usize) -> &PlacedPool {
+        &self.placed[id]
+    }
+
+    /// All the pools placed so far
+    pub fn pools(&self) -> &[PlacedPool] {
+        &self.placed
+    }
+}
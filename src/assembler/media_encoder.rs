@@ -0,0 +1,246 @@
+use crate::assembler::{asm_utilities as util, data_proc_encoder, symbol_table::SymbolTable};
+use util::*;
+
+/// Bits 25-27 and 4 of every media instruction
+const MEDIA_PATTERN: u32 = 0b011 << 25 | 1 << 4;
+/// Bits 8-11 of the instructions that don't use them
+const SBO_BITS: u32 = 0b1111 << 8;
+/// Bit 22, set for the unsigned forms
+const UNSIGNED_BIT: u32 = 1 << 22;
+const PC: u32 = 15;
+
+/// The prefixes of the parallel additions and subtractions, with bits 20-22 they set
+const PARALLEL_PREFIXES: [(&str, u32); 6] = [
+    ("uq", 0b110),
+    ("uh", 0b111),
+    ("sh", 0b011),
+    ("s", 0b001),
+    ("q", 0b010),
+    ("u", 0b101),
+];
+
+/// The operations of the parallel additions and subtractions, with bits 5-7 they set.
+/// The old `addsubx` and `subaddx` names are accepted for `asx` and `sax`
+const PARALLEL_OPERATIONS: [(&str, u32); 8] = [
+    ("add16", 0b000),
+    ("asx", 0b001),
+    ("addsubx", 0b001),
+    ("sax", 0b010),
+    ("subaddx", 0b010),
+    ("sub16", 0b011),
+    ("add8", 0b100),
+    ("sub8", 0b111),
+];
+
+/// Bits 20-22 of the extends, the adding forms using the same ones
+const EXTENDS: [(&str, u32); 6] = [
+    ("sxtb16", 0b000),
+    ("sxtb", 0b010),
+    ("sxth", 0b011),
+    ("uxtb16", 0b100),
+    ("uxtb", 0b110),
+    ("uxth", 0b111),
+];
+
+/// Whether a mnemonic is one of the parallel additions and subtractions,
+/// as opposed to QADD and QSUB, which share their base
+pub fn is_parallel(mnemonic: &Mnemonic) -> bool {
+    parallel_opcodes(mnemonic).is_some()
+}
+
+/// Bits 20-22 and 5-7 of a parallel addition or subtraction
+fn parallel_opcodes(mnemonic: &Mnemonic) -> Option<(u32, u32)> {
+    let name = format!("{}{}", mnemonic.base, mnemonic.suffix);
+    PARALLEL_PREFIXES.iter().find_map(|(prefix, op1)| {
+        let operation = name.strip_prefix(prefix)?;
+        PARALLEL_OPERATIONS
+            .iter()
+            .find(|(candidate, _)| *candidate == operation)
+            .map(|(_, op2)| (*op1, *op2))
+    })
+}
+
+/// Parses registers `Rd, Rn, Rm`
+fn parse_three_registers(operands: &[String], usage: &str) -> Result<(u32, u32, u32), String> {
+    expect_operands(operands, 3, usage)?;
+    Ok((
+        parse_register(&operands[0])?,
+        parse_register(&operands[1])?,
+        parse_register(&operands[2])?,
+    ))
+}
+
+/// Encodes the parallel additions and subtractions, such as `uadd8 Rd, Rn, Rm`
+/// or `qsax Rd, Rn, Rm`
+pub fn encode_parallel(mnemonic: &Mnemonic, operands: &[String]) -> EncodeResult {
+    let (op1, op2) = parallel_opcodes(mnemonic).ok_or(format!(
+        "unknown instruction `{}{}`",
+        mnemonic.base, mnemonic.suffix
+    ))?;
+    let (rd, rn, rm) = parse_three_registers(operands, "Rd, Rn, Rm")?;
+    Ok((mnemonic.cond << COND_SHIFT)
+        | MEDIA_PATTERN
+        | op1 << 20
+        | rn << RN_SHIFT
+        | rd << RD_SHIFT
+        | SBO_BITS
+        | op2 << 5
+        | rm)
+}
+
+/// Encodes `sel Rd, Rn, Rm`
+pub fn encode_select(mnemonic: &Mnemonic, operands: &[String]) -> EncodeResult {
+    let (rd, rn, rm) = parse_three_registers(operands, "sel Rd, Rn, Rm")?;
+    Ok((mnemonic.cond << COND_SHIFT)
+        | MEDIA_PATTERN
+        | 0b1000 << 20
+        | rn << RN_SHIFT
+        | rd << RD_SHIFT
+        | SBO_BITS
+        | 0b101 << 5
+        | rm)
+}
+
+/// Encodes `rev Rd, Rm`, `rev16 Rd, Rm` and `revsh Rd, Rm`
+pub fn encode_reverse(mnemonic: &Mnemonic, operands: &[String]) -> EncodeResult {
+    expect_operands(operands, 2, &format!("rev{} Rd, Rm", mnemonic.suffix))?;
+    let rd = parse_register(&operands[0])?;
+    let rm = parse_register(&operands[1])?;
+    let (op1, op2) = match mnemonic.suffix {
+        "16" => (0b1011, 0b101),
+        "sh" => (0b1111, 0b101),
+        _ => (0b1011, 0b001),
+    };
+    Ok((mnemonic.cond << COND_SHIFT)
+        | MEDIA_PATTERN
+        | op1 << 20
+        | 0b1111 << RN_SHIFT
+        | rd << RD_SHIFT
+        | SBO_BITS
+        | op2 << 5
+        | rm)
+}
+
+/// Encodes the extends, `sxtb Rd, Rm{, ror #<rotation>}`, and their adding forms,
+/// `sxtab Rd, Rn, Rm{, ror #<rotation>}`, where the rotation is 0, 8, 16 or 24
+pub fn encode_extend(
+    mnemonic: &Mnemonic,
+    operands: &[String],
+    symbols: &SymbolTable,
+) -> EncodeResult {
+    // The adding forms have an `a` after `sxt` or `uxt`
+    let adds = mnemonic.base.as_bytes()[3] == b'a';
+    let name = format!(
+        "{}{}{}",
+        &mnemonic.base[..3],
+        &mnemonic.base[3 + adds as usize..],
+        mnemonic.suffix
+    );
+    let op1 = EXTENDS
+        .iter()
+        .find(|(candidate, _)| *candidate == name)
+        .map(|(_, op1)| *op1)
+        .ok_or(format!("unknown instruction `{}`", name))?;
+
+    let registers = if adds { 3 } else { 2 };
+    if operands.len() != registers && operands.len() != registers + 1 {
+        return Err(format!(
+            "`{}{}` expects `{}Rm{{, ror #<rotation>}}`",
+            mnemonic.base,
+            mnemonic.suffix,
+            if adds { "Rd, Rn, " } else { "Rd, " }
+        ));
+    }
+    let rd = parse_register(&operands[0])?;
+    let rn = if adds {
+        parse_register(&operands[1])?
+    } else {
+        PC
+    };
+    let rm = parse_register(&operands[registers - 1])?;
+    let rotation = match operands.get(registers) {
+        Some(rotation) => {
+            let lower = rotation.trim().to_lowercase();
+            let amount = lower.strip_prefix("ror").ok_or(format!(
+                "expected a rotation `ror #<rotation>`, found `{}`",
+                rotation
+            ))?;
+            match parse_immediate(amount, symbols)? {
+                amount @ (0 | 8 | 16 | 24) => amount as u32 / 8,
+                amount => return Err(format!("rotation #{} must be 0, 8, 16 or 24", amount)),
+            }
+        }
+        None => 0,
+    };
+
+    Ok((mnemonic.cond << COND_SHIFT)
+        | MEDIA_PATTERN
+        | 0b1000 << 20
+        | op1 << 20
+        | rn << RN_SHIFT
+        | rd << RD_SHIFT
+        | rotation << 10
+        | 0b011 << 5
+        | rm)
+}
+
+/// Encodes `ssat Rd, #<width>, Rm{, lsl|asr #<amount>}` and its unsigned and halfword forms,
+/// `usat`, `ssat16 Rd, #<width>, Rm` and `usat16`. Signed widths go from 1 to 32
+/// (16 for halfwords) and unsigned ones from 0 to 31 (15)
+pub fn encode_saturate(
+    mnemonic: &Mnemonic,
+    operands: &[String],
+    symbols: &SymbolTable,
+) -> EncodeResult {
+    let unsigned = mnemonic.base == "usat";
+    let halfwords = mnemonic.suffix == "16";
+    if operands.len() != 3 && (halfwords || operands.len() != 4) {
+        let shift = if halfwords {
+            ""
+        } else {
+            "{, lsl|asr #<amount>}"
+        };
+        return Err(format!(
+            "`{}{}` expects `Rd, #<width>, Rm{}`",
+            mnemonic.base, mnemonic.suffix, shift
+        ));
+    }
+
+    let rd = parse_register(&operands[0])?;
+    let width = parse_immediate(&operands[1], symbols)?;
+    let widest = if halfwords { 16 } else { 32 };
+    let (lowest, field) = if unsigned { (0, width) } else { (1, width - 1) };
+    if width < lowest || width > widest - 1 + lowest {
+        return Err(format!(
+            "saturation width #{} out of range for {}{}, which takes {} to {}",
+            width,
+            mnemonic.base,
+            mnemonic.suffix,
+            lowest,
+            widest - 1 + lowest
+        ));
+    }
+    let unsigned = if unsigned { UNSIGNED_BIT } else { 0 };
+
+    let mut bits = (mnemonic.cond << COND_SHIFT)
+        | MEDIA_PATTERN
+        | 0b1010 << 20
+        | unsigned
+        | (field as u32) << 16
+        | rd << RD_SHIFT;
+    if halfwords {
+        bits |= SBO_BITS | 0b001 << 5 | parse_register(&operands[2])?;
+    } else {
+        // The shift is encoded the same way as for a shifted register, only lsl and asr by
+        // an immediate being allowed
+        let shifted = data_proc_encoder::encode_shifted_register(&operands[2..], symbols)?;
+        if shifted & 0b11 << 4 != 0 {
+            return Err(format!(
+                "`{}` can only shift Rm by lsl or asr #<amount>",
+                mnemonic.base
+            ));
+        }
+        bits |= shifted;
+    }
+    Ok(bits)
+}
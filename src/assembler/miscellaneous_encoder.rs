@@ -0,0 +1,41 @@
+use crate::assembler::asm_utilities::*;
+
+/// Bits 23-27 of the miscellaneous instructions
+const MISCELLANEOUS_PATTERN: u32 = 0b00010 << 23;
+
+/// Encodes `clz Rd, Rm`
+pub fn encode_count_leading_zeros(mnemonic: &Mnemonic, operands: &[String]) -> EncodeResult {
+    expect_operands(operands, 2, "clz Rd, Rm")?;
+    let rd = parse_register(&operands[0])?;
+    let rm = parse_register(&operands[1])?;
+    Ok((mnemonic.cond << COND_SHIFT)
+        | MISCELLANEOUS_PATTERN
+        | 0b11 << 21
+        | 0b1111 << RN_SHIFT
+        | rd << RD_SHIFT
+        | 0b1111 << 8
+        | 0b0001 << 4
+        | rm)
+}
+
+/// Encodes the saturating arithmetic, `qadd/qsub/qdadd/qdsub Rd, Rm, Rn`,
+/// where QDADD and QDSUB double Rn first
+pub fn encode_saturating(mnemonic: &Mnemonic, operands: &[String]) -> EncodeResult {
+    expect_operands(operands, 3, &format!("{} Rd, Rm, Rn", mnemonic.base))?;
+    let rd = parse_register(&operands[0])?;
+    let rm = parse_register(&operands[1])?;
+    let rn = parse_register(&operands[2])?;
+    let op = match mnemonic.base {
+        "qadd" => 0b00,
+        "qsub" => 0b01,
+        "qdadd" => 0b10,
+        _ => 0b11,
+    };
+    Ok((mnemonic.cond << COND_SHIFT)
+        | MISCELLANEOUS_PATTERN
+        | op << 21
+        | rn << RN_SHIFT
+        | rd << RD_SHIFT
+        | 0b0101 << 4
+        | rm)
+}
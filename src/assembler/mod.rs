@@ -0,0 +1,22 @@
+/// Module that contains all the assembler submodules
+pub mod asm_utilities;
+
+pub mod block_data_transfer_encoder;
+pub mod branch_encoder;
+pub mod coprocessor_encoder;
+pub mod data_proc_encoder;
+pub mod directives;
+pub mod expression;
+pub mod listing;
+pub mod literal_pool;
+pub mod media_encoder;
+pub mod miscellaneous_encoder;
+pub mod multiply_encoder;
+pub mod parser;
+pub mod preprocessor;
+pub mod psr_transfer_encoder;
+pub mod single_data_transfer_encoder;
+pub mod software_interrupt_encoder;
+pub mod symbol_table;
+pub mod synchronization_encoder;
+pub mod two_pass_assembler;
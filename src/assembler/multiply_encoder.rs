@@ -0,0 +1,133 @@
+use crate::assembler::asm_utilities::*;
+
+/// Bit 21, set for multiply-accumulate
+const ACCUMULATE_BIT: u32 = 1 << 21;
+/// Bit 20, set when the instruction updates the CPSR flags
+const SET_FLAGS_BIT: u32 = 1 << 20;
+/// Bit 23, set for the long multiplies
+const LONG_BIT: u32 = 1 << 23;
+/// Bit 22, set for signed long multiplies
+const SIGNED_BIT: u32 = 1 << 22;
+/// Bits 4-7 of every multiply instruction
+const MULTIPLY_PATTERN: u32 = 0b1001 << 4;
+
+/// Encodes `mul Rd, Rm, Rs` or `mla Rd, Rm, Rs, Rn`
+pub fn encode(mnemonic: &Mnemonic, operands: &[String]) -> EncodeResult {
+    let accumulate = mnemonic.base == "mla";
+    if accumulate {
+        expect_operands(operands, 4, "mla Rd, Rm, Rs, Rn")?;
+    } else {
+        expect_operands(operands, 3, "mul Rd, Rm, Rs")?;
+    }
+
+    let rd = parse_register(&operands[0])?;
+    let rm = parse_register(&operands[1])?;
+    let rs = parse_register(&operands[2])?;
+    let rn = if accumulate {
+        parse_register(&operands[3])?
+    } else {
+        0
+    };
+
+    let mut bits = (mnemonic.cond << COND_SHIFT)
+        | (rd << RN_SHIFT)
+        | (rn << RD_SHIFT)
+        | (rs << 8)
+        | MULTIPLY_PATTERN
+        | rm;
+    if accumulate {
+        bits |= ACCUMULATE_BIT;
+    }
+    if mnemonic.sets_flags() {
+        bits |= SET_FLAGS_BIT;
+    }
+    Ok(bits)
+}
+
+/// Encodes `umull/umlal/smull/smlal RdLo, RdHi, Rm, Rs`
+pub fn encode_long(mnemonic: &Mnemonic, operands: &[String]) -> EncodeResult {
+    let usage = format!("{} RdLo, RdHi, Rm, Rs", mnemonic.base);
+    expect_operands(operands, 4, &usage)?;
+    let rd_lo = parse_register(&operands[0])?;
+    let rd_hi = parse_register(&operands[1])?;
+    let rm = parse_register(&operands[2])?;
+    let rs = parse_register(&operands[3])?;
+    if rd_lo == rd_hi {
+        return Err(String::from("RdLo and RdHi must be different registers"));
+    }
+
+    let mut bits = (mnemonic.cond << COND_SHIFT)
+        | LONG_BIT
+        | (rd_hi << RN_SHIFT)
+        | (rd_lo << RD_SHIFT)
+        | (rs << 8)
+        | MULTIPLY_PATTERN
+        | rm;
+    if mnemonic.base.starts_with('s') {
+        bits |= SIGNED_BIT;
+    }
+    if mnemonic.base.ends_with("lal") {
+        bits |= ACCUMULATE_BIT;
+    }
+    if mnemonic.sets_flags() {
+        bits |= SET_FLAGS_BIT;
+    }
+    Ok(bits)
+}
+
+/// Bits 23-27 and 7 of the signed halfword multiplies
+const HALFWORD_MULTIPLY_PATTERN: u32 = 0b00010 << 23 | 1 << 7;
+
+/// Encodes the signed halfword multiplies, whose suffix picks the bottom or top
+/// halfword of Rm and Rs, or all of Rm for the `w` forms:
+/// `smul<x><y> Rd, Rm, Rs`, `smla<x><y> Rd, Rm, Rs, Rn`, `smulw<y> Rd, Rm, Rs`,
+/// `smlaw<y> Rd, Rm, Rs, Rn` and `smlal<x><y> RdLo, RdHi, Rm, Rs`
+pub fn encode_halfword(mnemonic: &Mnemonic, operands: &[String]) -> EncodeResult {
+    let (x, y) = match mnemonic.suffix.as_bytes() {
+        [x, y] => (*x == b't', *y == b't'),
+        _ => {
+            return Err(format!(
+                "`{}` needs the halfwords to multiply, as in `{}bb`",
+                mnemonic.base, mnemonic.base
+            ))
+        }
+    };
+    let word = mnemonic.suffix.starts_with('w');
+    let (op, accumulate) = match mnemonic.base {
+        "smla" if word => (0b01, true),
+        "smla" => (0b00, true),
+        "smlal" => (0b10, true),
+        // smulw<y> is told apart from smlaw<y> by the x bit
+        _ if word => (0b01, false),
+        _ => (0b11, false),
+    };
+    let usage = match (mnemonic.base, accumulate) {
+        ("smlal", _) => "RdLo, RdHi, Rm, Rs",
+        (_, true) => "Rd, Rm, Rs, Rn",
+        _ => "Rd, Rm, Rs",
+    };
+    expect_operands(operands, if accumulate { 4 } else { 3 }, usage)?;
+
+    let registers = operands
+        .iter()
+        .map(|operand| parse_register(operand))
+        .collect::<Result<Vec<u32>, String>>()?;
+    let (rd, rn, rm, rs) = match registers[..] {
+        // RdHi goes in the Rd field and RdLo in the Rn field
+        [rd_lo, rd_hi, rm, rs] if mnemonic.base == "smlal" => (rd_hi, rd_lo, rm, rs),
+        [rd, rm, rs, rn] => (rd, rn, rm, rs),
+        [rd, rm, rs] => (rd, 0, rm, rs),
+        _ => unreachable!(),
+    };
+    let x = x || (word && !accumulate);
+
+    Ok((mnemonic.cond << COND_SHIFT)
+        | HALFWORD_MULTIPLY_PATTERN
+        | op << 21
+        | rd << RN_SHIFT
+        | rn << RD_SHIFT
+        | rs << 8
+        | (y as u32) << 6
+        | (x as u32) << 5
+        | rm)
+}
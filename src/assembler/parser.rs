@@ -0,0 +1,123 @@
+use crate::assembler::asm_utilities::*;
+use crate::assembler::preprocessor::SourceLine;
+
+/// One line of assembly, split into its labels, mnemonic and operands
+#[derive(Debug, PartialEq)]
+pub struct Statement {
+    pub location: Location,
+    /// The source line, as written
+    pub text: String,
+    /// Whether the line comes from a macro expansion
+    pub from_macro: bool,
+    pub labels: Vec<String>,
+    /// None when the line only holds labels
+    pub mnemonic: Option<String>,
+    pub operands: Vec<String>,
+}
+
+/// Removes a trailing `@`, `;` or `//` comment from the line,
+/// ignoring comment characters inside string literals
+fn strip_comment(line: &str) -> &str {
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (ind, c) in line.char_indices() {
+        if in_quotes {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_quotes = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => in_quotes = true,
+            '@' | ';' => return &line[..ind],
+            '/' if line[ind..].starts_with("//") => return &line[..ind],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Whether the given name can be used as a label
+pub fn is_valid_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' || c == '$' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+/// Parses a single source line.
+/// Returns Ok(None) for blank or comment-only lines
+pub fn parse_line(text: &str, location: &Location) -> Result<Option<Statement>, String> {
+    let mut rest = strip_comment(text).trim();
+    if rest.is_empty() {
+        return Ok(None);
+    }
+
+    let mut labels = Vec::new();
+    while let Some(colon) = rest.find(':') {
+        let candidate = rest[..colon].trim();
+        if !is_valid_label(candidate) {
+            break;
+        }
+        labels.push(candidate.to_string());
+        rest = rest[colon + 1..].trim();
+    }
+
+    if rest.is_empty() {
+        return Ok(Some(Statement {
+            location: location.clone(),
+            text: text.to_string(),
+            from_macro: false,
+            labels,
+            mnemonic: None,
+            operands: Vec::new(),
+        }));
+    }
+
+    let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+        Some(space) => (&rest[..space], rest[space..].trim()),
+        None => (rest, ""),
+    };
+    if !is_valid_label(mnemonic) {
+        return Err(format!("unexpected `{}`", mnemonic));
+    }
+
+    Ok(Some(Statement {
+        location: location.clone(),
+        text: text.to_string(),
+        from_macro: false,
+        labels,
+        mnemonic: Some(mnemonic.to_string()),
+        operands: split_operands(operands),
+    }))
+}
+
+/// Parses the preprocessed source lines into statements,
+/// reporting every line that can't be parsed
+pub fn parse_lines(lines: &[SourceLine]) -> Result<Vec<Statement>, Vec<AsmError>> {
+    let mut statements = Vec::new();
+    let mut errors = Vec::new();
+    for line in lines {
+        match parse_line(&line.text, &line.location) {
+            Ok(Some(statement)) => statements.push(Statement {
+                from_macro: line.from_macro,
+                ..statement
+            }),
+            Ok(None) => {}
+            Err(message) => {
+                errors.push(AsmError::new(&line.location, message).with_source(&line.text))
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(statements)
+    } else {
+        Err(errors)
+    }
+}
@@ -0,0 +1,543 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::assembler::asm_utilities as util;
+use crate::assembler::directives::parse_string;
+use crate::assembler::parser::{is_valid_label, parse_line, Statement};
+use crate::assembler::symbol_table::{SymbolTable, Value};
+use util::*;
+
+/// How deep macros may expand inside each other before we assume a recursion
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// A line of source after includes, macros and conditionals have been resolved
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub text: String,
+    pub location: Location,
+    /// Whether the line comes from a macro expansion
+    pub from_macro: bool,
+}
+
+/// A macro defined with `.macro name param{=default}, ...` and `.endm`
+#[derive(Debug)]
+struct Macro {
+    params: Vec<(String, Option<String>)>,
+    body: Vec<String>,
+}
+
+/// The state of one `.if` block
+struct Conditional {
+    /// Whether the lines of the current branch are assembled
+    active: bool,
+    /// Whether one of the branches has already been taken
+    taken: bool,
+    /// Whether the enclosing block is active
+    parent_active: bool,
+    /// Where the `.if` is, for reporting a missing `.endif`
+    location: Location,
+}
+
+/// A macro being recorded, up to its `.endm`
+struct MacroDefinition {
+    name: String,
+    macro_def: Macro,
+    location: Location,
+    /// Nested `.macro` blocks inside the body
+    depth: usize,
+}
+
+/// Resolves `.include`, `.macro`/`.endm` and `.if`/`.ifdef`/`.else`/`.endif`
+/// before the two passes see the source
+pub struct Preprocessor<'a> {
+    include_dirs: &'a [PathBuf],
+    macros: HashMap<String, Rc<Macro>>,
+    /// Values of the `.equ` and `.set` symbols seen so far, for `.if`
+    symbols: SymbolTable,
+    /// Every symbol and label seen so far, for `.ifdef`
+    defined: HashSet<String>,
+    /// Files currently being included, to detect cycles
+    include_stack: Vec<PathBuf>,
+    /// Counts the expansions, substituted for `\@`
+    expansions: usize,
+    output: Vec<SourceLine>,
+    errors: Vec<AsmError>,
+}
+
+/// Builds an error at the given location
+fn error_at(location: &Location, message: String) -> AsmError {
+    AsmError::new(location, message)
+}
+
+/// Splits the source into lines tagged with their location
+fn numbered_lines(source: &str, file: &Rc<str>) -> Vec<(String, Location)> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(ind, text)| {
+            let location = Location {
+                file: Rc::clone(file),
+                line: ind + 1,
+            };
+            (text.to_string(), location)
+        })
+        .collect()
+}
+
+impl<'a> Preprocessor<'a> {
+    pub fn new(include_dirs: &'a [PathBuf]) -> Self {
+        Self {
+            include_dirs,
+            macros: HashMap::new(),
+            symbols: SymbolTable::new(),
+            defined: HashSet::new(),
+            include_stack: Vec::new(),
+            expansions: 0,
+            output: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Preprocesses the file at `path`
+    pub fn preprocess_file(mut self, path: &Path) -> Result<Vec<SourceLine>, Vec<AsmError>> {
+        let location = Location {
+            file: Rc::from(path.display().to_string()),
+            line: 0,
+        };
+        if let Err(err) = self.include(path, &location) {
+            self.errors.push(err);
+        }
+        self.finish()
+    }
+
+    /// Preprocesses in-memory source, naming it `name` in errors.
+    /// Includes are searched in the include directories only
+    #[cfg(test)]
+    pub fn preprocess_source(
+        mut self,
+        source: &str,
+        name: &str,
+    ) -> Result<Vec<SourceLine>, Vec<AsmError>> {
+        let lines = numbered_lines(source, &Rc::from(name));
+        self.process(lines, false, 0);
+        self.finish()
+    }
+
+    /// Returns the preprocessed lines, or every error found
+    fn finish(self) -> Result<Vec<SourceLine>, Vec<AsmError>> {
+        if self.errors.is_empty() {
+            Ok(self.output)
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    /// Finds an included file, first next to the file including it,
+    /// then in each of the include directories
+    fn resolve_include(&self, name: &str, from: &Location) -> Option<PathBuf> {
+        let including_dir = Path::new(&*from.file).parent().map(Path::to_path_buf);
+        including_dir
+            .into_iter()
+            .chain(self.include_dirs.iter().cloned())
+            .map(|dir| dir.join(name))
+            .find(|candidate| candidate.is_file())
+    }
+
+    /// Reads and processes an included file
+    fn include(&mut self, path: &Path, from: &Location) -> Result<(), AsmError> {
+        let canonical = fs::canonicalize(path)
+            .map_err(|err| error_at(from, format!("cannot open `{}`: {}", path.display(), err)))?;
+        if let Some(start) = self
+            .include_stack
+            .iter()
+            .position(|file| *file == canonical)
+        {
+            let cycle: Vec<String> = self.include_stack[start..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|file| file.display().to_string())
+                .collect();
+            return Err(error_at(
+                from,
+                format!("include cycle: {}", cycle.join(" -> ")),
+            ));
+        }
+
+        let source = fs::read_to_string(path)
+            .map_err(|err| error_at(from, format!("cannot read `{}`: {}", path.display(), err)))?;
+        let lines = numbered_lines(&source, &Rc::from(path.display().to_string()));
+        self.include_stack.push(canonical);
+        self.process(lines, false, 0);
+        self.include_stack.pop();
+        Ok(())
+    }
+
+    /// Evaluates the condition of an `.if`, `.ifdef` or `.ifndef`
+    fn condition(
+        &self,
+        directive: &str,
+        operands: &[String],
+        location: &Location,
+    ) -> Result<bool, AsmError> {
+        if operands.len() != 1 {
+            return Err(error_at(
+                location,
+                format!("`{}` expects a single operand", directive),
+            ));
+        }
+        match directive {
+            ".ifdef" => Ok(self.defined.contains(operands[0].trim())),
+            ".ifndef" => Ok(!self.defined.contains(operands[0].trim())),
+            _ => evaluate(&operands[0], &self.symbols)
+                .map(|value| value != 0)
+                .map_err(|message| error_at(location, message)),
+        }
+    }
+
+    /// Processes a list of lines: either a whole file or a macro expansion.
+    /// Errors are recorded and the lines after them still processed
+    fn process(&mut self, lines: Vec<(String, Location)>, from_macro: bool, depth: usize) {
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        let mut definition: Option<MacroDefinition> = None;
+
+        for (text, location) in lines {
+            let active = conditionals.last().is_none_or(|block| block.active);
+            let statement = match parse_line(&text, &location) {
+                Ok(Some(statement)) => statement,
+                Ok(None) => continue,
+                // Macro bodies are only parsed once their parameters are substituted
+                Err(_) if definition.is_some() => {
+                    definition.as_mut().unwrap().macro_def.body.push(text);
+                    continue;
+                }
+                Err(_) if !active => continue,
+                Err(message) => {
+                    self.errors
+                        .push(error_at(&location, message).with_source(&text));
+                    continue;
+                }
+            };
+            let directive = statement
+                .mnemonic
+                .as_ref()
+                .map(|mnemonic| mnemonic.to_lowercase());
+            let directive = directive.as_deref().unwrap_or("");
+
+            // Record macro bodies verbatim until the matching .endm
+            if let Some(current) = definition.as_mut() {
+                match directive {
+                    ".macro" => current.depth += 1,
+                    ".endm" if current.depth == 0 => {
+                        let finished = definition.take().unwrap();
+                        self.macros
+                            .insert(finished.name, Rc::new(finished.macro_def));
+                        continue;
+                    }
+                    ".endm" => current.depth -= 1,
+                    _ => {}
+                }
+                current.macro_def.body.push(text);
+                continue;
+            }
+
+            let result = match directive {
+                ".if" | ".ifdef" | ".ifndef" => {
+                    // A condition that can't be evaluated counts as false
+                    let condition = if active {
+                        self.condition(directive, &statement.operands, &location)
+                    } else {
+                        Ok(false)
+                    };
+                    let taken = *condition.as_ref().unwrap_or(&false);
+                    conditionals.push(Conditional {
+                        active: taken,
+                        taken,
+                        parent_active: active,
+                        location: location.clone(),
+                    });
+                    condition.map(|_| ())
+                }
+                ".elseif" | ".else" | ".endif" => match conditionals.last_mut() {
+                    None => Err(error_at(
+                        &location,
+                        format!("`{}` without a matching `.if`", directive),
+                    )),
+                    Some(_) if directive == ".endif" => {
+                        conditionals.pop();
+                        Ok(())
+                    }
+                    Some(block) if directive == ".else" => {
+                        block.active = block.parent_active && !block.taken;
+                        block.taken = true;
+                        Ok(())
+                    }
+                    Some(block) => {
+                        let condition = if block.parent_active && !block.taken {
+                            self.condition(".if", &statement.operands, &location)
+                        } else {
+                            Ok(false)
+                        };
+                        let take = *condition.as_ref().unwrap_or(&false);
+                        let block = conditionals.last_mut().unwrap();
+                        block.active = take;
+                        block.taken |= take;
+                        condition.map(|_| ())
+                    }
+                },
+                _ if !active => Ok(()),
+                ".macro" => self
+                    .start_macro(&statement.operands, &location)
+                    .map(|started| definition = Some(started)),
+                _ => self.process_statement(&statement, directive, text.clone(), from_macro, depth),
+            };
+            if let Err(err) = result {
+                self.errors.push(err.with_source(&text));
+            }
+        }
+
+        if let Some(current) = definition {
+            self.errors.push(error_at(
+                &current.location,
+                format!("macro `{}` is missing its `.endm`", current.name),
+            ));
+        }
+        if let Some(block) = conditionals.last() {
+            self.errors.push(error_at(
+                &block.location,
+                String::from("`.if` is missing its `.endif`"),
+            ));
+        }
+    }
+
+    /// Handles a line outside of macro definitions and conditional directives
+    fn process_statement(
+        &mut self,
+        statement: &Statement,
+        directive: &str,
+        text: String,
+        from_macro: bool,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let location = &statement.location;
+        for label in &statement.labels {
+            self.defined.insert(label.clone());
+        }
+        match directive {
+            ".include" => {
+                if statement.operands.len() != 1 {
+                    return Err(error_at(
+                        location,
+                        String::from("`.include` expects a file name"),
+                    ));
+                }
+                let name = parse_string(&statement.operands[0])
+                    .map_err(|message| error_at(location, message))?;
+                let name = String::from_utf8_lossy(&name).to_string();
+                let path = self.resolve_include(&name, location).ok_or_else(|| {
+                    error_at(location, format!("cannot find included file `{}`", name))
+                })?;
+                self.include(&path, location)
+            }
+            ".endm" => Err(error_at(
+                location,
+                String::from("`.endm` without a matching `.macro`"),
+            )),
+            ".equ" | ".set" => {
+                self.record_symbol(&statement.operands);
+                self.push_line(text, location.clone(), from_macro);
+                Ok(())
+            }
+            name if self.macros.contains_key(name) => {
+                // Labels on the invocation line stay in front of the expansion
+                for label in &statement.labels {
+                    self.push_line(format!("{}:", label), location.clone(), from_macro);
+                }
+                let macro_def = Rc::clone(&self.macros[name]);
+                self.expand(name, &macro_def, &statement.operands, location, depth)
+            }
+            _ => {
+                self.push_line(text, location.clone(), from_macro);
+                Ok(())
+            }
+        }
+    }
+
+    fn push_line(&mut self, text: String, location: Location, from_macro: bool) {
+        self.output.push(SourceLine {
+            text,
+            location,
+            from_macro,
+        });
+    }
+
+    /// Remembers the value of an `.equ` or `.set` for later `.if` conditions.
+    /// Values that can't be computed yet (e.g. forward labels) are only marked as defined
+    fn record_symbol(&mut self, operands: &[String]) {
+        if operands.len() != 2 {
+            return;
+        }
+        let name = operands[0].trim();
+        self.defined.insert(name.to_string());
+        if let Ok(value) = evaluate_word(&operands[1], &self.symbols) {
+            self.symbols.set(name, Value::absolute(value as i64));
+        }
+    }
+
+    /// Parses `.macro name param1, param2=default, ...`
+    fn start_macro(
+        &self,
+        operands: &[String],
+        location: &Location,
+    ) -> Result<MacroDefinition, AsmError> {
+        // Parameters may be separated by commas or spaces
+        let mut words = operands
+            .iter()
+            .flat_map(|operand| operand.split_whitespace());
+        let name = words
+            .next()
+            .ok_or_else(|| error_at(location, String::from("`.macro` expects a name")))?
+            .to_lowercase();
+        if !is_valid_label(&name) {
+            return Err(error_at(
+                location,
+                format!("`{}` is not a valid macro name", name),
+            ));
+        }
+
+        let mut params = Vec::new();
+        for param in words {
+            let (param, default) = match param.find('=') {
+                Some(eq) => (&param[..eq], Some(param[eq + 1..].to_string())),
+                None => (param, None),
+            };
+            if !is_valid_label(param) {
+                return Err(error_at(
+                    location,
+                    format!("`{}` is not a valid parameter name", param),
+                ));
+            }
+            params.push((param.to_string(), default));
+        }
+
+        Ok(MacroDefinition {
+            name,
+            macro_def: Macro {
+                params,
+                body: Vec::new(),
+            },
+            location: location.clone(),
+            depth: 0,
+        })
+    }
+
+    /// Expands a macro invocation and processes the resulting lines
+    fn expand(
+        &mut self,
+        name: &str,
+        macro_def: &Macro,
+        args: &[String],
+        location: &Location,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(error_at(
+                location,
+                format!("macro `{}` expands recursively", name),
+            ));
+        }
+
+        // Positional arguments first, then `param=value` ones
+        let mut values: Vec<Option<String>> = macro_def
+            .params
+            .iter()
+            .map(|(_, default)| default.clone())
+            .collect();
+        let mut position = 0;
+        for arg in args.iter().filter(|arg| !arg.is_empty()) {
+            let named = arg.find('=').and_then(|eq| {
+                let param = arg[..eq].trim();
+                macro_def
+                    .params
+                    .iter()
+                    .position(|(name, _)| name == param)
+                    .map(|ind| (ind, arg[eq + 1..].trim()))
+            });
+            match named {
+                Some((ind, value)) => values[ind] = Some(value.to_string()),
+                None => {
+                    if position >= values.len() {
+                        return Err(error_at(
+                            location,
+                            format!("too many arguments for macro `{}`", name),
+                        ));
+                    }
+                    values[position] = Some(arg.clone());
+                    position += 1;
+                }
+            }
+        }
+
+        let mut substitutions = HashMap::new();
+        for ((param, _), value) in macro_def.params.iter().zip(values) {
+            let value = value.ok_or_else(|| {
+                error_at(
+                    location,
+                    format!(
+                        "missing value for parameter `{}` of macro `{}`",
+                        param, name
+                    ),
+                )
+            })?;
+            substitutions.insert(param.as_str(), value);
+        }
+
+        self.expansions += 1;
+        let expansion = self.expansions.to_string();
+        let lines = macro_def
+            .body
+            .iter()
+            .map(|line| {
+                (
+                    substitute(line, &substitutions, &expansion),
+                    location.clone(),
+                )
+            })
+            .collect();
+        self.process(lines, true, depth + 1);
+        Ok(())
+    }
+}
+
+/// Replaces `\param` with the argument values, `\@` with the expansion
+/// number (for unique local labels) and removes the `\()` separators
+fn substitute(line: &str, substitutions: &HashMap<&str, String>, expansion: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(slash) = rest.find('\\') {
+        result.push_str(&rest[..slash]);
+        rest = &rest[slash + 1..];
+        if let Some(after) = rest.strip_prefix('@') {
+            result.push_str(expansion);
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("()") {
+            rest = after;
+            continue;
+        }
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        match substitutions.get(&rest[..end]) {
+            Some(value) => {
+                result.push_str(value);
+                rest = &rest[end..];
+            }
+            None => result.push('\\'),
+        }
+    }
+    result.push_str(rest);
+    result
+}
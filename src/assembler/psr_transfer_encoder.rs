@@ -0,0 +1,85 @@
+use crate::assembler::{asm_utilities as util, symbol_table::SymbolTable};
+use util::*;
+
+/// Bits 23-27 of MRS and of MSR with a register
+const PSR_TRANSFER_PATTERN: u32 = 0b00010 << 23;
+/// Bit 25, set for MSR with an immediate
+const IMMEDIATE_BIT: u32 = 1 << 25;
+/// Bit 22, set when the SPSR is transferred
+const SPSR_BIT: u32 = 1 << 22;
+/// Bit 21, set for MSR
+const MSR_BIT: u32 = 1 << 21;
+/// Bits 16-19 of MRS
+const MRS_ONES: u32 = 0b1111 << 16;
+/// Bits 12-15 of MSR
+const MSR_ONES: u32 = 0b1111 << 12;
+const FIELD_MASK_SHIFT: u32 = 16;
+
+/// Parses `cpsr` or `spsr`, returning the SPSR bit
+fn parse_psr(operand: &str) -> Result<u32, String> {
+    match operand.trim().to_lowercase().as_str() {
+        "cpsr" => Ok(0),
+        "spsr" => Ok(SPSR_BIT),
+        other => Err(format!("expected `cpsr` or `spsr`, found `{}`", other)),
+    }
+}
+
+/// Parses `cpsr_<fields>` or `spsr_<fields>`, where the fields are any of `fsxc`,
+/// into the SPSR bit and the field mask. Without fields, `_fc` is meant
+fn parse_psr_fields(operand: &str) -> Result<(u32, u32), String> {
+    let lower = operand.trim().to_lowercase();
+    let (psr, fields) = lower.split_once('_').unwrap_or((&lower, "fc"));
+    let psr = parse_psr(psr)?;
+    let fields = match fields {
+        // Older names of the field masks
+        "all" => "fc",
+        "flg" => "f",
+        "ctl" => "c",
+        fields => fields,
+    };
+
+    let mut mask = 0;
+    for field in fields.chars() {
+        let bit = match field {
+            'c' => 0b0001,
+            'x' => 0b0010,
+            's' => 0b0100,
+            'f' => 0b1000,
+            _ => return Err(format!("unknown PSR field `{}`", field)),
+        };
+        if mask & bit != 0 {
+            return Err(format!("PSR field `{}` given twice", field));
+        }
+        mask |= bit;
+    }
+    if mask == 0 {
+        return Err(String::from("expected at least one PSR field"));
+    }
+    Ok((psr, mask))
+}
+
+/// Encodes `mrs Rd, cpsr|spsr` or `msr cpsr|spsr_<fields>, Rm|#<immediate>`
+pub fn encode(mnemonic: &Mnemonic, operands: &[String], symbols: &SymbolTable) -> EncodeResult {
+    let cond = mnemonic.cond << COND_SHIFT;
+    if mnemonic.base == "mrs" {
+        expect_operands(operands, 2, "mrs Rd, cpsr|spsr")?;
+        let rd = parse_register(&operands[0])?;
+        let psr = parse_psr(&operands[1])?;
+        return Ok(cond | PSR_TRANSFER_PATTERN | psr | MRS_ONES | (rd << RD_SHIFT));
+    }
+
+    expect_operands(operands, 2, "msr cpsr|spsr_<fields>, Rm|#<immediate>")?;
+    let (psr, fields) = parse_psr_fields(&operands[0])?;
+    let bits =
+        cond | PSR_TRANSFER_PATTERN | psr | MSR_BIT | (fields << FIELD_MASK_SHIFT) | MSR_ONES;
+    if operands[1].trim().starts_with('#') {
+        let value = parse_word_immediate(&operands[1], symbols)?;
+        let operand = encode_rotated_immediate(value).ok_or(format!(
+            "immediate 0x{:x} can't be encoded as a rotated 8-bit value",
+            value
+        ))?;
+        Ok(bits | IMMEDIATE_BIT | operand)
+    } else {
+        Ok(bits | parse_register(&operands[1])?)
+    }
+}
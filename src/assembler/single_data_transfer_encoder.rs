@@ -0,0 +1,297 @@
+use crate::assembler::literal_pool;
+use crate::assembler::{asm_utilities as util, data_proc_encoder, symbol_table::SymbolTable};
+use util::*;
+
+/// Bits 26-27 of every single data transfer instruction
+const SDT_PATTERN: u32 = 0b01 << 26;
+/// Bit 25, set when the offset is a (shifted) register
+const REGISTER_OFFSET_BIT: u32 = 1 << 25;
+/// Bit 24, set when the offset is added before the transfer
+const PRE_INDEX_BIT: u32 = 1 << 24;
+/// Bit 23, set when the offset is added to the base
+const UP_BIT: u32 = 1 << 23;
+/// Bit 22, set when transferring a byte
+const BYTE_BIT: u32 = 1 << 22;
+/// Bit 21, set when the address is written back into the base
+const WRITE_BACK_BIT: u32 = 1 << 21;
+/// Bit 20, set for loads
+const LOAD_BIT: u32 = 1 << 20;
+/// Largest immediate offset
+const MAX_OFFSET: i64 = 0xFFF;
+/// Bit 22 of halfword transfers, set when the offset is an immediate
+const HALFWORD_IMMEDIATE_BIT: u32 = 1 << 22;
+/// Largest immediate offset of halfword transfers
+const MAX_HALFWORD_OFFSET: i64 = 0xFF;
+
+/// Encodes the offset operands following the base register
+type OffsetEncoder = fn(&[String], &SymbolTable) -> EncodeResult;
+
+/// Encodes an offset given as the operands following the base register:
+/// `#±imm` or `±Rm{, shift}`.
+/// Returns the offset bits, including the register and up bits
+fn encode_offset(operands: &[String], symbols: &SymbolTable) -> EncodeResult {
+    if operands.is_empty() {
+        return Ok(UP_BIT);
+    }
+
+    if operands[0].starts_with('#') {
+        if operands.len() > 1 {
+            return Err(format!("unexpected operand `{}`", operands[1]));
+        }
+        let offset = parse_immediate(&operands[0], symbols)?;
+        if offset.abs() > MAX_OFFSET {
+            return Err(format!(
+                "offset #{} out of range for a single data transfer",
+                offset
+            ));
+        }
+        let up = if offset >= 0 { UP_BIT } else { 0 };
+        return Ok(up | offset.unsigned_abs() as u32);
+    }
+
+    let mut operands = operands.to_vec();
+    let register = operands[0].trim();
+    let up = if let Some(register) = register.strip_prefix('-') {
+        operands[0] = register.trim().to_string();
+        0
+    } else {
+        operands[0] = register
+            .strip_prefix('+')
+            .unwrap_or(register)
+            .trim()
+            .to_string();
+        UP_BIT
+    };
+    let shifted = data_proc_encoder::encode_shifted_register(&operands, symbols)?;
+    Ok(REGISTER_OFFSET_BIT | up | shifted)
+}
+
+/// Encodes the offset of a halfword or signed byte transfer:
+/// `#±imm` split in two nibbles, or `±Rm` which can't be shifted.
+/// Returns the offset bits, including the immediate and up bits
+fn encode_halfword_offset(operands: &[String], symbols: &SymbolTable) -> EncodeResult {
+    if operands.is_empty() {
+        return Ok(HALFWORD_IMMEDIATE_BIT | UP_BIT);
+    }
+    if operands.len() > 1 {
+        return Err(String::from(
+            "halfword and signed byte transfers can't shift their offset",
+        ));
+    }
+
+    if operands[0].starts_with('#') {
+        let offset = parse_immediate(&operands[0], symbols)?;
+        if offset.abs() > MAX_HALFWORD_OFFSET {
+            return Err(format!(
+                "offset #{} out of range for a halfword transfer",
+                offset
+            ));
+        }
+        let up = if offset >= 0 { UP_BIT } else { 0 };
+        let offset = offset.unsigned_abs() as u32;
+        return Ok(HALFWORD_IMMEDIATE_BIT | up | (offset >> 4) << 8 | (offset & 0xF));
+    }
+
+    let register = operands[0].trim();
+    match register.strip_prefix('-') {
+        Some(register) => parse_register(register),
+        None => Ok(UP_BIT | parse_register(register.strip_prefix('+').unwrap_or(register))?),
+    }
+}
+
+/// Parses the `[Rn, <offset>]{!}` part, returning the base register
+/// and the indexing, write back and offset bits
+fn encode_pre_indexed(
+    address: &str,
+    symbols: &SymbolTable,
+    encode_offset: OffsetEncoder,
+) -> EncodeResult {
+    let (inner, write_back) = match address.strip_suffix('!') {
+        Some(inner) => (inner.trim(), WRITE_BACK_BIT),
+        None => (address, 0),
+    };
+    let inner = inner
+        .strip_prefix('[')
+        .and_then(|inner| inner.strip_suffix(']'))
+        .ok_or(format!(
+            "expected an address in brackets, found `{}`",
+            address
+        ))?;
+    let parts = split_operands(inner);
+    if parts.is_empty() {
+        return Err(String::from("missing base register"));
+    }
+
+    let rn = parse_register(&parts[0])?;
+    let offset = encode_offset(&parts[1..], symbols)?;
+    Ok(PRE_INDEX_BIT | write_back | (rn << RN_SHIFT) | offset)
+}
+
+/// Checks that `ldr Rd, =<value>` is used with a plain `ldr`
+fn check_literal_mnemonic(mnemonic: &Mnemonic, operands: &[String]) -> Result<(), String> {
+    if mnemonic.base != "ldr" || !mnemonic.suffix.is_empty() {
+        return Err(format!(
+            "`=<value>` can only be used with `ldr`, not `{}{}`",
+            mnemonic.base, mnemonic.suffix
+        ));
+    }
+    expect_operands(operands, 2, "ldr Rd, =<value>")
+}
+
+/// Encodes an `ldr Rd, =<value>` whose value fits a rotated immediate
+/// as `mov Rd, #<value>`
+fn encode_literal_move(
+    mnemonic: &Mnemonic,
+    operands: &[String],
+    expression: &str,
+    symbols: &SymbolTable,
+) -> EncodeResult {
+    check_literal_mnemonic(mnemonic, operands)?;
+    let value = literal_pool::immediate_value(expression, symbols).ok_or(format!(
+        "`={}` has not been placed in a literal pool",
+        expression.trim()
+    ))?;
+    let mov = Mnemonic {
+        base: "mov",
+        cond: mnemonic.cond,
+        suffix: "",
+    };
+    data_proc_encoder::encode(&mov, &[operands[0].clone(), format!("#{}", value)], symbols)
+}
+
+/// Encodes the addressing bits of `[pc, #offset]` for a transfer at `address`
+/// that reaches `target`, accounting for the pipeline offset.
+/// Fails with the distance to `target` if it is out of reach
+fn encode_pc_relative(address: u32, target: u32) -> Result<u32, i64> {
+    let offset = target as i64 - (address as i64 + PIPELINE_OFFSET);
+    if offset.abs() > MAX_OFFSET {
+        return Err(offset.abs());
+    }
+    let up = if offset >= 0 { UP_BIT } else { 0 };
+    Ok(PRE_INDEX_BIT | up | (15 << RN_SHIFT) | offset.unsigned_abs() as u32)
+}
+
+/// Encodes an `ldr Rd, =<value>` found at `address` whose value has been
+/// placed in a literal pool at `literal`, as `ldr Rd, [pc, #offset]`
+pub fn encode_literal_load(
+    mnemonic: &Mnemonic,
+    operands: &[String],
+    address: u32,
+    literal: u32,
+) -> EncodeResult {
+    check_literal_mnemonic(mnemonic, operands)?;
+    let rd = parse_register(&operands[0])?;
+    let addressing = encode_pc_relative(address, literal).map_err(|distance| {
+        format!(
+            "literal pool is {} bytes away from this `ldr`, more than the 4KB it can reach; \
+             place an `.ltorg` closer",
+            distance
+        )
+    })?;
+    Ok((mnemonic.cond << COND_SHIFT) | SDT_PATTERN | addressing | LOAD_BIT | (rd << RD_SHIFT))
+}
+
+/// Checks the registers of `ldrd/strd Rd, {Rd+1,} <address>`, which must be an even one
+/// other than lr and optionally the one after it, and drops the second register
+fn doubleword_operands(operands: &[String]) -> Result<Vec<String>, String> {
+    let rd = parse_register(&operands[0])?;
+    if rd % 2 != 0 || rd == 14 {
+        return Err(format!(
+            "doubleword transfers need an even register below lr, found `{}`",
+            operands[0]
+        ));
+    }
+
+    let mut operands = operands.to_vec();
+    if operands.len() > 2 {
+        if let Ok(second) = parse_register(&operands[1]) {
+            if second != rd + 1 {
+                return Err(format!(
+                    "the second register of a doubleword transfer must be r{}",
+                    rd + 1
+                ));
+            }
+            operands.remove(1);
+        }
+    }
+    Ok(operands)
+}
+
+/// Encodes `ldr/str{b|h|d} Rd, <address>` or `ldrs{b|h} Rd, <address>`,
+/// where the address is one of `[Rn]`, `[Rn, <offset>]{!}`, `[Rn], <offset>`,
+/// a label (PC relative) or `=<value>` for a small constant
+pub fn encode(
+    mnemonic: &Mnemonic,
+    operands: &[String],
+    instr_address: u32,
+    symbols: &SymbolTable,
+) -> EncodeResult {
+    if operands.len() < 2 {
+        return Err(format!("`{}` expects `Rd, <address>`", mnemonic.base));
+    }
+    let operands = &if mnemonic.suffix == "d" {
+        doubleword_operands(operands)?
+    } else {
+        operands.to_vec()
+    };
+    let rd = parse_register(&operands[0])?;
+    let address = operands[1].trim();
+    if let Some(expression) = address.strip_prefix('=') {
+        return encode_literal_move(mnemonic, operands, expression, symbols);
+    }
+
+    let halfword = matches!(mnemonic.suffix, "h" | "sb" | "sh" | "d");
+    let encode_offset: OffsetEncoder = if halfword {
+        encode_halfword_offset
+    } else {
+        encode_offset
+    };
+
+    let addressing = if operands.len() == 2 && !address.starts_with('[') {
+        // A label, reached relative to the PC
+        let target = evaluate_word(address, symbols)?;
+        if halfword {
+            let offset = target as i64 - (instr_address as i64 + PIPELINE_OFFSET);
+            PRE_INDEX_BIT | (15 << RN_SHIFT) | encode_offset(&[format!("#{}", offset)], symbols)?
+        } else {
+            encode_pc_relative(instr_address, target).map_err(|distance| {
+                format!(
+                    "address 0x{:x} is {} bytes away, more than the 4KB a single data transfer \
+                     can reach",
+                    target, distance
+                )
+            })?
+        }
+    } else if operands.len() == 2 {
+        // Pre-indexed, or just [Rn]
+        encode_pre_indexed(address, symbols, encode_offset)?
+    } else {
+        // Post-indexed: [Rn], <offset>
+        let rn = address
+            .strip_prefix('[')
+            .and_then(|inner| inner.strip_suffix(']'))
+            .ok_or(format!(
+                "expected a base register in brackets, found `{}`",
+                address
+            ))?;
+        let rn = parse_register(rn)?;
+        (rn << RN_SHIFT) | encode_offset(&operands[2..], symbols)?
+    };
+
+    let mut bits = (mnemonic.cond << COND_SHIFT) | addressing | (rd << RD_SHIFT);
+    if mnemonic.base == "ldr" && mnemonic.suffix != "d" {
+        bits |= LOAD_BIT;
+    }
+    // Bits 4-7 are 1SH1, S for signed and H for halfword.
+    // Doubleword transfers take the signed encodings without the load bit
+    match mnemonic.suffix {
+        "d" if mnemonic.base == "ldr" => bits |= 0b1101 << 4,
+        "d" => bits |= 0b1111 << 4,
+        "h" => bits |= 0b1011 << 4,
+        "sb" => bits |= 0b1101 << 4,
+        "sh" => bits |= 0b1111 << 4,
+        "b" => bits |= SDT_PATTERN | BYTE_BIT,
+        _ => bits |= SDT_PATTERN,
+    }
+    Ok(bits)
+}
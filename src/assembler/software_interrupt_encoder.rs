@@ -0,0 +1,19 @@
+use crate::assembler::{asm_utilities as util, symbol_table::SymbolTable};
+use util::*;
+
+/// Bits 24-27 of every software interrupt
+const SOFTWARE_INTERRUPT_PATTERN: u32 = 0b1111 << 24;
+/// The comment field is the low 24 bits
+const MAX_COMMENT: i64 = 0xFF_FFFF;
+
+/// Encodes `swi <number>`, or its newer name `svc <number>`.
+/// The `#` before the number is optional
+pub fn encode(mnemonic: &Mnemonic, operands: &[String], symbols: &SymbolTable) -> EncodeResult {
+    expect_operands(operands, 1, &format!("{} <number>", mnemonic.base))?;
+    let operand = operands[0].trim();
+    let comment = evaluate(operand.strip_prefix('#').unwrap_or(operand), symbols)?;
+    if !(0..=MAX_COMMENT).contains(&comment) {
+        return Err(format!("SWI number 0x{:x} doesn't fit in 24 bits", comment));
+    }
+    Ok((mnemonic.cond << COND_SHIFT) | SOFTWARE_INTERRUPT_PATTERN | comment as u32)
+}
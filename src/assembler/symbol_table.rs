@@ -0,0 +1,117 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+
+/// The sections code and data can be placed in.
+/// In the binary, `.data` comes after `.text`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Text,
+    Data,
+}
+
+impl Section {
+    pub const ALL: [Section; 2] = [Section::Text, Section::Data];
+
+    /// The name of the section, as written in the source
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Text => ".text",
+            Section::Data => ".data",
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A value known to the assembler: either a plain number,
+/// or an address inside one of the sections
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Value {
+    pub value: i64,
+    /// None for plain numbers
+    pub section: Option<Section>,
+}
+
+impl Value {
+    pub fn absolute(value: i64) -> Self {
+        Self {
+            value,
+            section: None,
+        }
+    }
+}
+
+/// Maps labels to the addresses they were defined at
+/// and `.equ` constants to their values.
+/// Filled in by the first pass and read by the second one
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    symbols: HashMap<String, Value>,
+    /// Whether the start address of each section is known yet.
+    /// `.text` starts at 0, `.data` is placed after the first pass
+    placed: [bool; 2],
+    /// The location counter `.`, updated before each statement
+    location: Cell<Option<Value>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self {
+            symbols: HashMap::new(),
+            placed: [true, false],
+            location: Cell::new(None),
+        }
+    }
+
+    /// Defines a new symbol
+    ///
+    /// Returns an error if the symbol has already been defined
+    pub fn define(&mut self, name: &str, value: Value) -> Result<(), String> {
+        if self.symbols.contains_key(name) {
+            return Err(format!("symbol `{}` is already defined", name));
+        }
+        self.symbols.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Sets a symbol, redefining it if it already exists
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.symbols.insert(name.to_string(), value);
+    }
+
+    /// Gets the value of the given symbol
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Whether the addresses in the section are final
+    pub fn is_placed(&self, section: Section) -> bool {
+        self.placed[section.index()]
+    }
+
+    /// Moves every symbol of the section to its start address,
+    /// turning their offsets into final addresses
+    pub fn place(&mut self, section: Section, start: u32) {
+        for value in self.symbols.values_mut() {
+            if value.section == Some(section) {
+                value.value += start as i64;
+            }
+        }
+        self.placed[section.index()] = true;
+    }
+
+    /// The value of `.`, if there is a current statement
+    pub fn location(&self) -> Option<Value> {
+        self.location.get()
+    }
+
+    /// Sets the value of `.` for the next statement
+    pub fn set_location(&self, section: Section, address: u32) {
+        self.location.set(Some(Value {
+            value: address as i64,
+            section: Some(section),
+        }));
+    }
+}
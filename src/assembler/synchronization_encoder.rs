@@ -0,0 +1,62 @@
+use crate::assembler::asm_utilities::*;
+
+/// Bits 24-27 and 4-7 of every synchronization instruction
+const SYNCHRONIZATION_PATTERN: u32 = 0b0001 << 24 | 0b1001 << 4;
+/// Bit 23, set for the exclusive transfers
+const EXCLUSIVE_BIT: u32 = 1 << 23;
+/// Bit 22, set when swapping a byte
+const BYTE_BIT: u32 = 1 << 22;
+/// Bit 20, set for LDREX
+const LOAD_BIT: u32 = 1 << 20;
+/// Bits 8-11 of every synchronization instruction
+const SBO_BITS: u32 = 0b1111 << 8;
+
+/// Parses an address of the form `[Rn]`
+fn parse_base_register(address: &str) -> Result<u32, String> {
+    let rn = address
+        .trim()
+        .strip_prefix('[')
+        .and_then(|inner| inner.strip_suffix(']'))
+        .ok_or(format!(
+            "expected a base register in brackets, found `{}`",
+            address.trim()
+        ))?;
+    parse_register(rn)
+}
+
+/// Encodes `swp{b} Rd, Rm, [Rn]` and `strex Rd, Rm, [Rn]`, which store Rm at Rn,
+/// writing what was there or the status of the store to Rd
+pub fn encode_store(mnemonic: &Mnemonic, operands: &[String]) -> EncodeResult {
+    let usage = format!("{}{} Rd, Rm, [Rn]", mnemonic.base, mnemonic.suffix);
+    expect_operands(operands, 3, &usage)?;
+    let rd = parse_register(&operands[0])?;
+    let rm = parse_register(&operands[1])?;
+    let rn = parse_base_register(&operands[2])?;
+
+    let mut bits = (mnemonic.cond << COND_SHIFT)
+        | SYNCHRONIZATION_PATTERN
+        | rn << RN_SHIFT
+        | rd << RD_SHIFT
+        | rm;
+    if mnemonic.base == "strex" {
+        bits |= EXCLUSIVE_BIT | SBO_BITS;
+    } else if mnemonic.suffix == "b" {
+        bits |= BYTE_BIT;
+    }
+    Ok(bits)
+}
+
+/// Encodes `ldrex Rd, [Rn]`
+pub fn encode_load_exclusive(mnemonic: &Mnemonic, operands: &[String]) -> EncodeResult {
+    expect_operands(operands, 2, "ldrex Rd, [Rn]")?;
+    let rd = parse_register(&operands[0])?;
+    let rn = parse_base_register(&operands[1])?;
+    Ok((mnemonic.cond << COND_SHIFT)
+        | SYNCHRONIZATION_PATTERN
+        | EXCLUSIVE_BIT
+        | LOAD_BIT
+        | rn << RN_SHIFT
+        | rd << RD_SHIFT
+        | SBO_BITS
+        | 0b1111)
+}
@@ -0,0 +1,651 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::assembler::block_data_transfer_encoder as block;
+use crate::assembler::branch_encoder as branch;
+use crate::assembler::coprocessor_encoder as coprocessor;
+use crate::assembler::data_proc_encoder as data_proc;
+use crate::assembler::directives;
+use crate::assembler::listing;
+use crate::assembler::literal_pool::{self, LiteralPool};
+use crate::assembler::media_encoder as media;
+use crate::assembler::miscellaneous_encoder as misc;
+use crate::assembler::multiply_encoder as mul;
+use crate::assembler::preprocessor::{Preprocessor, SourceLine};
+use crate::assembler::psr_transfer_encoder as psr;
+use crate::assembler::single_data_transfer_encoder as sdt;
+use crate::assembler::software_interrupt_encoder as swi;
+use crate::assembler::symbol_table::{Section, SymbolTable, Value};
+use crate::assembler::synchronization_encoder as sync;
+use crate::assembler::{asm_utilities as util, parser};
+use crate::elf;
+
+use parser::Statement;
+use util::*;
+
+/// What the first pass decided to place in the binary
+enum Item<'a> {
+    /// An instruction, encoded in the second pass
+    Instruction {
+        statement: &'a Statement,
+        address: u32,
+    },
+    /// An `ldr Rd, =<value>` reading its value from a literal pool
+    LiteralLoad {
+        statement: &'a Statement,
+        address: u32,
+        pool: usize,
+        index: usize,
+    },
+    /// Bytes placed by a data or layout directive
+    Data {
+        statement: &'a Statement,
+        address: u32,
+        size: u32,
+    },
+    /// A literal pool, placed by `.ltorg` or at the end of the program
+    Pool { id: usize },
+    /// A statement that places no bytes, such as a label or an `.equ`
+    Empty {
+        statement: &'a Statement,
+        address: u32,
+    },
+}
+
+impl Item<'_> {
+    /// The address of the statement, if the item has one
+    fn address(&self) -> Option<u32> {
+        match self {
+            Item::Instruction { address, .. }
+            | Item::LiteralLoad { address, .. }
+            | Item::Data { address, .. }
+            | Item::Empty { address, .. } => Some(*address),
+            Item::Pool { .. } => None,
+        }
+    }
+
+    /// Moves the item by `offset` bytes. Pools are moved by the `LiteralPool`
+    fn shift(&mut self, offset: u32) {
+        match self {
+            Item::Instruction { address, .. }
+            | Item::LiteralLoad { address, .. }
+            | Item::Data { address, .. }
+            | Item::Empty { address, .. } => *address += offset,
+            Item::Pool { .. } => {}
+        }
+    }
+}
+
+/// An `.equ` whose value depends on labels defined after it,
+/// evaluated once the first pass has placed every label
+struct Deferred<'a> {
+    statement: &'a Statement,
+    section: Section,
+    address: u32,
+}
+
+/// The result of the first pass
+struct Layout<'a> {
+    /// The items of each section, indexed by `Section::index`
+    items: [Vec<Item<'a>>; 2],
+    symbols: SymbolTable,
+    /// The labels and their addresses, in the order they were defined
+    labels: Vec<(String, u32)>,
+    literals: LiteralPool,
+    /// The start and end address of each section
+    bounds: [(u32, u32); 2],
+}
+
+/// Options of the `assemble` subcommand
+#[derive(Debug, Default)]
+pub struct AssembleOptions {
+    /// Where `.include` looks for files not found next to the including file
+    pub include_dirs: Vec<PathBuf>,
+    /// Whether to write a `.lst` listing next to the binary
+    pub listing: bool,
+    /// Whether to write a `.sym` map of the labels next to the binary
+    pub symbol_map: bool,
+    /// Whether to write an ELF executable instead of a raw binary
+    pub elf: bool,
+}
+
+/// Everything produced by assembling a program
+#[derive(Debug)]
+pub struct Assembly {
+    /// The little endian bytes `CpuState::init` loads
+    pub binary: Vec<u8>,
+    /// Address, encoding and source of every statement
+    pub listing: String,
+    /// The address of every label, as read by the emulator
+    pub symbol_map: String,
+    /// The same program as an ELF executable, starting at `_start`
+    /// if it is defined and at the start of `.text` otherwise
+    pub executable: Vec<u8>,
+}
+
+/// Assembles the file at `asm_path` and writes the binary
+/// or ELF executable to `out_path`, along with the listing (`.lst`) and symbol map (`.sym`) if asked to
+///
+/// Propagates std::io::Error to `main` if a path is invalid
+/// or if the source contains an error
+pub fn assemble(asm_path: &str, out_path: &str, options: &AssembleOptions) -> Result<(), Error> {
+    let assembly = assemble_file(Path::new(asm_path), &options.include_dirs)
+        .map_err(|errors| Error::new(ErrorKind::InvalidData, render_errors(&errors)))?;
+    if options.elf {
+        fs::write(out_path, &assembly.executable)?;
+    } else {
+        fs::write(out_path, &assembly.binary)?;
+    }
+    if options.listing {
+        fs::write(Path::new(out_path).with_extension("lst"), &assembly.listing)?;
+    }
+    if options.symbol_map {
+        fs::write(
+            Path::new(out_path).with_extension("sym"),
+            &assembly.symbol_map,
+        )?;
+    }
+    Ok(())
+}
+
+/// Assembles the file at `path`.
+/// `.include` looks for files next to the including file, then in `include_dirs`
+pub fn assemble_file(path: &Path, include_dirs: &[PathBuf]) -> Result<Assembly, Vec<AsmError>> {
+    let lines = Preprocessor::new(include_dirs).preprocess_file(path)?;
+    assemble_lines(&lines)
+}
+
+/// Assembles source text into the little endian bytes `CpuState::init` loads
+#[cfg(test)]
+pub fn assemble_source(source: &str) -> Result<Vec<u8>, Vec<AsmError>> {
+    let lines = Preprocessor::new(&[]).preprocess_source(source, "<source>")?;
+    Ok(assemble_lines(&lines)?.binary)
+}
+
+/// Runs both passes over preprocessed source lines.
+/// The second pass runs even if the first one found errors, to report them all
+fn assemble_lines(lines: &[SourceLine]) -> Result<Assembly, Vec<AsmError>> {
+    let statements = parser::parse_lines(lines)?;
+    let mut errors = Vec::new();
+    let layout = build_layout(&statements, &mut errors);
+    let binary = encode_layout(&layout, &mut errors);
+    if !errors.is_empty() {
+        // Literal pools are encoded after the lines that use them
+        errors.sort_by_key(|error| {
+            let location = &error.location;
+            (location.file.clone(), location.line, error.column)
+        });
+        return Err(errors);
+    }
+    Ok(Assembly {
+        listing: list_layout(&layout, &binary),
+        symbol_map: listing::symbol_map(&layout.labels),
+        executable: write_executable(&layout, &binary),
+        binary,
+    })
+}
+
+/// Builds an ELF executable with a segment for each section
+/// and a symbol table holding every label
+fn write_executable(layout: &Layout, binary: &[u8]) -> Vec<u8> {
+    let mut image = binary.to_vec();
+    let end = layout.bounds.iter().map(|(_, end)| *end).max().unwrap_or(0);
+    image.resize(image.len().max(end as usize), 0);
+
+    let sections: Vec<elf::ProgramSection> = Section::ALL
+        .iter()
+        .map(|section| {
+            let (start, end) = layout.bounds[section.index()];
+            let flags = match section {
+                Section::Text => elf::PF_R | elf::PF_X,
+                Section::Data => elf::PF_R | elf::PF_W,
+            };
+            elf::ProgramSection {
+                name: section.name(),
+                address: start,
+                data: &image[start as usize..end as usize],
+                flags,
+            }
+        })
+        .collect();
+    let symbols: Vec<(elf::Symbol, usize)> = layout
+        .labels
+        .iter()
+        .map(|(name, address)| {
+            let section = layout.symbols.get(name).and_then(|value| value.section);
+            let symbol = elf::Symbol {
+                name: name.clone(),
+                value: *address,
+            };
+            (symbol, section.unwrap_or(Section::Text).index())
+        })
+        .collect();
+    let entry = match layout.symbols.get("_start") {
+        Some(Value {
+            value,
+            section: Some(Section::Text),
+        }) => value as u32,
+        _ => layout.bounds[Section::Text.index()].0,
+    };
+    elf::write_executable(&sections, entry, &symbols)
+}
+
+/// Attaches the location of the statement to an encoder error
+fn at_statement(statement: &Statement) -> impl Fn(String) -> AsmError + '_ {
+    move |message| AsmError::new(&statement.location, message).with_source(&statement.text)
+}
+
+/// Returns the value expression of an `ldr Rd, =<value>` statement
+fn literal_operand(statement: &Statement) -> Option<&str> {
+    let mnemonic = split_mnemonic(statement.mnemonic.as_ref()?).ok()?;
+    if mnemonic.base != "ldr" || statement.operands.len() != 2 {
+        return None;
+    }
+    statement.operands[1].trim().strip_prefix('=')
+}
+
+/// Rounds the address up to the next word boundary
+fn align_word(address: u32) -> u32 {
+    align_to(address, INSTRUCTION_SIZE)
+}
+
+/// First pass: assigns an address to every label, instruction and literal pool.
+/// Each section is laid out from 0, then `.data` is moved after `.text`.
+/// Statements with errors are skipped, placing no bytes
+fn build_layout<'a>(statements: &'a [Statement], errors: &mut Vec<AsmError>) -> Layout<'a> {
+    let mut symbols = SymbolTable::new();
+    let mut labels = Vec::new();
+    let mut literals = LiteralPool::new();
+    let mut items: [Vec<Item>; 2] = [Vec::new(), Vec::new()];
+    let mut addresses = [0; 2];
+    // The start of each section must satisfy the alignments asked for inside it
+    let mut alignments = [INSTRUCTION_SIZE; 2];
+    let mut deferred = Vec::new();
+    let mut section = Section::Text;
+
+    for statement in statements {
+        let address = addresses[section.index()];
+        symbols.set_location(section, address);
+        for label in &statement.labels {
+            let value = Value {
+                value: address as i64,
+                section: Some(section),
+            };
+            match symbols.define(label, value) {
+                Ok(()) => labels.push(label.clone()),
+                Err(message) => errors.push(at_statement(statement)(message)),
+            }
+        }
+        let mnemonic = match &statement.mnemonic {
+            Some(mnemonic) => mnemonic.to_lowercase(),
+            None => {
+                items[section.index()].push(Item::Empty { statement, address });
+                continue;
+            }
+        };
+
+        if directives::is_section_directive(&mnemonic) {
+            match directives::section(&mnemonic, &statement.operands) {
+                Ok(next) => section = next,
+                Err(message) => errors.push(at_statement(statement)(message)),
+            }
+            let address = addresses[section.index()];
+            items[section.index()].push(Item::Empty { statement, address });
+            continue;
+        }
+        let (address, items) = (&mut addresses[section.index()], &mut items[section.index()]);
+        let statement_address = *address;
+
+        if mnemonic.starts_with('.') {
+            let name = mnemonic;
+            let operands = &statement.operands;
+            if name == ".ltorg" {
+                let start = align_word(*address);
+                if start > *address {
+                    items.push(Item::Data {
+                        statement,
+                        address: *address,
+                        size: start - *address,
+                    });
+                } else {
+                    items.push(Item::Empty {
+                        statement,
+                        address: *address,
+                    });
+                }
+                *address = start;
+                let id = literals.pools().len();
+                let size = literals.flush(*address);
+                if size > 0 {
+                    items.push(Item::Pool { id });
+                    *address += size;
+                }
+            } else if directives::is_symbol_directive(&name) {
+                if directives::define_symbol(&name, operands, &mut symbols).is_err() {
+                    // Try again once every label is known
+                    deferred.push(Deferred {
+                        statement,
+                        section,
+                        address: *address,
+                    });
+                }
+                items.push(Item::Empty {
+                    statement,
+                    address: *address,
+                });
+            } else {
+                let size = match directives::size(&name, operands, *address, &symbols) {
+                    Ok(size) => size,
+                    Err(message) => {
+                        errors.push(at_statement(statement)(message));
+                        continue;
+                    }
+                };
+                if let Ok(alignment) = directives::alignment(&name, operands, &symbols) {
+                    let required = &mut alignments[section.index()];
+                    *required = (*required).max(alignment);
+                }
+                items.push(Item::Data {
+                    statement,
+                    address: *address,
+                    size,
+                });
+                *address += size;
+            }
+            continue;
+        }
+
+        if !statement_address.is_multiple_of(INSTRUCTION_SIZE) {
+            errors.push(at_statement(statement)(format!(
+                "instruction at unaligned address 0x{:x}, use `.align 2` before it",
+                statement_address
+            )));
+            continue;
+        }
+        match literal_operand(statement) {
+            Some(expression) if literal_pool::immediate_value(expression, &symbols).is_none() => {
+                let (pool, index) = literals.add(expression, &statement.location, &statement.text);
+                items.push(Item::LiteralLoad {
+                    statement,
+                    address: statement_address,
+                    pool,
+                    index,
+                });
+            }
+            _ => items.push(Item::Instruction {
+                statement,
+                address: statement_address,
+            }),
+        }
+        *address += INSTRUCTION_SIZE;
+    }
+
+    // Whatever is left goes at the end of the code
+    let text = Section::Text.index();
+    let id = literals.pools().len();
+    let size = literals.flush(align_word(addresses[text]));
+    if size > 0 {
+        items[text].push(Item::Pool { id });
+        addresses[text] = align_word(addresses[text]) + size;
+    }
+
+    // Place the data after the code
+    let data = Section::Data.index();
+    let data_start = align_to(addresses[text], alignments[data]);
+    for item in &mut items[data] {
+        match item {
+            Item::Pool { id } => literals.relocate(*id, data_start),
+            item => item.shift(data_start),
+        }
+    }
+    symbols.place(Section::Data, data_start);
+
+    define_deferred(&deferred, &mut symbols, errors);
+    let labels = labels
+        .into_iter()
+        .map(|label| {
+            let address = symbols.get(&label).unwrap().value as u32;
+            (label, address)
+        })
+        .collect();
+
+    let bounds = [
+        (0, addresses[text]),
+        (data_start, data_start + addresses[data]),
+    ];
+    Layout {
+        items,
+        symbols,
+        labels,
+        literals,
+        bounds,
+    }
+}
+
+/// Defines the `.equ` symbols that referred to labels defined after them.
+/// Symbols may depend on each other, so keep going while some get defined
+fn define_deferred(deferred: &[Deferred], symbols: &mut SymbolTable, errors: &mut Vec<AsmError>) {
+    let mut pending: Vec<&Deferred> = deferred.iter().collect();
+    loop {
+        let before = pending.len();
+        pending.retain(|symbol| {
+            symbols.set_location(symbol.section, symbol.address);
+            let name = symbol.statement.mnemonic.as_ref().unwrap().to_lowercase();
+            directives::define_symbol(&name, &symbol.statement.operands, symbols).is_err()
+        });
+        if pending.is_empty() || pending.len() == before {
+            break;
+        }
+    }
+
+    for symbol in pending {
+        symbols.set_location(symbol.section, symbol.address);
+        let name = symbol.statement.mnemonic.as_ref().unwrap().to_lowercase();
+        if let Err(message) = directives::define_symbol(&name, &symbol.statement.operands, symbols)
+        {
+            errors.push(at_statement(symbol.statement)(message));
+        }
+    }
+}
+
+/// Rounds the address up to a multiple of `alignment`, a power of 2
+fn align_to(address: u32, alignment: u32) -> u32 {
+    (address + alignment - 1) & !(alignment - 1)
+}
+
+/// Writes bytes at the given address, growing the image if needed
+fn write_bytes(image: &mut Vec<u8>, address: u32, bytes: &[u8]) {
+    let address = address as usize;
+    if image.len() < address + bytes.len() {
+        image.resize(address + bytes.len(), 0);
+    }
+    image[address..address + bytes.len()].copy_from_slice(bytes);
+}
+
+/// Writes a little endian word at the given address
+fn write_word(image: &mut Vec<u8>, address: u32, word: u32) {
+    write_bytes(image, address, &word.to_le_bytes());
+}
+
+/// Second pass: encodes every item now that all labels are known.
+/// Items with errors are left as zeros
+fn encode_layout(layout: &Layout, errors: &mut Vec<AsmError>) -> Vec<u8> {
+    let symbols = &layout.symbols;
+    let mut image = Vec::new();
+
+    let items = Section::ALL.iter().flat_map(|section| {
+        layout.items[section.index()]
+            .iter()
+            .map(move |item| (*section, item))
+    });
+    for (section, item) in items {
+        if let Some(address) = item.address() {
+            symbols.set_location(section, address);
+        }
+        match item {
+            Item::Instruction { statement, address } => {
+                let mnemonic = statement.mnemonic.as_ref().unwrap();
+                match encode_instruction(mnemonic, &statement.operands, *address, symbols) {
+                    Ok(word) => write_word(&mut image, *address, word),
+                    Err(message) => errors.push(at_statement(statement)(message)),
+                }
+            }
+            Item::LiteralLoad {
+                statement,
+                address,
+                pool,
+                index,
+            } => {
+                let literal = layout.literals.pool(*pool).literal_address(*index);
+                let word =
+                    split_mnemonic(statement.mnemonic.as_ref().unwrap()).and_then(|mnemonic| {
+                        sdt::encode_literal_load(&mnemonic, &statement.operands, *address, literal)
+                    });
+                match word {
+                    Ok(word) => write_word(&mut image, *address, word),
+                    Err(message) => errors.push(at_statement(statement)(message)),
+                }
+            }
+            Item::Data {
+                statement,
+                address,
+                size,
+            } => {
+                let name = statement.mnemonic.as_ref().unwrap().to_lowercase();
+                match directives::emit(&name, &statement.operands, *size, symbols) {
+                    Ok(bytes) => write_bytes(&mut image, *address, &bytes),
+                    Err(message) => errors.push(at_statement(statement)(message)),
+                }
+            }
+            Item::Pool { id } => {
+                let pool = layout.literals.pool(*id);
+                for (index, literal) in pool.literals.iter().enumerate() {
+                    match evaluate_word(&literal.expression, symbols) {
+                        Ok(value) => write_word(&mut image, pool.literal_address(index), value),
+                        Err(message) => errors.push(
+                            AsmError::new(&literal.location, message)
+                                .with_source_at(&literal.text, &literal.expression),
+                        ),
+                    }
+                }
+            }
+            Item::Empty { .. } => {}
+        }
+    }
+
+    // The emulator loads whole words only
+    let padded = align_word(image.len() as u32) as usize;
+    image.resize(padded, 0);
+    image
+}
+
+/// Lists every item of the layout along with the bytes
+/// the second pass encoded for it
+fn list_layout(layout: &Layout, image: &[u8]) -> String {
+    let bytes_at = |address: u32, size: u32| &image[address as usize..(address + size) as usize];
+    let word_at = |address: u32| {
+        let bytes = bytes_at(address, INSTRUCTION_SIZE);
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    };
+    let mut lines = Vec::new();
+    for item in layout.items.iter().flatten() {
+        match item {
+            Item::Instruction { statement, address }
+            | Item::LiteralLoad {
+                statement, address, ..
+            } => {
+                let word = word_at(*address);
+                lines.push(listing::word_line(
+                    *address,
+                    word,
+                    statement.from_macro,
+                    &statement.location,
+                    &statement.text,
+                ));
+            }
+            Item::Data {
+                statement,
+                address,
+                size,
+            } => {
+                lines.extend(listing::data_lines(
+                    *address,
+                    bytes_at(*address, *size),
+                    statement.from_macro,
+                    &statement.location,
+                    &statement.text,
+                ));
+            }
+            Item::Empty { statement, address } => {
+                lines.extend(listing::data_lines(
+                    *address,
+                    &[],
+                    statement.from_macro,
+                    &statement.location,
+                    &statement.text,
+                ));
+            }
+            Item::Pool { id } => {
+                let pool = layout.literals.pool(*id);
+                for (index, literal) in pool.literals.iter().enumerate() {
+                    let address = pool.literal_address(index);
+                    let word = word_at(address);
+                    let text = format!("    .word {} @ literal pool", literal.expression);
+                    lines.push(listing::word_line(
+                        address,
+                        word,
+                        false,
+                        &literal.location,
+                        &text,
+                    ));
+                }
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Encodes a single instruction found at `address`
+fn encode_instruction(
+    mnemonic: &str,
+    operands: &[String],
+    address: u32,
+    symbols: &SymbolTable,
+) -> EncodeResult {
+    let mnemonic = split_mnemonic(mnemonic)?;
+    match mnemonic.base {
+        "mul" | "mla" => mul::encode(&mnemonic, operands),
+        "smlal" if mnemonic.suffix.len() == 2 => mul::encode_halfword(&mnemonic, operands),
+        "umull" | "umlal" | "smull" | "smlal" => mul::encode_long(&mnemonic, operands),
+        "smul" | "smla" => mul::encode_halfword(&mnemonic, operands),
+        "clz" => misc::encode_count_leading_zeros(&mnemonic, operands),
+        "qadd" | "qsub" if mnemonic.suffix.is_empty() => {
+            misc::encode_saturating(&mnemonic, operands)
+        }
+        "qdadd" | "qdsub" => misc::encode_saturating(&mnemonic, operands),
+        "sel" => media::encode_select(&mnemonic, operands),
+        "rev" => media::encode_reverse(&mnemonic, operands),
+        "sxtb" | "sxth" | "uxtb" | "uxth" | "sxtab" | "sxtah" | "uxtab" | "uxtah" => {
+            media::encode_extend(&mnemonic, operands, symbols)
+        }
+        "ssat" | "usat" => media::encode_saturate(&mnemonic, operands, symbols),
+        "swp" | "strex" => sync::encode_store(&mnemonic, operands),
+        "ldrex" => sync::encode_load_exclusive(&mnemonic, operands),
+        "ldr" | "str" => sdt::encode(&mnemonic, operands, address, symbols),
+        "ldm" | "stm" | "push" | "pop" => block::encode(&mnemonic, operands),
+        "b" | "bl" => branch::encode(&mnemonic, operands, address, symbols),
+        "bx" | "blx" => branch::encode_exchange(&mnemonic, operands, address, symbols),
+        "mrs" | "msr" => psr::encode(&mnemonic, operands, symbols),
+        "swi" | "svc" => swi::encode(&mnemonic, operands, symbols),
+        "cdp" | "mcr" | "mrc" | "mcrr" | "mrrc" => {
+            coprocessor::encode_register(&mnemonic, operands, symbols)
+        }
+        "ldc" | "stc" => coprocessor::encode_data_transfer(&mnemonic, operands, symbols),
+        "lsl" | "lsr" | "asr" | "ror" => {
+            data_proc::encode_shift_instr(&mnemonic, operands, symbols)
+        }
+        _ if media::is_parallel(&mnemonic) => media::encode_parallel(&mnemonic, operands),
+        _ => data_proc::encode(&mnemonic, operands, symbols),
+    }
+}
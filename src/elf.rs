@@ -0,0 +1,392 @@
+//! Reading and writing of the parts of 32-bit little endian ARM ELF files
+//! the assembler and emulator use: loadable segments, sections and symbols
+
+/// The first bytes of every ELF file
+pub const MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+
+const CLASS_32: u8 = 1;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_ARM: u16 = 40;
+/// Version 5 of the ARM embedded ABI
+const FLAGS_EABI5: u32 = 0x0500_0000;
+
+const HEADER_SIZE: usize = 52;
+const PROGRAM_HEADER_SIZE: usize = 32;
+const SECTION_HEADER_SIZE: usize = 40;
+const SYMBOL_SIZE: usize = 16;
+
+const PT_LOAD: u32 = 1;
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHF_WRITE: u32 = 1;
+const SHF_ALLOC: u32 = 2;
+const SHF_EXECINSTR: u32 = 4;
+
+const STB_LOCAL: u8 = 0;
+const STT_NOTYPE: u8 = 0;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+
+/// A section of code or data placed in memory
+#[derive(Debug)]
+pub struct ProgramSection<'a> {
+    pub name: &'a str,
+    pub address: u32,
+    pub data: &'a [u8],
+    /// Combination of `PF_R`, `PF_W` and `PF_X`
+    pub flags: u32,
+}
+
+/// A symbol of an executable, pointing into one of its sections
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u32,
+}
+
+/// A loadable segment: `data` goes at `address`, followed by zeros up to `memory_size`
+#[derive(Debug, PartialEq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+    pub memory_size: u32,
+}
+
+/// What the emulator needs from an executable
+#[derive(Debug, PartialEq)]
+pub struct Executable {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    pub symbols: Vec<Symbol>,
+}
+
+/// Whether the bytes start like an ELF file
+pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Pads with zeros up to a multiple of 4 bytes
+fn align(bytes: &mut Vec<u8>) {
+    bytes.resize((bytes.len() + 3) & !3, 0);
+}
+
+/// A string table: names separated by 0 bytes, starting with an empty name
+struct StringTable {
+    bytes: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        Self { bytes: vec![0] }
+    }
+
+    /// Appends a name and returns its offset in the table
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+/// The fields of a section header
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u32,
+    address: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+    info: u32,
+    alignment: u32,
+    entry_size: u32,
+}
+
+/// Builds an executable with one loadable segment per non-empty section,
+/// the sections themselves, and a symbol table.
+/// `symbols` pairs each symbol with the index in `sections` it points into
+pub fn write_executable(
+    sections: &[ProgramSection],
+    entry: u32,
+    symbols: &[(Symbol, usize)],
+) -> Vec<u8> {
+    let loaded: Vec<&ProgramSection> = sections.iter().filter(|s| !s.data.is_empty()).collect();
+    let mut bytes = vec![0; HEADER_SIZE + PROGRAM_HEADER_SIZE * loaded.len()];
+
+    // Section contents, each word aligned
+    let mut offsets = Vec::new();
+    for section in sections {
+        align(&mut bytes);
+        offsets.push(bytes.len() as u32);
+        bytes.extend_from_slice(section.data);
+    }
+
+    let mut names = StringTable::new();
+    let mut symbol_table = vec![0; SYMBOL_SIZE];
+    for (symbol, section) in symbols {
+        push_u32(&mut symbol_table, names.add(&symbol.name));
+        push_u32(&mut symbol_table, symbol.value);
+        push_u32(&mut symbol_table, 0);
+        symbol_table.push((STB_LOCAL << 4) | STT_NOTYPE);
+        symbol_table.push(0);
+        // Section 0 is the null section
+        push_u16(&mut symbol_table, *section as u16 + 1);
+    }
+    align(&mut bytes);
+    let symtab_offset = bytes.len() as u32;
+    bytes.extend_from_slice(&symbol_table);
+    let strtab_offset = bytes.len() as u32;
+    bytes.extend_from_slice(&names.bytes);
+
+    let mut section_names = StringTable::new();
+    let mut headers = vec![SectionHeader {
+        name: 0,
+        kind: 0,
+        flags: 0,
+        address: 0,
+        offset: 0,
+        size: 0,
+        link: 0,
+        info: 0,
+        alignment: 0,
+        entry_size: 0,
+    }];
+    for (section, offset) in sections.iter().zip(&offsets) {
+        let mut flags = SHF_ALLOC;
+        if section.flags & PF_W != 0 {
+            flags |= SHF_WRITE;
+        }
+        if section.flags & PF_X != 0 {
+            flags |= SHF_EXECINSTR;
+        }
+        headers.push(SectionHeader {
+            name: section_names.add(section.name),
+            kind: SHT_PROGBITS,
+            flags,
+            address: section.address,
+            offset: *offset,
+            size: section.data.len() as u32,
+            link: 0,
+            info: 0,
+            alignment: 4,
+            entry_size: 0,
+        });
+    }
+    let strtab_index = headers.len() as u32 + 1;
+    headers.push(SectionHeader {
+        name: section_names.add(".symtab"),
+        kind: SHT_SYMTAB,
+        flags: 0,
+        address: 0,
+        offset: symtab_offset,
+        size: symbol_table.len() as u32,
+        link: strtab_index,
+        // Every symbol is local
+        info: symbols.len() as u32 + 1,
+        alignment: 4,
+        entry_size: SYMBOL_SIZE as u32,
+    });
+    headers.push(SectionHeader {
+        name: section_names.add(".strtab"),
+        kind: SHT_STRTAB,
+        flags: 0,
+        address: 0,
+        offset: strtab_offset,
+        size: names.bytes.len() as u32,
+        link: 0,
+        info: 0,
+        alignment: 1,
+        entry_size: 0,
+    });
+    let shstrtab_name = section_names.add(".shstrtab");
+    headers.push(SectionHeader {
+        name: shstrtab_name,
+        kind: SHT_STRTAB,
+        flags: 0,
+        address: 0,
+        offset: bytes.len() as u32,
+        size: section_names.bytes.len() as u32,
+        link: 0,
+        info: 0,
+        alignment: 1,
+        entry_size: 0,
+    });
+    bytes.extend_from_slice(&section_names.bytes);
+
+    align(&mut bytes);
+    let section_headers_offset = bytes.len() as u32;
+    for header in &headers {
+        for field in &[
+            header.name,
+            header.kind,
+            header.flags,
+            header.address,
+            header.offset,
+            header.size,
+            header.link,
+            header.info,
+            header.alignment,
+            header.entry_size,
+        ] {
+            push_u32(&mut bytes, *field);
+        }
+    }
+
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(&MAGIC);
+    header.extend_from_slice(&[CLASS_32, DATA_LITTLE_ENDIAN, VERSION_CURRENT]);
+    header.resize(16, 0);
+    push_u16(&mut header, TYPE_EXECUTABLE);
+    push_u16(&mut header, MACHINE_ARM);
+    push_u32(&mut header, VERSION_CURRENT as u32);
+    push_u32(&mut header, entry);
+    push_u32(&mut header, HEADER_SIZE as u32);
+    push_u32(&mut header, section_headers_offset);
+    push_u32(&mut header, FLAGS_EABI5);
+    push_u16(&mut header, HEADER_SIZE as u16);
+    push_u16(&mut header, PROGRAM_HEADER_SIZE as u16);
+    push_u16(&mut header, loaded.len() as u16);
+    push_u16(&mut header, SECTION_HEADER_SIZE as u16);
+    push_u16(&mut header, headers.len() as u16);
+    push_u16(&mut header, headers.len() as u16 - 1);
+
+    for (section, offset) in sections.iter().zip(&offsets) {
+        if section.data.is_empty() {
+            continue;
+        }
+        for field in &[
+            PT_LOAD,
+            *offset,
+            section.address,
+            section.address,
+            section.data.len() as u32,
+            section.data.len() as u32,
+            section.flags,
+            4,
+        ] {
+            push_u32(&mut header, *field);
+        }
+    }
+    bytes[..header.len()].copy_from_slice(&header);
+    bytes
+}
+
+/// Reads little endian fields, failing instead of panicking on truncated files
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn slice(&self, offset: usize, size: usize) -> Result<&'a [u8], String> {
+        offset
+            .checked_add(size)
+            .and_then(|end| self.bytes.get(offset..end))
+            .ok_or_else(|| String::from("truncated ELF file"))
+    }
+
+    fn u8(&self, offset: usize) -> Result<u8, String> {
+        Ok(self.slice(offset, 1)?[0])
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, String> {
+        let bytes = self.slice(offset, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, String> {
+        let bytes = self.slice(offset, 4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a 0 terminated string
+    fn string(&self, offset: usize) -> Result<String, String> {
+        let rest = self.bytes.get(offset..).ok_or("truncated ELF file")?;
+        let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        Ok(String::from_utf8_lossy(&rest[..end]).to_string())
+    }
+}
+
+/// Reads the entry point, loadable segments and symbols of an ARM executable
+pub fn read_executable(bytes: &[u8]) -> Result<Executable, String> {
+    let reader = Reader { bytes };
+    if !is_elf(bytes) {
+        return Err(String::from("not an ELF file"));
+    }
+    if reader.u8(4)? != CLASS_32 || reader.u8(5)? != DATA_LITTLE_ENDIAN {
+        return Err(String::from(
+            "only 32-bit little endian ELF files are supported",
+        ));
+    }
+    if reader.u16(18)? != MACHINE_ARM {
+        return Err(String::from("the ELF file is not for ARM"));
+    }
+    let entry = reader.u32(24)?;
+    let program_headers = reader.u32(28)? as usize;
+    let section_headers = reader.u32(32)? as usize;
+    let program_header_size = reader.u16(42)? as usize;
+    let program_header_count = reader.u16(44)? as usize;
+    let section_header_size = reader.u16(46)? as usize;
+    let section_header_count = reader.u16(48)? as usize;
+
+    let mut segments = Vec::new();
+    for ind in 0..program_header_count {
+        let header = program_headers + ind * program_header_size;
+        if reader.u32(header)? != PT_LOAD {
+            continue;
+        }
+        let offset = reader.u32(header + 4)? as usize;
+        let file_size = reader.u32(header + 16)? as usize;
+        segments.push(Segment {
+            address: reader.u32(header + 8)?,
+            data: reader.slice(offset, file_size)?.to_vec(),
+            memory_size: reader.u32(header + 20)?,
+        });
+    }
+
+    let mut symbols = Vec::new();
+    for ind in 0..section_header_count {
+        let header = section_headers + ind * section_header_size;
+        if reader.u32(header + 4)? != SHT_SYMTAB {
+            continue;
+        }
+        let offset = reader.u32(header + 16)? as usize;
+        let size = reader.u32(header + 20)? as usize;
+        let names_header =
+            section_headers + reader.u32(header + 24)? as usize * section_header_size;
+        let names = reader.u32(names_header + 16)? as usize;
+        // Skip the null symbol
+        for symbol in (offset + SYMBOL_SIZE..offset + size).step_by(SYMBOL_SIZE) {
+            let kind = reader.u8(symbol + 12)? & 0xf;
+            let name = reader.string(names + reader.u32(symbol)? as usize)?;
+            if kind == STT_SECTION || kind == STT_FILE || name.is_empty() {
+                continue;
+            }
+            symbols.push(Symbol {
+                name,
+                value: reader.u32(symbol + 4)?,
+            });
+        }
+    }
+
+    Ok(Executable {
+        entry,
+        segments,
+        symbols,
+    })
+}
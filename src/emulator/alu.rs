@@ -0,0 +1,87 @@
+use crate::emulator::em_utilities::*;
+
+/// The value computed by the ALU along with the flags it produces.
+/// The C and V flags are None when the operation leaves them unchanged
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AluResult {
+    pub value: u32,
+    pub carry: Option<bool>,
+    pub overflow: Option<bool>,
+}
+
+impl AluResult {
+    /// Writes the N, Z, C and V flags of the result to the CPSR
+    pub fn set_flags(&self, cpu: &mut CpuState) {
+        cpu.set_CPSR_flag(Flag::N, mask![self.value, 31]);
+        cpu.set_CPSR_flag(Flag::Z, self.value == 0);
+        if let Some(carry) = self.carry {
+            cpu.set_CPSR_flag(Flag::C, carry);
+        }
+        if let Some(overflow) = self.overflow {
+            cpu.set_CPSR_flag(Flag::V, overflow);
+        }
+    }
+}
+
+/// Adds two operands and a carry (`AddWithCarry` in the ARM ARM).
+/// C is the unsigned carry out and V the signed overflow
+pub fn add(lhs: u32, rhs: u32, carry: bool) -> AluResult {
+    let unsigned = lhs as u64 + rhs as u64 + carry as u64;
+    let signed = lhs as i32 as i64 + rhs as i32 as i64 + carry as i64;
+    let value = unsigned as u32;
+    AluResult {
+        value,
+        carry: Some(unsigned > u32::MAX as u64),
+        overflow: Some(signed != value as i32 as i64),
+    }
+}
+
+/// Computes `lhs - rhs - NOT(carry)` by adding the complement of `rhs`,
+/// so C is set when there is no borrow
+pub fn subtract(lhs: u32, rhs: u32, carry: bool) -> AluResult {
+    add(lhs, !rhs, carry)
+}
+
+/// Computes `rhs - lhs - NOT(carry)`
+pub fn reverse_subtract(lhs: u32, rhs: u32, carry: bool) -> AluResult {
+    subtract(rhs, lhs, carry)
+}
+
+/// A bitwise operation: C is the carry out of the barrel shifter,
+/// if it produced one, and V is unchanged
+pub fn logical(value: u32, shifter_carry: Option<bool>) -> AluResult {
+    AluResult {
+        value,
+        carry: shifter_carry,
+        overflow: None,
+    }
+}
+
+/// A multiplication: only N and Z depend on the result
+pub fn multiply(value: u32) -> AluResult {
+    logical(value, None)
+}
+
+/// A long multiplication: N and Z depend on the whole 64-bit result,
+/// C and V are unchanged
+pub fn set_long_multiply_flags(value: u64, cpu: &mut CpuState) {
+    cpu.set_CPSR_flag(Flag::N, value >> 63 != 0);
+    cpu.set_CPSR_flag(Flag::Z, value == 0);
+}
+
+/// Saturates a value to the range of a `bits` wide signed integer (`SignedSatQ`),
+/// returning the value sign extended to 32 bits and whether it had to saturate
+pub fn signed_saturate(value: i64, bits: u32) -> (u32, bool) {
+    let max = (1i64 << (bits - 1)) - 1;
+    let min = -(1i64 << (bits - 1));
+    let saturated = value.clamp(min, max);
+    (saturated as u32, saturated != value)
+}
+
+/// Saturates a value to the range of a `bits` wide unsigned integer (`UnsignedSatQ`),
+/// returning whether it had to saturate
+pub fn unsigned_saturate(value: i64, bits: u32) -> (u32, bool) {
+    let max = (1i64 << bits) - 1;
+    let saturated = value.clamp(0, max);
+    (saturated as u32, saturated != value)
+}
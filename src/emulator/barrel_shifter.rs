@@ -4,7 +4,7 @@ use util::*;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
-#[derive(FromPrimitive, Debug)]
+#[derive(FromPrimitive, Debug, Clone, Copy)]
 pub enum ShiftOp {
     LSL = 0,
     LSR = 1,
@@ -29,7 +29,7 @@ macro_rules! shift_mode_bit {
 
 macro_rules! shift_type_bits {
     ($bits:expr) => {
-        mask![$bits, 5, 6];
+        mask![$bits, 5, 6]
     }
 }
 
@@ -45,77 +45,103 @@ macro_rules! shift_constant_bits {
     }
 }
 
+/// The result of a shift and its carry out.
+/// The carry is None when the shift leaves the C flag unchanged
+pub type ShiftResult = (u32, Option<bool>);
 
-fn execute_shift(operand: u32, shift_amount: u32, shift_opcode: ShiftOp,
-                 c_bit: &mut u8) -> u32 {
-    let result: u32;
-    let cbit: u64;
+/// Shifts by an amount which may be 32 or more, as register-specified shifts do.
+/// Shifting by 0 leaves both the operand and the carry alone
+pub fn shift_by_register(operand: u32, shift_amount: u32, shift_opcode: ShiftOp) -> ShiftResult {
+    if shift_amount == 0 {
+        return (operand, None);
+    }
+    let bit = |pos: u32| operand & (1 << pos) != 0;
     match shift_opcode {
-        ShiftOp::LSL => {
-            result = operand << shift_amount;
-            // CPSR c bit is set to bit 32 after lsl (carry out)
-            cbit = (((operand as u64) << shift_amount as u64) >> 32) & 1;
-        }
-        ShiftOp::LSR => {
-            result = operand >> shift_amount;
-            cbit = ( (operand >> (shift_amount - 1)) & 1 ) as u64;
-        }
-        ShiftOp::ASR => {
-            result = arithmetic_shift_right(operand, shift_amount);
-            cbit = ( (operand >> (shift_amount - 1)) & 1 ) as u64;
-        }
-        ShiftOp::ROR => {
-            result = rotate_right(operand, shift_amount);
-            cbit = ( (operand >> (shift_amount - 1)) & 1 ) as u64;
-        }
-    };
-    *c_bit = cbit as u8;
-    result
+        ShiftOp::LSL => match shift_amount {
+            1..=31 => (operand << shift_amount, Some(bit(32 - shift_amount))),
+            32 => (0, Some(bit(0))),
+            _ => (0, Some(false)),
+        },
+        ShiftOp::LSR => match shift_amount {
+            1..=31 => (operand >> shift_amount, Some(bit(shift_amount - 1))),
+            32 => (0, Some(bit(31))),
+            _ => (0, Some(false)),
+        },
+        ShiftOp::ASR => match shift_amount {
+            1..=31 => (
+                arithmetic_shift_right(operand, shift_amount),
+                Some(bit(shift_amount - 1)),
+            ),
+            // Every bit is a copy of the sign bit
+            _ => (arithmetic_shift_right(operand, 31), Some(bit(31))),
+        },
+        ShiftOp::ROR => match shift_amount % 32 {
+            // Rotating by a multiple of 32 gives the operand back
+            0 => (operand, Some(bit(31))),
+            amount => (rotate_right(operand, amount), Some(bit(amount - 1))),
+        },
+    }
 }
 
-pub fn reg_offset_shift(cpu: &CpuState, instr: &Instruction, c_bit: &mut u8) -> u32 {
-    let mut result: u32 = 0;
-    let bits = instr.code;
-    let reg_contents: u32 = cpu.registers[shifted_reg_m_bits![bits] as usize];
+/// Shifts by a 5 bit immediate. An amount of 0 encodes `LSL #0`,
+/// `LSR #32`, `ASR #32` and `RRX`, which rotates right by one through the carry
+pub fn shift_by_immediate(operand: u32, shift_amount: u32, shift_opcode: ShiftOp,
+                          carry: bool) -> ShiftResult {
+    match (shift_opcode, shift_amount) {
+        (ShiftOp::LSL, 0) => (operand, None),
+        (ShiftOp::LSR, 0) | (ShiftOp::ASR, 0) => shift_by_register(operand, 32, shift_opcode),
+        (ShiftOp::ROR, 0) => ((carry as u32) << 31 | operand >> 1, Some(operand & 1 != 0)),
+        _ => shift_by_register(operand, shift_amount, shift_opcode),
+    }
+}
 
-    if shift_mode_bit![bits] {
-        let lower_byte: u8 = cpu.registers[shift_register_bits![bits] as usize] as u8;
-        let shift_type = shift_type_bits![bits];
-        let shift_type = FromPrimitive::from_u32(shift_type).unwrap();
-        result = execute_shift(reg_contents, lower_byte as u32, shift_type, c_bit);
+/// Computes an 8 bit immediate rotated right by twice the 4 bit rotation,
+/// the operand2 of data processing instructions.
+/// The carry out is bit 31 of the result unless the rotation is 0
+pub fn rotated_immediate(bits: u32) -> ShiftResult {
+    let rotation = mask![bits, 8, 11] * 2;
+    let operand = rotate_right(mask![bits, 0, 7], rotation);
+    if rotation == 0 {
+        (operand, None)
     } else {
-        let shift_type = shift_type_bits![bits];
-        let shift_type = FromPrimitive::from_u32(shift_type).unwrap();
-        result = execute_shift(reg_contents, shift_constant_bits![bits], shift_type, c_bit);
+        (operand, Some(mask![operand, 31]))
     }
-
-    result
 }
 
-pub fn rotate_right(operand: u32, rotate_amount: u32) -> u32 {
-    let mut result: u32 = operand >> rotate_amount;
-    if rotate_amount == 0 {
-        // edge case when the result should be 1
-        return operand;
+/// Reads a register operand of an instruction whose operand2 is in `bits`.
+/// The PC reads as the address of the instruction plus 8, or plus 12
+/// with a register specified shift, which takes an extra cycle to read the shift
+pub fn read_operand(cpu: &CpuState, bits: u32, index: usize) -> u32 {
+    let value = cpu.registers[index];
+    if index == PC && !mask![bits, 25] && shift_mode_bit![bits] {
+        value.wrapping_add(4)
+    } else {
+        value
     }
-    result |= operand << (32 - rotate_amount);
-
-    result
 }
 
-pub fn arithmetic_shift_right(operand: u32, shift_amount: u32) -> u32 {
-    let mut result: u32 = 0;
-    if ((1 << 31) & operand) != 0 {
-        // MSB is 1
-        result = operand >> shift_amount;
-        for _ in 0..shift_amount {
-            result |= 1 << (31 - 1);
-        }
+/// Computes a shifted register operand and the carry out of the shift
+pub fn reg_offset_shift(cpu: &CpuState, instr: &Instruction) -> ShiftResult {
+    let bits = instr.code;
+    let reg_contents: u32 = read_operand(cpu, bits, shifted_reg_m_bits![bits] as usize);
+    let shift_type = shift_type_bits![bits];
+    let shift_type = FromPrimitive::from_u32(shift_type).unwrap();
+
+    if shift_mode_bit![bits] {
+        // Only the bottom byte of the register is used
+        let amount = read_operand(cpu, bits, shift_register_bits![bits] as usize) & 0xff;
+        shift_by_register(reg_contents, amount, shift_type)
     } else {
-        result = operand >> shift_amount;
+        let amount = shift_constant_bits![bits];
+        shift_by_immediate(reg_contents, amount, shift_type, cpu.get_flag(Flag::C))
     }
-    result
 }
 
+pub fn rotate_right(operand: u32, rotate_amount: u32) -> u32 {
+    operand.rotate_right(rotate_amount)
+}
 
-
+/// Shifts right by less than 32, copying the sign bit into the top bits
+pub fn arithmetic_shift_right(operand: u32, shift_amount: u32) -> u32 {
+    ((operand as i32) >> shift_amount) as u32
+}
@@ -0,0 +1,123 @@
+use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::Exception;
+use util::*;
+
+macro_rules! indexing_bit {
+    ($bits:expr) => {
+        mask![$bits, 24]
+    };
+}
+
+macro_rules! up_bit {
+    ($bits:expr) => {
+        mask![$bits, 23]
+    };
+}
+
+/// Bit 22, written `^`
+macro_rules! user_bank_bit {
+    ($bits:expr) => {
+        mask![$bits, 22]
+    };
+}
+
+macro_rules! write_back_bit {
+    ($bits:expr) => {
+        mask![$bits, 21]
+    };
+}
+
+macro_rules! load_bit {
+    ($bits:expr) => {
+        mask![$bits, 20]
+    };
+}
+
+macro_rules! base_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 16, 19] as usize
+    };
+}
+
+macro_rules! register_list_bits {
+    ($bits:expr) => {
+        mask![$bits, 0, 15]
+    };
+}
+
+/// Executes an LDM or STM, returning whether it loaded the PC, which interworks
+/// from ARMv5 on. A stored PC is the address of the STM plus 8.
+/// Registers are always transferred in ascending order from the lowest address,
+/// so the addressing mode only decides where that lowest address is.
+/// With `^`, an LDM that loads the PC also restores the CPSR from the SPSR,
+/// the other forms transfer the user mode registers.
+/// If any of the addresses is outside memory, nothing is transferred
+/// and the instruction aborts
+pub fn execute_block_data_instr(instr: &Instruction, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let bits = instr.code;
+    let rn = base_reg_bits![bits];
+    let list = register_list_bits![bits];
+    let size = 4 * list.count_ones();
+    let base = cpu.registers[rn];
+
+    let (lowest, new_base) = match (up_bit![bits], indexing_bit![bits]) {
+        // Increment after and increment before
+        (true, false) => (base, base.wrapping_add(size)),
+        (true, true) => (base.wrapping_add(4), base.wrapping_add(size)),
+        // Decrement after and decrement before
+        (false, false) => (base.wrapping_sub(size).wrapping_add(4), base.wrapping_sub(size)),
+        (false, true) => (base.wrapping_sub(size), base.wrapping_sub(size)),
+    };
+
+    let mut address = lowest & !3;
+    let in_memory = (0..list.count_ones())
+        .all(|i| cpu.physical_address(address.wrapping_add(4 * i), 4).is_some());
+    if !in_memory {
+        return Err(Exception::DataAbort);
+    }
+    let registers = (0..16).filter(|reg| list & (1 << reg) != 0);
+    let loads_pc = load_bit![bits] && list & (1 << PC) != 0;
+    let user_bank = user_bank_bit![bits] && !loads_pc;
+    if load_bit![bits] {
+        if write_back_bit![bits] {
+            cpu.registers[rn] = new_base;
+        }
+        // A loaded base takes priority over the written back one
+        for reg in registers {
+            let value = cpu.read_word(address)?;
+            if reg == PC {
+                cpu.registers[PC] = value;
+            } else if user_bank {
+                cpu.set_user_register(reg, value);
+            } else {
+                cpu.registers[reg] = value;
+            }
+            address = address.wrapping_add(4);
+        }
+        if loads_pc && user_bank_bit![bits] {
+            // The SPSR decides the state, the loaded value is just aligned for it
+            if let Some(spsr) = cpu.spsr() {
+                cpu.set_cpsr(spsr);
+            }
+            cpu.branch_to(cpu.pc());
+        } else if loads_pc {
+            cpu.load_pc(cpu.pc());
+        }
+    } else {
+        // Stores use the values from before the write back
+        for reg in registers {
+            let value = if user_bank {
+                cpu.user_register(reg)
+            } else {
+                cpu.registers[reg]
+            };
+            cpu.write_word(address, value)?;
+            address = address.wrapping_add(4);
+        }
+        if write_back_bit![bits] {
+            cpu.registers[rn] = new_base;
+        }
+    }
+
+    Ok(loads_pc)
+}
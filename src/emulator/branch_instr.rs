@@ -1,21 +1,28 @@
-
-use num_traits::FromPrimitive;
-
 use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::Exception;
 use util::*;
 
-/// Executes a branch instruction, returning whether it succeeded or not
-pub fn execute_branch_instr(instr: &Instruction, cpu: &mut CpuState, pipe: &mut Pipe) -> bool {
-    let condition = process_mask(instr.code, BitPos32::from_u8(28), BitPos32::from_u8(31));
-    let condition = FromPrimitive::from_u32(condition).unwrap();
-
-    if !cpu.check_CPSR_cond(condition) {
-        // Failed jump condition, not jumping, clearing the current branch instruction
-        // from the pipe
-        pipe.clear_executing();
-        return false;
-    }
+macro_rules! link_bit {
+    ($bits:expr) => {
+        mask![$bits, 24]
+    };
+}
+
+/// Bit 5 of BX and BLX, set when the return address is written to LR
+macro_rules! exchange_link_bit {
+    ($bits:expr) => {
+        mask![$bits, 5]
+    };
+}
+
+macro_rules! target_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 0, 3] as usize
+    };
+}
 
+/// Executes a branch instruction whose condition passed, returning whether it flushed the pipe
+pub fn execute_branch_instr(instr: &Instruction, cpu: &mut CpuState, pipe: &mut Pipe) -> bool {
     // Processes offset from bits 0-23
     let mut offset: i32 =
         (process_mask(instr.code, BitPos32::from_u8(0), BitPos32::from_u8(23)) << 2) as i32;
@@ -26,10 +33,36 @@ pub fn execute_branch_instr(instr: &Instruction, cpu: &mut CpuState, pipe: &mut
         offset |= -mask;
     }
 
+    if link_bit![instr.code] {
+        // The return address is the instruction after the branch,
+        // the PC being 8 bytes ahead of it
+        cpu.registers[LR] = cpu.pc() - 4;
+    }
     cpu.offset_pc(offset);
-    pipe.clear();
-    pipe.set_fetching(cpu.fetch(cpu.pc() as usize));
-    cpu.increment_pc();
+    pipe.flush(cpu);
 
     true
 }
+
+/// Executes BX or BLX with a register target, switching to Thumb state
+/// if bit 0 of the target is set. Always flushes the pipe.
+/// BLX is undefined before ARMv5, which only has BX
+pub fn execute_branch_exchange_instr(
+    instr: &Instruction,
+    cpu: &mut CpuState,
+    pipe: &mut Pipe,
+) -> Result<bool, Exception> {
+    let link = exchange_link_bit![instr.code];
+    if link && cpu.architecture < Architecture::ARMv5 {
+        return Err(Exception::UndefinedInstruction);
+    }
+    // Read the target first, as `blx lr` overwrites it
+    let target = cpu.registers[target_reg_bits![instr.code]];
+    if link {
+        cpu.registers[LR] = cpu.pc() - 4;
+    }
+    cpu.branch_exchange(target);
+    pipe.flush(cpu);
+
+    Ok(true)
+}
@@ -0,0 +1,167 @@
+//! The coprocessor interface: CDP, MCR, MRC, MCRR, MRRC, LDC and STC
+//! are passed to the coprocessor attached to the slot they name
+
+use std::fmt;
+
+use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::Exception;
+use crate::emulator::system_control::{SystemControl, SYSTEM_CONTROL};
+use crate::emulator::vfp::Vfp;
+use util::*;
+
+/// The number of coprocessor slots
+pub const COPROCESSORS_NO: usize = 16;
+
+/// The fields of a coprocessor instruction. Which ones are used depends on the instruction,
+/// the others are 0. The whole instruction is there for the fields only a coprocessor knows
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct CoprocessorInstr {
+    pub code: u32,
+    /// Bits 20-23 of CDP, 21-23 of MCR and MRC or 4-7 of MCRR and MRRC
+    pub opcode1: u32,
+    /// Bits 5-7 of CDP, MCR and MRC
+    pub opcode2: u32,
+    /// The coprocessor register in bits 12-15 of CDP, LDC and STC
+    pub crd: u32,
+    /// The coprocessor register in bits 16-19 of CDP, MCR and MRC
+    pub crn: u32,
+    /// The coprocessor register in bits 0-3
+    pub crm: u32,
+    /// The N bit (22) of LDC and STC, which usually selects a long transfer
+    pub long: bool,
+}
+
+impl CoprocessorInstr {
+    /// Picks out the fields of a coprocessor instruction
+    pub fn decode(code: u32) -> Self {
+        let mut instr = Self {
+            code,
+            crm: mask![code, 0, 3],
+            ..Self::default()
+        };
+        match mask![code, 24, 27] {
+            // CDP, then MCR and MRC with bit 4 set
+            0b1110 if !mask![code, 4] => {
+                instr.opcode1 = mask![code, 20, 23];
+                instr.opcode2 = mask![code, 5, 7];
+                instr.crd = mask![code, 12, 15];
+                instr.crn = mask![code, 16, 19];
+            }
+            0b1110 => {
+                instr.opcode1 = mask![code, 21, 23];
+                instr.opcode2 = mask![code, 5, 7];
+                instr.crn = mask![code, 16, 19];
+            }
+            // MCRR and MRRC share the space of LDC and STC without an offset or write back
+            _ if is_double_register_transfer(code) => instr.opcode1 = mask![code, 4, 7],
+            _ => {
+                instr.crd = mask![code, 12, 15];
+                instr.long = mask![code, 22];
+                instr.crm = 0;
+            }
+        }
+        instr
+    }
+}
+
+/// Returns whether an instruction in the LDC and STC space is an MCRR or MRRC:
+/// bits 21-24 are 0010, so neither indexed nor written back
+pub fn is_double_register_transfer(code: u32) -> bool {
+    mask![code, 21, 24] == 0b0010
+}
+
+/// A coprocessor that can be attached to one of the 16 slots.
+/// Every instruction it doesn't implement is undefined
+pub trait Coprocessor: fmt::Debug {
+    /// CDP, an operation on coprocessor registers
+    fn data_operation(&mut self, _instr: &CoprocessorInstr) -> Result<(), Exception> {
+        Err(Exception::UndefinedInstruction)
+    }
+
+    /// MCR, writing an ARM register to the coprocessor
+    fn write_register(&mut self, _instr: &CoprocessorInstr, _value: u32) -> Result<(), Exception> {
+        Err(Exception::UndefinedInstruction)
+    }
+
+    /// MRC, reading a coprocessor register into an ARM register
+    fn read_register(&mut self, _instr: &CoprocessorInstr) -> Result<u32, Exception> {
+        Err(Exception::UndefinedInstruction)
+    }
+
+    /// MCRR, writing two ARM registers to the coprocessor
+    fn write_registers(
+        &mut self,
+        _instr: &CoprocessorInstr,
+        _low: u32,
+        _high: u32,
+    ) -> Result<(), Exception> {
+        Err(Exception::UndefinedInstruction)
+    }
+
+    /// MRRC, reading the coprocessor into two ARM registers, low word first
+    fn read_registers(&mut self, _instr: &CoprocessorInstr) -> Result<(u32, u32), Exception> {
+        Err(Exception::UndefinedInstruction)
+    }
+
+    /// The number of words an LDC or STC transfers, which the coprocessor decides
+    fn transfer_length(&self, _instr: &CoprocessorInstr) -> Result<usize, Exception> {
+        Err(Exception::UndefinedInstruction)
+    }
+
+    /// LDC, given the words loaded from memory
+    fn load(&mut self, _instr: &CoprocessorInstr, _words: &[u32]) -> Result<(), Exception> {
+        Err(Exception::UndefinedInstruction)
+    }
+
+    /// STC, giving the words to store to memory
+    fn store(&mut self, _instr: &CoprocessorInstr) -> Result<Vec<u32>, Exception> {
+        Err(Exception::UndefinedInstruction)
+    }
+
+    /// Whether the exception vectors are at 0xffff0000, which only CP15 decides
+    fn high_vectors(&self) -> bool {
+        false
+    }
+}
+
+/// The coprocessors attached to the CPU, by slot number
+#[derive(Debug, Default)]
+pub struct Coprocessors {
+    slots: [Option<Box<dyn Coprocessor>>; COPROCESSORS_NO],
+}
+
+impl Coprocessors {
+    /// The coprocessors of the core emulated: CP15 and, from ARMv6 on, VFP
+    pub fn built_in(architecture: Architecture, high_vectors: bool) -> Self {
+        let mut coprocessors = Self::default();
+        let cp15 = SystemControl::new(architecture, high_vectors);
+        coprocessors.attach(SYSTEM_CONTROL, Box::new(cp15));
+        if architecture >= Architecture::ARMv6 {
+            Vfp::attach(&mut coprocessors);
+        }
+        coprocessors
+    }
+
+    /// Attaches a coprocessor to a slot, replacing the one there
+    pub fn attach(&mut self, number: usize, coprocessor: Box<dyn Coprocessor>) {
+        self.slots[number] = Some(coprocessor);
+    }
+
+    pub fn get(&self, number: usize) -> Option<&dyn Coprocessor> {
+        self.slots[number].as_deref()
+    }
+
+    pub fn get_mut(&mut self, number: usize) -> Option<&mut (dyn Coprocessor + 'static)> {
+        self.slots[number].as_deref_mut()
+    }
+}
+
+/// Coprocessors can't be compared, so only the slots they are attached to are
+impl PartialEq for Coprocessors {
+    fn eq(&self, other: &Self) -> bool {
+        self.slots
+            .iter()
+            .zip(other.slots.iter())
+            .all(|(slot, other)| slot.is_some() == other.is_some())
+    }
+}
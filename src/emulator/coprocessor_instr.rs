@@ -0,0 +1,187 @@
+use crate::emulator::coprocessor::{is_double_register_transfer, Coprocessor, CoprocessorInstr};
+use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::Exception;
+use crate::emulator::single_data_transfer_instr::compute_address;
+use util::*;
+
+macro_rules! coprocessor_bits {
+    ($bits:expr) => {
+        mask![$bits, 8, 11] as usize
+    };
+}
+
+macro_rules! load_bit {
+    ($bits:expr) => {
+        mask![$bits, 20]
+    };
+}
+
+macro_rules! indexing_bit {
+    ($bits:expr) => {
+        mask![$bits, 24]
+    };
+}
+
+macro_rules! up_bit {
+    ($bits:expr) => {
+        mask![$bits, 23]
+    };
+}
+
+macro_rules! write_back_bit {
+    ($bits:expr) => {
+        mask![$bits, 21]
+    };
+}
+
+macro_rules! base_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 16, 19] as usize
+    };
+}
+
+macro_rules! transfer_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 12, 15] as usize
+    };
+}
+
+/// The 8 bit word offset of LDC and STC
+macro_rules! offset_bits {
+    ($bits:expr) => {
+        mask![$bits, 0, 7] << 2
+    };
+}
+
+/// Executes a coprocessor instruction by passing it to the coprocessor in the slot it names,
+/// returning whether it wrote the PC.
+/// Instructions for an empty slot, or which the coprocessor rejects, are undefined
+pub fn execute_coprocessor_instr(
+    instr: &Instruction,
+    cpu: &mut CpuState,
+) -> Result<bool, Exception> {
+    let bits = instr.code;
+    let fields = CoprocessorInstr::decode(bits);
+    let number = coprocessor_bits![bits];
+    if cpu.coprocessors.get(number).is_none() {
+        return Err(Exception::UndefinedInstruction);
+    }
+
+    match mask![bits, 24, 27] {
+        0b1110 if !mask![bits, 4] => {
+            coprocessor(cpu, number).data_operation(&fields)?;
+            Ok(false)
+        }
+        0b1110 => {
+            register_transfer(cpu, number, &fields)?;
+            Ok(false)
+        }
+        _ if is_double_register_transfer(bits) => {
+            double_register_transfer(cpu, number, &fields)?;
+            Ok(false)
+        }
+        _ => data_transfer(cpu, number, &fields),
+    }
+}
+
+/// The coprocessor in a slot known not to be empty
+fn coprocessor(cpu: &mut CpuState, number: usize) -> &mut dyn Coprocessor {
+    cpu.coprocessors.get_mut(number).unwrap()
+}
+
+/// MCR and MRC. An MRC to the PC sets the condition flags
+/// to the top 4 bits of the value instead
+fn register_transfer(
+    cpu: &mut CpuState,
+    number: usize,
+    fields: &CoprocessorInstr,
+) -> Result<(), Exception> {
+    let rd = transfer_reg_bits![fields.code];
+    if !load_bit![fields.code] {
+        let value = cpu.registers[rd];
+        return coprocessor(cpu, number).write_register(fields, value);
+    }
+
+    let value = coprocessor(cpu, number).read_register(fields)?;
+    if rd == PC {
+        let flags = value & 0xf000_0000;
+        cpu.set_cpsr(cpu.cpsr() & 0x0fff_ffff | flags);
+    } else {
+        cpu.registers[rd] = value;
+    }
+    Ok(())
+}
+
+/// MCRR and MRRC, with the low word in Rd and the high word in Rn
+fn double_register_transfer(
+    cpu: &mut CpuState,
+    number: usize,
+    fields: &CoprocessorInstr,
+) -> Result<(), Exception> {
+    let rd = transfer_reg_bits![fields.code];
+    let rn = base_reg_bits![fields.code];
+    if load_bit![fields.code] {
+        let (low, high) = coprocessor(cpu, number).read_registers(fields)?;
+        cpu.registers[rd] = low;
+        cpu.registers[rn] = high;
+    } else {
+        let (low, high) = (cpu.registers[rd], cpu.registers[rn]);
+        coprocessor(cpu, number).write_registers(fields, low, high)?;
+    }
+    Ok(())
+}
+
+/// LDC and STC, transferring as many consecutive words as the coprocessor asks for.
+/// Unindexed transfers, neither pre-indexed nor written back,
+/// use the base as it is and leave bits 0-7 to the coprocessor
+fn data_transfer(
+    cpu: &mut CpuState,
+    number: usize,
+    fields: &CoprocessorInstr,
+) -> Result<bool, Exception> {
+    let bits = fields.code;
+    let (address, write_back) = if indexing_bit![bits] || write_back_bit![bits] {
+        compute_address(cpu, bits, offset_bits![bits])
+    } else if up_bit![bits] {
+        (cpu.registers[base_reg_bits![bits]], None)
+    } else {
+        return Err(Exception::UndefinedInstruction);
+    };
+
+    // Every word is checked before the coprocessor or the base are changed
+    let length = coprocessor(cpu, number).transfer_length(fields)?;
+    let addresses: Vec<u32> = (0..length as u32)
+        .map(|word| address.wrapping_add(word * 4))
+        .collect();
+    if addresses
+        .iter()
+        .any(|&address| cpu.physical_address(address, 4).is_none())
+    {
+        return Err(Exception::DataAbort);
+    }
+
+    if load_bit![bits] {
+        let words = addresses
+            .iter()
+            .map(|&address| cpu.read_word(address))
+            .collect::<Result<Vec<u32>, Exception>>()?;
+        coprocessor(cpu, number).load(fields, &words)?;
+    } else {
+        let words = coprocessor(cpu, number).store(fields)?;
+        for (&address, &word) in addresses.iter().zip(words.iter()) {
+            cpu.write_word(address, word)?;
+        }
+    }
+
+    match write_back {
+        Some(base) if base_reg_bits![bits] == PC => {
+            cpu.branch_to(base);
+            Ok(true)
+        }
+        Some(base) => {
+            cpu.registers[base_reg_bits![bits]] = base;
+            Ok(false)
+        }
+        None => Ok(false),
+    }
+}
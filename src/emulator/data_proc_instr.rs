@@ -1,4 +1,4 @@
-use crate::emulator::{barrel_shifter as shifter, em_utilities as util};
+use crate::emulator::{alu, barrel_shifter as shifter, em_utilities as util};
 use shifter::*;
 use util::*;
 
@@ -12,11 +12,17 @@ enum DataProcOpcode {
     SUB = 2,
     RSB = 3,
     ADD = 4,
+    ADC = 5,
+    SBC = 6,
+    RSC = 7,
     TST = 8,
     TEQ = 9,
     CMP = 10,
+    CMN = 11,
     ORR = 12,
     MOV = 13,
+    BIC = 14,
+    MVN = 15,
 }
 
 macro_rules! immediate_enabled {
@@ -39,7 +45,7 @@ macro_rules! dest_reg {
 
 macro_rules! operand1_reg_bits {
     ($bits:expr) => {
-        mask![$bits, 16, 19];
+        mask![$bits, 16, 19]
     };
 }
 
@@ -56,117 +62,66 @@ macro_rules! opcode_bits {
     };
 }
 
-/// Executes a data processing instruction
-pub fn execute_data_processing_instr(instr: &Instruction, cpu: &mut CpuState) {
+/// Executes a data processing instruction, returning whether it wrote the PC.
+/// Writing the PC with the S bit set returns from an exception:
+/// the CPSR is restored from the SPSR instead of setting the flags
+pub fn execute_data_processing_instr(instr: &Instruction, cpu: &mut CpuState) -> bool {
     let bits = instr.code;
-    let operand1: u32 = cpu.registers[operand1_reg_bits![bits] as usize];
-    let mut operand2: u32 = operand2_reg_bits![bits];
-
-    // will be the computed result that is written into the dest_register
-    let mut result: u32 = 0;
-    // if write result is 0 then the result is NOT written to the dest_register
-    let mut write_result: u8 = 1;
-    // c_bit is 1 if 1 is to be written to the C bit fo CPSR
-    let mut c_bit: u8 = 0;
-
-    // Compute operand2
-    if immediate_enabled![bits] {
-        operand2 = process_mask(bits, bp32![0], bp32![7]);
-        operand2 = rotate_right(operand2, process_mask(bits, bp32![8], bp32![11]) * 2);
-        c_bit = ((operand2 >> (process_mask(bits, bp32![8], bp32![11]) * 2)) as u8) & 1;
+    let operand1: u32 = read_operand(cpu, bits, operand1_reg_bits![bits] as usize);
+    // Compute operand2 and the carry out of the barrel shifter,
+    // None if it doesn't produce one
+    let (operand2, shifter_carry) = if immediate_enabled![bits] {
+        rotated_immediate(operand2_reg_bits![bits])
     } else {
-        operand2 = reg_offset_shift(cpu, &instr, &mut c_bit);
-    }
+        reg_offset_shift(cpu, instr)
+    };
 
     let opcode = opcode_bits![bits];
     let opcode = FromPrimitive::from_u32(opcode).unwrap();
+    let carry = cpu.get_flag(Flag::C);
 
-    match opcode {
-        DataProcOpcode::AND => {
-            result = operand1 & operand2;
-        }
-        DataProcOpcode::EOR => {
-            result = operand1 ^ operand2;
-        }
-        DataProcOpcode::SUB => {
-            if operand2 > operand1 {
-                // set c_bit if op2 > op1 (overflow)
-                result = u32::MAX - (operand2 - operand1) + 1;
-                c_bit = 0;
-            } else {
-                result = operand1 - operand2;
-                c_bit = 1;
-            }
-            //c_bit = if operand2 > operand1 { 0 } else { 1 };
-        }
-        DataProcOpcode::RSB => {
-            if operand1 > operand2 {
-                result = u32::MAX - (operand1 - operand2) + 1;
-                c_bit = 0;
-            } else {
-                result = operand2 - operand1;
-                c_bit = 1;
-            }
-            //c_bit = if operand1 > operand2 { 0 } else { 1 };
-        }
-        DataProcOpcode::ADD => {
-            // TODO: fix overflow
-            result = operand1 + operand2;
-            // set c_bit if it overflows
-            let overflow_check: u64 = (operand1 as u64) + (operand2 as u64);
-            c_bit = if overflow_check >= ((1 as u64) << 32) {
-                result = overflow_check as u32 - u32::MAX - 1;
-                1
-            } else {
-                0
-            };
+    let result = match opcode {
+        DataProcOpcode::AND | DataProcOpcode::TST => {
+            alu::logical(operand1 & operand2, shifter_carry)
         }
-        DataProcOpcode::TST => {
-            result = operand1 & operand2;
-            write_result = 0;
+        DataProcOpcode::EOR | DataProcOpcode::TEQ => {
+            alu::logical(operand1 ^ operand2, shifter_carry)
         }
-        DataProcOpcode::TEQ => {
-            result = operand1 ^ operand2;
-            write_result = 0;
-        }
-        DataProcOpcode::CMP => {
-            write_result = 0;
-            if operand2 > operand1 {
-                result = u32::MAX - (operand2 - operand1) + 1;
-                // overflows so it wraps
-                c_bit = 0;
-            } else {
-                c_bit = 1;
-                result = operand1 - operand2;
-                write_result = 0;
-                //c_bit = if operand2 > operand1 { 0 } else { 1 };
+        DataProcOpcode::SUB | DataProcOpcode::CMP => alu::subtract(operand1, operand2, true),
+        DataProcOpcode::RSB => alu::reverse_subtract(operand1, operand2, true),
+        DataProcOpcode::ADD | DataProcOpcode::CMN => alu::add(operand1, operand2, false),
+        DataProcOpcode::ADC => alu::add(operand1, operand2, carry),
+        DataProcOpcode::SBC => alu::subtract(operand1, operand2, carry),
+        DataProcOpcode::RSC => alu::reverse_subtract(operand1, operand2, carry),
+        DataProcOpcode::ORR => alu::logical(operand1 | operand2, shifter_carry),
+        DataProcOpcode::MOV => alu::logical(operand2, shifter_carry),
+        DataProcOpcode::BIC => alu::logical(operand1 & !operand2, shifter_carry),
+        DataProcOpcode::MVN => alu::logical(!operand2, shifter_carry),
+    };
+
+    // Test and compare instructions only set the flags
+    let write_result = !matches!(
+        opcode,
+        DataProcOpcode::TST | DataProcOpcode::TEQ | DataProcOpcode::CMP | DataProcOpcode::CMN
+    );
+    let writes_pc = write_result && dest_reg![bits] as usize == PC;
+    if writes_pc {
+        if cpsr_enabled![bits] {
+            // Modes without an SPSR leave the CPSR alone
+            if let Some(spsr) = cpu.spsr() {
+                cpu.set_cpsr(spsr);
             }
         }
-        DataProcOpcode::ORR => {
-            result = operand1 | operand2;
-        }
-        DataProcOpcode::MOV => {
-            result = operand2;
-        }
+        // Aligned for the state the CPSR may have just switched to
+        cpu.branch_to(result.value);
+        return true;
     }
 
-    if write_result != 0 {
-        cpu.registers[dest_reg![bits] as usize] = result;
+    if write_result {
+        cpu.registers[dest_reg![bits] as usize] = result.value;
     }
-
-    // CPSR flags
-
     if cpsr_enabled![bits] {
-        // C bit (bit 29 CPSR) - set to c_bit which is determined by the opcode:
-        cpu.set_CPSR_flag(Flag::C, c_bit != 0);
-
-        //Z bit (bit 30 of CPSR) - Z is 1 iff result == 0:
-        if result == 0 {
-            cpu.set_CPSR_flag(Flag::Z, true);
-        } else {
-            cpu.set_CPSR_flag(Flag::Z, false);
-        }
-
-        cpu.set_CPSR_flag(Flag::N, mask![result, 31]);
+        result.set_flags(cpu);
     }
+    false
 }
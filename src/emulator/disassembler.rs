@@ -0,0 +1,629 @@
+use std::fs;
+
+use crate::emulator::coprocessor::is_double_register_transfer;
+use crate::emulator::em_utilities as util;
+use crate::emulator::pipeline_executor::decode_instruction;
+use util::*;
+
+/// Mnemonics of the data processing opcodes, indexed by opcode
+const DATA_PROC_MNEMONICS: [&str; 16] = [
+    "and", "eor", "sub", "rsb", "add", "adc", "sbc", "rsc", "tst", "teq", "cmp", "cmn", "orr",
+    "mov", "bic", "mvn",
+];
+
+/// Condition suffixes, indexed by condition code. `al` is left out
+const CONDITION_SUFFIXES: [&str; 15] = [
+    "eq", "ne", "cs", "cc", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le", "",
+];
+
+/// Shift names, indexed by shift type
+const SHIFT_NAMES: [&str; 4] = ["lsl", "lsr", "asr", "ror"];
+
+/// Prefixes of the parallel additions and subtractions, indexed by bits 20-22
+const PARALLEL_PREFIXES: [&str; 8] = ["", "s", "q", "sh", "", "u", "uq", "uh"];
+
+/// Operations of the parallel additions and subtractions, indexed by bits 5-7
+const PARALLEL_OPERATIONS: [&str; 8] = ["add16", "asx", "sax", "sub16", "add8", "", "", "sub8"];
+
+/// Extend instructions, indexed by bits 20-22. They add Rn unless it is the PC
+const EXTEND_MNEMONICS: [(&str, &str); 8] = [
+    ("sxtb16", "sxtab16"),
+    ("", ""),
+    ("sxtb", "sxtab"),
+    ("sxth", "sxtah"),
+    ("uxtb16", "uxtab16"),
+    ("", ""),
+    ("uxtb", "uxtab"),
+    ("uxth", "uxtah"),
+];
+
+/// Reads the binary at `path` and prints the address, raw word and
+/// assembly of every instruction, in the layout of the emulator's memory dump
+///
+/// Propagates std::io::Error to `main` if the file path is invalid
+pub fn disassemble(path: &str) -> Result<(), std::io::Error> {
+    let binary = fs::read(path)?;
+    for line in disassemble_binary(&binary) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Disassembles a whole binary, one line per word.
+/// A trailing partial word is padded with zeros
+pub fn disassemble_binary(binary: &[u8]) -> Vec<String> {
+    binary
+        .chunks(4)
+        .enumerate()
+        .map(|(ind, chunk)| {
+            let mut bytes = [0; 4];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            let address = (ind * 4) as u32;
+            let code = u32::from_le_bytes(bytes);
+            // The raw word is shown as the bytes appear in memory
+            format!(
+                "0x{:0>8x}: 0x{:0>8x}    {}",
+                address,
+                u32::from_be_bytes(bytes),
+                disassemble_instr(code, address)
+            )
+        })
+        .collect()
+}
+
+/// Renders a 32-bit word found at `address` as assembly.
+/// Words that don't encode a known instruction are shown as `.word`
+pub fn disassemble_instr(code: u32, address: u32) -> String {
+    let cond = mask![code, 28, 31];
+    if cond == 0b1111 {
+        if mask![code, 25, 27] == 0b101 {
+            return branch_link_exchange(code, address);
+        }
+        return raw_word(code);
+    }
+    let cond = CONDITION_SUFFIXES[cond as usize];
+
+    if mask![code, 25, 27] == 0b101 {
+        // Branch with link has bit 24 set on top of the branch pattern
+        return branch(code, cond, address);
+    }
+    let instr = decode_instruction(code);
+    let text = match instr.instruction_type {
+        InstructionType::DATA_PROCESS => data_processing(code, cond),
+        InstructionType::MULTIPLTY => Some(multiply(code, cond)),
+        InstructionType::SINGLE_DATA_TRANSFER => single_data_transfer(code, cond, address),
+        InstructionType::HALFWORD_DATA_TRANSFER => halfword_data_transfer(code, cond, address),
+        InstructionType::BLOCK_DATA_TRANSFER => Some(block_data_transfer(code, cond)),
+        InstructionType::BRANCH => Some(branch(code, cond, address)),
+        InstructionType::PSR_TRANSFER => psr_transfer(code, cond),
+        InstructionType::SOFTWARE_INTERRUPT => {
+            Some(format!("swi{} 0x{:x}", cond, mask![code, 0, 23]))
+        }
+        InstructionType::BRANCH_EXCHANGE => {
+            let link = if mask![code, 5] { "l" } else { "" };
+            Some(format!("b{}x{} {}", link, cond, reg(mask![code, 0, 3])))
+        }
+        InstructionType::COPROCESSOR => coprocessor(code, cond),
+        InstructionType::MISCELLANEOUS => miscellaneous(code, cond),
+        InstructionType::MEDIA => media(code, cond),
+        InstructionType::SYNCHRONIZATION => synchronization(code, cond),
+        // Already shown as a raw word above, and never decoded from a word
+        InstructionType::UNCONDITIONAL | InstructionType::PREFETCH_ABORT => None,
+        // Words are never decoded as Thumb instructions
+        InstructionType::THUMB => None,
+    };
+    text.unwrap_or_else(|| raw_word(code))
+}
+
+/// A word that can't be shown as an instruction
+fn raw_word(code: u32) -> String {
+    format!(".word 0x{:0>8x}", code)
+}
+
+/// Register name, using `sp`, `lr` and `pc` for r13-r15
+fn reg(index: u32) -> String {
+    match index {
+        13 => String::from("sp"),
+        14 => String::from("lr"),
+        15 => String::from("pc"),
+        n => format!("r{}", n),
+    }
+}
+
+/// An immediate value, in decimal if small and in hex otherwise
+fn immediate(value: u32) -> String {
+    if value < 10 {
+        format!("#{}", value)
+    } else {
+        format!("#0x{:x}", value)
+    }
+}
+
+/// Renders the low 12 bits of a register operand: `Rm{, <shift>}`
+fn shifted_register(code: u32) -> String {
+    let rm = reg(mask![code, 0, 3]);
+    let shift = SHIFT_NAMES[mask![code, 5, 6] as usize];
+    if mask![code, 4] {
+        return format!("{}, {} {}", rm, shift, reg(mask![code, 8, 11]));
+    }
+    match (shift, mask![code, 7, 11]) {
+        ("lsl", 0) => rm,
+        ("ror", 0) => format!("{}, rrx", rm),
+        // lsr #0 and asr #0 encode a shift by 32
+        (shift, 0) => format!("{}, {} #32", rm, shift),
+        (shift, amount) => format!("{}, {} #{}", rm, shift, amount),
+    }
+}
+
+/// The value of a rotated 8 bit immediate operand. The assembler always picks
+/// the smallest rotation, so None for the other encodings of the value
+fn rotated_immediate(code: u32) -> Option<u32> {
+    let rotate = mask![code, 8, 11];
+    let value = mask![code, 0, 7].rotate_right(rotate * 2);
+    let smallest = (0..16).find(|smaller| value.rotate_left(smaller * 2) <= 0xff);
+    if smallest == Some(rotate) {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Renders a data processing instruction
+fn data_processing(code: u32, cond: &str) -> Option<String> {
+    if mask![code, 26, 27] != 0 {
+        // Coprocessor instructions, which the decoder doesn't tell apart yet
+        return None;
+    }
+    let immediate_operand = mask![code, 25];
+    if !immediate_operand && mask![code, 4] && mask![code, 7] {
+        // Not a data processing encoding (bit 7 must be clear for register shifts)
+        return None;
+    }
+
+    let opcode = mask![code, 21, 24];
+    let set_flags = mask![code, 20];
+    let mnemonic = DATA_PROC_MNEMONICS[opcode as usize];
+    let operand2 = if immediate_operand {
+        immediate(rotated_immediate(code)?)
+    } else {
+        shifted_register(code)
+    };
+    let rd = reg(mask![code, 12, 15]);
+    let rn = reg(mask![code, 16, 19]);
+
+    let text = match opcode {
+        // tst, teq, cmp, cmn always set the flags
+        8..=11 => {
+            // Rd should be zero
+            if !set_flags || mask![code, 12, 15] != 0 {
+                return None;
+            }
+            format!("{}{} {}, {}", mnemonic, cond, rn, operand2)
+        }
+        13 | 15 => {
+            // Rn should be zero
+            if mask![code, 16, 19] != 0 {
+                return None;
+            }
+            let s = if set_flags { "s" } else { "" };
+            format!("{}{}{} {}, {}", mnemonic, s, cond, rd, operand2)
+        }
+        _ => {
+            let s = if set_flags { "s" } else { "" };
+            format!("{}{}{} {}, {}, {}", mnemonic, s, cond, rd, rn, operand2)
+        }
+    };
+    Some(text)
+}
+
+/// Renders MRS, or MSR with its `_fsxc` field mask
+fn psr_transfer(code: u32, cond: &str) -> Option<String> {
+    let psr = if mask![code, 22] { "spsr" } else { "cpsr" };
+    if !mask![code, 21] {
+        return Some(format!("mrs{} {}, {}", cond, reg(mask![code, 12, 15]), psr));
+    }
+
+    let fields: String = ["c", "x", "s", "f"]
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(ind, _)| mask![code, 16 + *ind as u8])
+        .map(|(_, field)| *field)
+        .collect();
+    if fields.is_empty() {
+        // Hints such as NOP share the encoding of MSR with an empty mask
+        return None;
+    }
+    let operand = if mask![code, 25] {
+        immediate(rotated_immediate(code)?)
+    } else {
+        reg(mask![code, 0, 3])
+    };
+    Some(format!("msr{} {}_{}, {}", cond, psr, fields, operand))
+}
+
+/// Renders a multiply instruction
+fn multiply(code: u32, cond: &str) -> String {
+    let s = if mask![code, 20] { "s" } else { "" };
+    let rd = reg(mask![code, 16, 19]);
+    let rn = reg(mask![code, 12, 15]);
+    let rs = reg(mask![code, 8, 11]);
+    let rm = reg(mask![code, 0, 3]);
+    if mask![code, 23] {
+        let sign = if mask![code, 22] { "s" } else { "u" };
+        let op = if mask![code, 21] { "mlal" } else { "mull" };
+        // The Rd and Rn fields hold RdHi and RdLo
+        return format!("{}{}{}{} {}, {}, {}, {}", sign, op, s, cond, rn, rd, rm, rs);
+    }
+    if mask![code, 21] {
+        format!("mla{}{} {}, {}, {}, {}", s, cond, rd, rm, rs, rn)
+    } else {
+        format!("mul{}{} {}, {}, {}", s, cond, rd, rm, rs)
+    }
+}
+
+/// Renders a single data transfer instruction.
+/// PC relative loads get the address they read as a comment
+fn single_data_transfer(code: u32, cond: &str, address: u32) -> Option<String> {
+    let register_offset = mask![code, 25];
+    if register_offset && mask![code, 4] {
+        // Register specified shifts can't be used for offsets
+        return None;
+    }
+
+    let pre_index = mask![code, 24];
+    let up = mask![code, 23];
+    let write_back = mask![code, 21];
+    if !pre_index && write_back {
+        // The user mode (`ldrt`) forms are not supported
+        return None;
+    }
+    let mnemonic = if mask![code, 20] { "ldr" } else { "str" };
+    let byte = if mask![code, 22] { "b" } else { "" };
+    let rd = reg(mask![code, 12, 15]);
+    let rn = mask![code, 16, 19];
+    let sign = if up { "" } else { "-" };
+
+    let offset = if register_offset {
+        Some(format!("{}{}", sign, shifted_register(code)))
+    } else {
+        match mask![code, 0, 11] {
+            0 => None,
+            offset => Some(format!("#{}{}", sign, offset)),
+        }
+    };
+
+    let addressing = addressing(rn, pre_index, write_back, offset);
+    let mut text = format!("{}{}{} {}, {}", mnemonic, byte, cond, rd, addressing);
+    if rn == 15 && pre_index && !register_offset {
+        text.push_str(&pc_relative_comment(address, up, mask![code, 0, 11]));
+    }
+    Some(text)
+}
+
+/// Renders a halfword or signed byte transfer
+fn halfword_data_transfer(code: u32, cond: &str, address: u32) -> Option<String> {
+    let pre_index = mask![code, 24];
+    let up = mask![code, 23];
+    let immediate = mask![code, 22];
+    let write_back = mask![code, 21];
+    let load = mask![code, 20];
+    if !pre_index && write_back {
+        return None;
+    }
+    let kind = match (load, mask![code, 5, 6]) {
+        (true, 1) | (false, 1) => "h",
+        (true, 2) => "sb",
+        (true, 3) => "sh",
+        (false, 2) | (false, 3) => "d",
+        _ => return None,
+    };
+    // The doubleword transfers load with the S and H bits of a store
+    let load = load || mask![code, 5, 6] == 2;
+    let mnemonic = if load { "ldr" } else { "str" };
+    let rd = match (kind, mask![code, 12, 15]) {
+        ("d", rd) if rd % 2 == 1 || rd == 14 => return None,
+        ("d", rd) => format!("{}, {}", reg(rd), reg(rd + 1)),
+        (_, rd) => reg(rd),
+    };
+    let rn = mask![code, 16, 19];
+    let sign = if up { "" } else { "-" };
+
+    let immediate_offset = mask![code, 8, 11] << 4 | mask![code, 0, 3];
+    let offset = if !immediate {
+        Some(format!("{}{}", sign, reg(mask![code, 0, 3])))
+    } else if immediate_offset == 0 {
+        None
+    } else {
+        Some(format!("#{}{}", sign, immediate_offset))
+    };
+
+    let addressing = addressing(rn, pre_index, write_back, offset);
+    let mut text = format!("{}{}{} {}, {}", mnemonic, kind, cond, rd, addressing);
+    if rn == 15 && pre_index && immediate {
+        text.push_str(&pc_relative_comment(address, up, immediate_offset));
+    }
+    Some(text)
+}
+
+/// Renders CLZ, QADD, QSUB, QDADD, QDSUB, BKPT or a signed halfword multiply
+fn miscellaneous(code: u32, cond: &str) -> Option<String> {
+    let op = mask![code, 21, 22];
+    let (high, middle) = (mask![code, 16, 19], mask![code, 12, 15]);
+    let (rs, rm) = (reg(mask![code, 8, 11]), reg(mask![code, 0, 3]));
+    match mask![code, 4, 7] {
+        0b0001 if op == 0b11 && high == 15 && mask![code, 8, 11] == 15 => {
+            Some(format!("clz{} {}, {}", cond, reg(middle), rm))
+        }
+        0b0101 if mask![code, 8, 11] == 0 => {
+            let mnemonic = ["qadd", "qsub", "qdadd", "qdsub"][op as usize];
+            Some(format!("{}{} {}, {}, {}", mnemonic, cond, reg(middle), rm, reg(high)))
+        }
+        // BKPT splits its 16 bit immediate around bits 4-7 and can't be conditional
+        0b0111 if op == 0b01 && cond.is_empty() => {
+            Some(format!("bkpt #{}", mask![code, 8, 19] << 4 | mask![code, 0, 3]))
+        }
+        0b1000 | 0b1010 | 0b1100 | 0b1110 => {
+            let x = if mask![code, 5] { "t" } else { "b" };
+            let y = if mask![code, 6] { "t" } else { "b" };
+            let (rd, rn) = (reg(high), reg(middle));
+            match op {
+                0b00 => Some(format!("smla{}{}{} {}, {}, {}, {}", x, y, cond, rd, rm, rs, rn)),
+                // The x bit tells smulw from smlaw
+                0b01 if mask![code, 5] && middle == 0 => {
+                    Some(format!("smulw{}{} {}, {}, {}", y, cond, rd, rm, rs))
+                }
+                0b01 if mask![code, 5] => None,
+                0b01 => Some(format!("smlaw{}{} {}, {}, {}, {}", y, cond, rd, rm, rs, rn)),
+                // The Rd and Rn fields hold RdHi and RdLo
+                0b10 => Some(format!("smlal{}{}{} {}, {}, {}, {}", x, y, cond, rn, rd, rm, rs)),
+                _ if middle == 0 => Some(format!("smul{}{}{} {}, {}, {}", x, y, cond, rd, rm, rs)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Renders an ARMv6 media instruction
+fn media(code: u32, cond: &str) -> Option<String> {
+    let (op1, op2) = (mask![code, 20, 22], mask![code, 5, 7]);
+    let (rn, rd, rm) = (mask![code, 16, 19], reg(mask![code, 12, 15]), reg(mask![code, 0, 3]));
+    let ones = mask![code, 8, 11] == 15;
+    let text = match (mask![code, 23, 24], op2, op1) {
+        (0b00, _, _) => {
+            let prefix = PARALLEL_PREFIXES[op1 as usize];
+            let operation = PARALLEL_OPERATIONS[op2 as usize];
+            if prefix.is_empty() || operation.is_empty() || !ones {
+                return None;
+            }
+            format!("{}{}{} {}, {}, {}", prefix, operation, cond, rd, reg(rn), rm)
+        }
+        (0b01, op2, op1) if op2 & 1 == 0 && op1 & 0b010 != 0 => {
+            let unsigned = mask![code, 22];
+            let mnemonic = if unsigned { "usat" } else { "ssat" };
+            let width = mask![code, 16, 20] + if unsigned { 0 } else { 1 };
+            let shift = match (mask![code, 6], mask![code, 7, 11]) {
+                (false, 0) => String::new(),
+                (false, amount) => format!(", lsl #{}", amount),
+                (true, 0) => String::from(", asr #32"),
+                (true, amount) => format!(", asr #{}", amount),
+            };
+            format!("{}{} {}, #{}, {}{}", mnemonic, cond, rd, width, rm, shift)
+        }
+        (0b01, 0b011, op1) if op1 & 0b011 != 0b001 && mask![code, 8, 9] == 0 => {
+            let rotation = match mask![code, 10, 11] {
+                0 => String::new(),
+                rotation => format!(", ror #{}", 8 * rotation),
+            };
+            let (plain, adding) = EXTEND_MNEMONICS[op1 as usize];
+            if rn == 15 {
+                format!("{}{} {}, {}{}", plain, cond, rd, rm, rotation)
+            } else {
+                format!("{}{} {}, {}, {}{}", adding, cond, rd, reg(rn), rm, rotation)
+            }
+        }
+        (0b01, 0b001, 0b010) | (0b01, 0b001, 0b110) if ones => {
+            let unsigned = mask![code, 22];
+            let mnemonic = if unsigned { "usat16" } else { "ssat16" };
+            let width = mask![code, 16, 19] + if unsigned { 0 } else { 1 };
+            format!("{}{} {}, #{}, {}", mnemonic, cond, rd, width, rm)
+        }
+        (0b01, 0b001, 0b011) | (0b01, 0b101, 0b011) | (0b01, 0b101, 0b111)
+            if ones && rn == 15 =>
+        {
+            let mnemonic = match (op1, op2) {
+                (0b011, 0b001) => "rev",
+                (0b011, _) => "rev16",
+                _ => "revsh",
+            };
+            format!("{}{} {}, {}", mnemonic, cond, rd, rm)
+        }
+        (0b01, 0b101, 0b000) if ones => format!("sel{} {}, {}, {}", cond, rd, reg(rn), rm),
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// Renders SWP, SWPB, LDREX or STREX
+fn synchronization(code: u32, cond: &str) -> Option<String> {
+    let (rn, rd, rm) = (reg(mask![code, 16, 19]), reg(mask![code, 12, 15]), mask![code, 0, 3]);
+    let (ones, zeros) = (mask![code, 8, 11] == 15, mask![code, 8, 11] == 0);
+    match (mask![code, 23], mask![code, 20, 22]) {
+        (false, 0b000) if zeros => Some(format!("swp{} {}, {}, [{}]", cond, rd, reg(rm), rn)),
+        (false, 0b100) if zeros => Some(format!("swpb{} {}, {}, [{}]", cond, rd, reg(rm), rn)),
+        (true, 0b001) if ones && rm == 15 => Some(format!("ldrex{} {}, [{}]", cond, rd, rn)),
+        (true, 0b000) if ones => Some(format!("strex{} {}, {}, [{}]", cond, rd, reg(rm), rn)),
+        _ => None,
+    }
+}
+
+/// Renders a register list such as `{r0-r3, r5, lr}`, using ranges for runs of three or more
+fn register_list(list: u32) -> String {
+    let mut items = Vec::new();
+    let mut reg_ind = 0;
+    while reg_ind < 16 {
+        if list & (1 << reg_ind) == 0 {
+            reg_ind += 1;
+            continue;
+        }
+        let first = reg_ind;
+        while reg_ind < 16 && list & (1 << reg_ind) != 0 {
+            reg_ind += 1;
+        }
+        let last = reg_ind - 1;
+        match last - first {
+            0 => items.push(reg(first)),
+            1 => items.extend([reg(first), reg(last)]),
+            _ => items.push(format!("{}-{}", reg(first), reg(last))),
+        }
+    }
+    format!("{{{}}}", items.join(", "))
+}
+
+/// Renders a block data transfer, using `push` and `pop` for the full descending stack at sp
+fn block_data_transfer(code: u32, cond: &str) -> String {
+    let load = mask![code, 20];
+    let write_back = mask![code, 21];
+    let user_bank = if mask![code, 22] { "^" } else { "" };
+    let mode = match (mask![code, 23], mask![code, 24]) {
+        (true, false) => "ia",
+        (true, true) => "ib",
+        (false, false) => "da",
+        (false, true) => "db",
+    };
+    let rn = mask![code, 16, 19];
+    let list = register_list(mask![code, 0, 15]);
+
+    match (load, mode) {
+        (true, "ia") | (false, "db") if rn == 13 && write_back && user_bank.is_empty() => {
+            let mnemonic = if load { "pop" } else { "push" };
+            format!("{}{} {}", mnemonic, cond, list)
+        }
+        _ => {
+            let mnemonic = if load { "ldm" } else { "stm" };
+            let write_back = if write_back { "!" } else { "" };
+            format!(
+                "{}{}{} {}{}, {}{}",
+                mnemonic,
+                mode,
+                cond,
+                reg(rn),
+                write_back,
+                list,
+                user_bank
+            )
+        }
+    }
+}
+
+/// Renders CDP, MCR, MRC, MCRR, MRRC, LDC or STC
+fn coprocessor(code: u32, cond: &str) -> Option<String> {
+    let number = mask![code, 8, 11];
+    let rd = mask![code, 12, 15];
+    let crn = mask![code, 16, 19];
+    let crm = mask![code, 0, 3];
+    let load = mask![code, 20];
+    if mask![code, 24, 27] == 0b1110 {
+        let opcode2 = mask![code, 5, 7];
+        return Some(if !mask![code, 4] {
+            let opcode1 = mask![code, 20, 23];
+            format!(
+                "cdp{} p{}, {}, c{}, c{}, c{}, {}",
+                cond, number, opcode1, rd, crn, crm, opcode2
+            )
+        } else {
+            let mnemonic = if load { "mrc" } else { "mcr" };
+            let opcode1 = mask![code, 21, 23];
+            format!(
+                "{}{} p{}, {}, {}, c{}, c{}, {}",
+                mnemonic, cond, number, opcode1, reg(rd), crn, crm, opcode2
+            )
+        });
+    }
+    if is_double_register_transfer(code) {
+        let mnemonic = if load { "mrrc" } else { "mcrr" };
+        return Some(format!(
+            "{}{} p{}, {}, {}, {}, c{}",
+            mnemonic,
+            cond,
+            number,
+            mask![code, 4, 7],
+            reg(rd),
+            reg(crn),
+            crm
+        ));
+    }
+
+    let pre_index = mask![code, 24];
+    let up = mask![code, 23];
+    let write_back = mask![code, 21];
+    let offset = mask![code, 0, 7];
+    let address = match (pre_index, write_back) {
+        // Unindexed, with an option for the coprocessor
+        (false, false) if up => format!("[{}], {{{}}}", reg(crn), offset),
+        (false, false) => return None,
+        // A zero offset can't be told apart from a negative one when assembled
+        _ if offset == 0 && !up => return None,
+        (true, _) if offset == 0 => format!("[{}]{}", reg(crn), if write_back { "!" } else { "" }),
+        _ => {
+            let sign = if up { "" } else { "-" };
+            let offset = Some(format!("#{}{}", sign, offset << 2));
+            addressing(crn, pre_index, write_back, offset)
+        }
+    };
+    let mnemonic = if load { "ldc" } else { "stc" };
+    let long = if mask![code, 22] { "l" } else { "" };
+    Some(format!(
+        "{}{}{} p{}, c{}, {}",
+        mnemonic, cond, long, number, rd, address
+    ))
+}
+
+/// Renders the `[Rn, <offset>]{!}` or `[Rn], <offset>` address of a transfer
+fn addressing(rn: u32, pre_index: bool, write_back: bool, offset: Option<String>) -> String {
+    match (pre_index, offset) {
+        (true, None) => format!("[{}]{}", reg(rn), if write_back { "!" } else { "" }),
+        (true, Some(offset)) => {
+            format!(
+                "[{}, {}]{}",
+                reg(rn),
+                offset,
+                if write_back { "!" } else { "" }
+            )
+        }
+        (false, None) => format!("[{}], #0", reg(rn)),
+        (false, Some(offset)) => format!("[{}], {}", reg(rn), offset),
+    }
+}
+
+/// The address a PC relative transfer at `address` reads, as a comment
+fn pc_relative_comment(address: u32, up: bool, offset: u32) -> String {
+    let base = address.wrapping_add(8);
+    let target = if up {
+        base.wrapping_add(offset)
+    } else {
+        base.wrapping_sub(offset)
+    };
+    format!(" @ 0x{:x}", target)
+}
+
+/// Renders a branch, with its target as an absolute address
+fn branch(code: u32, cond: &str, address: u32) -> String {
+    let link = if mask![code, 24] { "l" } else { "" };
+    // Sign extend the 24-bit word offset
+    let offset = ((mask![code, 0, 23] << 8) as i32) >> 6;
+    let target = address.wrapping_add(8).wrapping_add(offset as u32);
+    format!("b{}{} 0x{:x}", link, cond, target)
+}
+
+/// Renders a BLX with an immediate offset, whose H bit adds a halfword to the target
+fn branch_link_exchange(code: u32, address: u32) -> String {
+    let offset = ((mask![code, 0, 23] << 8) as i32) >> 6;
+    let halfword = (mask![code, 24] as u32) << 1;
+    let target = address
+        .wrapping_add(8)
+        .wrapping_add(offset as u32)
+        .wrapping_add(halfword);
+    format!("blx 0x{:x}", target)
+}
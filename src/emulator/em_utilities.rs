@@ -1,10 +1,19 @@
 use std::fs;
+use std::io::{Error, ErrorKind};
 use std::rc::Rc;
 
 use num_derive::FromPrimitive;
 
+use crate::elf;
+use crate::emulator::coprocessor::Coprocessors;
+use crate::emulator::exceptions::Exception;
+use crate::emulator::modes::{Mode, RegisterBanks};
+use crate::emulator::symbol_map::SymbolMap;
+use crate::emulator::system_control::SYSTEM_CONTROL;
+
 /// Println!'s a statement
 /// with the given format if the program is run in debug mode
+#[allow(unused_macros)]
 macro_rules! debug_println {
     ($($args:tt)*) => {
         if cfg!(debug_assertions) {
@@ -40,13 +49,15 @@ macro_rules! bp32 {
     }};
 }
 
-/// The state flags of the ARM processor
+/// The state flags of the ARM processor.
+/// Q is the sticky saturation flag of ARMv5TE, which only MSR clears
 #[derive(Debug, FromPrimitive)]
 pub enum Flag {
     N = 0,
     Z = 1,
     C = 2,
     V = 3,
+    Q = 4,
 }
 
 #[allow(non_camel_case_types)]
@@ -55,7 +66,24 @@ pub enum InstructionType {
     DATA_PROCESS,
     MULTIPLTY,
     SINGLE_DATA_TRANSFER,
+    BLOCK_DATA_TRANSFER,
+    HALFWORD_DATA_TRANSFER,
     BRANCH,
+    BRANCH_EXCHANGE,
+    PSR_TRANSFER,
+    SOFTWARE_INTERRUPT,
+    COPROCESSOR,
+    /// CLZ, saturating arithmetic and the signed halfword multiplies
+    MISCELLANEOUS,
+    /// The ARMv6 media instructions: packing, saturation and parallel arithmetic
+    MEDIA,
+    /// SWP and the exclusive loads and stores
+    SYNCHRONIZATION,
+    UNCONDITIONAL,
+    /// Stands in for an instruction whose fetch aborted
+    PREFETCH_ABORT,
+    /// A 16-bit instruction fetched in Thumb state
+    THUMB,
 }
 
 impl Eq for InstructionType {}
@@ -71,6 +99,14 @@ pub struct Instruction {
 pub enum FlagCode {
     EQ = 0,
     NE = 1,
+    CS = 2,
+    CC = 3,
+    MI = 4,
+    PL = 5,
+    VS = 6,
+    VC = 7,
+    HI = 8,
+    LS = 9,
     GE = 10,
     LT = 11,
     GT = 12,
@@ -78,11 +114,30 @@ pub enum FlagCode {
     AL = 14,
 }
 
+/// The version of the ARM architecture being emulated
+#[derive(Debug, Default, PartialEq, PartialOrd, Clone, Copy)]
+pub enum Architecture {
+    ARMv4,
+    ARMv5,
+    #[default]
+    ARMv6,
+}
+
 const REGISTERS_NO: usize = 17;
 const MEMORY_SIZE: usize = 65536;
-const PC: usize = 15;
+/// Memory also appears at the top of the address space,
+/// which is where the high exception vectors are
+const HIGH_MEMORY: u32 = 0xffff_0000;
+/// The index of the link register, which holds return addresses
+pub const LR: usize = 14;
+/// The index of the program counter
+pub const PC: usize = 15;
 const CPSR: usize = 16;
 const MAX_BIT_INDEX: u8 = 31;
+/// The T bit of the CPSR, set while executing Thumb code
+pub const THUMB_BIT: u32 = 1 << 5;
+/// The lowest of the four GE bits of the CPSR
+const GE_SHIFT: u32 = 16;
 
 /// Enum that holds a position of a bit from a 32-bit number
 pub enum BitPos32 {
@@ -133,18 +188,50 @@ pub struct Pipe {
     pub executing: Option<Rc<Instruction>>,
     pub decoding: Option<Rc<Instruction>>,
     pub fetching: u32,
+    /// Whether fetching the instruction in `fetching` aborted
+    pub fetch_aborted: bool,
 }
 
 impl Pipe {
     /// The pipeline lag is 8 bytes (aka 2 instructions)
     /// because of the pipeline execution cycle
     pub fn init(cpu: &mut CpuState) -> Self {
-        cpu.increment_pc();
-        Self {
+        let mut pipe = Self {
             executing: None,
             decoding: None,
-            fetching: cpu.fetch(0),
+            fetching: 0,
+            fetch_aborted: false,
+        };
+        pipe.fetch(cpu);
+        pipe
+    }
+
+    /// Fetches the instruction at the PC and moves the PC past it,
+    /// a halfword in Thumb state and a word otherwise.
+    /// Fetching outside memory aborts, which only raises an exception
+    /// if the instruction gets to be executed
+    pub fn fetch(&mut self, cpu: &mut CpuState) {
+        let fetched = if cpu.in_thumb_state() {
+            cpu.read_halfword(cpu.pc())
+        } else {
+            cpu.read_word(cpu.pc())
+        };
+        match fetched {
+            Ok(code) => {
+                self.fetching = code;
+                self.fetch_aborted = false;
+            }
+            Err(_) => {
+                self.fetching = 0;
+                self.fetch_aborted = true;
+            }
         }
+        cpu.increment_pc();
+    }
+
+    /// Whether there is still an instruction being fetched
+    pub fn is_fetching(&self) -> bool {
+        self.fetching != 0 || self.fetch_aborted
     }
 
     pub fn clear_executing(&mut self) {
@@ -155,18 +242,18 @@ impl Pipe {
         self.decoding = None;
     }
 
-    pub fn clear_fetching(&mut self) {
-        self.fetching = 0;
-    }
-
-    pub fn set_fetching(&mut self, code: u32) {
-        self.fetching = code;
-    }
-
     pub fn clear(&mut self) {
         self.executing = None;
         self.decoding = None;
         self.fetching = 0;
+        self.fetch_aborted = false;
+    }
+
+    /// Throws away the instructions in the pipeline after the PC was written
+    /// and starts fetching again from the new PC
+    pub fn flush(&mut self, cpu: &mut CpuState) {
+        self.clear();
+        self.fetch(cpu);
     }
 }
 
@@ -174,20 +261,57 @@ impl Pipe {
 pub struct CpuState {
     pub registers: Box<[u32]>,
     pub memory: Box<[u8]>,
+    pub architecture: Architecture,
+    /// The registers of the modes the CPU isn't in, and the SPSRs
+    pub banks: RegisterBanks,
+    /// The coprocessors attached to the CPU, CP15 and VFP among them
+    pub coprocessors: Coprocessors,
+    /// The address LDREX tagged for exclusive access,
+    /// None while the local monitor is in the open access state
+    pub exclusive_address: Option<u32>,
+    /// Whether the IRQ and FIQ lines are asserted. They stay so until their source
+    /// clears them and are taken between instructions while the CPSR doesn't mask them
+    pub irq_line: bool,
+    pub fiq_line: bool,
 }
 
 impl Eq for CpuState {}
 
+impl Default for CpuState {
+    /// A CPU with every register and all of memory cleared
+    /// and the coprocessors of an ARMv6 core attached
+    fn default() -> Self {
+        Self {
+            registers: Box::new([0; REGISTERS_NO]),
+            memory: vec![0; MEMORY_SIZE].into_boxed_slice(),
+            architecture: Architecture::default(),
+            banks: RegisterBanks::default(),
+            coprocessors: Coprocessors::built_in(Architecture::default(), false),
+            exclusive_address: None,
+            irq_line: false,
+            fiq_line: false,
+        }
+    }
+}
+
 impl CpuState {
     /// Initializes an ARM Cpu
     /// with 17 registers
     /// and 65536 bytes of memory
     ///
+    /// ELF executables are loaded at the addresses of their segments
+    /// and start at their entry point, other files are loaded at address 0
+    ///
     /// # Panics
-    /// Panics if the number of bytes from the binary file isn't divisible by 4
+    /// Panics if the number of bytes from a raw binary file isn't divisible by 4
     /// (Must mean the file is corrupted)
     pub fn init(path: &str) -> Result<Self, std::io::Error> {
         let mut instruction_vec = fs::read(path)?;
+        if elf::is_elf(&instruction_vec) {
+            return Self::load_elf(&instruction_vec).map_err(|message| {
+                Error::new(ErrorKind::InvalidData, format!("{}: {}", path, message))
+            });
+        }
         panic_on!(
             instruction_vec.len() % 4 != 0,
             "Can only have a number of bytes in the file which is divisible by 4"
@@ -195,30 +319,104 @@ impl CpuState {
         instruction_vec.resize(MEMORY_SIZE, 0);
         let memory: Box<[u8]> = instruction_vec.into_boxed_slice();
         Ok(Self {
-            registers: Box::new([0; REGISTERS_NO]),
             memory,
+            ..Self::default()
+        })
+    }
+
+    /// Copies the segments of an ELF executable into memory
+    /// and points the ProgramCounter at its entry point
+    fn load_elf(bytes: &[u8]) -> Result<Self, String> {
+        let executable = elf::read_executable(bytes)?;
+        let mut memory = vec![0; MEMORY_SIZE].into_boxed_slice();
+        for segment in &executable.segments {
+            let start = segment.address as usize;
+            let end = start + (segment.memory_size as usize).max(segment.data.len());
+            if end > MEMORY_SIZE {
+                return Err(format!(
+                    "segment at 0x{:x}..0x{:x} doesn't fit in {} bytes of memory",
+                    start, end, MEMORY_SIZE
+                ));
+            }
+            memory[start..start + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        if executable.entry as usize + 4 > MEMORY_SIZE {
+            return Err(format!("entry point 0x{:x} is outside memory", executable.entry));
+        }
+        let mut registers = Box::new([0; REGISTERS_NO]);
+        registers[PC] = executable.entry;
+        Ok(Self {
+            registers,
+            memory,
+            ..Self::default()
         })
     }
 
     /// Fetches a big endian u32 at location ptr from the memory
+    #[cfg(test)]
     pub fn fetch_big_endian(&self, ptr: usize) -> u32 {
         self.index_big_endian(ptr)
     }
 
-    /// Fetches a little endian u32 at location ptr from the memory
-    pub fn fetch(&self, ptr: usize) -> u32 {
-        self.index_little_endian(ptr)
+    /// The index in memory of the `size` bytes at `address`,
+    /// None if they aren't all in memory
+    pub fn physical_address(&self, address: u32, size: usize) -> Option<usize> {
+        let ptr = if address >= HIGH_MEMORY {
+            address - HIGH_MEMORY
+        } else {
+            address
+        } as usize;
+        if ptr + size <= self.memory.len() {
+            Some(ptr)
+        } else {
+            None
+        }
     }
 
-    /// Indexes in little endian an instruction from memory
-    pub fn index_little_endian(&self, ptr: usize) -> u32 {
-        self.memory[ptr] as u32
+    /// Reads the little endian word at `address`, aborting outside memory
+    pub fn read_word(&self, address: u32) -> Result<u32, Exception> {
+        let ptr = self.physical_address(address, 4).ok_or(Exception::DataAbort)?;
+        Ok(self.memory[ptr] as u32
             | (self.memory[ptr + 1] as u32) << 8
             | (self.memory[ptr + 2] as u32) << 16
-            | (self.memory[ptr + 3] as u32) << 24
+            | (self.memory[ptr + 3] as u32) << 24)
+    }
+
+    /// Reads the little endian halfword at `address`, aborting outside memory
+    pub fn read_halfword(&self, address: u32) -> Result<u32, Exception> {
+        let ptr = self.physical_address(address, 2).ok_or(Exception::DataAbort)?;
+        Ok(self.memory[ptr] as u32 | (self.memory[ptr + 1] as u32) << 8)
+    }
+
+    /// Reads the byte at `address`, aborting outside memory
+    pub fn read_byte(&self, address: u32) -> Result<u32, Exception> {
+        let ptr = self.physical_address(address, 1).ok_or(Exception::DataAbort)?;
+        Ok(self.memory[ptr] as u32)
+    }
+
+    /// Stores a word in little endian at `address`, aborting outside memory
+    pub fn write_word(&mut self, address: u32, value: u32) -> Result<(), Exception> {
+        let ptr = self.physical_address(address, 4).ok_or(Exception::DataAbort)?;
+        self.memory[ptr..ptr + 4].copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    /// Stores the bottom 16 bits of the value in little endian at `address`
+    pub fn write_halfword(&mut self, address: u32, value: u32) -> Result<(), Exception> {
+        let ptr = self.physical_address(address, 2).ok_or(Exception::DataAbort)?;
+        self.memory[ptr..ptr + 2].copy_from_slice(&(value as u16).to_le_bytes());
+        Ok(())
+    }
+
+    /// Stores the bottom 8 bits of the value at `address`
+    pub fn write_byte(&mut self, address: u32, value: u32) -> Result<(), Exception> {
+        let ptr = self.physical_address(address, 1).ok_or(Exception::DataAbort)?;
+        self.memory[ptr] = value as u8;
+        Ok(())
     }
 
     /// Indexes in big endian an instruction from memory
+    #[cfg(test)]
     fn index_big_endian(&self, ptr: usize) -> u32 {
         (self.memory[ptr] as u32) << 24
             | (self.memory[ptr + 1] as u32) << 16
@@ -226,6 +424,13 @@ impl CpuState {
             | (self.memory[ptr + 3] as u32)
     }
 
+    /// Whether the exception vectors are at 0xffff0000 instead of 0, which CP15 decides
+    pub fn high_vectors(&self) -> bool {
+        self.coprocessors
+            .get(SYSTEM_CONTROL)
+            .is_some_and(|cp15| cp15.high_vectors())
+    }
+
     /// Returns the current ProgramCounter value
     pub fn pc(&self) -> u32 {
         self.registers[PC]
@@ -253,6 +458,17 @@ impl CpuState {
         (self.registers[CPSR] & mask) != 0
     }
 
+    /// The GE flags of the CPSR, one per byte of the result of a parallel addition
+    /// or subtraction, which SEL selects bytes with
+    pub fn ge_flags(&self) -> u32 {
+        (self.cpsr() >> GE_SHIFT) & 0xf
+    }
+
+    /// Writes the four GE flags of the CPSR
+    pub fn set_ge_flags(&mut self, flags: u32) {
+        self.registers[CPSR] = self.cpsr() & !(0xf << GE_SHIFT) | (flags & 0xf) << GE_SHIFT;
+    }
+
     /// Checks if the CPSR condition meets the flag requirements
     #[allow(non_snake_case)]
     pub fn check_CPSR_cond(&self, flag_code: FlagCode) -> bool {
@@ -261,6 +477,22 @@ impl CpuState {
             FlagCode::EQ => self.get_flag(Flag::Z),
             // Not equal
             FlagCode::NE => !self.get_flag(Flag::Z),
+            // Carry set aka unsigned higher or same
+            FlagCode::CS => self.get_flag(Flag::C),
+            // Carry clear aka unsigned lower
+            FlagCode::CC => !self.get_flag(Flag::C),
+            // Negative
+            FlagCode::MI => self.get_flag(Flag::N),
+            // Positive or zero
+            FlagCode::PL => !self.get_flag(Flag::N),
+            // Overflow
+            FlagCode::VS => self.get_flag(Flag::V),
+            // No overflow
+            FlagCode::VC => !self.get_flag(Flag::V),
+            // Unsigned higher
+            FlagCode::HI => self.get_flag(Flag::C) && !self.get_flag(Flag::Z),
+            // Unsigned lower or same
+            FlagCode::LS => !self.get_flag(Flag::C) || self.get_flag(Flag::Z),
             // Greater than or equal
             FlagCode::GE => self.get_flag(Flag::N) == self.get_flag(Flag::V),
             // Less than
@@ -278,10 +510,19 @@ impl CpuState {
         }
     }
 
-    /// Increments the ProgramCounter (registers[15])
-    /// by 4 bytes aka 32 bits, passing to the next instruction
+    /// Increments the ProgramCounter (registers[15]) by the size of an instruction,
+    /// 4 bytes or 2 in Thumb state, passing to the next instruction
     pub fn increment_pc(&mut self) {
-        self.registers[PC] += 4;
+        self.registers[PC] = self.registers[PC].wrapping_add(self.instruction_size());
+    }
+
+    /// The size in bytes of the instructions of the current state
+    pub fn instruction_size(&self) -> u32 {
+        if self.in_thumb_state() {
+            2
+        } else {
+            4
+        }
     }
 
     /// Offsets the ProgramCounter with 'offset' bytes
@@ -291,15 +532,106 @@ impl CpuState {
         self.registers[PC] = ((self.registers[PC] as i32) + offset) as u32;
     }
 
-    /// Pretty prints the registers
-    pub fn print_registers(&self) {
+    /// The current processor mode
+    pub fn mode(&self) -> Mode {
+        Mode::from_psr(self.cpsr())
+    }
+
+    /// Writes the whole CPSR, switching the banked registers if the mode changes
+    pub fn set_cpsr(&mut self, value: u32) {
+        let (from, to) = (self.mode(), Mode::from_psr(value));
+        self.banks.switch(&mut self.registers, from, to);
+        self.registers[CPSR] = value;
+    }
+
+    /// The SPSR of the current mode, None in user and system mode which have none
+    pub fn spsr(&self) -> Option<u32> {
+        let mode = self.mode();
+        if mode.has_spsr() {
+            Some(self.banks.spsr(mode))
+        } else {
+            None
+        }
+    }
+
+    /// Writes the SPSR of the current mode, if it has one
+    pub fn set_spsr(&mut self, value: u32) {
+        let mode = self.mode();
+        if mode.has_spsr() {
+            self.banks.set_spsr(mode, value);
+        }
+    }
+
+    /// Reads a register as user mode sees it, for the `^` forms of LDM and STM
+    pub fn user_register(&self, index: usize) -> u32 {
+        self.banks
+            .user_register(self.mode(), index)
+            .unwrap_or(self.registers[index])
+    }
+
+    /// Writes a register as user mode sees it
+    pub fn set_user_register(&mut self, index: usize, value: u32) {
+        if !self.banks.set_user_register(self.mode(), index, value) {
+            self.registers[index] = value;
+        }
+    }
+
+    /// Whether the T bit of the CPSR is set
+    pub fn in_thumb_state(&self) -> bool {
+        self.cpsr() & THUMB_BIT != 0
+    }
+
+    /// Sets or clears the T bit of the CPSR
+    pub fn set_thumb_state(&mut self, thumb: bool) {
+        self.registers[CPSR] = if thumb {
+            self.cpsr() | THUMB_BIT
+        } else {
+            self.cpsr() & !THUMB_BIT
+        };
+    }
+
+    /// Writes the result of an instruction to a register, branching if it is the PC.
+    /// Returns whether it wrote the PC
+    pub fn write_register(&mut self, index: usize, value: u32) -> bool {
+        if index == PC {
+            self.branch_to(value);
+            return true;
+        }
+        self.registers[index] = value;
+        false
+    }
+
+    /// Writes the PC as a branch to `target`, which stays in the current state.
+    /// The target is aligned to the size of its instructions
+    pub fn branch_to(&mut self, target: u32) {
+        self.registers[PC] = target & !(self.instruction_size() - 1);
+    }
+
+    /// Writes a value loaded from memory to the PC. From ARMv5 on
+    /// this interworks as BX does, before it is a plain branch
+    pub fn load_pc(&mut self, value: u32) {
+        if self.architecture >= Architecture::ARMv5 {
+            self.branch_exchange(value);
+        } else {
+            self.branch_to(value);
+        }
+    }
+
+    /// Jumps to `target` as BX does: bit 0 of the target selects
+    /// the Thumb state and is cleared from the new PC
+    pub fn branch_exchange(&mut self, target: u32) {
+        self.set_thumb_state(target & 1 != 0);
+        self.registers[PC] = target & !1;
+    }
+
+    /// Pretty prints the registers,
+    /// showing the PC relative to the closest label if the symbols are known
+    pub fn print_registers(&self, symbols: &SymbolMap) {
         let registers = &*self.registers;
 
         println!("Registers:");
         for (ind, reg) in registers.iter().enumerate() {
             let identifier = match ind {
-                // Unused registers
-                13 | 14 => continue,
                 PC => {
                     String::from("$PC:   ")
                     //println!("$PC:    (0x{:0>8x})", reg);
@@ -318,7 +650,12 @@ impl CpuState {
                     //println!("${}:    (0x{:0>8x})", ind, reg);
                 }
             };
-            println!("{} {:>12} (0x{:0>8x})", identifier, reg, reg);
+            match symbols.describe(*reg) {
+                Some(label) if ind == PC => {
+                    println!("{} {:>12} (0x{:0>8x}) <{}>", identifier, reg, reg, label)
+                }
+                _ => println!("{} {:>12} (0x{:0>8x})", identifier, reg, reg),
+            }
         }
     }
 }
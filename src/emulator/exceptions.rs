@@ -0,0 +1,129 @@
+//! The ARM exception model: entering the handler of an exception
+//! through the vector table
+
+use crate::emulator::em_utilities as util;
+use crate::emulator::modes::{Mode, MODE_MASK};
+use util::*;
+
+/// The I bit of the CPSR, which masks IRQs
+pub const IRQ_MASK: u32 = 1 << 7;
+/// The F bit of the CPSR, which masks FIQs
+pub const FIQ_MASK: u32 = 1 << 6;
+/// The A bit of the CPSR, which masks imprecise aborts from ARMv6 on
+pub const ABORT_MASK: u32 = 1 << 8;
+
+/// Where the vector table is when high vectors are selected
+const HIGH_VECTORS: u32 = 0xffff_0000;
+
+/// The exceptions, in the order of their vectors.
+/// Resets only happen at power on, which starts at the entry point instead
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Exception {
+    UndefinedInstruction,
+    SoftwareInterrupt,
+    PrefetchAbort,
+    DataAbort,
+    IRQ,
+    FIQ,
+}
+
+impl Exception {
+    /// The offset of the exception's vector in the vector table
+    pub fn vector(self) -> u32 {
+        match self {
+            Exception::UndefinedInstruction => 0x04,
+            Exception::SoftwareInterrupt => 0x08,
+            Exception::PrefetchAbort => 0x0c,
+            Exception::DataAbort => 0x10,
+            // 0x14 is left unused since 26-bit addressing went away
+            Exception::IRQ => 0x18,
+            Exception::FIQ => 0x1c,
+        }
+    }
+
+    /// The mode the exception is handled in
+    pub fn mode(self) -> Mode {
+        match self {
+            Exception::SoftwareInterrupt => Mode::SVC,
+            Exception::UndefinedInstruction => Mode::UND,
+            Exception::PrefetchAbort | Exception::DataAbort => Mode::ABT,
+            Exception::IRQ => Mode::IRQ,
+            Exception::FIQ => Mode::FIQ,
+        }
+    }
+
+    /// The CPSR bits masked on entry: IRQs always are,
+    /// FIQs only by another FIQ
+    fn masks(self, architecture: Architecture) -> u32 {
+        let fiq = match self {
+            Exception::FIQ => FIQ_MASK,
+            _ => 0,
+        };
+        let abort = match self {
+            Exception::UndefinedInstruction | Exception::SoftwareInterrupt => 0,
+            _ if architecture >= Architecture::ARMv6 => ABORT_MASK,
+            _ => 0,
+        };
+        IRQ_MASK | fiq | abort
+    }
+
+    /// What LR is set to in the handler, given the address of the instruction
+    /// the exception was raised by (or, for interrupts, the next one to execute).
+    /// The offsets make `movs pc, lr` return past SWIs and undefined instructions,
+    /// `subs pc, lr, #4` retry the instruction or return from an interrupt
+    /// and `subs pc, lr, #8` retry an aborted data access
+    fn return_address(self, address: u32, thumb: bool) -> u32 {
+        let offset = match self {
+            Exception::UndefinedInstruction | Exception::SoftwareInterrupt if thumb => 2,
+            Exception::UndefinedInstruction | Exception::SoftwareInterrupt => 4,
+            Exception::PrefetchAbort | Exception::IRQ | Exception::FIQ => 4,
+            Exception::DataAbort => 8,
+        };
+        address.wrapping_add(offset)
+    }
+}
+
+/// Enters the handler of the exception raised by the instruction at `address`:
+/// saves the CPSR to the SPSR of the exception's mode, switches to that mode
+/// in ARM state with interrupts masked, sets LR and jumps to the vector,
+/// flushing the pipe
+pub fn take_exception(cpu: &mut CpuState, pipe: &mut Pipe, exception: Exception, address: u32) {
+    let old_cpsr = cpu.cpsr();
+    let lr = exception.return_address(address, cpu.in_thumb_state());
+    let new_cpsr = (old_cpsr & !(MODE_MASK | THUMB_BIT))
+        | exception.mode() as u32
+        | exception.masks(cpu.architecture);
+    cpu.set_cpsr(new_cpsr);
+    cpu.set_spsr(old_cpsr);
+    cpu.registers[LR] = lr;
+
+    let base = if cpu.high_vectors() { HIGH_VECTORS } else { 0 };
+    cpu.registers[PC] = base + exception.vector();
+    pipe.flush(cpu);
+}
+
+/// Takes an IRQ or FIQ between instructions, unless the CPSR masks it.
+/// Returns whether it was taken
+pub fn interrupt(cpu: &mut CpuState, pipe: &mut Pipe, exception: Exception) -> bool {
+    let mask = match exception {
+        Exception::FIQ => FIQ_MASK,
+        _ => IRQ_MASK,
+    };
+    if cpu.cpsr() & mask != 0 {
+        return false;
+    }
+    // The PC is one instruction ahead of the one being fetched,
+    // which is behind the one being decoded, if any
+    let size = if cpu.in_thumb_state() { 2 } else { 4 };
+    let queued = if pipe.decoding.is_some() { 2 } else { 1 };
+    let next = cpu.pc().wrapping_sub(size * queued);
+    take_exception(cpu, pipe, exception, next);
+    true
+}
+
+/// Takes the interrupt whose line is asserted, FIQs first, unless the CPSR masks it.
+/// Returns whether one was taken
+pub fn take_pending_interrupt(cpu: &mut CpuState, pipe: &mut Pipe) -> bool {
+    (cpu.fiq_line && interrupt(cpu, pipe, Exception::FIQ))
+        || (cpu.irq_line && interrupt(cpu, pipe, Exception::IRQ))
+}
@@ -0,0 +1,633 @@
+//! IEEE 754 arithmetic on the bits of single and double precision numbers,
+//! rounded in any of the four rounding modes and raising the exception flags,
+//! neither of which the host's floats give access to
+
+use std::cmp::Ordering;
+
+/// The cumulative exception flags, in their FPSCR positions
+pub const INVALID_OPERATION: u32 = 1 << 0;
+pub const DIVISION_BY_ZERO: u32 = 1 << 1;
+pub const OVERFLOW: u32 = 1 << 2;
+pub const UNDERFLOW: u32 = 1 << 3;
+pub const INEXACT: u32 = 1 << 4;
+pub const INPUT_DENORMAL: u32 = 1 << 7;
+
+/// The condition flags of a comparison, as NZCV
+pub const LESS_THAN: u32 = 0b1000;
+pub const EQUAL: u32 = 0b0110;
+pub const GREATER_THAN: u32 = 0b0010;
+pub const UNORDERED: u32 = 0b0011;
+
+/// A floating point format
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Format {
+    exponent_bits: u32,
+    fraction_bits: u32,
+}
+
+pub const SINGLE: Format = Format {
+    exponent_bits: 8,
+    fraction_bits: 23,
+};
+
+pub const DOUBLE: Format = Format {
+    exponent_bits: 11,
+    fraction_bits: 52,
+};
+
+impl Format {
+    fn bias(self) -> i32 {
+        (1 << (self.exponent_bits - 1)) - 1
+    }
+
+    /// The biased exponent of infinities and NaNs
+    fn max_exponent(self) -> u64 {
+        (1 << self.exponent_bits) - 1
+    }
+
+    /// The exponent of the smallest normal number
+    fn min_exponent(self) -> i32 {
+        1 - self.bias()
+    }
+
+    fn sign_bit(self) -> u64 {
+        1 << (self.exponent_bits + self.fraction_bits)
+    }
+
+    fn fraction_mask(self) -> u64 {
+        (1 << self.fraction_bits) - 1
+    }
+
+    fn quiet_bit(self) -> u64 {
+        1 << (self.fraction_bits - 1)
+    }
+
+    fn sign(self, negative: bool) -> u64 {
+        if negative {
+            self.sign_bit()
+        } else {
+            0
+        }
+    }
+
+    pub fn zero(self, negative: bool) -> u64 {
+        self.sign(negative)
+    }
+
+    pub fn infinity(self, negative: bool) -> u64 {
+        self.sign(negative) | self.max_exponent() << self.fraction_bits
+    }
+
+    fn max_finite(self, negative: bool) -> u64 {
+        self.infinity(negative) - 1
+    }
+
+    /// The quiet NaN produced by invalid operations, or by any operation in default NaN mode
+    pub fn default_nan(self) -> u64 {
+        self.infinity(false) | self.quiet_bit()
+    }
+
+    pub fn is_nan(self, bits: u64) -> bool {
+        bits & !self.sign_bit() > self.infinity(false)
+    }
+
+    pub fn is_signalling_nan(self, bits: u64) -> bool {
+        self.is_nan(bits) && bits & self.quiet_bit() == 0
+    }
+
+    pub fn negate(self, bits: u64) -> u64 {
+        bits ^ self.sign_bit()
+    }
+
+    pub fn absolute(self, bits: u64) -> u64 {
+        bits & !self.sign_bit()
+    }
+}
+
+/// The rounding modes, numbered as in the RMode field of the FPSCR
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Rounding {
+    Nearest,
+    PlusInfinity,
+    MinusInfinity,
+    Zero,
+}
+
+impl Rounding {
+    pub fn from_bits(bits: u32) -> Self {
+        match bits & 0b11 {
+            0 => Rounding::Nearest,
+            1 => Rounding::PlusInfinity,
+            2 => Rounding::MinusInfinity,
+            _ => Rounding::Zero,
+        }
+    }
+
+    /// Whether a value is rounded away from zero, given its sign, whether the bits kept
+    /// are odd and the bits dropped: the one worth half the last bit kept and the rest
+    fn rounds_up(self, negative: bool, odd: bool, half: bool, sticky: bool) -> bool {
+        match self {
+            Rounding::Nearest => half && (sticky || odd),
+            Rounding::PlusInfinity => !negative && (half || sticky),
+            Rounding::MinusInfinity => negative && (half || sticky),
+            Rounding::Zero => false,
+        }
+    }
+}
+
+/// How the operations round and treat denormals and NaNs,
+/// and the exception flags they have raised
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Environment {
+    pub rounding: Rounding,
+    /// Denormal operands are read as zero and denormal results written as zero
+    pub flush_to_zero: bool,
+    /// NaN results are always the default NaN instead of an operand
+    pub default_nan: bool,
+    pub flags: u32,
+}
+
+/// A number taken apart. Finite numbers are an exponent and a significand
+/// with its top bit set, worth `significand * 2^exponent`
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Value {
+    Zero,
+    Finite(i32, u64),
+    Infinity,
+    NaN,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Unpacked {
+    negative: bool,
+    value: Value,
+}
+
+fn unpack(format: Format, bits: u64, env: &mut Environment) -> Unpacked {
+    let negative = bits & format.sign_bit() != 0;
+    let biased = (bits >> format.fraction_bits) & format.max_exponent();
+    let fraction = bits & format.fraction_mask();
+    let value = if biased == format.max_exponent() {
+        if fraction == 0 {
+            Value::Infinity
+        } else {
+            Value::NaN
+        }
+    } else if biased == 0 && fraction == 0 {
+        Value::Zero
+    } else if biased == 0 && env.flush_to_zero {
+        env.flags |= INPUT_DENORMAL;
+        Value::Zero
+    } else {
+        let (exponent, significand) = if biased == 0 {
+            (format.min_exponent(), fraction)
+        } else {
+            (
+                biased as i32 - format.bias(),
+                fraction | 1 << format.fraction_bits,
+            )
+        };
+        let shift = significand.leading_zeros();
+        Value::Finite(
+            exponent - format.fraction_bits as i32 - shift as i32,
+            significand << shift,
+        )
+    };
+    Unpacked { negative, value }
+}
+
+/// Rounds `significand * 2^exponent` to the format, raising overflow, underflow
+/// and inexact. Bits the significand is short of are ORed into its lowest bit.
+/// Tininess is detected before rounding
+fn round_pack(
+    format: Format,
+    negative: bool,
+    exponent: i32,
+    significand: u128,
+    env: &mut Environment,
+) -> u64 {
+    if significand == 0 {
+        return format.zero(negative);
+    }
+    let top = 127 - significand.leading_zeros() as i32;
+    let magnitude = exponent + top;
+    let tiny = magnitude < format.min_exponent();
+    if tiny && env.flush_to_zero {
+        env.flags |= UNDERFLOW;
+        return format.zero(negative);
+    }
+
+    // The exponent of the last bit kept, which denormals have in common
+    let mut quantum = magnitude.max(format.min_exponent()) - format.fraction_bits as i32;
+    let shift = quantum - exponent;
+    let (mut kept, half, sticky) = if shift <= 0 {
+        (significand << -shift, false, false)
+    } else if shift > 128 {
+        (0, false, true)
+    } else if shift == 128 {
+        (0, significand >> 127 != 0, significand << 1 != 0)
+    } else {
+        let half = significand >> (shift - 1) & 1 != 0;
+        let sticky = significand & ((1 << (shift - 1)) - 1) != 0;
+        (significand >> shift, half, sticky)
+    };
+    if env
+        .rounding
+        .rounds_up(negative, kept & 1 != 0, half, sticky)
+    {
+        kept += 1;
+        if kept == 1 << (format.fraction_bits + 1) {
+            kept >>= 1;
+            quantum += 1;
+        }
+    }
+
+    let inexact = half || sticky;
+    if quantum + format.fraction_bits as i32 > format.bias() {
+        env.flags |= OVERFLOW | INEXACT;
+        let to_infinity = match env.rounding {
+            Rounding::Nearest => true,
+            Rounding::PlusInfinity => !negative,
+            Rounding::MinusInfinity => negative,
+            Rounding::Zero => false,
+        };
+        return if to_infinity {
+            format.infinity(negative)
+        } else {
+            format.max_finite(negative)
+        };
+    }
+    if inexact {
+        env.flags |= INEXACT;
+        if tiny {
+            env.flags |= UNDERFLOW;
+        }
+    }
+
+    let kept = kept as u64;
+    let biased = if kept >> format.fraction_bits == 0 {
+        0
+    } else {
+        (quantum + format.fraction_bits as i32 + format.bias()) as u64
+    };
+    format.sign(negative) | biased << format.fraction_bits | kept & format.fraction_mask()
+}
+
+/// The NaN an operation on NaNs results in: the first signalling NaN made quiet,
+/// which is an invalid operation, otherwise the first quiet NaN
+fn process_nans(format: Format, operands: &[u64], env: &mut Environment) -> Option<u64> {
+    let nan = match operands
+        .iter()
+        .find(|&&bits| format.is_signalling_nan(bits))
+    {
+        Some(&signalling) => {
+            env.flags |= INVALID_OPERATION;
+            signalling | format.quiet_bit()
+        }
+        None => *operands.iter().find(|&&bits| format.is_nan(bits))?,
+    };
+    Some(if env.default_nan {
+        format.default_nan()
+    } else {
+        nan
+    })
+}
+
+/// The result of an invalid operation
+fn invalid(format: Format, env: &mut Environment) -> u64 {
+    env.flags |= INVALID_OPERATION;
+    format.default_nan()
+}
+
+/// An exact zero result of opposite signed operands, negative only when rounding down
+fn cancelled_zero(format: Format, env: &Environment) -> u64 {
+    format.zero(env.rounding == Rounding::MinusInfinity)
+}
+
+/// Adds two finite numbers exactly, then rounds the sum
+fn add_finite(
+    format: Format,
+    (x_negative, x_exponent, x_significand): (bool, i32, u64),
+    (y_negative, y_exponent, y_significand): (bool, i32, u64),
+    env: &mut Environment,
+) -> u64 {
+    // Line the smaller number up with the larger one, which has 62 bits to spare
+    let (large, small) = if x_exponent >= y_exponent {
+        (
+            (x_negative, x_exponent, x_significand),
+            (y_negative, y_exponent, y_significand),
+        )
+    } else {
+        (
+            (y_negative, y_exponent, y_significand),
+            (x_negative, x_exponent, x_significand),
+        )
+    };
+    let exponent = large.1 - 62;
+    let large_significand = (large.2 as u128) << 62;
+    let small_significand = (small.2 as u128) << 62;
+    let distance = (large.1 - small.1) as u32;
+    let small_significand = if distance >= 127 {
+        1
+    } else {
+        let sticky = small_significand & ((1 << distance) - 1) != 0;
+        small_significand >> distance | sticky as u128
+    };
+
+    if large.0 == small.0 {
+        let sum = large_significand + small_significand;
+        return round_pack(format, large.0, exponent, sum, env);
+    }
+    match large_significand.cmp(&small_significand) {
+        Ordering::Equal => cancelled_zero(format, env),
+        Ordering::Greater => {
+            let difference = large_significand - small_significand;
+            round_pack(format, large.0, exponent, difference, env)
+        }
+        Ordering::Less => {
+            let difference = small_significand - large_significand;
+            round_pack(format, small.0, exponent, difference, env)
+        }
+    }
+}
+
+pub fn add(format: Format, lhs: u64, rhs: u64, env: &mut Environment) -> u64 {
+    if let Some(nan) = process_nans(format, &[lhs, rhs], env) {
+        return nan;
+    }
+    let (x, y) = (unpack(format, lhs, env), unpack(format, rhs, env));
+    match (x.value, y.value) {
+        (Value::Infinity, Value::Infinity) if x.negative != y.negative => invalid(format, env),
+        (Value::Infinity, _) => format.infinity(x.negative),
+        (_, Value::Infinity) => format.infinity(y.negative),
+        (Value::Zero, Value::Zero) if x.negative == y.negative => format.zero(x.negative),
+        (Value::Zero, Value::Zero) => cancelled_zero(format, env),
+        (Value::Zero, _) => rhs,
+        (_, Value::Zero) => lhs,
+        (Value::Finite(x_exponent, x_significand), Value::Finite(y_exponent, y_significand)) => {
+            add_finite(
+                format,
+                (x.negative, x_exponent, x_significand),
+                (y.negative, y_exponent, y_significand),
+                env,
+            )
+        }
+        (Value::NaN, _) | (_, Value::NaN) => unreachable!("NaNs were processed"),
+    }
+}
+
+pub fn subtract(format: Format, lhs: u64, rhs: u64, env: &mut Environment) -> u64 {
+    match process_nans(format, &[lhs, rhs], env) {
+        Some(nan) => nan,
+        None => add(format, lhs, format.negate(rhs), env),
+    }
+}
+
+pub fn multiply(format: Format, lhs: u64, rhs: u64, env: &mut Environment) -> u64 {
+    if let Some(nan) = process_nans(format, &[lhs, rhs], env) {
+        return nan;
+    }
+    let (x, y) = (unpack(format, lhs, env), unpack(format, rhs, env));
+    let negative = x.negative != y.negative;
+    match (x.value, y.value) {
+        (Value::Infinity, Value::Zero) | (Value::Zero, Value::Infinity) => invalid(format, env),
+        (Value::Infinity, _) | (_, Value::Infinity) => format.infinity(negative),
+        (Value::Zero, _) | (_, Value::Zero) => format.zero(negative),
+        (Value::Finite(x_exponent, x_significand), Value::Finite(y_exponent, y_significand)) => {
+            let product = x_significand as u128 * y_significand as u128;
+            round_pack(format, negative, x_exponent + y_exponent, product, env)
+        }
+        (Value::NaN, _) | (_, Value::NaN) => unreachable!("NaNs were processed"),
+    }
+}
+
+pub fn divide(format: Format, lhs: u64, rhs: u64, env: &mut Environment) -> u64 {
+    if let Some(nan) = process_nans(format, &[lhs, rhs], env) {
+        return nan;
+    }
+    let (x, y) = (unpack(format, lhs, env), unpack(format, rhs, env));
+    let negative = x.negative != y.negative;
+    match (x.value, y.value) {
+        (Value::Infinity, Value::Infinity) | (Value::Zero, Value::Zero) => invalid(format, env),
+        (Value::Infinity, _) => format.infinity(negative),
+        (_, Value::Infinity) | (Value::Zero, _) => format.zero(negative),
+        (_, Value::Zero) => {
+            env.flags |= DIVISION_BY_ZERO;
+            format.infinity(negative)
+        }
+        (Value::Finite(x_exponent, x_significand), Value::Finite(y_exponent, y_significand)) => {
+            // A quotient of at least 64 bits, with the remainder as the sticky bit
+            let dividend = (x_significand as u128) << 64;
+            let quotient = dividend / y_significand as u128;
+            let sticky = !dividend.is_multiple_of(y_significand as u128);
+            let exponent = x_exponent - y_exponent - 65;
+            round_pack(
+                format,
+                negative,
+                exponent,
+                quotient << 1 | sticky as u128,
+                env,
+            )
+        }
+        (Value::NaN, _) | (_, Value::NaN) => unreachable!("NaNs were processed"),
+    }
+}
+
+/// The integer square root of a number, and whether it was exact
+fn integer_square_root(mut number: u128) -> (u128, bool) {
+    let mut root = 0;
+    let mut bit = 1 << 126;
+    while bit > number {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if number >= root + bit {
+            number -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    (root, number == 0)
+}
+
+pub fn square_root(format: Format, operand: u64, env: &mut Environment) -> u64 {
+    if let Some(nan) = process_nans(format, &[operand], env) {
+        return nan;
+    }
+    let x = unpack(format, operand, env);
+    match x.value {
+        // The square root of -0 is -0
+        Value::Zero => format.zero(x.negative),
+        _ if x.negative => invalid(format, env),
+        Value::Infinity => format.infinity(false),
+        Value::Finite(exponent, significand) => {
+            // Halving the exponent needs it even
+            let (mut radicand, mut exponent) = ((significand as u128) << 64, exponent - 64);
+            if exponent % 2 != 0 {
+                radicand >>= 1;
+                exponent += 1;
+            }
+            let (root, exact) = integer_square_root(radicand);
+            round_pack(
+                format,
+                false,
+                exponent / 2 - 1,
+                root << 1 | !exact as u128,
+                env,
+            )
+        }
+        Value::NaN => unreachable!("NaNs were processed"),
+    }
+}
+
+/// Orders two numbers that aren't NaNs. Zeros are equal whatever their sign
+fn order(x: Unpacked, y: Unpacked) -> Ordering {
+    let magnitude = |value: Value| match value {
+        Value::Zero => (0, 0, 0),
+        Value::Finite(exponent, significand) => (1, exponent, significand),
+        _ => (2, 0, 0),
+    };
+    match (x.value, y.value) {
+        (Value::Zero, Value::Zero) => Ordering::Equal,
+        _ if x.negative != y.negative => {
+            if x.negative {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        }
+        _ if x.negative => magnitude(y.value).cmp(&magnitude(x.value)),
+        _ => magnitude(x.value).cmp(&magnitude(y.value)),
+    }
+}
+
+/// Compares two numbers, returning the NZCV flags of the result.
+/// Comparing a signalling NaN is an invalid operation, and so is comparing
+/// a quiet one when `quiet_nans_signal` is set
+pub fn compare(
+    format: Format,
+    lhs: u64,
+    rhs: u64,
+    quiet_nans_signal: bool,
+    env: &mut Environment,
+) -> u32 {
+    if format.is_nan(lhs) || format.is_nan(rhs) {
+        if quiet_nans_signal || format.is_signalling_nan(lhs) || format.is_signalling_nan(rhs) {
+            env.flags |= INVALID_OPERATION;
+        }
+        return UNORDERED;
+    }
+    match order(unpack(format, lhs, env), unpack(format, rhs, env)) {
+        Ordering::Less => LESS_THAN,
+        Ordering::Equal => EQUAL,
+        Ordering::Greater => GREATER_THAN,
+    }
+}
+
+/// Converts a number between formats, rounding when the new one is narrower.
+/// NaNs keep as much of their payload as fits
+pub fn convert(from: Format, to: Format, operand: u64, env: &mut Environment) -> u64 {
+    let x = unpack(from, operand, env);
+    match x.value {
+        Value::NaN => {
+            if from.is_signalling_nan(operand) {
+                env.flags |= INVALID_OPERATION;
+            }
+            if env.default_nan {
+                return to.default_nan();
+            }
+            let fraction = operand & from.fraction_mask();
+            let payload = if to.fraction_bits > from.fraction_bits {
+                fraction << (to.fraction_bits - from.fraction_bits)
+            } else {
+                fraction >> (from.fraction_bits - to.fraction_bits)
+            };
+            to.infinity(x.negative) | to.quiet_bit() | payload
+        }
+        Value::Infinity => to.infinity(x.negative),
+        Value::Zero => to.zero(x.negative),
+        Value::Finite(exponent, significand) => {
+            round_pack(to, x.negative, exponent, significand as u128, env)
+        }
+    }
+}
+
+/// Converts a signed or unsigned 32-bit integer, rounding if it doesn't fit the format
+pub fn from_integer(format: Format, integer: u32, signed: bool, env: &mut Environment) -> u64 {
+    let negative = signed && (integer as i32) < 0;
+    let magnitude = if negative {
+        (integer as i32).unsigned_abs()
+    } else {
+        integer
+    };
+    round_pack(format, negative, 0, magnitude as u128, env)
+}
+
+/// Converts to a signed or unsigned 32-bit integer, rounding in the given mode.
+/// NaNs convert to 0 and numbers out of range saturate, both invalid operations
+pub fn to_integer(
+    format: Format,
+    operand: u64,
+    signed: bool,
+    rounding: Rounding,
+    env: &mut Environment,
+) -> u32 {
+    if format.is_nan(operand) {
+        env.flags |= INVALID_OPERATION;
+        return 0;
+    }
+    let x = unpack(format, operand, env);
+    let saturated = match (signed, x.negative) {
+        (true, true) => i32::MIN as u32,
+        (true, false) => i32::MAX as u32,
+        (false, true) => 0,
+        (false, false) => u32::MAX,
+    };
+    let (exponent, significand) = match x.value {
+        Value::Zero => return 0,
+        Value::Finite(exponent, significand) => (exponent, significand),
+        _ => {
+            env.flags |= INVALID_OPERATION;
+            return saturated;
+        }
+    };
+
+    // Anything of 2^33 or more is out of range whatever the rounding
+    if exponent > -31 {
+        env.flags |= INVALID_OPERATION;
+        return saturated;
+    }
+    let shift = (-exponent) as u32;
+    let (mut magnitude, half, sticky) = if shift > 64 {
+        (0, false, true)
+    } else {
+        let significand = significand as u128;
+        let half = significand >> (shift - 1) & 1 != 0;
+        let sticky = significand & ((1 << (shift - 1)) - 1) != 0;
+        ((significand >> shift) as u64, half, sticky)
+    };
+    if rounding.rounds_up(x.negative, magnitude & 1 != 0, half, sticky) {
+        magnitude += 1;
+    }
+
+    let limit = match (signed, x.negative) {
+        (true, true) => 1 << 31,
+        (true, false) => i32::MAX as u64,
+        (false, true) => 0,
+        (false, false) => u32::MAX as u64,
+    };
+    if magnitude > limit {
+        env.flags |= INVALID_OPERATION;
+        return saturated;
+    }
+    if half || sticky {
+        env.flags |= INEXACT;
+    }
+    if x.negative {
+        (magnitude as u32).wrapping_neg()
+    } else {
+        magnitude as u32
+    }
+}
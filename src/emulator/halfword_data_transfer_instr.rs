@@ -0,0 +1,110 @@
+use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::Exception;
+use crate::emulator::single_data_transfer_instr::compute_address;
+use util::*;
+
+macro_rules! immediate_bit {
+    ($bits:expr) => {
+        mask![$bits, 22]
+    };
+}
+
+macro_rules! load_bit {
+    ($bits:expr) => {
+        mask![$bits, 20]
+    };
+}
+
+macro_rules! base_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 16, 19] as usize
+    };
+}
+
+macro_rules! transfer_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 12, 15] as usize
+    };
+}
+
+/// The S and H bits, which tell the size and signedness of the transfer
+macro_rules! transfer_kind_bits {
+    ($bits:expr) => {
+        mask![$bits, 5, 6]
+    };
+}
+
+macro_rules! offset_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 0, 3] as usize
+    };
+}
+
+/// Computes the offset, an 8 bit immediate split in two nibbles or a register
+fn compute_offset(cpu: &CpuState, bits: u32) -> u32 {
+    if immediate_bit![bits] {
+        mask![bits, 8, 11] << 4 | mask![bits, 0, 3]
+    } else {
+        cpu.registers[offset_reg_bits![bits]]
+    }
+}
+
+/// Executes a halfword or signed byte transfer: LDRH, STRH, LDRSB or LDRSH,
+/// or one of the ARMv5TE doubleword transfers LDRD and STRD, which use the S and H bits
+/// of a store. Returns whether it wrote the PC
+pub fn execute_halfword_data_instr(instr: &Instruction, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let bits = instr.code;
+    let offset = compute_offset(cpu, bits);
+    let (address, write_back) = compute_address(cpu, bits, offset);
+    let rd = transfer_reg_bits![bits];
+
+    // Loaded values go to Rd and the registers after it
+    let loaded = match (load_bit![bits], transfer_kind_bits![bits]) {
+        (true, 1) => vec![cpu.read_halfword(address)?],
+        (true, 2) => vec![cpu.read_byte(address)? as i8 as u32],
+        (true, _) => vec![cpu.read_halfword(address)? as i16 as u32],
+        (false, 1) => {
+            // Stores use the value the register had before the write back
+            cpu.write_halfword(address, cpu.registers[rd])?;
+            vec![]
+        }
+        (false, kind) => {
+            check_doubleword(cpu, rd)?;
+            let second = address.wrapping_add(4);
+            if kind == 2 {
+                vec![cpu.read_word(address)?, cpu.read_word(second)?]
+            } else {
+                // Neither word is stored if the second one would abort
+                cpu.physical_address(second, 4).ok_or(Exception::DataAbort)?;
+                cpu.write_word(address, cpu.registers[rd])?;
+                cpu.write_word(second, cpu.registers[rd + 1])?;
+                vec![]
+            }
+        }
+    };
+    let mut wrote_pc = false;
+    if let Some(base) = write_back {
+        let rn = base_reg_bits![bits];
+        if rn == PC {
+            cpu.branch_to(base);
+            wrote_pc = true;
+        } else {
+            cpu.registers[rn] = base;
+        }
+    }
+
+    // A loaded value takes priority over the written back base
+    for (register, value) in (rd..).zip(loaded) {
+        wrote_pc |= cpu.write_register(register, value);
+    }
+    Ok(wrote_pc)
+}
+
+/// LDRD and STRD transfer an even register and the one after it,
+/// which can't be the PC. They are undefined otherwise, and before ARMv5
+fn check_doubleword(cpu: &CpuState, rd: usize) -> Result<(), Exception> {
+    if cpu.architecture < Architecture::ARMv5 || rd % 2 == 1 || rd == LR {
+        return Err(Exception::UndefinedInstruction);
+    }
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use crate::emulator::alu;
+use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::Exception;
+use util::*;
+
+/// Bits 20-22, the first opcode of the media space
+macro_rules! op1_bits {
+    ($bits:expr) => {
+        mask![$bits, 20, 22]
+    };
+}
+
+/// Bits 5-7, the second opcode of the media space
+macro_rules! op2_bits {
+    ($bits:expr) => {
+        mask![$bits, 5, 7]
+    };
+}
+
+macro_rules! reg_n_bits {
+    ($bits:expr) => {
+        mask![$bits, 16, 19] as usize
+    };
+}
+
+macro_rules! dest_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 12, 15] as usize
+    };
+}
+
+macro_rules! reg_m_bits {
+    ($bits:expr) => {
+        mask![$bits, 0, 3] as usize
+    };
+}
+
+/// Bit 22, set for the unsigned forms
+macro_rules! unsigned_bit {
+    ($bits:expr) => {
+        mask![$bits, 22]
+    };
+}
+
+/// Executes an instruction from the media space of ARMv6 (bits 25-27 are 011 and bit 4 is set),
+/// returning whether it wrote the PC. The parallel additions and subtractions, SEL,
+/// the extends, the byte reversals and SSAT, USAT and their halfword forms are supported,
+/// the rest of the space and all of it before ARMv6 is undefined
+pub fn execute_media_instr(instr: &Instruction, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let bits = instr.code;
+    if cpu.architecture < Architecture::ARMv6 {
+        return Err(Exception::UndefinedInstruction);
+    }
+    match mask![bits, 23, 24] {
+        0b00 => parallel_arithmetic(bits, cpu),
+        0b01 => packing(bits, cpu),
+        _ => Err(Exception::UndefinedInstruction),
+    }
+}
+
+/// The packing, unpacking, saturation and reversal instructions,
+/// which have bits 23-24 set to 01
+fn packing(bits: u32, cpu: &mut CpuState) -> Result<bool, Exception> {
+    match (op2_bits![bits], op1_bits![bits]) {
+        // Bit 5 clear, bit 21 set
+        (op2, op1) if op2 & 1 == 0 && op1 & 0b010 != 0 => Ok(saturate(bits, cpu)),
+        (0b011, op1) if op1 & 0b011 != 0b001 => Ok(extend(bits, cpu)),
+        (0b001, 0b010) | (0b001, 0b110) => Ok(saturate_halfwords(bits, cpu)),
+        (0b001, 0b011) | (0b101, 0b011) | (0b101, 0b111) => Ok(reverse(bits, cpu)),
+        (0b101, 0b000) => Ok(select(bits, cpu)),
+        _ => Err(Exception::UndefinedInstruction),
+    }
+}
+
+/// The parallel additions and subtractions, which work on each halfword or byte
+/// of Rn and Rm at once. Bits 20-21 pick modular arithmetic, which sets the GE flags,
+/// saturating arithmetic or halving arithmetic, and bit 22 unsigned lanes
+fn parallel_arithmetic(bits: u32, cpu: &mut CpuState) -> Result<bool, Exception> {
+    // Each result lane is (whether it subtracts, the lane of Rn, the lane of Rm)
+    let lanes: Vec<(bool, u32, u32)> = match op2_bits![bits] {
+        0b000 => vec![(false, 0, 0), (false, 1, 1)],
+        // ASX and SAX exchange the halfwords of Rm
+        0b001 => vec![(true, 0, 1), (false, 1, 0)],
+        0b010 => vec![(false, 0, 1), (true, 1, 0)],
+        0b011 => vec![(true, 0, 0), (true, 1, 1)],
+        0b100 => (0..4).map(|lane| (false, lane, lane)).collect(),
+        0b111 => (0..4).map(|lane| (true, lane, lane)).collect(),
+        _ => return Err(Exception::UndefinedInstruction),
+    };
+    let kind = mask![bits, 20, 21];
+    if kind == 0 {
+        return Err(Exception::UndefinedInstruction);
+    }
+
+    let unsigned = unsigned_bit![bits];
+    let width = 32 / lanes.len() as u32;
+    let lane_mask = (1 << width) - 1;
+    // Halfword lanes have two GE flags each
+    let lane_flags = (1 << (width / 8)) - 1;
+    let lane = |value: u32, index: u32| {
+        let field = (value >> (index * width)) & lane_mask;
+        if unsigned {
+            field as i64
+        } else {
+            ((field << (32 - width)) as i32 >> (32 - width)) as i64
+        }
+    };
+
+    let (rn, rm) = (
+        cpu.registers[reg_n_bits![bits]],
+        cpu.registers[reg_m_bits![bits]],
+    );
+    let mut result = 0;
+    let mut flags = 0;
+    for (index, &(subtract, n, m)) in lanes.iter().enumerate() {
+        let value = if subtract {
+            lane(rn, n) - lane(rm, m)
+        } else {
+            lane(rn, n) + lane(rm, m)
+        };
+        let field = match kind {
+            0b01 => {
+                // Set when there is no signed overflow below zero, no borrow or a carry
+                let greater_or_equal = if unsigned && !subtract {
+                    value > lane_mask as i64
+                } else {
+                    value >= 0
+                };
+                if greater_or_equal {
+                    flags |= lane_flags << (index as u32 * width / 8);
+                }
+                value as u32
+            }
+            0b10 if unsigned => alu::unsigned_saturate(value, width).0,
+            0b10 => alu::signed_saturate(value, width).0,
+            _ => (value >> 1) as u32,
+        };
+        result |= (field & lane_mask) << (index as u32 * width);
+    }
+
+    if kind == 0b01 {
+        cpu.set_ge_flags(flags);
+    }
+    Ok(cpu.write_register(dest_reg_bits![bits], result))
+}
+
+/// Saturates a value to `width` bits, signed or unsigned, setting the Q flag if it had to
+fn saturate_to(cpu: &mut CpuState, value: i64, width: u32, unsigned: bool) -> u32 {
+    let (result, saturated) = if unsigned {
+        alu::unsigned_saturate(value, width)
+    } else {
+        alu::signed_saturate(value, width)
+    };
+    if saturated {
+        cpu.set_CPSR_flag(Flag::Q, true);
+    }
+    result
+}
+
+/// SSAT and USAT, which saturate Rm shifted left or arithmetically right (bit 6)
+/// to the width in bits 16-20. SSAT saturates to one bit more than the field,
+/// and an arithmetic shift by 0 stands for a shift by 32
+fn saturate(bits: u32, cpu: &mut CpuState) -> bool {
+    let rm = cpu.registers[reg_m_bits![bits]];
+    let amount = mask![bits, 7, 11];
+    let operand = if mask![bits, 6] {
+        // Shifting by 31 leaves only the sign, as shifting by 32 would
+        rm as i32 >> if amount == 0 { 31 } else { amount }
+    } else {
+        (rm << amount) as i32
+    };
+
+    let unsigned = unsigned_bit![bits];
+    let width = mask![bits, 16, 20] + if unsigned { 0 } else { 1 };
+    let result = saturate_to(cpu, operand as i64, width, unsigned);
+    cpu.write_register(dest_reg_bits![bits], result)
+}
+
+/// SSAT16 and USAT16, which saturate each halfword of Rm to the width in bits 16-19,
+/// plus one for SSAT16
+fn saturate_halfwords(bits: u32, cpu: &mut CpuState) -> bool {
+    let rm = cpu.registers[reg_m_bits![bits]];
+    let unsigned = unsigned_bit![bits];
+    let width = mask![bits, 16, 19] + if unsigned { 0 } else { 1 };
+    let low = saturate_to(cpu, rm as i16 as i64, width, unsigned);
+    let high = saturate_to(cpu, (rm >> 16) as i16 as i64, width, unsigned);
+    cpu.write_register(dest_reg_bits![bits], high << 16 | low & 0xffff)
+}
+
+/// SXTAB, SXTAH, UXTAB, UXTAH, SXTAB16 and UXTAB16, which extend the byte, halfword
+/// or bytes 0 and 2 of Rm rotated right by 8 times bits 10-11, and add them to Rn.
+/// Rn being the PC stands for no addition: SXTB, SXTH, UXTB, UXTH, SXTB16 and UXTB16
+fn extend(bits: u32, cpu: &mut CpuState) -> bool {
+    let rotated = cpu.registers[reg_m_bits![bits]].rotate_right(8 * mask![bits, 10, 11]);
+    let rn = reg_n_bits![bits];
+    let addend = if rn == PC { 0 } else { cpu.registers[rn] };
+    let unsigned = unsigned_bit![bits];
+    let extend_byte = |byte: u32| {
+        if unsigned {
+            byte & 0xff
+        } else {
+            byte as i8 as u32
+        }
+    };
+
+    let result = match mask![bits, 20, 21] {
+        0b00 => (0..2).fold(0, |result, lane| {
+            let byte = extend_byte(rotated >> (16 * lane));
+            let sum = (addend >> (16 * lane)).wrapping_add(byte) & 0xffff;
+            result | sum << (16 * lane)
+        }),
+        0b10 => addend.wrapping_add(extend_byte(rotated)),
+        _ if unsigned => addend.wrapping_add(rotated & 0xffff),
+        _ => addend.wrapping_add(rotated as i16 as u32),
+    };
+    cpu.write_register(dest_reg_bits![bits], result)
+}
+
+/// REV, REV16 and REVSH, which reverse the bytes of Rm, the bytes of each of its halfwords,
+/// or the bytes of its bottom halfword, sign extending the result
+fn reverse(bits: u32, cpu: &mut CpuState) -> bool {
+    let rm = cpu.registers[reg_m_bits![bits]];
+    let result = match (mask![bits, 22], mask![bits, 7]) {
+        (false, false) => rm.swap_bytes(),
+        (false, true) => (rm & 0x00ff_00ff) << 8 | (rm >> 8) & 0x00ff_00ff,
+        (true, _) => (rm as u16).swap_bytes() as i16 as u32,
+    };
+    cpu.write_register(dest_reg_bits![bits], result)
+}
+
+/// SEL, which takes each byte from Rn if its GE flag is set and from Rm otherwise
+fn select(bits: u32, cpu: &mut CpuState) -> bool {
+    let flags = cpu.ge_flags();
+    let from_rn = (0..4)
+        .filter(|byte| flags & (1 << byte) != 0)
+        .fold(0, |mask, byte| mask | 0xff << (8 * byte));
+    let rn = cpu.registers[reg_n_bits![bits]];
+    let rm = cpu.registers[reg_m_bits![bits]];
+    cpu.write_register(dest_reg_bits![bits], rn & from_rn | rm & !from_rn)
+}
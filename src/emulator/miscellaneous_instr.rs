@@ -0,0 +1,158 @@
+use crate::emulator::alu;
+use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::Exception;
+use util::*;
+
+/// Bits 21-22, which tell apart the instructions sharing bits 4-7
+macro_rules! op_bits {
+    ($bits:expr) => {
+        mask![$bits, 21, 22]
+    };
+}
+
+/// The result register of CLZ and the saturating instructions
+macro_rules! dest_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 12, 15] as usize
+    };
+}
+
+/// The second operand of the saturating instructions, which QDADD and QDSUB double
+macro_rules! operand_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 16, 19] as usize
+    };
+}
+
+/// The result register of the multiplies, RdHi for SMLAL<x><y>
+macro_rules! multiply_dest_bits {
+    ($bits:expr) => {
+        mask![$bits, 16, 19] as usize
+    };
+}
+
+/// The register the multiplies accumulate, RdLo for SMLAL<x><y>
+macro_rules! accumulate_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 12, 15] as usize
+    };
+}
+
+macro_rules! reg_s_bits {
+    ($bits:expr) => {
+        mask![$bits, 8, 11] as usize
+    };
+}
+
+macro_rules! reg_m_bits {
+    ($bits:expr) => {
+        mask![$bits, 0, 3] as usize
+    };
+}
+
+/// Bit 5, set to multiply the top halfword of Rm
+macro_rules! x_bit {
+    ($bits:expr) => {
+        mask![$bits, 5]
+    };
+}
+
+/// Bit 6, set to multiply the top halfword of Rs
+macro_rules! y_bit {
+    ($bits:expr) => {
+        mask![$bits, 6]
+    };
+}
+
+/// Executes an instruction from the miscellaneous space of ARMv5TE: CLZ, QADD, QSUB,
+/// QDADD, QDSUB, BKPT or a signed halfword multiply. Returns whether it wrote the PC.
+/// They are undefined before ARMv5, as is the rest of the space
+pub fn execute_miscellaneous_instr(
+    instr: &Instruction,
+    cpu: &mut CpuState,
+) -> Result<bool, Exception> {
+    let bits = instr.code;
+    if cpu.architecture < Architecture::ARMv5 {
+        return Err(Exception::UndefinedInstruction);
+    }
+    match (mask![bits, 4, 7], op_bits![bits]) {
+        (0b0001, 0b11) => Ok(count_leading_zeros(bits, cpu)),
+        (0b0101, op) => Ok(saturating_arithmetic(bits, op, cpu)),
+        // BKPT, with no debugger attached
+        (0b0111, 0b01) => Err(Exception::PrefetchAbort),
+        // Bit 7 set and bit 4 clear
+        (pattern, op) if pattern & 0b1001 == 0b1000 => Ok(halfword_multiply(bits, op, cpu)),
+        _ => Err(Exception::UndefinedInstruction),
+    }
+}
+
+/// CLZ: the number of zeros above the highest set bit of Rm, 32 if Rm is 0
+fn count_leading_zeros(bits: u32, cpu: &mut CpuState) -> bool {
+    let zeros = cpu.registers[reg_m_bits![bits]].leading_zeros();
+    cpu.write_register(dest_reg_bits![bits], zeros)
+}
+
+/// QADD, QSUB, QDADD and QDSUB: Rm plus or minus Rn, which bit 22 doubles first.
+/// Both the doubling and the result saturate, either of which sets the Q flag
+fn saturating_arithmetic(bits: u32, op: u32, cpu: &mut CpuState) -> bool {
+    let rm = cpu.registers[reg_m_bits![bits]] as i32 as i64;
+    let mut rn = cpu.registers[operand_reg_bits![bits]] as i32 as i64;
+    let mut saturated = false;
+    if op & 0b10 != 0 {
+        let (doubled, doubling_saturated) = alu::signed_saturate(2 * rn, 32);
+        rn = doubled as i32 as i64;
+        saturated = doubling_saturated;
+    }
+
+    let value = if op & 1 == 0 { rm + rn } else { rm - rn };
+    let (result, result_saturated) = alu::signed_saturate(value, 32);
+    if saturated || result_saturated {
+        cpu.set_CPSR_flag(Flag::Q, true);
+    }
+    cpu.write_register(dest_reg_bits![bits], result)
+}
+
+/// The top or bottom halfword of a register, sign extended
+fn halfword(value: u32, top: bool) -> i64 {
+    let value = if top { value >> 16 } else { value };
+    value as i16 as i64
+}
+
+/// SMLA<x><y>, SMLAW<y>, SMULW<y>, SMLAL<x><y> and SMUL<x><y>, which multiply
+/// the halfwords of Rm and Rs picked by the x and y bits (the W forms take all of Rm
+/// and keep the top 32 bits of the 48-bit product). The 32-bit accumulations set
+/// the Q flag when they overflow, the 64-bit one wraps around
+fn halfword_multiply(bits: u32, op: u32, cpu: &mut CpuState) -> bool {
+    let rm = cpu.registers[reg_m_bits![bits]];
+    let rs = halfword(cpu.registers[reg_s_bits![bits]], y_bit![bits]);
+    let (rd, rn) = (multiply_dest_bits![bits], accumulate_reg_bits![bits]);
+    match op {
+        0b00 => accumulate(cpu, rd, halfword(rm, x_bit![bits]) * rs, rn),
+        // The x bit tells SMULW<y> from SMLAW<y>
+        0b01 => {
+            let product = (rm as i32 as i64 * rs) >> 16;
+            if x_bit![bits] {
+                cpu.write_register(rd, product as u32)
+            } else {
+                accumulate(cpu, rd, product, rn)
+            }
+        }
+        0b10 => {
+            let product = halfword(rm, x_bit![bits]) * rs;
+            let accumulator = (cpu.registers[rd] as u64) << 32 | cpu.registers[rn] as u64;
+            let result = accumulator.wrapping_add(product as u64);
+            let wrote_lo = cpu.write_register(rn, result as u32);
+            cpu.write_register(rd, (result >> 32) as u32) || wrote_lo
+        }
+        _ => cpu.write_register(rd, (halfword(rm, x_bit![bits]) * rs) as u32),
+    }
+}
+
+/// Adds Rn to a product that fits in 32 bits, setting the Q flag if the sum doesn't
+fn accumulate(cpu: &mut CpuState, rd: usize, product: i64, rn: usize) -> bool {
+    let sum = product + cpu.registers[rn] as i32 as i64;
+    if sum != sum as i32 as i64 {
+        cpu.set_CPSR_flag(Flag::Q, true);
+    }
+    cpu.write_register(rd, sum as u32)
+}
@@ -3,10 +3,30 @@
 pub mod em_utilities;
 
 pub mod pipeline_executor;
+pub mod alu;
+pub mod float;
+pub mod modes;
+pub mod exceptions;
+pub mod coprocessor;
+pub mod system_control;
+pub mod vfp;
 pub mod branch_instr;
 pub mod data_proc_instr;
 pub mod barrel_shifter;
 pub mod multiply_instr;
 pub mod single_data_transfer_instr;
+pub mod halfword_data_transfer_instr;
+pub mod block_data_transfer_instr;
+pub mod psr_transfer_instr;
+pub mod software_interrupt_instr;
+pub mod unconditional_instr;
+pub mod coprocessor_instr;
+pub mod miscellaneous_instr;
+pub mod media_instr;
+pub mod synchronization_instr;
+pub mod thumb_instr;
+pub mod semihosting;
+pub mod disassembler;
+pub mod symbol_map;
 
 
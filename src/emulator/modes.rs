@@ -0,0 +1,120 @@
+//! Processor modes and the registers each of them banks
+
+/// The processor modes, as encoded in the low 5 bits of the CPSR
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Mode {
+    USR = 0x10,
+    FIQ = 0x11,
+    IRQ = 0x12,
+    SVC = 0x13,
+    ABT = 0x17,
+    UND = 0x1b,
+    SYS = 0x1f,
+}
+
+/// The mode bits of the CPSR
+pub const MODE_MASK: u32 = 0x1f;
+
+/// The number of register banks: user and system mode share one,
+/// every exception mode has its own
+const BANKS: usize = 6;
+
+impl Mode {
+    /// Reads the mode from the low bits of a PSR.
+    /// Values that don't name a mode, like the 0 the CPSR starts with,
+    /// behave as system mode: privileged, with the user registers
+    pub fn from_psr(psr: u32) -> Self {
+        match psr & MODE_MASK {
+            0x10 => Mode::USR,
+            0x11 => Mode::FIQ,
+            0x12 => Mode::IRQ,
+            0x13 => Mode::SVC,
+            0x17 => Mode::ABT,
+            0x1b => Mode::UND,
+            _ => Mode::SYS,
+        }
+    }
+
+    /// Whether the mode can change the control bits of the CPSR
+    pub fn is_privileged(self) -> bool {
+        self != Mode::USR
+    }
+
+    /// Whether the mode has an SPSR
+    pub fn has_spsr(self) -> bool {
+        !matches!(self, Mode::USR | Mode::SYS)
+    }
+
+    /// The bank holding the mode's r13, r14 and SPSR
+    fn bank(self) -> usize {
+        match self {
+            Mode::USR | Mode::SYS => 0,
+            Mode::FIQ => 1,
+            Mode::IRQ => 2,
+            Mode::SVC => 3,
+            Mode::ABT => 4,
+            Mode::UND => 5,
+        }
+    }
+
+    /// The bank holding the mode's r8-r12: FIQ mode has its own,
+    /// every other mode uses the user ones
+    fn high_bank(self) -> usize {
+        (self == Mode::FIQ) as usize
+    }
+}
+
+/// The registers of the modes the CPU isn't in.
+/// `CpuState.registers` always holds the ones of the current mode,
+/// they are swapped in and out here when the mode changes
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct RegisterBanks {
+    r8_r12: [[u32; 5]; 2],
+    r13_r14: [[u32; 2]; BANKS],
+    /// The saved PSR of each exception mode, unused for the user bank
+    spsrs: [u32; BANKS],
+}
+
+impl RegisterBanks {
+    /// Saves the banked registers of mode `from` and loads the ones of mode `to`
+    pub fn switch(&mut self, registers: &mut [u32], from: Mode, to: Mode) {
+        if from.high_bank() != to.high_bank() {
+            self.r8_r12[from.high_bank()].copy_from_slice(&registers[8..13]);
+            registers[8..13].copy_from_slice(&self.r8_r12[to.high_bank()]);
+        }
+        if from.bank() != to.bank() {
+            self.r13_r14[from.bank()].copy_from_slice(&registers[13..15]);
+            registers[13..15].copy_from_slice(&self.r13_r14[to.bank()]);
+        }
+    }
+
+    /// The user mode copy of r8-r14 while in mode `current`,
+    /// None if `registers` already holds it
+    pub fn user_register(&self, current: Mode, index: usize) -> Option<u32> {
+        match index {
+            8..=12 if current.high_bank() != 0 => Some(self.r8_r12[0][index - 8]),
+            13 | 14 if current.bank() != 0 => Some(self.r13_r14[0][index - 13]),
+            _ => None,
+        }
+    }
+
+    /// Writes the user mode copy of r8-r14 while in mode `current`,
+    /// returning false if it is the one in `registers`
+    pub fn set_user_register(&mut self, current: Mode, index: usize, value: u32) -> bool {
+        match index {
+            8..=12 if current.high_bank() != 0 => self.r8_r12[0][index - 8] = value,
+            13 | 14 if current.bank() != 0 => self.r13_r14[0][index - 13] = value,
+            _ => return false,
+        }
+        true
+    }
+
+    /// The SPSR of the given mode
+    pub fn spsr(&self, mode: Mode) -> u32 {
+        self.spsrs[mode.bank()]
+    }
+
+    pub fn set_spsr(&mut self, mode: Mode, value: u32) {
+        self.spsrs[mode.bank()] = value;
+    }
+}
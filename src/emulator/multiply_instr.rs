@@ -1,5 +1,18 @@
+use crate::emulator::alu;
 use crate::emulator::em_utilities::*;
 
+macro_rules! long_bit {
+    ($bits:expr) => {
+        mask![$bits, 23]
+    }
+}
+
+macro_rules! signed_bit {
+    ($bits:expr) => {
+        mask![$bits, 22]
+    }
+}
+
 macro_rules! accumulate_bits {
     ($bits:expr) => {
         mask![$bits, 21]
@@ -32,22 +45,49 @@ macro_rules! reg_m_bits {
 }
 
 
-pub fn execute_multiply_instruction(instr: &Instruction, cpu: &mut CpuState){
+/// Executes MUL or MLA, keeping the low 32 bits of the result,
+/// or one of the long multiplies. Returns whether it wrote the PC
+pub fn execute_multiply_instruction(instr: &Instruction, cpu: &mut CpuState) -> bool {
     let bits = instr.code;
+    if long_bit![bits] {
+        return execute_long_multiply(bits, cpu);
+    }
     let set = mask![bits, 20];
-    let mut result: u32 = cpu.registers[reg_m_bits![bits]] * cpu.registers[reg_s_bits![bits]];
+    let mut result: u32 =
+        cpu.registers[reg_m_bits![bits]].wrapping_mul(cpu.registers[reg_s_bits![bits]]);
 
     if accumulate_bits![bits] {
-        result += cpu.registers[reg_n_bits![bits]];
+        result = result.wrapping_add(cpu.registers[reg_n_bits![bits]]);
     }
     
     if set {
-        cpu.set_CPSR_flag(Flag::N, mask![result, 31]);
-        if result == 0 {
-            cpu.set_CPSR_flag(Flag::N, true);
-        }
+        alu::multiply(result).set_flags(cpu);
+    }
+
+    cpu.write_register(reg_d_bits![bits], result)
+}
+
+/// Executes UMULL, UMLAL, SMULL or SMLAL, which write the 64-bit result
+/// to RdHi:RdLo (the Rd and Rn fields of MUL)
+fn execute_long_multiply(bits: u32, cpu: &mut CpuState) -> bool {
+    let (rd_hi, rd_lo) = (reg_d_bits![bits], reg_n_bits![bits]);
+    let rm = cpu.registers[reg_m_bits![bits]];
+    let rs = cpu.registers[reg_s_bits![bits]];
+    let mut result = if signed_bit![bits] {
+        (rm as i32 as i64).wrapping_mul(rs as i32 as i64) as u64
+    } else {
+        rm as u64 * rs as u64
+    };
+
+    if accumulate_bits![bits] {
+        let accumulator = (cpu.registers[rd_hi] as u64) << 32 | cpu.registers[rd_lo] as u64;
+        result = result.wrapping_add(accumulator);
     }
 
-    cpu.registers[reg_d_bits![bits]] = result;
+    if mask![bits, 20] {
+        alu::set_long_multiply_flags(result, cpu);
+    }
 
+    let wrote_lo = cpu.write_register(rd_lo, result as u32);
+    cpu.write_register(rd_hi, (result >> 32) as u32) || wrote_lo
 }
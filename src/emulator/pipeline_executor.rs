@@ -1,70 +1,218 @@
+use std::path::PathBuf;
 use std::rc::Rc;
 
 use num_traits::FromPrimitive;
 
+use crate::emulator::block_data_transfer_instr::execute_block_data_instr;
 use crate::emulator::branch_instr as branch;
+use crate::emulator::coprocessor::Coprocessors;
+use crate::emulator::coprocessor_instr::execute_coprocessor_instr;
 use crate::emulator::data_proc_instr as data_proc;
+use crate::emulator::disassembler::disassemble_instr;
 use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::{take_exception, take_pending_interrupt, Exception};
+use crate::emulator::halfword_data_transfer_instr::execute_halfword_data_instr;
+use crate::emulator::media_instr::execute_media_instr;
+use crate::emulator::miscellaneous_instr::execute_miscellaneous_instr;
 use crate::emulator::multiply_instr as mul;
+use crate::emulator::psr_transfer_instr::execute_psr_transfer_instr;
+use crate::emulator::semihosting::Semihosting;
 use crate::emulator::single_data_transfer_instr as sdt;
+use crate::emulator::software_interrupt_instr::execute_software_interrupt_instr;
+use crate::emulator::symbol_map::SymbolMap;
+use crate::emulator::synchronization_instr::execute_synchronization_instr;
+use crate::emulator::thumb_instr::execute_thumb_instr;
+use crate::emulator::unconditional_instr::execute_unconditional_instr;
 
-use branch::execute_branch_instr;
+use branch::{execute_branch_exchange_instr, execute_branch_instr};
 use data_proc::execute_data_processing_instr;
 use mul::execute_multiply_instruction;
 use sdt::execute_single_data_instr;
 
 use util::*;
 
+/// Options of the `emulate` subcommand
+#[derive(Debug, Default)]
+pub struct EmulateOptions {
+    /// The `.sym` file to read labels from,
+    /// defaults to the one next to the binary if it exists
+    pub symbols_path: Option<PathBuf>,
+    /// Whether to print every instruction before executing it
+    pub trace: bool,
+    /// The architecture version to emulate, which decides
+    /// what the 0b1111 condition means
+    pub architecture: Architecture,
+    /// The directory semihosting calls can access files in.
+    /// Without one, programs can't open, remove or rename any file
+    pub host_directory: Option<PathBuf>,
+    /// Whether the exception vectors start at 0xffff0000 instead of 0,
+    /// the reset value of the V bit in CP15 which programs can change
+    pub high_vectors: bool,
+}
+
 /// Executes the emulator given the instruction vector
+#[cfg(test)]
 pub fn emulate(path: &str) -> Result<CpuState, std::io::Error> {
+    emulate_with(path, &EmulateOptions::default())
+}
+
+/// Executes the emulator with the given options
+#[cfg(test)]
+pub fn emulate_with(path: &str, options: &EmulateOptions) -> Result<CpuState, std::io::Error> {
+    run(path, options).map(|(cpu, _)| cpu)
+}
+
+/// Executes the emulator with the given options, also returning
+/// the exit status of the program if it exited through semihosting
+pub fn run(path: &str, options: &EmulateOptions) -> Result<(CpuState, Option<i32>), std::io::Error> {
     let mut cpu = util::CpuState::init(path)?;
+    cpu.architecture = options.architecture;
+    // Cores take the reset value of the high vectors bit from a pin
+    cpu.coprocessors = Coprocessors::built_in(options.architecture, options.high_vectors);
+    let symbols = match &options.symbols_path {
+        Some(symbols_path) => SymbolMap::load(symbols_path)?,
+        None => SymbolMap::find_for(path)?,
+    };
+
+    let mut host = Semihosting::new(options.host_directory.clone(), path);
 
-    start_pipeline(&mut cpu);
-    cpu.print_registers();
-    Ok(cpu)
+    let trace = if options.trace { Some(&symbols) } else { None };
+    start_pipeline(&mut cpu, &mut host, trace);
+    cpu.print_registers(&symbols);
+    Ok((cpu, host.exit_status))
 }
 
-/// Executes the given instruction
-fn execute_instr(instr: &Instruction, cpu: &mut CpuState, pipe: &mut Pipe) -> bool {
-    let flag_code = process_mask(instr.code, BitPos32::from_u8(28), BitPos32::from_u8(31));
-    let flag_code = FromPrimitive::from_u32(flag_code);
-    match flag_code {
-        Some(code) => {
-            // Don't execute if the CPSR condition is failed
-            if !cpu.check_CPSR_cond(code) {
-                return false;
-            }
+/// Prints the instruction about to be executed at `address`
+fn trace_instr(instr: &Instruction, address: u32, symbols: &SymbolMap) {
+    if instr.instruction_type == InstructionType::PREFETCH_ABORT {
+        println!("{}: <prefetch abort>", symbols.annotate(address));
+    } else if instr.instruction_type == InstructionType::THUMB {
+        println!("{}: .hword 0x{:0>4x}", symbols.annotate(address), instr.code);
+    } else {
+        println!("{}: {}", symbols.annotate(address), disassemble_instr(instr.code, address));
+    }
+}
+
+/// Executes the given instruction, returning whether it flushed the pipe
+/// by writing the PC or raising an exception
+fn execute_instr(
+    instr: &Instruction,
+    cpu: &mut CpuState,
+    pipe: &mut Pipe,
+    host: &mut Semihosting,
+) -> bool {
+    // The PC is two instructions ahead of the executing one
+    let address = cpu.pc().wrapping_sub(2 * cpu.instruction_size());
+    if instr.instruction_type == InstructionType::PREFETCH_ABORT {
+        take_exception(cpu, pipe, Exception::PrefetchAbort, address);
+        return true;
+    }
+    if instr.instruction_type == InstructionType::THUMB {
+        // Only conditional branches have a condition, which they check themselves
+    } else if instr.instruction_type == InstructionType::UNCONDITIONAL {
+        // Before ARMv5 the 0b1111 condition means "never"
+        if cpu.architecture < Architecture::ARMv5 {
+            pipe.clear_executing();
+            return false;
+        }
+    } else {
+        let flag_code = process_mask(instr.code, BitPos32::from_u8(28), BitPos32::from_u8(31));
+        // Every condition but 0b1111 is a FlagCode
+        let flag_code = FromPrimitive::from_u32(flag_code).unwrap();
+        // Don't execute if the CPSR condition is failed
+        if !cpu.check_CPSR_cond(flag_code) {
+            pipe.clear_executing();
+            return false;
         }
-        // We are assuming the binary file has correct instructions, so an instruction with a wrong
-        // CPSR flag will panic
-        None => {
-            panic!("You gave me a wrong CPSR flag code, something is wrong with your binary file!")
+    }
+
+    match dispatch_instr(instr, cpu, pipe, host) {
+        Ok(flushed) => flushed,
+        Err(exception) => {
+            take_exception(cpu, pipe, exception, address);
+            true
         }
-    };
+    }
+}
 
+/// Executes an instruction whose condition passed, returning whether it flushed the pipe
+/// or the exception it raised, in which case it didn't touch the pipe
+fn dispatch_instr(
+    instr: &Instruction,
+    cpu: &mut CpuState,
+    pipe: &mut Pipe,
+    host: &mut Semihosting,
+) -> Result<bool, Exception> {
     // A HashMap with functions as values would look more fancy
     // but in case of testing loop01 the additional overhead cost was immense
     // If run like this, the loop01 test case is finished faster than the C version
     // which is quite impressive
     match instr.instruction_type {
-        InstructionType::BRANCH => return execute_branch_instr(instr, cpu, pipe),
+        InstructionType::BRANCH => Ok(execute_branch_instr(instr, cpu, pipe)),
+        InstructionType::BRANCH_EXCHANGE => execute_branch_exchange_instr(instr, cpu, pipe),
         InstructionType::DATA_PROCESS => {
-            execute_data_processing_instr(instr, cpu);
-            pipe.clear_executing();
-            true
+            let wrote_pc = execute_data_processing_instr(instr, cpu);
+            Ok(flush_if(wrote_pc, cpu, pipe))
         }
         InstructionType::MULTIPLTY =>  {
-            execute_multiply_instruction(instr, cpu);
-            pipe.clear_executing();
-            true
+            let wrote_pc = execute_multiply_instruction(instr, cpu);
+            Ok(flush_if(wrote_pc, cpu, pipe))
         },
         InstructionType::SINGLE_DATA_TRANSFER =>  {
-            execute_single_data_instr(instr, cpu);
-            pipe.clear_executing();
-            true
+            let loaded_pc = execute_single_data_instr(instr, cpu)?;
+            Ok(flush_if(loaded_pc, cpu, pipe))
         },
+        InstructionType::HALFWORD_DATA_TRANSFER => {
+            let wrote_pc = execute_halfword_data_instr(instr, cpu)?;
+            Ok(flush_if(wrote_pc, cpu, pipe))
+        }
+        InstructionType::BLOCK_DATA_TRANSFER => {
+            let loaded_pc = execute_block_data_instr(instr, cpu)?;
+            Ok(flush_if(loaded_pc, cpu, pipe))
+        }
+        InstructionType::PSR_TRANSFER => {
+            let wrote_pc = execute_psr_transfer_instr(instr, cpu);
+            Ok(flush_if(wrote_pc, cpu, pipe))
+        }
+        InstructionType::SOFTWARE_INTERRUPT => {
+            execute_software_interrupt_instr(instr, cpu, host)?;
+            pipe.clear_executing();
+            Ok(false)
+        }
+        InstructionType::COPROCESSOR => {
+            let wrote_pc = execute_coprocessor_instr(instr, cpu)?;
+            Ok(flush_if(wrote_pc, cpu, pipe))
+        }
+        InstructionType::MISCELLANEOUS => {
+            let wrote_pc = execute_miscellaneous_instr(instr, cpu)?;
+            Ok(flush_if(wrote_pc, cpu, pipe))
+        }
+        InstructionType::MEDIA => {
+            let wrote_pc = execute_media_instr(instr, cpu)?;
+            Ok(flush_if(wrote_pc, cpu, pipe))
+        }
+        InstructionType::SYNCHRONIZATION => {
+            let wrote_pc = execute_synchronization_instr(instr, cpu)?;
+            Ok(flush_if(wrote_pc, cpu, pipe))
+        }
+        InstructionType::UNCONDITIONAL => execute_unconditional_instr(instr, cpu, pipe),
+        InstructionType::PREFETCH_ABORT => Err(Exception::PrefetchAbort),
+        InstructionType::THUMB => {
+            let wrote_pc = execute_thumb_instr(instr, cpu, host)?;
+            Ok(flush_if(wrote_pc, cpu, pipe))
+        }
     }
+}
 
+/// Flushes the pipe if the executed instruction wrote the PC,
+/// otherwise just retires it. Returns whether it flushed
+fn flush_if(wrote_pc: bool, cpu: &mut CpuState, pipe: &mut Pipe) -> bool {
+    if wrote_pc {
+        pipe.flush(cpu);
+    } else {
+        pipe.clear_executing();
+    }
+    wrote_pc
 }
 
 /// Helper function that helps with checking which instruction type
@@ -73,16 +221,97 @@ fn instruction_condition(bits: u32, start: u8, end: u8, target: u32) -> bool {
     process_mask(bits, BitPos32::from_u8(start), BitPos32::from_u8(end)) == target
 }
 
+/// Returns whether the given instruction is of type HALFWORD_DATA_TRANSFER
+fn is_halfword_data_transfer_instr(bits: u32) -> bool {
+    // Bits 25-27 are 000, bits 7 and 4 are 1 and bits 5-6 aren't 00,
+    // which would be a multiply
+    instruction_condition(bits, 25, 27, 0)
+        && mask![bits, 7]
+        && mask![bits, 4]
+        && !instruction_condition(bits, 5, 6, 0)
+}
+
+/// Returns whether the given instruction is of type UNCONDITIONAL
+fn is_unconditional_instr(bits: u32) -> bool {
+    // Bits 28-31 are 1111
+    instruction_condition(bits, 28, 31, 15)
+}
+
 /// Returns whether the given instruction is of type BRANCH
 fn is_branch_instr(bits: u32) -> bool {
-    // Bits 24-27 are 1010
-    instruction_condition(bits, 24, 27, 10)
+    // Bits 25-27 are 101, bit 24 is the link bit
+    instruction_condition(bits, 25, 27, 5)
+}
+
+/// Returns whether the given instruction is of type SOFTWARE_INTERRUPT
+fn is_software_interrupt_instr(bits: u32) -> bool {
+    // Bits 24-27 are 1111
+    instruction_condition(bits, 24, 27, 15)
+}
+
+/// Returns whether the given instruction is of type COPROCESSOR
+fn is_coprocessor_instr(bits: u32) -> bool {
+    // Bits 26-27 are 11, once SWIs are ruled out
+    instruction_condition(bits, 26, 27, 3)
+}
+
+/// Returns whether the given instruction is of type BRANCH_EXCHANGE
+fn is_branch_exchange_instr(bits: u32) -> bool {
+    // Bits 4-27 are 0x12fff1 for BX and 0x12fff3 for BLX
+    let pattern = mask![bits, 4, 27];
+    pattern == 0x12_fff1 || pattern == 0x12_fff3
+}
+
+/// Returns whether the given instruction is of type PSR_TRANSFER
+fn is_psr_transfer_instr(bits: u32) -> bool {
+    // Bits 23-27 are 00010, or 00110 for MSR with an immediate (bit 21 set),
+    // and bit 20 is 0 since it would make them compares.
+    // MRS and MSR with a register have bits 4-11 clear
+    let operand_bits = if mask![bits, 25] {
+        mask![bits, 21]
+    } else {
+        instruction_condition(bits, 4, 11, 0)
+    };
+    instruction_condition(bits, 23, 24, 2)
+        && instruction_condition(bits, 26, 27, 0)
+        && !mask![bits, 20]
+        && operand_bits
+}
+
+/// Returns whether the given instruction is of type MISCELLANEOUS
+fn is_miscellaneous_instr(bits: u32) -> bool {
+    // Bits 23-27 are 00010 and bit 20 is 0, like the PSR transfers which are ruled out,
+    // and bits 4 and 7 aren't both 1
+    instruction_condition(bits, 23, 27, 2)
+        && !mask![bits, 20]
+        && !(mask![bits, 4] && mask![bits, 7])
+}
+
+/// Returns whether the given instruction is of type SYNCHRONIZATION
+fn is_synchronization_instr(bits: u32) -> bool {
+    // Bits 24-27 are 0001 and bits 4-7 are 1001
+    instruction_condition(bits, 24, 27, 1) && instruction_condition(bits, 4, 7, 9)
+}
+
+/// Returns whether the given instruction is of type MEDIA
+fn is_media_instr(bits: u32) -> bool {
+    // Bits 25-27 are 011 and bit 4 is 1,
+    // which single data transfers with a register offset leave clear
+    instruction_condition(bits, 25, 27, 3) && mask![bits, 4]
 }
 
 /// Returns whether the given instruction is of type MULTIPLY
 fn is_multiply_instr(bits: u32) -> bool {
-    // Bits 22-27 are all 9 and bits 4-7 are 1001
-    instruction_condition(bits, 22, 27, 0) && instruction_condition(bits, 4, 7, 9)
+    // Bits 4-7 are 1001 and bits 22-27 are all 0,
+    // or bits 23-27 are 00001 for the long multiplies
+    (instruction_condition(bits, 22, 27, 0) || instruction_condition(bits, 23, 27, 1))
+        && instruction_condition(bits, 4, 7, 9)
+}
+
+/// Returns whether the given instruction is of type BLOCK_DATA_TRANSFER
+fn is_block_data_transfer_instr(bits: u32) -> bool {
+    // Bits 25-27 are 100
+    instruction_condition(bits, 25, 27, 4)
 }
 
 /// Returns whether the given instruction is of type SINGLE_DATA_TRANSFER
@@ -91,14 +320,51 @@ fn is_single_data_transfer_instr(bits: u32) -> bool {
     instruction_condition(bits, 26, 27, 1)
 }
 
+/// Decodes the instruction in the fetch stage of the pipe,
+/// or stands in for it if its fetch aborted.
+/// Thumb instructions are told apart when they are executed
+fn decode_fetched(pipe: &Pipe, cpu: &CpuState) -> Rc<Instruction> {
+    let instruction_type = if pipe.fetch_aborted {
+        InstructionType::PREFETCH_ABORT
+    } else if cpu.in_thumb_state() {
+        InstructionType::THUMB
+    } else {
+        return decode_instruction(pipe.fetching);
+    };
+    Rc::new(Instruction {
+        code: pipe.fetching,
+        instruction_type,
+    })
+}
+
 pub fn decode_instruction(bits: u32) -> Rc<Instruction> {
     let instruction_type;
-    if is_branch_instr(bits) {
+    if is_unconditional_instr(bits) {
+        instruction_type = InstructionType::UNCONDITIONAL;
+    } else if is_branch_instr(bits) {
         instruction_type = InstructionType::BRANCH;
+    } else if is_software_interrupt_instr(bits) {
+        instruction_type = InstructionType::SOFTWARE_INTERRUPT;
+    } else if is_coprocessor_instr(bits) {
+        instruction_type = InstructionType::COPROCESSOR;
+    } else if is_branch_exchange_instr(bits) {
+        instruction_type = InstructionType::BRANCH_EXCHANGE;
+    } else if is_psr_transfer_instr(bits) {
+        instruction_type = InstructionType::PSR_TRANSFER;
+    } else if is_miscellaneous_instr(bits) {
+        instruction_type = InstructionType::MISCELLANEOUS;
+    } else if is_synchronization_instr(bits) {
+        instruction_type = InstructionType::SYNCHRONIZATION;
     } else if is_multiply_instr(bits) {
         instruction_type = InstructionType::MULTIPLTY;
+    } else if is_media_instr(bits) {
+        instruction_type = InstructionType::MEDIA;
     } else if is_single_data_transfer_instr(bits) {
         instruction_type = InstructionType::SINGLE_DATA_TRANSFER;
+    } else if is_block_data_transfer_instr(bits) {
+        instruction_type = InstructionType::BLOCK_DATA_TRANSFER;
+    } else if is_halfword_data_transfer_instr(bits) {
+        instruction_type = InstructionType::HALFWORD_DATA_TRANSFER;
     } else {
         instruction_type = InstructionType::DATA_PROCESS;
     }
@@ -108,34 +374,45 @@ pub fn decode_instruction(bits: u32) -> Rc<Instruction> {
     })
 }
 
-pub fn start_pipeline(cpu: &mut CpuState) {
+/// Runs the pipeline until it fetches a 0 instruction or the program exits
+/// through semihosting, tracing every instruction if given the program's symbols
+pub fn start_pipeline(cpu: &mut CpuState, host: &mut Semihosting, trace: Option<&SymbolMap>) {
     let mut pipe = Pipe::init(cpu);
-    start_pipeline_helper(cpu, &mut pipe);
+    start_pipeline_helper(cpu, &mut pipe, host, trace);
 }
 
-fn start_pipeline_helper(cpu: &mut CpuState, pipe: &mut Pipe) {
+fn start_pipeline_helper(
+    cpu: &mut CpuState,
+    pipe: &mut Pipe,
+    host: &mut Semihosting,
+    trace: Option<&SymbolMap>,
+) {
     loop {
-        if pipe.fetching != 0 {
+        // Interrupts are taken between instructions, before the next one executes
+        take_pending_interrupt(cpu, pipe);
+        if pipe.is_fetching() {
             // Set decoding to None and move the previous decoding value to executing
             let new_exec = pipe.decoding.take();
             pipe.executing = new_exec;
-            pipe.decoding = Some(decode_instruction(pipe.fetching));
-            let mut branch_succeeded = false;
+            pipe.decoding = Some(decode_fetched(pipe, cpu));
+            let mut flushed = false;
             if let Some(instr) = &pipe.executing {
-                let instr_type = instr.instruction_type;
-                let succeeded = execute_instr(&Rc::clone(instr), cpu, pipe);
-                if succeeded && (instr_type == InstructionType::BRANCH) {
-                    branch_succeeded = true;
+                if let Some(symbols) = trace {
+                    // The PC is two instructions ahead of the executing one
+                    trace_instr(instr, cpu.pc() - 2 * cpu.instruction_size(), symbols);
                 }
+                flushed = execute_instr(&Rc::clone(instr), cpu, pipe, host);
             }
-            if !branch_succeeded {
-                pipe.fetching = cpu.fetch(cpu.pc() as usize);
-                cpu.increment_pc();
+            if host.exit_status.is_some() {
+                break;
+            }
+            if !flushed {
+                pipe.fetch(cpu);
             }
             //start_pipeline_helper(cpu, pipe);
         } else {
-            let ended = end_pipeline(cpu, pipe);
-            if ended {
+            let ended = end_pipeline(cpu, pipe, host, trace);
+            if ended || host.exit_status.is_some() {
                 break;
             }
         }
@@ -144,22 +421,29 @@ fn start_pipeline_helper(cpu: &mut CpuState, pipe: &mut Pipe) {
 
 /// Function that tries to end the pipeline and returns whether it did actually
 /// succeed in ending it
-fn end_pipeline(cpu: &mut CpuState, pipe: &mut Pipe) -> bool {
+fn end_pipeline(
+    cpu: &mut CpuState,
+    pipe: &mut Pipe,
+    host: &mut Semihosting,
+    trace: Option<&SymbolMap>,
+) -> bool {
     if let Some(instr) = &pipe.executing {
-        let instr_type = instr.instruction_type;
-        let succeeded = execute_instr(&Rc::clone(instr), cpu, pipe);
-        if succeeded && (instr_type == InstructionType::BRANCH) {
-            // executed a branch instruction which succeeded, so no longer terminating
+        if let Some(symbols) = trace {
+            trace_instr(instr, cpu.pc() - 3 * cpu.instruction_size(), symbols);
+        }
+        if execute_instr(&Rc::clone(instr), cpu, pipe, host) {
+            // the instruction wrote the PC, so no longer terminating
             return false;
         }
         cpu.increment_pc();
         pipe.clear_decoding();
     } else {
         if let Some(instr) = &pipe.decoding {
-            let instr_type = instr.instruction_type;
-            let succeeded = execute_instr(&Rc::clone(instr), cpu, pipe);
-            if succeeded && (instr_type == InstructionType::BRANCH) {
-                // executed a branch instruction which succeeded, so no longer terminating
+            if let Some(symbols) = trace {
+                trace_instr(instr, cpu.pc() - 2 * cpu.instruction_size(), symbols);
+            }
+            if execute_instr(&Rc::clone(instr), cpu, pipe, host) {
+                // the instruction wrote the PC, so no longer terminating
                 return false;
             }
         }
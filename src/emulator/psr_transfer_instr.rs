@@ -0,0 +1,97 @@
+use crate::emulator::barrel_shifter::rotated_immediate;
+use crate::emulator::em_utilities as util;
+use util::*;
+
+macro_rules! immediate_bit {
+    ($bits:expr) => {
+        mask![$bits, 25]
+    };
+}
+
+/// Bit 22, set when the SPSR is transferred instead of the CPSR
+macro_rules! spsr_bit {
+    ($bits:expr) => {
+        mask![$bits, 22]
+    };
+}
+
+/// Bit 21, set for MSR and clear for MRS
+macro_rules! msr_bit {
+    ($bits:expr) => {
+        mask![$bits, 21]
+    };
+}
+
+/// The `fsxc` field mask of MSR, one bit per byte of the PSR
+macro_rules! field_mask_bits {
+    ($bits:expr) => {
+        mask![$bits, 16, 19]
+    };
+}
+
+macro_rules! dest_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 12, 15] as usize
+    };
+}
+
+macro_rules! source_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 0, 3] as usize
+    };
+}
+
+/// The bits of the CPSR user mode can write: the condition flags, Q and the GE flags
+const USER_WRITABLE: u32 = 0xf80f_0000;
+
+/// Expands the `fsxc` field mask into a mask of the PSR bits it selects
+fn byte_mask(fields: u32) -> u32 {
+    (0..4)
+        .filter(|field| fields & (1 << field) != 0)
+        .fold(0, |mask, field| mask | 0xff << (8 * field))
+}
+
+/// Executes MRS, which reads the CPSR or SPSR into a register,
+/// or MSR, which writes the fields selected by its mask from a register or an immediate.
+/// User mode can only write the flags of the CPSR. Returns whether MRS wrote the PC
+pub fn execute_psr_transfer_instr(instr: &Instruction, cpu: &mut CpuState) -> bool {
+    let bits = instr.code;
+    if !msr_bit![bits] {
+        // Reading the SPSR of a mode without one is unpredictable, it reads as 0
+        let value = if spsr_bit![bits] {
+            cpu.spsr().unwrap_or(0)
+        } else {
+            cpu.cpsr()
+        };
+        let rd = dest_reg_bits![bits];
+        if rd == PC {
+            cpu.branch_to(value);
+            return true;
+        }
+        cpu.registers[rd] = value;
+        return false;
+    }
+
+    let operand = if immediate_bit![bits] {
+        rotated_immediate(mask![bits, 0, 11]).0
+    } else {
+        cpu.registers[source_reg_bits![bits]]
+    };
+
+    let fields = field_mask_bits![bits];
+    if spsr_bit![bits] {
+        if let Some(spsr) = cpu.spsr() {
+            let mask = byte_mask(fields);
+            cpu.set_spsr((spsr & !mask) | (operand & mask));
+        }
+        return false;
+    }
+
+    // MSR can't change the T bit
+    let mut mask = byte_mask(fields) & !THUMB_BIT;
+    if !cpu.mode().is_privileged() {
+        mask &= USER_WRITABLE;
+    }
+    cpu.set_cpsr((cpu.cpsr() & !mask) | (operand & mask));
+    false
+}
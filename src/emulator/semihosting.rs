@@ -0,0 +1,407 @@
+//! ARM semihosting: the host calls a program makes with `swi 0x123456`
+//! to do I/O on the machine running the emulator.
+//! The operation is in r0, r1 points to its parameter block
+//! and the result is returned in r0
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::emulator::em_utilities::CpuState;
+
+/// The comment field of an ARM state SWI that is a semihosting call
+pub const ARM_SEMIHOSTING_SWI: u32 = 0x12_3456;
+/// The comment field of a Thumb state SWI that is a semihosting call
+pub const THUMB_SEMIHOSTING_SWI: u32 = 0xab;
+
+const SYS_OPEN: u32 = 0x01;
+const SYS_CLOSE: u32 = 0x02;
+const SYS_WRITEC: u32 = 0x03;
+const SYS_WRITE0: u32 = 0x04;
+const SYS_WRITE: u32 = 0x05;
+const SYS_READ: u32 = 0x06;
+const SYS_READC: u32 = 0x07;
+const SYS_ISERROR: u32 = 0x08;
+const SYS_ISTTY: u32 = 0x09;
+const SYS_SEEK: u32 = 0x0a;
+const SYS_FLEN: u32 = 0x0c;
+const SYS_TMPNAM: u32 = 0x0d;
+const SYS_REMOVE: u32 = 0x0e;
+const SYS_RENAME: u32 = 0x0f;
+const SYS_CLOCK: u32 = 0x10;
+const SYS_TIME: u32 = 0x11;
+const SYS_SYSTEM: u32 = 0x12;
+const SYS_ERRNO: u32 = 0x13;
+const SYS_GET_CMDLINE: u32 = 0x15;
+const SYS_HEAPINFO: u32 = 0x16;
+const SYS_EXIT: u32 = 0x18;
+const SYS_EXIT_EXTENDED: u32 = 0x20;
+const SYS_ELAPSED: u32 = 0x30;
+const SYS_TICKFREQ: u32 = 0x31;
+
+/// The SYS_EXIT reason of a program returning normally
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x2_0026;
+
+/// The errno values reported by SYS_ERRNO when the host gives none
+const EBADF: i32 = 9;
+const EACCES: i32 = 13;
+const EFAULT: i32 = 14;
+const EINVAL: i32 = 22;
+const ENOSYS: i32 = 38;
+
+/// The result of most calls when they fail
+const FAILURE: u32 = u32::MAX;
+
+/// Bytes at the top of memory SYS_HEAPINFO reports as the stack
+const STACK_SIZE: u32 = 0x1000;
+
+/// A file the program opened
+#[derive(Debug)]
+enum HostFile {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+/// The state kept by the host between semihosting calls
+#[derive(Debug)]
+pub struct Semihosting {
+    /// The directory the program's file names are relative to.
+    /// Files outside of it can't be opened, removed or renamed,
+    /// and without one the program can't touch any file
+    root: Option<PathBuf>,
+    /// The program's open files, a handle is an index in here
+    files: Vec<Option<HostFile>>,
+    /// The command line returned by SYS_GET_CMDLINE
+    command_line: String,
+    start: Instant,
+    errno: i32,
+    /// The status the program exited with, if it called SYS_EXIT
+    pub exit_status: Option<i32>,
+}
+
+/// The errno of a failed call, so calls can fail with `?`
+struct HostError(i32);
+
+impl From<io::Error> for HostError {
+    fn from(err: io::Error) -> Self {
+        HostError(err.raw_os_error().unwrap_or(EINVAL))
+    }
+}
+
+impl Semihosting {
+    /// Creates the host for a program, sandboxing its files to `root`
+    pub fn new(root: Option<PathBuf>, command_line: &str) -> Self {
+        Self {
+            root,
+            // Handle 0 is never given out
+            files: vec![None],
+            command_line: command_line.to_string(),
+            start: Instant::now(),
+            errno: 0,
+            exit_status: None,
+        }
+    }
+
+    /// Performs the call in r0 with the parameter block at r1,
+    /// writing the result to r0
+    pub fn call(&mut self, cpu: &mut CpuState) {
+        let (operation, parameter) = (cpu.registers[0], cpu.registers[1]);
+        let result = self
+            .dispatch(cpu, operation, parameter)
+            .unwrap_or_else(|HostError(errno)| {
+                self.errno = errno;
+                FAILURE
+            });
+        cpu.registers[0] = result;
+    }
+
+    fn dispatch(
+        &mut self,
+        cpu: &mut CpuState,
+        operation: u32,
+        parameter: u32,
+    ) -> Result<u32, HostError> {
+        // Parameter blocks have up to four words
+        let mut args = [None; 4];
+        for (index, arg) in args.iter_mut().enumerate() {
+            *arg = read_word(cpu, parameter.wrapping_add(4 * index as u32)).ok();
+        }
+        let arg = |index: usize| args[index].ok_or(HostError(EFAULT));
+        match operation {
+            SYS_OPEN => {
+                let name = read_string(cpu, arg(0)?, arg(2)?)?;
+                self.open(&name, arg(1)?)
+            }
+            SYS_CLOSE => {
+                let handle = arg(0)?;
+                self.file(handle)?;
+                self.files[handle as usize] = None;
+                Ok(0)
+            }
+            SYS_WRITEC => {
+                let byte = memory_range(cpu, parameter, 1)?[0];
+                write_console(&[byte])?;
+                Ok(0)
+            }
+            SYS_WRITE0 => {
+                let start = cpu
+                    .physical_address(parameter, 1)
+                    .ok_or(HostError(EFAULT))?;
+                let text = &cpu.memory[start..];
+                let length = text
+                    .iter()
+                    .position(|&byte| byte == 0)
+                    .ok_or(HostError(EFAULT))?;
+                write_console(&text[..length])?;
+                Ok(0)
+            }
+            SYS_WRITE => {
+                let (handle, length) = (arg(0)?, arg(2)?);
+                let data = memory_range(cpu, arg(1)?, length)?.to_vec();
+                let written = match self.file(handle)? {
+                    HostFile::Stdout => write_console(&data).map(|_| data.len())?,
+                    HostFile::Stderr => io::stderr().write_all(&data).map(|_| data.len())?,
+                    HostFile::File(file) => file.write(&data)?,
+                    HostFile::Stdin => return Err(HostError(EBADF)),
+                };
+                // Returns the number of bytes that weren't written
+                Ok(length - written as u32)
+            }
+            SYS_READ => {
+                let (handle, address, length) = (arg(0)?, arg(1)?, arg(2)?);
+                let start = cpu
+                    .physical_address(address, length as usize)
+                    .ok_or(HostError(EFAULT))?;
+                let mut buffer = vec![0; length as usize];
+                let read = match self.file(handle)? {
+                    HostFile::Stdin => io::stdin().read(&mut buffer)?,
+                    HostFile::File(file) => file.read(&mut buffer)?,
+                    _ => return Err(HostError(EBADF)),
+                };
+                cpu.memory[start..start + read].copy_from_slice(&buffer[..read]);
+                // Returns the number of bytes that weren't read
+                Ok(length - read as u32)
+            }
+            SYS_READC => {
+                let mut byte = [0];
+                io::stdin().read_exact(&mut byte)?;
+                Ok(byte[0] as u32)
+            }
+            SYS_ISERROR => Ok(((arg(0)? as i32) < 0) as u32),
+            SYS_ISTTY => match self.file(arg(0)?)? {
+                HostFile::File(_) => Ok(0),
+                _ => Ok(1),
+            },
+            SYS_SEEK => {
+                let position = arg(1)?;
+                match self.file(arg(0)?)? {
+                    HostFile::File(file) => file.seek(SeekFrom::Start(position as u64))?,
+                    _ => return Err(HostError(EBADF)),
+                };
+                Ok(0)
+            }
+            SYS_FLEN => match self.file(arg(0)?)? {
+                HostFile::File(file) => Ok(file.metadata()?.len() as u32),
+                _ => Err(HostError(EBADF)),
+            },
+            SYS_TMPNAM => {
+                let (address, id, length) = (arg(0)?, arg(1)?, arg(2)?);
+                let name = format!("tmp{:03}\0", id & 0xff);
+                if name.len() as u32 > length {
+                    return Err(HostError(EINVAL));
+                }
+                write_bytes(cpu, address, name.as_bytes())?;
+                Ok(0)
+            }
+            SYS_REMOVE => {
+                let name = read_string(cpu, arg(0)?, arg(1)?)?;
+                fs::remove_file(self.sandboxed(&name)?)?;
+                Ok(0)
+            }
+            SYS_RENAME => {
+                let from = read_string(cpu, arg(0)?, arg(1)?)?;
+                let to = read_string(cpu, arg(2)?, arg(3)?)?;
+                fs::rename(self.sandboxed(&from)?, self.sandboxed(&to)?)?;
+                Ok(0)
+            }
+            // Centiseconds since the program started
+            SYS_CLOCK => Ok((self.start.elapsed().as_millis() / 10) as u32),
+            SYS_TIME => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                Ok(now.as_secs() as u32)
+            }
+            // Host commands would escape the sandbox
+            SYS_SYSTEM => Err(HostError(EACCES)),
+            SYS_ERRNO => Ok(self.errno as u32),
+            SYS_GET_CMDLINE => {
+                let (address, length) = (arg(0)?, arg(1)?);
+                let command_line = format!("{}\0", self.command_line);
+                if command_line.len() as u32 > length {
+                    return Err(HostError(EINVAL));
+                }
+                write_bytes(cpu, address, command_line.as_bytes())?;
+                let written = (command_line.len() - 1) as u32;
+                write_bytes(cpu, parameter.wrapping_add(4), &written.to_le_bytes())?;
+                Ok(0)
+            }
+            SYS_HEAPINFO => {
+                // A heap base of 0 tells the C library to start it after the program.
+                // The stack is at the top of memory
+                let top = cpu.memory.len() as u32;
+                let limit = top - STACK_SIZE;
+                let block = [0, limit, top, limit];
+                let bytes: Vec<u8> = block
+                    .iter()
+                    .flat_map(|word: &u32| word.to_le_bytes())
+                    .collect();
+                write_bytes(cpu, arg(0)?, &bytes)?;
+                Ok(0)
+            }
+            SYS_EXIT => {
+                // r1 holds the reason itself, without a status code
+                let status = if parameter == ADP_STOPPED_APPLICATION_EXIT {
+                    0
+                } else {
+                    1
+                };
+                self.exit_status = Some(status);
+                Ok(0)
+            }
+            SYS_EXIT_EXTENDED => {
+                let (reason, code) = (arg(0)?, arg(1)?);
+                let status = if reason == ADP_STOPPED_APPLICATION_EXIT {
+                    code as i32
+                } else {
+                    1
+                };
+                self.exit_status = Some(status);
+                Ok(0)
+            }
+            SYS_ELAPSED => {
+                let ticks = self.start.elapsed().as_micros() as u64;
+                write_bytes(cpu, parameter, &ticks.to_le_bytes())?;
+                Ok(0)
+            }
+            // SYS_ELAPSED counts microseconds
+            SYS_TICKFREQ => Ok(1_000_000),
+            _ => Err(HostError(ENOSYS)),
+        }
+    }
+
+    /// Opens a file with one of the fopen modes, numbered `r`, `rb`, `r+`, `r+b`,
+    /// `w`, `wb`, `w+`, `w+b`, `a`, `ab`, `a+` and `a+b`.
+    /// The special name `:tt` opens the console
+    fn open(&mut self, name: &str, mode: u32) -> Result<u32, HostError> {
+        if mode > 11 {
+            return Err(HostError(EINVAL));
+        }
+        let file = if name == ":tt" {
+            match mode / 4 {
+                0 => HostFile::Stdin,
+                1 => HostFile::Stdout,
+                _ => HostFile::Stderr,
+            }
+        } else {
+            let update = mode % 4 >= 2;
+            let mut options = OpenOptions::new();
+            match mode / 4 {
+                0 => options.read(true).write(update),
+                1 => options.write(true).read(update).create(true).truncate(true),
+                _ => options.append(true).read(update).create(true),
+            };
+            HostFile::File(options.open(self.sandboxed(name)?)?)
+        };
+
+        let handle = match self.files.iter().skip(1).position(Option::is_none) {
+            Some(free) => free + 1,
+            None => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+        };
+        self.files[handle] = Some(file);
+        Ok(handle as u32)
+    }
+
+    /// The open file with the given handle
+    fn file(&mut self, handle: u32) -> Result<&mut HostFile, HostError> {
+        match self.files.get_mut(handle as usize) {
+            Some(Some(file)) => Ok(file),
+            _ => Err(HostError(EBADF)),
+        }
+    }
+
+    /// Resolves a file name of the program inside the host directory.
+    /// Only relative names that stay inside it are allowed, symbolic links included
+    fn sandboxed(&self, name: &str) -> Result<PathBuf, HostError> {
+        let root = self
+            .root
+            .as_ref()
+            .ok_or(HostError(EACCES))?
+            .canonicalize()?;
+        let path = Path::new(name);
+        let inside = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if name.is_empty() || !inside {
+            return Err(HostError(EACCES));
+        }
+
+        let path = root.join(path);
+        // A file about to be created doesn't exist yet, but its directory must.
+        // A dangling link does exist, and can't be followed to check where it leads
+        let resolved = match fs::symlink_metadata(&path) {
+            Ok(_) => path.canonicalize().map_err(|_| HostError(EACCES))?,
+            Err(_) => {
+                let parent = path.parent().ok_or(HostError(EACCES))?.canonicalize()?;
+                parent.join(path.file_name().ok_or(HostError(EACCES))?)
+            }
+        };
+        if !resolved.starts_with(&root) {
+            return Err(HostError(EACCES));
+        }
+        Ok(path)
+    }
+}
+
+/// Reads a little endian word of a parameter block
+fn read_word(cpu: &CpuState, address: u32) -> Result<u32, HostError> {
+    let bytes = memory_range(cpu, address, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The `length` bytes of memory at `address`, mapped as the data transfers map it
+fn memory_range(cpu: &CpuState, address: u32, length: u32) -> Result<&[u8], HostError> {
+    let start = cpu
+        .physical_address(address, length as usize)
+        .ok_or(HostError(EFAULT))?;
+    Ok(&cpu.memory[start..start + length as usize])
+}
+
+/// Reads a file name of the given length
+fn read_string(cpu: &CpuState, address: u32, length: u32) -> Result<String, HostError> {
+    let bytes = memory_range(cpu, address, length)?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| HostError(EINVAL))
+}
+
+/// Copies bytes into memory at `address`
+fn write_bytes(cpu: &mut CpuState, address: u32, bytes: &[u8]) -> Result<(), HostError> {
+    let start = cpu
+        .physical_address(address, bytes.len())
+        .ok_or(HostError(EFAULT))?;
+    cpu.memory[start..start + bytes.len()].copy_from_slice(bytes);
+    Ok(())
+}
+
+/// Writes program output to stdout straight away, so it isn't
+/// held back until the registers are printed
+fn write_console(bytes: &[u8]) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    stdout.write_all(bytes)?;
+    stdout.flush()
+}
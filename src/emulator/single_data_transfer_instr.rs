@@ -1,5 +1,6 @@
 use crate::emulator::barrel_shifter::reg_offset_shift;
 use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::Exception;
 use util::*;
 
 macro_rules! immediate_bit {
@@ -20,6 +21,18 @@ macro_rules! up_bit {
     };
 }
 
+macro_rules! byte_bit {
+    ($bits:expr) => {
+        mask![$bits, 22]
+    };
+}
+
+macro_rules! write_back_bit {
+    ($bits:expr) => {
+        mask![$bits, 21]
+    };
+}
+
 macro_rules! transfer_type_bit {
     ($bits:expr) => {
         mask![$bits, 20]
@@ -28,7 +41,7 @@ macro_rules! transfer_type_bit {
 
 macro_rules! offset_bits {
     ($bits:expr) => {
-        mask![$bits, 0, 11] as u16
+        mask![$bits, 0, 11]
     };
 }
 
@@ -44,61 +57,81 @@ macro_rules! transfer_reg_bits {
     };
 }
 
-/// Computes the address given to an offset of an SDT instruction
-fn compute_address(cpu: &mut CpuState, instr: &Instruction, offset: u32) -> u32 {
-    let address: u32;
-    let bits = instr.code;
+/// Computes the address to transfer from or to and the new value of the base register.
+/// Pre-indexed transfers use the offset base, post-indexed ones use the base
+/// as it is and always write the offset base back
+pub fn compute_address(cpu: &CpuState, bits: u32, offset: u32) -> (u32, Option<u32>) {
     let base_reg_val: u32 = cpu.registers[base_reg_bits![bits]];
+    let offset_base = if up_bit![bits] {
+        base_reg_val.wrapping_add(offset)
+    } else {
+        base_reg_val.wrapping_sub(offset)
+    };
 
-    if up_bit![bits] {
-        if indexing_bit![bits] {
-            address = base_reg_val + offset;
-        } else {
-            address = base_reg_val;
-            cpu.registers[base_reg_bits![bits]] += offset;
-        }
+    if !indexing_bit![bits] {
+        (base_reg_val, Some(offset_base))
+    } else if write_back_bit![bits] {
+        (offset_base, Some(offset_base))
     } else {
-        if indexing_bit![bits] {
-            address = base_reg_val - offset;
-        } else {
-            address = base_reg_val;
-            cpu.registers[base_reg_bits![bits]] -= offset;
-        }
+        (offset_base, None)
     }
-
-    address
 }
 
 /// Computes the offset of an SDT instruction
-fn compute_offset(cpu: &mut CpuState, instr: &Instruction) -> u16 {
-    let offset: u16;
+fn compute_offset(cpu: &mut CpuState, instr: &Instruction) -> u32 {
     let bits = instr.code;
     if immediate_bit![bits] {
         // Register shifted offset (as in data processing type instruction)
-        let mut carry: u8 = 0;
-        offset = reg_offset_shift(cpu, instr, &mut carry) as u16;
+        reg_offset_shift(cpu, instr).0
     } else {
-        offset = offset_bits![bits];
+        offset_bits![bits]
     }
-    offset
 }
 
-const NUM_REGISTERS: u32 = 17;
-pub fn execute_single_data_instr(instr: &Instruction, cpu: &mut CpuState) {
+/// Executes a single data transfer of a word or a byte, returning whether it wrote the PC.
+/// A loaded PC interworks from ARMv5 on, a stored one is the address of the STR plus 8.
+/// An access outside memory aborts before the base is written back
+pub fn execute_single_data_instr(instr: &Instruction, cpu: &mut CpuState) -> Result<bool, Exception> {
     let bits = instr.code;
-    assert!(transfer_reg_bits![bits] < NUM_REGISTERS as usize);
-    let offset: u16 = compute_offset(cpu, instr);
-    let address: u32 = compute_address(cpu, instr, offset as u32);
+    let offset = compute_offset(cpu, instr);
+    let (address, write_back) = compute_address(cpu, bits, offset);
+    let rd = transfer_reg_bits![bits];
 
-    if transfer_type_bit![bits] {
-       let word: u32 = cpu.index_little_endian(address as usize);
-        cpu.registers[transfer_reg_bits![bits]] = word;
+    let loaded = if transfer_type_bit![bits] {
+        Some(if byte_bit![bits] {
+            cpu.read_byte(address)?
+        } else {
+            cpu.read_word(address)?
+        })
     } else {
-        let reg_val: u32 = cpu.registers[transfer_reg_bits![bits]];
-        let address = address as usize;
-        cpu.memory[address] = mask![reg_val, 0, 7] as u8;
-        cpu.memory[address + 1] = mask![reg_val, 8, 15] as u8;
-        cpu.memory[address + 2] = mask![reg_val, 16, 23] as u8;
-        cpu.memory[address + 3] = mask![reg_val, 24, 31] as u8;
+        // Stores use the value the register had before the write back
+        let value = cpu.registers[rd];
+        if byte_bit![bits] {
+            cpu.write_byte(address, value)?;
+        } else {
+            cpu.write_word(address, value)?;
+        }
+        None
+    };
+    let mut wrote_pc = false;
+    if let Some(base) = write_back {
+        let rn = base_reg_bits![bits];
+        if rn == PC {
+            cpu.branch_to(base);
+            wrote_pc = true;
+        } else {
+            cpu.registers[rn] = base;
+        }
+    }
+
+    // A loaded value takes priority over the written back base
+    match loaded {
+        Some(value) if rd == PC => {
+            cpu.load_pc(value);
+            wrote_pc = true;
+        }
+        Some(value) => cpu.registers[rd] = value,
+        None => (),
     }
+    Ok(wrote_pc)
 }
@@ -0,0 +1,25 @@
+use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::Exception;
+use crate::emulator::semihosting::{Semihosting, ARM_SEMIHOSTING_SWI};
+use util::*;
+
+macro_rules! comment_bits {
+    ($bits:expr) => {
+        mask![$bits, 0, 23]
+    };
+}
+
+/// Executes a SWI, passing semihosting calls to the host.
+/// Any other SWI enters the guest's handler through the SWI vector
+pub fn execute_software_interrupt_instr(
+    instr: &Instruction,
+    cpu: &mut CpuState,
+    host: &mut Semihosting,
+) -> Result<(), Exception> {
+    let comment = comment_bits![instr.code];
+    if comment == ARM_SEMIHOSTING_SWI {
+        host.call(cpu);
+        return Ok(());
+    }
+    Err(Exception::SoftwareInterrupt)
+}
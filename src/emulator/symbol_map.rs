@@ -0,0 +1,101 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::elf;
+
+/// The labels of a program, read from the `.sym` file written by `assemble`,
+/// used to show addresses as `label+offset`
+#[derive(Debug, Default)]
+pub struct SymbolMap {
+    /// Labels sorted by address
+    symbols: Vec<(u32, String)>,
+}
+
+impl SymbolMap {
+    /// Reads a `.sym` file made of `<hex-address> <label>` lines
+    ///
+    /// Propagates std::io::Error if the file can't be read or is malformed
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|message| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), message),
+            )
+        })
+    }
+
+    /// Reads the symbol table of an ELF executable,
+    /// or loads the `.sym` file next to a raw binary at `binary_path` if there is one
+    pub fn find_for(binary_path: &str) -> Result<Self, Error> {
+        let bytes = fs::read(binary_path)?;
+        if elf::is_elf(&bytes) {
+            let executable = elf::read_executable(&bytes).map_err(|message| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}: {}", binary_path, message),
+                )
+            })?;
+            return Ok(Self::from_symbols(executable.symbols));
+        }
+        let path = Path::new(binary_path).with_extension("sym");
+        if path.is_file() {
+            Self::load(&path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Builds the map from the symbols of an ELF executable
+    pub fn from_symbols(symbols: Vec<elf::Symbol>) -> Self {
+        let mut symbols: Vec<(u32, String)> = symbols
+            .into_iter()
+            .map(|symbol| (symbol.value, symbol.name))
+            .collect();
+        symbols.sort_by_key(|(address, _)| *address);
+        Self { symbols }
+    }
+
+    /// Parses the contents of a `.sym` file
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = Vec::new();
+        for (ind, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let entry = line
+                .split_once(char::is_whitespace)
+                .and_then(|(address, label)| {
+                    let address = u32::from_str_radix(address.trim_start_matches("0x"), 16).ok()?;
+                    Some((address, label.trim().to_string()))
+                });
+            match entry {
+                Some(entry) => symbols.push(entry),
+                None => return Err(format!("line {}: expected `<address> <label>`", ind + 1)),
+            }
+        }
+        symbols.sort_by_key(|(address, _)| *address);
+        Ok(Self { symbols })
+    }
+
+    /// Describes an address relative to the closest label at or before it,
+    /// such as `loop` or `loop+0x8`. Returns None if there's no such label
+    pub fn describe(&self, address: u32) -> Option<String> {
+        let after = self.symbols.partition_point(|(start, _)| *start <= address);
+        let (start, label) = self.symbols.get(after.checked_sub(1)?)?;
+        match address - start {
+            0 => Some(label.clone()),
+            offset => Some(format!("{}+0x{:x}", label, offset)),
+        }
+    }
+
+    /// Describes an address as `0x%08x`, followed by `<label+offset>` when known
+    pub fn annotate(&self, address: u32) -> String {
+        match self.describe(address) {
+            Some(description) => format!("0x{:0>8x} <{}>", address, description),
+            None => format!("0x{:0>8x}", address),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::Exception;
+use util::*;
+
+macro_rules! base_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 16, 19] as usize
+    };
+}
+
+macro_rules! dest_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 12, 15] as usize
+    };
+}
+
+/// The register SWP and STREX store
+macro_rules! source_reg_bits {
+    ($bits:expr) => {
+        mask![$bits, 0, 3] as usize
+    };
+}
+
+/// Executes SWP, SWPB, LDREX or STREX, returning whether it wrote the PC.
+/// The exclusive transfers only exist from ARMv6 on, and only for words
+pub fn execute_synchronization_instr(
+    instr: &Instruction,
+    cpu: &mut CpuState,
+) -> Result<bool, Exception> {
+    let bits = instr.code;
+    let exclusives = cpu.architecture >= Architecture::ARMv6;
+    match (mask![bits, 23], mask![bits, 20, 22]) {
+        (false, 0b000) => swap(bits, false, cpu),
+        (false, 0b100) => swap(bits, true, cpu),
+        (true, 0b001) if exclusives => load_exclusive(bits, cpu),
+        (true, 0b000) if exclusives => store_exclusive(bits, cpu),
+        _ => Err(Exception::UndefinedInstruction),
+    }
+}
+
+/// SWP and SWPB: loads the word or byte at Rn into Rd and stores Rm in its place.
+/// Rm is read before the load, so it can be the same register as Rd
+fn swap(bits: u32, byte: bool, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let address = cpu.registers[base_reg_bits![bits]];
+    let value = cpu.registers[source_reg_bits![bits]];
+    let loaded = if byte {
+        let loaded = cpu.read_byte(address)?;
+        cpu.write_byte(address, value)?;
+        loaded
+    } else {
+        let loaded = cpu.read_word(address)?;
+        cpu.write_word(address, value)?;
+        loaded
+    };
+    Ok(cpu.write_register(dest_reg_bits![bits], loaded))
+}
+
+/// LDREX: loads the word at Rn and tags its address in the local monitor
+fn load_exclusive(bits: u32, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let address = cpu.registers[base_reg_bits![bits]];
+    let loaded = cpu.read_word(address)?;
+    cpu.exclusive_address = Some(address);
+    Ok(cpu.write_register(dest_reg_bits![bits], loaded))
+}
+
+/// STREX: stores Rm at Rn only if the local monitor still has the address tagged,
+/// writing 0 to Rd if it did and 1 if it didn't.
+/// Either way the monitor goes back to the open access state
+fn store_exclusive(bits: u32, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let address = cpu.registers[base_reg_bits![bits]];
+    let status = if cpu.exclusive_address == Some(address) {
+        cpu.write_word(address, cpu.registers[source_reg_bits![bits]])?;
+        0
+    } else {
+        1
+    };
+    cpu.exclusive_address = None;
+    Ok(cpu.write_register(dest_reg_bits![bits], status))
+}
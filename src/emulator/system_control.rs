@@ -0,0 +1,128 @@
+//! CP15, the system control coprocessor. There is no MMU or cache,
+//! so of the control register bits only V, which selects the high vectors, has an effect.
+//! M, A, C and I read back as they were written, the other bits keep their reset values
+
+use crate::emulator::coprocessor::{Coprocessor, CoprocessorInstr};
+use crate::emulator::em_utilities::Architecture;
+use crate::emulator::exceptions::Exception;
+
+/// The slot CP15 is attached to
+pub const SYSTEM_CONTROL: usize = 15;
+
+/// The V bit of the control register, set for vectors at 0xffff0000
+pub const HIGH_VECTORS_BIT: u32 = 1 << 13;
+/// The M, A, C and I bits of the control register: MMU, alignment checking,
+/// data and instruction cache enables
+pub const MMU_BIT: u32 = 1 << 0;
+pub const ALIGNMENT_BIT: u32 = 1 << 1;
+pub const DATA_CACHE_BIT: u32 = 1 << 2;
+pub const INSTRUCTION_CACHE_BIT: u32 = 1 << 12;
+
+/// The reset value of the control register, with the bits that should be one set
+const CONTROL_RESET: u32 = 0x0005_0078;
+/// The bits of the control register that programs can write
+const CONTROL_WRITABLE: u32 =
+    MMU_BIT | ALIGNMENT_BIT | DATA_CACHE_BIT | INSTRUCTION_CACHE_BIT | HIGH_VECTORS_BIT;
+
+/// The registers of CP15
+#[derive(Debug, Clone, PartialEq)]
+pub struct SystemControl {
+    main_id: u32,
+    architecture: Architecture,
+    control: u32,
+    auxiliary_control: u32,
+    coprocessor_access: u32,
+    translation_table_bases: [u32; 2],
+    translation_table_control: u32,
+    domain_access: u32,
+}
+
+impl SystemControl {
+    /// CP15 as it is out of reset, identifying as a core of the given architecture.
+    /// `high_vectors` is the reset value of the V bit, which cores take from a pin
+    pub fn new(architecture: Architecture, high_vectors: bool) -> Self {
+        // An ARM920T, ARM926EJ-S or ARM1176JZF-S
+        let main_id = match architecture {
+            Architecture::ARMv4 => 0x4112_9200,
+            Architecture::ARMv5 => 0x4106_9265,
+            Architecture::ARMv6 => 0x410f_b767,
+        };
+        let control = if high_vectors {
+            CONTROL_RESET | HIGH_VECTORS_BIT
+        } else {
+            CONTROL_RESET
+        };
+        Self {
+            main_id,
+            architecture,
+            control,
+            auxiliary_control: 0,
+            coprocessor_access: 0,
+            translation_table_bases: [0; 2],
+            translation_table_control: 0,
+            domain_access: 0,
+        }
+    }
+
+    /// The register named by CRn, CRm and opcode2, None if it isn't implemented.
+    /// The ARMv6 registers only exist when emulating ARMv6
+    fn register(&mut self, instr: &CoprocessorInstr) -> Option<&mut u32> {
+        let armv6 = self.architecture >= Architecture::ARMv6;
+        match (instr.crn, instr.crm, instr.opcode2) {
+            (1, 0, 0) => Some(&mut self.control),
+            (1, 0, 1) if armv6 => Some(&mut self.auxiliary_control),
+            (1, 0, 2) if armv6 => Some(&mut self.coprocessor_access),
+            (2, 0, 0) => Some(&mut self.translation_table_bases[0]),
+            (2, 0, 1) if armv6 => Some(&mut self.translation_table_bases[1]),
+            (2, 0, 2) if armv6 => Some(&mut self.translation_table_control),
+            (3, 0, 0) => Some(&mut self.domain_access),
+            _ => None,
+        }
+    }
+}
+
+impl Default for SystemControl {
+    fn default() -> Self {
+        Self::new(Architecture::default(), false)
+    }
+}
+
+impl Coprocessor for SystemControl {
+    fn write_register(&mut self, instr: &CoprocessorInstr, value: u32) -> Result<(), Exception> {
+        if instr.opcode1 != 0 {
+            return Err(Exception::UndefinedInstruction);
+        }
+        // Cache and TLB maintenance, with no caches or TLBs, does nothing
+        if instr.crn == 7 || instr.crn == 8 {
+            return Ok(());
+        }
+        let control = (instr.crn, instr.crm, instr.opcode2) == (1, 0, 0);
+        let register = self
+            .register(instr)
+            .ok_or(Exception::UndefinedInstruction)?;
+        *register = if control {
+            value & CONTROL_WRITABLE | CONTROL_RESET
+        } else {
+            value
+        };
+        Ok(())
+    }
+
+    fn read_register(&mut self, instr: &CoprocessorInstr) -> Result<u32, Exception> {
+        match (instr.opcode1, instr.crn, instr.crm, instr.opcode2) {
+            // The ID registers that aren't implemented read as the main ID register
+            (0, 0, _, _) => Ok(self.main_id),
+            // Testing whether the data cache is clean, which it always is: sets Z
+            (0, 7, 10, 3) | (0, 7, 14, 3) => Ok(1 << 30),
+            (0, _, _, _) => self
+                .register(instr)
+                .map(|register| *register)
+                .ok_or(Exception::UndefinedInstruction),
+            _ => Err(Exception::UndefinedInstruction),
+        }
+    }
+
+    fn high_vectors(&self) -> bool {
+        self.control & HIGH_VECTORS_BIT != 0
+    }
+}
@@ -0,0 +1,529 @@
+//! Thumb state: the 16-bit instructions of the ARMv6 Thumb instruction set.
+//! They reuse the ALU and barrel shifter of the ARM instructions,
+//! the PC reading 4 bytes ahead of the executing instruction
+
+use num_traits::FromPrimitive;
+
+use crate::emulator::barrel_shifter::{shift_by_immediate, shift_by_register, ShiftOp};
+use crate::emulator::exceptions::Exception;
+use crate::emulator::semihosting::{Semihosting, THUMB_SEMIHOSTING_SWI};
+use crate::emulator::{alu, em_utilities as util};
+use util::*;
+
+/// The stack pointer, which Thumb instructions address implicitly
+const SP: usize = 13;
+
+/// The mask and value of the top bits of each instruction format
+const FORMATS: [(u32, u32, Format); 19] = [
+    (0xf800, 0x1800, Format::AddSubtract),
+    (0xe000, 0x0000, Format::MoveShifted),
+    (0xe000, 0x2000, Format::Immediate),
+    (0xfc00, 0x4000, Format::Alu),
+    (0xfc00, 0x4400, Format::HighRegister),
+    (0xf800, 0x4800, Format::PcRelativeLoad),
+    (0xf000, 0x5000, Format::RegisterOffset),
+    (0xe000, 0x6000, Format::ImmediateOffset),
+    (0xf000, 0x8000, Format::HalfwordOffset),
+    (0xf000, 0x9000, Format::SpRelative),
+    (0xf000, 0xa000, Format::LoadAddress),
+    (0xf000, 0xb000, Format::Miscellaneous),
+    (0xf000, 0xc000, Format::Multiple),
+    (0xff00, 0xde00, Format::Undefined),
+    (0xff00, 0xdf00, Format::SoftwareInterrupt),
+    (0xf000, 0xd000, Format::ConditionalBranch),
+    (0xf800, 0xe000, Format::Branch),
+    (0xf800, 0xf000, Format::LinkPrefix),
+    (0xe800, 0xe800, Format::LinkSuffix),
+];
+
+/// The kinds of Thumb instructions, named as in the ARM7TDMI data sheet
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    MoveShifted,
+    AddSubtract,
+    Immediate,
+    Alu,
+    HighRegister,
+    PcRelativeLoad,
+    RegisterOffset,
+    ImmediateOffset,
+    HalfwordOffset,
+    SpRelative,
+    LoadAddress,
+    Miscellaneous,
+    Multiple,
+    ConditionalBranch,
+    Undefined,
+    SoftwareInterrupt,
+    Branch,
+    LinkPrefix,
+    LinkSuffix,
+}
+
+/// The low register in bits 0-2, usually the destination
+macro_rules! rd_bits {
+    ($bits:expr) => {
+        mask![$bits, 0, 2] as usize
+    };
+}
+
+/// The low register in bits 3-5, usually the source or base
+macro_rules! rs_bits {
+    ($bits:expr) => {
+        mask![$bits, 3, 5] as usize
+    };
+}
+
+/// The low register in bits 6-8, usually the offset or second operand
+macro_rules! rn_bits {
+    ($bits:expr) => {
+        mask![$bits, 6, 8] as usize
+    };
+}
+
+/// The low register in bits 8-10, used with an 8 bit immediate
+macro_rules! high_rd_bits {
+    ($bits:expr) => {
+        mask![$bits, 8, 10] as usize
+    };
+}
+
+macro_rules! offset5_bits {
+    ($bits:expr) => {
+        mask![$bits, 6, 10]
+    };
+}
+
+macro_rules! offset8_bits {
+    ($bits:expr) => {
+        mask![$bits, 0, 7]
+    };
+}
+
+macro_rules! offset11_bits {
+    ($bits:expr) => {
+        mask![$bits, 0, 10]
+    };
+}
+
+macro_rules! load_bit {
+    ($bits:expr) => {
+        mask![$bits, 11]
+    };
+}
+
+/// Sign extends the low `width` bits of the value
+fn sign_extend(value: u32, width: u32) -> u32 {
+    let shift = 32 - width;
+    ((value << shift) as i32 >> shift) as u32
+}
+
+/// Finds the format of a Thumb instruction
+fn format(bits: u32) -> Format {
+    FORMATS
+        .iter()
+        .find(|(mask, value, _)| bits & mask == *value)
+        .map(|(_, _, format)| *format)
+        .unwrap_or(Format::Undefined)
+}
+
+/// Executes a Thumb instruction, returning whether it wrote the PC
+/// or the exception it raised
+pub fn execute_thumb_instr(
+    instr: &Instruction,
+    cpu: &mut CpuState,
+    host: &mut Semihosting,
+) -> Result<bool, Exception> {
+    let bits = instr.code;
+    match format(bits) {
+        Format::MoveShifted => Ok(execute_move_shifted(bits, cpu)),
+        Format::AddSubtract => Ok(execute_add_subtract(bits, cpu)),
+        Format::Immediate => Ok(execute_immediate(bits, cpu)),
+        Format::Alu => Ok(execute_alu(bits, cpu)),
+        Format::HighRegister => execute_high_register(bits, cpu),
+        Format::PcRelativeLoad => {
+            let address = (cpu.pc() & !3).wrapping_add(offset8_bits![bits] << 2);
+            cpu.registers[high_rd_bits![bits]] = cpu.read_word(address)?;
+            Ok(false)
+        }
+        Format::RegisterOffset => execute_register_offset(bits, cpu),
+        Format::ImmediateOffset => execute_immediate_offset(bits, cpu),
+        Format::HalfwordOffset => {
+            let rd = rd_bits![bits];
+            let address = cpu.registers[rs_bits![bits]].wrapping_add(offset5_bits![bits] << 1);
+            if load_bit![bits] {
+                cpu.registers[rd] = cpu.read_halfword(address)?;
+            } else {
+                cpu.write_halfword(address, cpu.registers[rd])?;
+            }
+            Ok(false)
+        }
+        Format::SpRelative => {
+            let rd = high_rd_bits![bits];
+            let address = cpu.registers[SP].wrapping_add(offset8_bits![bits] << 2);
+            if load_bit![bits] {
+                cpu.registers[rd] = cpu.read_word(address)?;
+            } else {
+                cpu.write_word(address, cpu.registers[rd])?;
+            }
+            Ok(false)
+        }
+        Format::LoadAddress => {
+            // Bit 11 selects SP as the base, otherwise it is the word aligned PC
+            let base = if mask![bits, 11] {
+                cpu.registers[SP]
+            } else {
+                cpu.pc() & !3
+            };
+            cpu.registers[high_rd_bits![bits]] = base.wrapping_add(offset8_bits![bits] << 2);
+            Ok(false)
+        }
+        Format::Miscellaneous => execute_miscellaneous(bits, cpu),
+        Format::Multiple => execute_multiple(bits, cpu),
+        Format::ConditionalBranch => {
+            // Bits 8-11 are the condition, 1110 and 1111 are other formats
+            let flag_code = FromPrimitive::from_u32(mask![bits, 8, 11]).unwrap();
+            if !cpu.check_CPSR_cond(flag_code) {
+                return Ok(false);
+            }
+            let offset = sign_extend(offset8_bits![bits], 8) << 1;
+            cpu.registers[PC] = cpu.pc().wrapping_add(offset);
+            Ok(true)
+        }
+        Format::Undefined => Err(Exception::UndefinedInstruction),
+        Format::SoftwareInterrupt => {
+            if offset8_bits![bits] == THUMB_SEMIHOSTING_SWI {
+                host.call(cpu);
+                return Ok(false);
+            }
+            Err(Exception::SoftwareInterrupt)
+        }
+        Format::Branch => {
+            let offset = sign_extend(offset11_bits![bits], 11) << 1;
+            cpu.registers[PC] = cpu.pc().wrapping_add(offset);
+            Ok(true)
+        }
+        Format::LinkPrefix => {
+            // The first half of BL and BLX keeps the high part of the offset in LR
+            let offset = sign_extend(offset11_bits![bits], 11) << 12;
+            cpu.registers[LR] = cpu.pc().wrapping_add(offset);
+            Ok(false)
+        }
+        Format::LinkSuffix => execute_link_suffix(bits, cpu),
+    }
+}
+
+/// Executes LSL, LSR or ASR by an immediate, which always set the flags
+fn execute_move_shifted(bits: u32, cpu: &mut CpuState) -> bool {
+    let op = FromPrimitive::from_u32(mask![bits, 11, 12]).unwrap();
+    let operand = cpu.registers[rs_bits![bits]];
+    let carry = cpu.get_flag(Flag::C);
+    let (value, carry) = shift_by_immediate(operand, offset5_bits![bits], op, carry);
+    cpu.registers[rd_bits![bits]] = value;
+    alu::logical(value, carry).set_flags(cpu);
+    false
+}
+
+/// Executes ADD or SUB of a register or a 3 bit immediate
+fn execute_add_subtract(bits: u32, cpu: &mut CpuState) -> bool {
+    let operand1 = cpu.registers[rs_bits![bits]];
+    // Bit 10 makes bits 6-8 an immediate instead of a register
+    let operand2 = if mask![bits, 10] {
+        mask![bits, 6, 8]
+    } else {
+        cpu.registers[rn_bits![bits]]
+    };
+    let result = if mask![bits, 9] {
+        alu::subtract(operand1, operand2, true)
+    } else {
+        alu::add(operand1, operand2, false)
+    };
+    cpu.registers[rd_bits![bits]] = result.value;
+    result.set_flags(cpu);
+    false
+}
+
+/// Executes MOV, CMP, ADD or SUB with an 8 bit immediate
+fn execute_immediate(bits: u32, cpu: &mut CpuState) -> bool {
+    let rd = high_rd_bits![bits];
+    let (operand1, operand2) = (cpu.registers[rd], offset8_bits![bits]);
+    let result = match mask![bits, 11, 12] {
+        0 => alu::logical(operand2, None),
+        1 | 3 => alu::subtract(operand1, operand2, true),
+        _ => alu::add(operand1, operand2, false),
+    };
+    // CMP only sets the flags
+    if mask![bits, 11, 12] != 1 {
+        cpu.registers[rd] = result.value;
+    }
+    result.set_flags(cpu);
+    false
+}
+
+/// Executes one of the 16 two register ALU operations, which all set the flags
+fn execute_alu(bits: u32, cpu: &mut CpuState) -> bool {
+    let rd = rd_bits![bits];
+    let (operand1, operand2) = (cpu.registers[rd], cpu.registers[rs_bits![bits]]);
+    let carry = cpu.get_flag(Flag::C);
+    let shift = |op| {
+        let (value, carry) = shift_by_register(operand1, operand2 & 0xff, op);
+        alu::logical(value, carry)
+    };
+    let opcode = mask![bits, 6, 9];
+    let result = match opcode {
+        0x0 | 0x8 => alu::logical(operand1 & operand2, None),
+        0x1 => alu::logical(operand1 ^ operand2, None),
+        0x2 => shift(ShiftOp::LSL),
+        0x3 => shift(ShiftOp::LSR),
+        0x4 => shift(ShiftOp::ASR),
+        0x5 => alu::add(operand1, operand2, carry),
+        0x6 => alu::subtract(operand1, operand2, carry),
+        0x7 => shift(ShiftOp::ROR),
+        0x9 => alu::reverse_subtract(operand2, 0, true),
+        0xa => alu::subtract(operand1, operand2, true),
+        0xb => alu::add(operand1, operand2, false),
+        0xc => alu::logical(operand1 | operand2, None),
+        0xd => alu::multiply(operand1.wrapping_mul(operand2)),
+        0xe => alu::logical(operand1 & !operand2, None),
+        _ => alu::logical(!operand2, None),
+    };
+    // TST, CMP and CMN only set the flags
+    if !matches!(opcode, 0x8 | 0xa | 0xb) {
+        cpu.registers[rd] = result.value;
+    }
+    result.set_flags(cpu);
+    false
+}
+
+/// Executes ADD, CMP or MOV with registers from r0-r15, or BX and the ARMv5 BLX.
+/// Only CMP sets the flags, returns whether the PC was written
+fn execute_high_register(bits: u32, cpu: &mut CpuState) -> Result<bool, Exception> {
+    // Bit 7 extends the destination to r8-r15 and bit 6 the source
+    let rd = (mask![bits, 7] as usize) << 3 | rd_bits![bits];
+    let rs = mask![bits, 3, 6] as usize;
+    let (operand1, operand2) = (cpu.registers[rd], cpu.registers[rs]);
+    let value = match mask![bits, 8, 9] {
+        0 => operand1.wrapping_add(operand2),
+        1 => {
+            alu::subtract(operand1, operand2, true).set_flags(cpu);
+            return Ok(false);
+        }
+        2 => operand2,
+        _ => {
+            // Bit 7 makes BX a BLX, which returns to the next instruction in Thumb state
+            if mask![bits, 7] {
+                if cpu.architecture < Architecture::ARMv5 {
+                    return Err(Exception::UndefinedInstruction);
+                }
+                cpu.registers[LR] = (cpu.pc() - 2) | 1;
+            }
+            cpu.branch_exchange(operand2);
+            return Ok(true);
+        }
+    };
+    if rd == PC {
+        // Writing the PC stays in Thumb state
+        cpu.registers[PC] = value & !1;
+        return Ok(true);
+    }
+    cpu.registers[rd] = value;
+    Ok(false)
+}
+
+/// Executes a load or store with a register offset:
+/// STR, STRH, STRB, LDRSB, LDR, LDRH, LDRB or LDRSH
+fn execute_register_offset(bits: u32, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let rd = rd_bits![bits];
+    let address = cpu.registers[rs_bits![bits]].wrapping_add(cpu.registers[rn_bits![bits]]);
+    let value = cpu.registers[rd];
+    match mask![bits, 9, 11] {
+        0 => cpu.write_word(address, value)?,
+        1 => cpu.write_halfword(address, value)?,
+        2 => cpu.write_byte(address, value)?,
+        3 => cpu.registers[rd] = cpu.read_byte(address)? as i8 as u32,
+        4 => cpu.registers[rd] = cpu.read_word(address)?,
+        5 => cpu.registers[rd] = cpu.read_halfword(address)?,
+        6 => cpu.registers[rd] = cpu.read_byte(address)?,
+        _ => cpu.registers[rd] = cpu.read_halfword(address)? as i16 as u32,
+    }
+    Ok(false)
+}
+
+/// Executes a load or store of a word or a byte with a 5 bit immediate offset,
+/// which counts words for word transfers
+fn execute_immediate_offset(bits: u32, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let rd = rd_bits![bits];
+    let byte = mask![bits, 12];
+    let offset = if byte {
+        offset5_bits![bits]
+    } else {
+        offset5_bits![bits] << 2
+    };
+    let address = cpu.registers[rs_bits![bits]].wrapping_add(offset);
+    match (load_bit![bits], byte) {
+        (true, true) => cpu.registers[rd] = cpu.read_byte(address)?,
+        (true, false) => cpu.registers[rd] = cpu.read_word(address)?,
+        (false, true) => cpu.write_byte(address, cpu.registers[rd])?,
+        (false, false) => cpu.write_word(address, cpu.registers[rd])?,
+    }
+    Ok(false)
+}
+
+/// Executes the instructions of the 1011 space: adjusting SP, PUSH and POP,
+/// the ARMv6 extends and byte reverses, CPS, SETEND and the ARMv5 BKPT
+fn execute_miscellaneous(bits: u32, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let (rd, operand) = (rd_bits![bits], cpu.registers[rs_bits![bits]]);
+    let introduced = match mask![bits, 8, 11] {
+        0b0010 | 0b0110 | 0b1010 => Architecture::ARMv6,
+        0b1110 => Architecture::ARMv5,
+        _ => Architecture::ARMv4,
+    };
+    if cpu.architecture < introduced {
+        return Err(Exception::UndefinedInstruction);
+    }
+    match mask![bits, 8, 11] {
+        0b0000 => {
+            // Bit 7 subtracts the 7 bit word offset
+            let offset = mask![bits, 0, 6] << 2;
+            cpu.registers[SP] = if mask![bits, 7] {
+                cpu.registers[SP].wrapping_sub(offset)
+            } else {
+                cpu.registers[SP].wrapping_add(offset)
+            };
+        }
+        0b0010 => {
+            cpu.registers[rd] = match mask![bits, 6, 7] {
+                0 => operand as i16 as u32,
+                1 => operand as i8 as u32,
+                2 => operand & 0xffff,
+                _ => operand & 0xff,
+            };
+        }
+        0b0100 | 0b0101 => return push(bits, cpu),
+        0b1100 | 0b1101 => return pop(bits, cpu),
+        0b0110 if mask![bits, 5, 7] == 0b011 => {
+            // CPS does nothing in user mode
+            if cpu.mode().is_privileged() {
+                // The A, I and F bits are 8, 7 and 6 in the CPSR
+                let masks = mask![bits, 0, 2] << 6;
+                let cpsr = if mask![bits, 4] {
+                    cpu.cpsr() | masks
+                } else {
+                    cpu.cpsr() & !masks
+                };
+                cpu.set_cpsr(cpsr);
+            }
+        }
+        // SETEND LE is all the emulator does, as memory is little endian
+        0b0110 if bits & 0xfff7 == 0xb650 => {
+            if mask![bits, 3] {
+                return Err(Exception::UndefinedInstruction);
+            }
+        }
+        0b1010 => {
+            cpu.registers[rd] = match mask![bits, 6, 7] {
+                0 => operand.swap_bytes(),
+                1 => (operand & 0x00ff_00ff) << 8 | (operand >> 8) & 0x00ff_00ff,
+                3 => (operand as u16).swap_bytes() as i16 as u32,
+                _ => return Err(Exception::UndefinedInstruction),
+            };
+        }
+        // BKPT, with no debugger attached
+        0b1110 => return Err(Exception::PrefetchAbort),
+        _ => return Err(Exception::UndefinedInstruction),
+    }
+    Ok(false)
+}
+
+/// The registers in the 8 bit list, and LR or PC if bit 8 is set
+fn register_list(bits: u32, extra: usize) -> impl Iterator<Item = usize> {
+    let list = offset8_bits![bits] as usize | (mask![bits, 8] as usize) << extra;
+    (0..16).filter(move |reg| list & (1 << reg) != 0)
+}
+
+/// Whether every word of the transfer starting at `lowest` is in memory
+fn in_memory(cpu: &CpuState, lowest: u32, count: usize) -> bool {
+    (0..count as u32).all(|i| {
+        cpu.physical_address(lowest.wrapping_add(4 * i), 4)
+            .is_some()
+    })
+}
+
+/// Executes PUSH, a full descending store of the list and optionally LR
+fn push(bits: u32, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let count = register_list(bits, LR).count();
+    let lowest = cpu.registers[SP].wrapping_sub(4 * count as u32);
+    if !in_memory(cpu, lowest, count) {
+        return Err(Exception::DataAbort);
+    }
+    for (i, reg) in register_list(bits, LR).enumerate() {
+        cpu.write_word(lowest.wrapping_add(4 * i as u32), cpu.registers[reg])?;
+    }
+    cpu.registers[SP] = lowest;
+    Ok(false)
+}
+
+/// Executes POP, optionally into the PC, which interworks as BX does from ARMv5 on
+fn pop(bits: u32, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let count = register_list(bits, PC).count();
+    let lowest = cpu.registers[SP];
+    if !in_memory(cpu, lowest, count) {
+        return Err(Exception::DataAbort);
+    }
+    cpu.registers[SP] = lowest.wrapping_add(4 * count as u32);
+    let mut loads_pc = false;
+    for (i, reg) in register_list(bits, PC).enumerate() {
+        let value = cpu.read_word(lowest.wrapping_add(4 * i as u32))?;
+        if reg == PC {
+            cpu.load_pc(value);
+            loads_pc = true;
+        } else {
+            cpu.registers[reg] = value;
+        }
+    }
+    Ok(loads_pc)
+}
+
+/// Executes LDMIA or STMIA with write back.
+/// A base in the list of an LDMIA is loaded instead of written back
+fn execute_multiple(bits: u32, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let rb = high_rd_bits![bits];
+    let list = offset8_bits![bits];
+    let count = list.count_ones();
+    let base = cpu.registers[rb];
+    if !in_memory(cpu, base, count as usize) {
+        return Err(Exception::DataAbort);
+    }
+    let registers = (0..8).filter(|reg| list & (1 << reg) != 0);
+    if load_bit![bits] {
+        cpu.registers[rb] = base.wrapping_add(4 * count);
+        for (i, reg) in registers.enumerate() {
+            cpu.registers[reg] = cpu.read_word(base.wrapping_add(4 * i as u32))?;
+        }
+    } else {
+        for (i, reg) in registers.enumerate() {
+            cpu.write_word(base.wrapping_add(4 * i as u32), cpu.registers[reg])?;
+        }
+        cpu.registers[rb] = base.wrapping_add(4 * count);
+    }
+    Ok(false)
+}
+
+/// Executes the second half of BL, or of BLX which switches to ARM state from ARMv5 on.
+/// LR holds the target without the low part of the offset,
+/// it is replaced by the address of the next instruction
+fn execute_link_suffix(bits: u32, cpu: &mut CpuState) -> Result<bool, Exception> {
+    let exchange = !mask![bits, 12];
+    if exchange && (mask![bits, 0] || cpu.architecture < Architecture::ARMv5) {
+        return Err(Exception::UndefinedInstruction);
+    }
+    let target = cpu.registers[LR].wrapping_add(offset11_bits![bits] << 1);
+    cpu.registers[LR] = (cpu.pc() - 2) | 1;
+    if exchange {
+        cpu.set_thumb_state(false);
+        cpu.registers[PC] = target & !3;
+    } else {
+        cpu.registers[PC] = target;
+    }
+    Ok(true)
+}
@@ -0,0 +1,47 @@
+use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::Exception;
+use util::*;
+
+/// Returns whether the instruction is a PLD, a hint to preload a cache line
+fn is_preload_instr(bits: u32) -> bool {
+    // Bits 26-27 are 01, bit 24 is 1 and bits 20-22 are 101
+    mask![bits, 26, 27] == 1 && mask![bits, 24] && mask![bits, 20, 22] == 5
+}
+
+/// Returns whether the instruction is a BLX with an immediate offset
+fn is_branch_link_exchange_instr(bits: u32) -> bool {
+    // Bits 25-27 are 101
+    mask![bits, 25, 27] == 5
+}
+
+/// Executes BLX <offset>, which always calls Thumb code.
+/// The H bit (24) adds a halfword to the word offset
+fn execute_branch_link_exchange_instr(instr: &Instruction, cpu: &mut CpuState, pipe: &mut Pipe) {
+    // Sign extend the 24-bit word offset
+    let offset = ((mask![instr.code, 0, 23] << 8) as i32 >> 6) as u32;
+    let halfword = (mask![instr.code, 24] as u32) << 1;
+    let target = cpu.pc().wrapping_add(offset).wrapping_add(halfword);
+    cpu.registers[LR] = cpu.pc() - 4;
+    cpu.branch_exchange(target | 1);
+    pipe.flush(cpu);
+}
+
+/// Executes an instruction from the unconditional space of ARMv5 and later,
+/// where the condition bits are 1111, returning whether it flushed the pipe.
+/// The ones the emulator doesn't support are undefined
+pub fn execute_unconditional_instr(
+    instr: &Instruction,
+    cpu: &mut CpuState,
+    pipe: &mut Pipe,
+) -> Result<bool, Exception> {
+    // There is no cache, so preloading does nothing
+    if is_preload_instr(instr.code) {
+        pipe.clear_executing();
+        return Ok(false);
+    }
+    if is_branch_link_exchange_instr(instr.code) {
+        execute_branch_link_exchange_instr(instr, cpu, pipe);
+        return Ok(true);
+    }
+    Err(Exception::UndefinedInstruction)
+}
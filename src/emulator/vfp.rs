@@ -0,0 +1,455 @@
+//! VFPv2, the floating point coprocessor of the ARM1176JZF-S. It answers to CP10
+//! for single precision and CP11 for double precision, which share its registers
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::emulator::coprocessor::{Coprocessor, CoprocessorInstr, Coprocessors};
+use crate::emulator::em_utilities as util;
+use crate::emulator::exceptions::Exception;
+use crate::emulator::float::{self, Environment, Format, Rounding};
+use util::*;
+
+/// The slots VFP is attached to
+pub const SINGLE_PRECISION: usize = 10;
+pub const DOUBLE_PRECISION: usize = 11;
+
+/// The FPSID of the VFP11 in the ARM1176JZF-S
+const FPSID: u32 = 0x4101_20b5;
+/// The EN bit of FPEXC. Until it is set, only FPSID and FPEXC can be accessed
+pub const ENABLE_BIT: u32 = 1 << 30;
+/// The FPSCR bits that can be written. Exceptions can't be trapped,
+/// so the trap enable bits read as zero
+const FPSCR_MASK: u32 = 0xf3f7_009f;
+
+/// The system registers, as numbered in FMRX and FMXR
+const FPSID_REGISTER: u32 = 0b0000;
+const FPSCR_REGISTER: u32 = 0b0001;
+const FPEXC_REGISTER: u32 = 0b1000;
+
+/// The number of registers in a bank of a short vector, in either precision
+const SINGLE_BANK: usize = 8;
+const DOUBLE_BANK: usize = 4;
+
+/// The registers of VFP: 32 single precision ones, which also make up
+/// the 16 double precision ones, and the system registers
+#[derive(Debug, Default)]
+pub struct VfpRegisters {
+    pub registers: [u32; 32],
+    pub fpscr: u32,
+    pub fpexc: u32,
+}
+
+impl VfpRegisters {
+    fn check_enabled(&self) -> Result<(), Exception> {
+        if self.fpexc & ENABLE_BIT != 0 {
+            Ok(())
+        } else {
+            Err(Exception::UndefinedInstruction)
+        }
+    }
+
+    /// Reads the single or double precision register `index`
+    pub fn get(&self, double: bool, index: usize) -> u64 {
+        if double {
+            self.registers[2 * index] as u64 | (self.registers[2 * index + 1] as u64) << 32
+        } else {
+            self.registers[index] as u64
+        }
+    }
+
+    pub fn set(&mut self, double: bool, index: usize, value: u64) {
+        if double {
+            self.registers[2 * index] = value as u32;
+            self.registers[2 * index + 1] = (value >> 32) as u32;
+        } else {
+            self.registers[index] = value as u32;
+        }
+    }
+
+    /// The rounding mode, flush to zero and default NaN bits of the FPSCR
+    fn environment(&self) -> Environment {
+        Environment {
+            rounding: Rounding::from_bits(self.fpscr >> 22),
+            flush_to_zero: mask![self.fpscr, 24],
+            default_nan: mask![self.fpscr, 25],
+            flags: 0,
+        }
+    }
+
+    /// The length and stride of short vectors, from the LEN and STRIDE fields of the FPSCR
+    fn vector_shape(&self) -> (usize, usize) {
+        let length = mask![self.fpscr, 16, 18] as usize + 1;
+        let stride = if mask![self.fpscr, 20, 21] == 0b11 {
+            2
+        } else {
+            1
+        };
+        (length, stride)
+    }
+
+    /// Executes an arithmetic instruction or one of its extensions
+    fn data_operation(&mut self, code: u32, double: bool) -> Result<(), Exception> {
+        let opcode = (mask![code, 23] as u32) << 3
+            | mask![code, 20, 21] << 1
+            | mask![code, 6] as u32;
+        let mut env = self.environment();
+        if opcode == 0b1111 {
+            let extension = mask![code, 16, 19] << 1 | mask![code, 7] as u32;
+            self.extension(code, double, extension, &mut env)?;
+        } else {
+            let (d, n, m) = (
+                register(code, double, 12, 22)?,
+                register(code, double, 16, 7)?,
+                register(code, double, 0, 5)?,
+            );
+            let operation = arithmetic(opcode).ok_or(Exception::UndefinedInstruction)?;
+            let format = precision(double);
+            for (d, n, m) in self.vector(double, d, n, m) {
+                let result = operation(
+                    format,
+                    self.get(double, d),
+                    self.get(double, n),
+                    self.get(double, m),
+                    &mut env,
+                );
+                self.set(double, d, result);
+            }
+        }
+        self.fpscr |= env.flags;
+        Ok(())
+    }
+
+    /// The registers each element of a short vector operation uses. Operations
+    /// whose destination is in the first bank are scalar, and so is the operand Fm
+    /// when it is in the first bank. The others step through their bank by the stride
+    fn vector(&self, double: bool, d: usize, n: usize, m: usize) -> Vec<(usize, usize, usize)> {
+        let bank = if double { DOUBLE_BANK } else { SINGLE_BANK };
+        let (length, stride) = if d < bank {
+            (1, 1)
+        } else {
+            self.vector_shape()
+        };
+        let step = |register: usize, element: usize| {
+            register - register % bank + (register + element * stride) % bank
+        };
+        (0..length)
+            .map(|element| {
+                let m = if m < bank { m } else { step(m, element) };
+                (step(d, element), step(n, element), m)
+            })
+            .collect()
+    }
+
+    /// The instructions with the extension opcode, in the Fn and N fields:
+    /// copies, absolute values, negations, square roots, compares and conversions
+    fn extension(
+        &mut self,
+        code: u32,
+        double: bool,
+        extension: u32,
+        env: &mut Environment,
+    ) -> Result<(), Exception> {
+        let format = precision(double);
+        match extension {
+            0b00000..=0b00011 => {
+                let (d, m) = (
+                    register(code, double, 12, 22)?,
+                    register(code, double, 0, 5)?,
+                );
+                for (d, _, m) in self.vector(double, d, 0, m) {
+                    let operand = self.get(double, m);
+                    let result = match extension {
+                        0b00000 => operand,
+                        0b00001 => format.absolute(operand),
+                        0b00010 => format.negate(operand),
+                        _ => float::square_root(format, operand, env),
+                    };
+                    self.set(double, d, result);
+                }
+            }
+            0b01000..=0b01011 => {
+                let (d, m) = (
+                    register(code, double, 12, 22)?,
+                    register(code, double, 0, 5)?,
+                );
+                let rhs = if mask![extension, 1] {
+                    0
+                } else {
+                    self.get(double, m)
+                };
+                let flags =
+                    float::compare(format, self.get(double, d), rhs, mask![extension, 0], env);
+                self.fpscr = self.fpscr & 0x0fff_ffff | flags << 28;
+            }
+            0b01111 => {
+                // Between the precisions, so the destination is of the other one
+                let (d, m) = (
+                    register(code, !double, 12, 22)?,
+                    register(code, double, 0, 5)?,
+                );
+                let result = float::convert(format, precision(!double), self.get(double, m), env);
+                self.set(!double, d, result);
+            }
+            0b10000 | 0b10001 => {
+                // From an integer in a single precision register
+                let (d, m) = (
+                    register(code, double, 12, 22)?,
+                    register(code, false, 0, 5)?,
+                );
+                let integer = self.registers[m];
+                let result = float::from_integer(format, integer, mask![extension, 0], env);
+                self.set(double, d, result);
+            }
+            0b11000..=0b11011 => {
+                // To an integer in a single precision register, rounding towards zero
+                // with the Z bit set and in the FPSCR's rounding mode otherwise
+                let (d, m) = (
+                    register(code, false, 12, 22)?,
+                    register(code, double, 0, 5)?,
+                );
+                let rounding = if mask![extension, 0] {
+                    Rounding::Zero
+                } else {
+                    env.rounding
+                };
+                let signed = mask![extension, 1];
+                let integer = float::to_integer(format, self.get(double, m), signed, rounding, env);
+                self.registers[d] = integer;
+            }
+            _ => return Err(Exception::UndefinedInstruction),
+        }
+        Ok(())
+    }
+}
+
+fn precision(double: bool) -> Format {
+    if double {
+        float::DOUBLE
+    } else {
+        float::SINGLE
+    }
+}
+
+/// The register in the 4-bit field at `field` with the extra bit at `low_bit`:
+/// the low bit of single precision registers, which has to be clear for double ones
+fn register(code: u32, double: bool, field: u8, low_bit: u8) -> Result<usize, Exception> {
+    let number = mask![code, field, field + 3] as usize;
+    let low = mask![code, low_bit] as usize;
+    match (double, low) {
+        (true, 0) => Ok(number),
+        (true, _) => Err(Exception::UndefinedInstruction),
+        (false, _) => Ok(number << 1 | low),
+    }
+}
+
+/// An arithmetic operation on Fd, Fn and Fm
+type Arithmetic = fn(Format, u64, u64, u64, &mut Environment) -> u64;
+
+/// The operation of one of the arithmetic opcodes, in the p, q, r and s bits.
+/// The multiply and accumulate ones round the product before adding it
+fn arithmetic(opcode: u32) -> Option<Arithmetic> {
+    let operation: Arithmetic = match opcode {
+        // FMAC, FNMAC, FMSC and FNMSC
+        0b0000 => |f, d, n, m, env| {
+            let product = float::multiply(f, n, m, env);
+            float::add(f, d, product, env)
+        },
+        0b0001 => |f, d, n, m, env| {
+            let product = float::multiply(f, n, m, env);
+            float::add(f, d, f.negate(product), env)
+        },
+        0b0010 => |f, d, n, m, env| {
+            let product = float::multiply(f, n, m, env);
+            float::add(f, f.negate(d), product, env)
+        },
+        0b0011 => |f, d, n, m, env| {
+            let product = float::multiply(f, n, m, env);
+            float::add(f, f.negate(d), f.negate(product), env)
+        },
+        // FMUL, FNMUL, FADD, FSUB and FDIV
+        0b0100 => |f, _, n, m, env| float::multiply(f, n, m, env),
+        0b0101 => |f, _, n, m, env| f.negate(float::multiply(f, n, m, env)),
+        0b0110 => |f, _, n, m, env| float::add(f, n, m, env),
+        0b0111 => |f, _, n, m, env| float::subtract(f, n, m, env),
+        0b1000 => |f, _, n, m, env| float::divide(f, n, m, env),
+        _ => return None,
+    };
+    Some(operation)
+}
+
+/// One of the two halves of VFP: single precision in CP10 or double precision in CP11
+#[derive(Debug)]
+pub struct Vfp {
+    registers: Rc<RefCell<VfpRegisters>>,
+    double: bool,
+}
+
+impl Vfp {
+    /// Attaches VFP to CP10 and CP11
+    pub fn attach(coprocessors: &mut Coprocessors) {
+        let registers = Rc::new(RefCell::new(VfpRegisters::default()));
+        for &(number, double) in &[(SINGLE_PRECISION, false), (DOUBLE_PRECISION, true)] {
+            let vfp = Vfp {
+                registers: Rc::clone(&registers),
+                double,
+            };
+            coprocessors.attach(number, Box::new(vfp));
+        }
+    }
+
+    /// The register an FMSR, FMRS or FMDxR names, or the system register
+    /// of FMXR or FMRX. Bits 5 and 6 have to be clear
+    fn transfer_register(&self, instr: &CoprocessorInstr) -> Result<usize, Exception> {
+        if instr.opcode2 & 0b011 != 0 {
+            return Err(Exception::UndefinedInstruction);
+        }
+        register(instr.code, self.double, 16, 7)
+    }
+
+    /// The first register of an FLD or FST and the number of words it transfers.
+    /// Without pre-indexing or with write back, it is a multiple transfer of as many
+    /// words as the offset, either incrementing after or decrementing before,
+    /// and FLDMX and FSTMX transfer an extra one
+    fn data_transfer(&self, instr: &CoprocessorInstr) -> Result<(usize, usize), Exception> {
+        let code = instr.code;
+        let first = register(code, self.double, 12, 22)?;
+        let words = match (mask![code, 24], mask![code, 23], mask![code, 21]) {
+            (true, _, false) if self.double => 2,
+            (true, _, false) => 1,
+            (false, true, _) | (true, false, true) => mask![code, 0, 7] as usize,
+            _ => return Err(Exception::UndefinedInstruction),
+        };
+        let registers = if self.double { words / 2 } else { words };
+        let limit = if self.double { 16 } else { 32 };
+        if registers == 0 || first + registers > limit {
+            return Err(Exception::UndefinedInstruction);
+        }
+        Ok((first, words))
+    }
+}
+
+impl Coprocessor for Vfp {
+    fn data_operation(&mut self, instr: &CoprocessorInstr) -> Result<(), Exception> {
+        let mut vfp = self.registers.borrow_mut();
+        vfp.check_enabled()?;
+        vfp.data_operation(instr.code, self.double)
+    }
+
+    /// FMSR, FMDLR, FMDHR and FMXR
+    fn write_register(&mut self, instr: &CoprocessorInstr, value: u32) -> Result<(), Exception> {
+        let mut vfp = self.registers.borrow_mut();
+        let register = self.transfer_register(instr)?;
+        match (self.double, instr.opcode1) {
+            (false, 0b111) => match register as u32 >> 1 {
+                // Writes to FPSID are ignored
+                FPSID_REGISTER => {}
+                FPEXC_REGISTER => vfp.fpexc = value & ENABLE_BIT,
+                FPSCR_REGISTER => {
+                    vfp.check_enabled()?;
+                    vfp.fpscr = value & FPSCR_MASK;
+                }
+                _ => return Err(Exception::UndefinedInstruction),
+            },
+            (false, 0b000) => {
+                vfp.check_enabled()?;
+                vfp.registers[register] = value;
+            }
+            (true, 0b000) | (true, 0b001) => {
+                vfp.check_enabled()?;
+                vfp.registers[2 * register + instr.opcode1 as usize] = value;
+            }
+            _ => return Err(Exception::UndefinedInstruction),
+        }
+        Ok(())
+    }
+
+    /// FMRS, FMRDL, FMRDH and FMRX
+    fn read_register(&mut self, instr: &CoprocessorInstr) -> Result<u32, Exception> {
+        let vfp = self.registers.borrow();
+        let register = self.transfer_register(instr)?;
+        match (self.double, instr.opcode1) {
+            (false, 0b111) => match register as u32 >> 1 {
+                FPSID_REGISTER => Ok(FPSID),
+                FPEXC_REGISTER => Ok(vfp.fpexc),
+                FPSCR_REGISTER => vfp.check_enabled().map(|_| vfp.fpscr),
+                _ => Err(Exception::UndefinedInstruction),
+            },
+            (false, 0b000) => vfp.check_enabled().map(|_| vfp.registers[register]),
+            (true, 0b000) | (true, 0b001) => vfp
+                .check_enabled()
+                .map(|_| vfp.registers[2 * register + instr.opcode1 as usize]),
+            _ => Err(Exception::UndefinedInstruction),
+        }
+    }
+
+    /// FMSRR, writing two consecutive single precision registers, or FMDRR
+    fn write_registers(
+        &mut self,
+        instr: &CoprocessorInstr,
+        low: u32,
+        high: u32,
+    ) -> Result<(), Exception> {
+        let mut vfp = self.registers.borrow_mut();
+        vfp.check_enabled()?;
+        let first = self.double_transfer_register(instr)?;
+        vfp.registers[first] = low;
+        vfp.registers[first + 1] = high;
+        Ok(())
+    }
+
+    /// FMRRS or FMRRD
+    fn read_registers(&mut self, instr: &CoprocessorInstr) -> Result<(u32, u32), Exception> {
+        let vfp = self.registers.borrow();
+        vfp.check_enabled()?;
+        let first = self.double_transfer_register(instr)?;
+        Ok((vfp.registers[first], vfp.registers[first + 1]))
+    }
+
+    fn transfer_length(&self, instr: &CoprocessorInstr) -> Result<usize, Exception> {
+        self.registers.borrow().check_enabled()?;
+        self.data_transfer(instr).map(|(_, words)| words)
+    }
+
+    /// FLDS, FLDD and FLDM. The extra word of FLDMX is skipped
+    fn load(&mut self, instr: &CoprocessorInstr, words: &[u32]) -> Result<(), Exception> {
+        let mut vfp = self.registers.borrow_mut();
+        let (first, _) = self.data_transfer(instr)?;
+        let first = if self.double { 2 * first } else { first };
+        let length = if self.double {
+            words.len() & !1
+        } else {
+            words.len()
+        };
+        vfp.registers[first..first + length].copy_from_slice(&words[..length]);
+        Ok(())
+    }
+
+    /// FSTS, FSTD and FSTM. The extra word of FSTMX is stored as 0
+    fn store(&mut self, instr: &CoprocessorInstr) -> Result<Vec<u32>, Exception> {
+        let vfp = self.registers.borrow();
+        let (first, words) = self.data_transfer(instr)?;
+        let first = if self.double { 2 * first } else { first };
+        let length = if self.double { words & !1 } else { words };
+        let mut stored = vfp.registers[first..first + length].to_vec();
+        stored.resize(words, 0);
+        Ok(stored)
+    }
+}
+
+impl Vfp {
+    /// The first of the two single precision registers of FMSRR and FMRRS,
+    /// or the double precision register of FMDRR and FMRRD, as a single precision one.
+    /// The opcode has to be 0b00M1
+    fn double_transfer_register(&self, instr: &CoprocessorInstr) -> Result<usize, Exception> {
+        if instr.opcode1 & 0b1101 != 0b0001 {
+            return Err(Exception::UndefinedInstruction);
+        }
+        let first = register(instr.code, self.double, 0, 5)?;
+        match self.double {
+            true => Ok(2 * first),
+            false if first < 31 => Ok(first),
+            false => Err(Exception::UndefinedInstruction),
+        }
+    }
+}
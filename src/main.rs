@@ -1,24 +1,45 @@
+// Instruction and register names mirror the ARM mnemonics
+#![allow(clippy::upper_case_acronyms)]
+
 use std::env;
+use std::io::ErrorKind;
+use std::process;
+use std::path::PathBuf;
 
+mod assembler;
+mod elf;
 mod emulator;
-use emulator::pipeline_executor;
+use assembler::two_pass_assembler::{self, AssembleOptions};
+use emulator::pipeline_executor::{self, EmulateOptions};
+use emulator::disassembler;
+use emulator::em_utilities::Architecture;
 mod tests;
 
 
 
 #[derive(Debug)]
 enum Task<'a> {
-    Emulate(&'a str),
+    Emulate {
+        path: &'a str,
+        options: EmulateOptions,
+    },
+    Disassemble(&'a str),
     Assemble {
         asm_path: &'a str,
         out_path: &'a str,
+        options: AssembleOptions,
     },
 }
 
 /// Runs the emulator or assembler
 /// Run it using this command:
-/// emulate <binary-file-path>
-/// assemble <asm-file-path> <output-path>
+/// emulate <binary-file-path> [--symbols <sym-file-path>] [--trace] [--arch v4|v5|v6]
+///     [--host-dir <dir>] [--high-vectors]
+/// assemble <asm-file-path> <output-path> [-I <include-dir>]... [--listing] [--symbols] [--elf]
+/// disassemble <binary-file-path>
+///
+/// Emulated programs can only reach files through semihosting inside `--host-dir`,
+/// and can't reach any without it
 ///
 /// # Panics
 ///
@@ -28,31 +49,64 @@ fn main() -> Result<(), std::io::Error> {
     let task_description = assert_cmd_line_params(&args);
 
     match task_description {
-        Task::Emulate(path) => emulate(path),
-        // Initially wanted to support asm -> binary 
-        // but I'm not sure if I'll bother implementing that.
-        // Just leaving the emulator for now
-        Task::Assemble { asm_path, out_path } => Ok(())
+        Task::Emulate { path, options } => emulate(path, &options),
+        Task::Disassemble(path) => disassemble(path),
+        Task::Assemble {
+            asm_path,
+            out_path,
+            options,
+        } => assemble(asm_path, out_path, &options),
     }
 }
 
 
-/// Reads an emulator binary file which contains lines of u32 
-/// and produces the required output
+/// Reads an emulator binary file which contains lines of u32,
+/// or an ARM ELF executable, and produces the required output
 ///
 /// # Panics
-/// Panics if the file has a number of bytes indivisible by 4
+/// Panics if a raw binary file has a number of bytes indivisible by 4
 ///
 /// Propagates std::io::Error to `main` if the file path is invalid
-fn emulate(path: &str) -> Result<(), std::io::Error> {
-    pipeline_executor::emulate(path)?;
+fn emulate(path: &str, options: &EmulateOptions) -> Result<(), std::io::Error> {
+    let (_, exit_status) = pipeline_executor::run(path, options)?;
+    // Programs that exit through semihosting pass their status on to the shell
+    if let Some(status) = exit_status {
+        process::exit(status);
+    }
+
+    Ok(())
+}
+
+/// Assembles an ARM source file into a binary file of little endian u32
+/// which can then be run by `emulate`
+///
+/// If the source contains errors, prints all of them
+/// and exits with a non-zero code
+///
+/// Propagates std::io::Error to `main` if the output can't be written
+fn assemble(asm_path: &str, out_path: &str, options: &AssembleOptions) -> Result<(), std::io::Error> {
+    match two_pass_assembler::assemble(asm_path, out_path, options) {
+        Err(err) if err.kind() == ErrorKind::InvalidData => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        result => result,
+    }
+}
+
+/// Prints the address, raw word and assembly of every instruction
+/// of an emulator binary file
+///
+/// Propagates std::io::Error to `main` if the file path is invalid
+fn disassemble(path: &str) -> Result<(), std::io::Error> {
+    disassembler::disassemble(path)?;
 
     Ok(())
 }
 
 #[allow(non_snake_case)]
-fn assert_cmd_line_params(args: &[String]) -> Task {
-    let good_len = args.len() == 3 || args.len() == 4;
+fn assert_cmd_line_params(args: &[String]) -> Task<'_> {
+    let good_len = args.len() >= 3;
     if !good_len {
         panic!("You gave me a wrong command format, please check the documentation!");
     }
@@ -62,16 +116,73 @@ fn assert_cmd_line_params(args: &[String]) -> Task {
     let OUT_PATH_INDEX: usize = 3;
 
     if &args[TASK_INDEX] == "emulate" {
-        return Task::Emulate(&args[FILE_PATH_INDEX]);
+        let usage = "Wrong emulate information! Please use \
+            `emulate <binary-path> [--symbols <sym-path>] [--trace] [--arch v4|v5|v6] \
+            [--host-dir <dir>] [--high-vectors]`";
+        let mut options = EmulateOptions::default();
+        let mut flags = args[FILE_PATH_INDEX + 1..].iter();
+        while let Some(flag) = flags.next() {
+            match flag.as_str() {
+                "--symbols" => {
+                    let path = flags.next().unwrap_or_else(|| panic!("{}", usage));
+                    options.symbols_path = Some(PathBuf::from(path));
+                }
+                "--trace" => options.trace = true,
+                "--high-vectors" => options.high_vectors = true,
+                "--host-dir" => {
+                    let dir = flags.next().unwrap_or_else(|| panic!("{}", usage));
+                    options.host_directory = Some(PathBuf::from(dir));
+                }
+                "--arch" => {
+                    options.architecture = match flags.next().map(String::as_str) {
+                        Some("v4") => Architecture::ARMv4,
+                        Some("v5") => Architecture::ARMv5,
+                        Some("v6") => Architecture::ARMv6,
+                        _ => panic!("{}", usage),
+                    };
+                }
+                _ => panic!("{}", usage),
+            }
+        }
+        return Task::Emulate {
+            path: &args[FILE_PATH_INDEX],
+            options,
+        };
+    }
+    if &args[TASK_INDEX] == "disassemble" {
+        if args.len() != 3 {
+            panic!("Wrong disassemble information! Please use `disassemble <binary-path>`");
+        }
+        return Task::Disassemble(&args[FILE_PATH_INDEX]);
     }
     if &args[TASK_INDEX] == "assemble" {
-        if args.len() != 4 {
-            panic!("Wrong assemble information! Please use `assemble <asm-path> <output-path>`");
+        let usage = "Wrong assemble information! Please use \
+            `assemble <asm-path> <output-path> [-I <include-dir>]... [--listing] [--symbols] [--elf]`";
+        if args.len() < 4 {
+            panic!("{}", usage);
+        }
+        let mut options = AssembleOptions::default();
+        let mut flags = args[OUT_PATH_INDEX + 1..].iter();
+        while let Some(flag) = flags.next() {
+            match flag.as_str() {
+                "--listing" => options.listing = true,
+                "--symbols" => options.symbol_map = true,
+                "--elf" => options.elf = true,
+                "-I" => {
+                    let dir = flags.next().unwrap_or_else(|| panic!("{}", usage));
+                    options.include_dirs.push(PathBuf::from(dir));
+                }
+                flag => match flag.strip_prefix("-I") {
+                    Some(dir) => options.include_dirs.push(PathBuf::from(dir)),
+                    None => panic!("{}", usage),
+                },
+            }
         }
         return Task::Assemble {
             asm_path: &args[FILE_PATH_INDEX],
             out_path: &args[OUT_PATH_INDEX],
+            options,
         };
     }
-    panic!("The first argument must be either `emulate` or `assemble`");
+    panic!("The first argument must be either `emulate`, `assemble` or `disassemble`");
 }
@@ -1,13 +1,15 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
-    use super::*;
     // TODO: Add all tests!
 
     const PC: usize = 15;
     const CPSR: usize = 16;
 
     use crate::emulator::em_utilities as util;
-    use crate::emulator::pipeline_executor::emulate;
+    use crate::emulator::modes::Mode;
+    use crate::emulator::pipeline_executor::{emulate, emulate_with, run, EmulateOptions};
+    use crate::emulator::semihosting::Semihosting;
     use util::*;
 
     #[doc = "empty vector for memory, just for creating a CpuState"]
@@ -62,6 +64,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn adc01() {
+        let cpu = emulate("tests/adc01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![(1, 0xffffffff), (3, 2), (PC, 20), (CPSR, 0x60000000)];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(&mut cpu, vec![(0, 0x0010e0e3), (4, 0x012091e2), (8, 0x0230a1e2)]);
+    }
+
     #[test]
     fn add01() {
         let cpu = emulate("tests/add01");
@@ -73,6 +92,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(&mut cpu, vec![(0, 0x0110a0e3), (4, 0x022081e2)]);
@@ -89,6 +109,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -108,6 +129,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(special_registers),
             memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(&mut cpu, vec![(0, 0x0110a0e3), (4, 0x011081e0)]);
@@ -124,6 +146,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(special_registers),
             memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -148,6 +171,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(special_registers),
             memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(&mut cpu, vec![(0, 0xff10a0e3), (4, 0xab2001e2)]);
@@ -164,6 +188,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(special_registers),
             memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -182,6 +207,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: vec![].into_boxed_slice(),
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -205,6 +231,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -230,6 +257,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -245,6 +273,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn bic01() {
+        let cpu = emulate("tests/bic01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![(1, 255), (2, 15), (PC, 20), (CPSR, 0x40000000)];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(&mut cpu, vec![(0, 0xff10a0e3), (4, 0xf020c1e3), (8, 0x0130d1e1)]);
+    }
+
     #[test]
     fn bne01() {
         let cpu = emulate("tests/bne01");
@@ -255,6 +300,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -280,6 +326,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -295,6 +342,170 @@ mod tests {
         );
     }
 
+    #[test]
+    fn call01() {
+        let cpu = emulate("tests/call01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (0, 32),
+            (5, 16),
+            (6, 1),
+            (7, 7),
+            (8, 36),
+            (13, 0x400),
+            (14, 32),
+            (PC, 92),
+            (CPSR, 0),
+        ];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x01dba0e3),
+                (4, 0x0500a0e3),
+                (8, 0x050000eb),
+                (0xc, 0x080000eb),
+                (0x10, 0x0050a0e1),
+                (0x14, 0x0a0000eb),
+                (0x18, 0x2480a0e3),
+                (0x1c, 0x38ff2fe1),
+                (0x20, 0x0a0000ea),
+                (0x24, 0x000080e0),
+                (0x28, 0x0ef0a0e1),
+                (0x2c, 0x030080e2),
+                (0x30, 0x1eff2fe1),
+                (0x34, 0x00402de9),
+                (0x38, 0xfbffffeb),
+                (0x3c, 0xfaffffeb),
+                (0x40, 0x0080bde8),
+                (0x44, 0x04e02de5),
+                (0x48, 0x0160a0e3),
+                (0x4c, 0x04f09de4),
+                (0x50, 0x0770a0e3),
+                (0x3fc, 0x18000000),
+            ],
+        );
+    }
+
+    #[test]
+    fn cmn01() {
+        let cpu = emulate("tests/cmn01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![(1, 0x7fffffff), (3, 1), (4, 2), (PC, 28), (CPSR, 0x90000000)];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x0211e0e3),
+                (4, 0x010071e3),
+                (8, 0x0120a0b3),
+                (0xc, 0x0130a0a3),
+                (0x10, 0x0240a0e3),
+            ],
+        );
+    }
+
+    #[test]
+    fn cond01() {
+        let cpu = emulate("tests/cond01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (1, 1),
+            (3, 1),
+            (5, 1),
+            (7, 1),
+            (9, 1),
+            (PC, 52),
+            (CPSR, 0x80000000),
+        ];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x0000a0e3),
+                (4, 0x010050e3),
+                (8, 0x0110a043),
+                (0xc, 0x0120a053),
+                (0x10, 0x0130a033),
+                (0x14, 0x0140a023),
+                (0x18, 0x0150a093),
+                (0x1c, 0x0160a083),
+                (0x20, 0x0170a073),
+                (0x24, 0x0180a063),
+                (0x28, 0x0190a0e3),
+            ],
+        );
+    }
+
+    #[test]
+    fn cond02() {
+        let cpu = emulate("tests/cond02");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (0, 2),
+            (1, 1),
+            (3, 1),
+            (5, 0x7fffffff),
+            (6, 0xfffffffe),
+            (7, 1),
+            (10, 1),
+            (11, 1),
+            (PC, 60),
+            (CPSR, 0x90000000),
+        ];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x0200a0e3),
+                (4, 0x010050e3),
+                (8, 0x0110a083),
+                (0xc, 0x0120a093),
+                (0x10, 0x0130a023),
+                (0x14, 0x0140a033),
+                (0x18, 0x0251e0e3),
+                (0x1c, 0x056095e0),
+                (0x20, 0x0170a063),
+                (0x24, 0x0180a073),
+                (0x28, 0x0190a0b3),
+                (0x2c, 0x01a0a0a3),
+                (0x30, 0x01b0a0e3),
+            ],
+        );
+    }
+
     #[test]
     fn eor01() {
         let cpu = emulate("tests/eor01");
@@ -305,6 +516,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -323,6 +535,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(&mut cpu, vec![(0, 0x0ff10a0e3), (4, 0x0f2021e2)]);
@@ -344,6 +557,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -363,6 +577,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn flags01() {
+        let cpu = emulate("tests/flags01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![(1, 1), (2, 3), (4, 3), (5, 5), (PC, 36), (CPSR, 0x20000000)];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x0000a0e3),
+                (4, 0x000050e3),
+                (8, 0x0110b0e3),
+                (0xc, 0x0320a0e3),
+                (0x10, 0x900213e0),
+                (0x14, 0x024012e0),
+                (0x18, 0x0550a0e3),
+            ],
+        );
+    }
+
     #[test]
     fn ldr01() {
         let cpu = emulate("tests/ldr01");
@@ -373,6 +615,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -395,6 +638,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -419,6 +663,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -443,6 +688,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -464,6 +710,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -488,6 +735,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -511,6 +759,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -535,6 +784,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -557,6 +807,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -569,6 +820,151 @@ mod tests {
         );
     }
 
+    #[test]
+    fn ldrb01() {
+        let cpu = emulate("tests/ldrb01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (0, 0x87654321),
+            (1, 258),
+            (2, 33),
+            (3, 135),
+            (4, 255),
+            (5, 0x8765ff21),
+            (6, 255),
+            (PC, 44),
+            (CPSR, 0),
+        ];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x20009fe5),
+                (4, 0x011ca0e3),
+                (8, 0x000081e5),
+                (0xc, 0x0020d1e5),
+                (0x10, 0x0330d1e5),
+                (0x14, 0xff40a0e3),
+                (0x18, 0x0140e1e5),
+                (0x1c, 0x015011e5),
+                (0x20, 0x0160d1e4),
+                (0x28, 0x21436587),
+                (0x100, 0x21ff6587),
+            ],
+        );
+    }
+
+    #[test]
+    fn ldrh01() {
+        let cpu = emulate("tests/ldrh01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (0, 0x87654321),
+            (1, 256),
+            (2, 0x4321),
+            (3, 0xffff8765),
+            (4, 0xffffff87),
+            (5, 33),
+            (6, 2),
+            (7, 0x8765),
+            (8, 0x4321),
+            (9, 0x43210000),
+            (PC, 60),
+            (CPSR, 0),
+        ];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x30009fe5),
+                (4, 0x011ca0e3),
+                (8, 0x000081e5),
+                (0xc, 0xb020d1e1),
+                (0x10, 0xf230d1e1),
+                (0x14, 0xd340d1e1),
+                (0x18, 0xd050d1e1),
+                (0x1c, 0x0260a0e3),
+                (0x20, 0xb67091e1),
+                (0x24, 0xb400e1e1),
+                (0x28, 0xb48051e0),
+                (0x2c, 0xb60001e1),
+                (0x30, 0x049011e5),
+                (0x38, 0x21436587),
+                (0xfc, 0x00002143),
+                (0x100, 0x21436587),
+                (0x104, 0x21430000),
+            ],
+        );
+    }
+
+    #[test]
+    fn ldm01() {
+        let cpu = emulate("tests/ldm01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (0, 1),
+            (1, 1),
+            (2, 2),
+            (3, 3),
+            (4, 1),
+            (5, 2),
+            (6, 3),
+            (7, 536),
+            (8, 1),
+            (9, 2),
+            (10, 2),
+            (PC, 52),
+            (CPSR, 0),
+        ];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x020ca0e3),
+                (4, 0x0110a0e3),
+                (8, 0x0220a0e3),
+                (0xc, 0x0330a0e3),
+                (0x10, 0x0e00a0e8),
+                (0x14, 0x060080e9),
+                (0x18, 0x700030e9),
+                (0x1c, 0x867fa0e3),
+                (0x20, 0x0e0007e8),
+                (0x24, 0x000390e8),
+                (0x28, 0x0104b0e8),
+                (0x200, 0x01000000),
+                (0x204, 0x02000000),
+                (0x208, 0x03000000),
+                (0x210, 0x01000000),
+                (0x214, 0x02000000),
+                (0x218, 0x03000000),
+            ],
+        );
+    }
+
     #[test]
     fn loop01() {
         let cpu = emulate("tests/loop01");
@@ -579,6 +975,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -602,6 +999,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -629,6 +1027,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -652,6 +1051,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -673,6 +1073,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -694,6 +1095,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -707,6 +1109,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn mode01() {
+        let cpu = emulate("tests/mode01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (0, 210),
+            (1, 16),
+            (2, 256),
+            (3, 1),
+            (4, 8),
+            (5, 16),
+            (6, 0xf00000d3),
+            (7, 0xf0000010),
+            (8, 8),
+            (13, 0x400),
+            (PC, 100),
+            (CPSR, 0xf0000010),
+        ];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0xd200a0e3),
+                (4, 0x00f021e1),
+                (8, 0x01dca0e3),
+                (0xc, 0x01e0a0e3),
+                (0x10, 0x10f06fe3),
+                (0x14, 0x00104fe1),
+                (0x18, 0xd3f021e3),
+                (0x1c, 0x02dca0e3),
+                (0x20, 0x0880a0e3),
+                (0x24, 0xd1f021e3),
+                (0x28, 0x8880a0e3),
+                (0x2c, 0x03dca0e3),
+                (0x30, 0xd2f021e3),
+                (0x34, 0x0d20a0e1),
+                (0x38, 0x0e30a0e1),
+                (0x3c, 0x0840a0e1),
+                (0x40, 0x10f021e3),
+                (0x44, 0x01dba0e3),
+                (0x48, 0x00500fe1),
+                (0x4c, 0xd360a0e3),
+                (0x50, 0x0f6286e3),
+                (0x54, 0x06f029e1),
+                (0x58, 0x00700fe1),
+            ],
+        );
+    }
+
     #[test]
     fn mov01() {
         let cpu = emulate("tests/mov01");
@@ -717,6 +1176,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -737,6 +1197,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -757,6 +1218,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -780,6 +1242,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -800,6 +1263,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -821,6 +1285,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -841,6 +1306,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -862,6 +1328,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -876,110 +1343,607 @@ mod tests {
     }
 
     #[test]
-    fn opt_add05() {
-        let cpu = emulate("tests/opt_add05");
+    fn mull01() {
+        let cpu = emulate("tests/mull01");
         assert!(cpu.is_ok());
         let mut cpu = cpu.unwrap();
-        let registers_special = vec![(1, 4), (2, 2), (3, 6), (4, 10), (5, 9), (PC, 28)];
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (0, 0xffffffff),
+            (1, 2),
+            (2, 0xfffffffe),
+            (3, 1),
+            (4, 2),
+            (6, 2),
+            (7, 0xfffffffe),
+            (8, 1),
+            (9, 0xfffffffd),
+            (10, 0xfffffffe),
+            (11, 0xffffffff),
+            (PC, 52),
+            (CPSR, 0x80000000),
+        ];
 
         let mut expected = CpuState {
-            registers: reg_from(registers_special),
-            memory: mem_empty![],
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
             &mut cpu,
             vec![
-                (0, 0x0410a0e3),
-                (4, 0x0220a0e3),
-                (8, 0x023081e0),
-                (0xc, 0x44083e2),
-                (0x10, 0x335244e0)
+                (0, 0x0000e0e3),
+                (4, 0x0210a0e3),
+                (8, 0x902183e0),
+                (0xc, 0x9041c5e0),
+                (0x10, 0x0160a0e3),
+                (0x14, 0x0070a0e3),
+                (0x18, 0x9060a7e0),
+                (0x1c, 0x9141f5e0),
+                (0x20, 0x900008e0),
+                (0x24, 0x900129e0),
+                (0x28, 0x90a1dbe0),
             ],
-
         );
     }
 
     #[test]
-    fn opt_ldr10() {
-        let cpu = emulate("tests/opt_ldr10");
+    fn mvn01() {
+        let cpu = emulate("tests/mvn01");
         assert!(cpu.is_ok());
         let mut cpu = cpu.unwrap();
-        let registers_special = vec![(1, 12), (2, 10), (3, 14), (4, 0x5122e593), (5, 0x5122e593), (PC, 28)];
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (1, 15),
+            (2, 0xfffffff0),
+            (3, 15),
+            (4, 0xffffffff),
+            (PC, 24),
+            (CPSR, 0x80000000),
+        ];
 
         let mut expected = CpuState {
-            registers: reg_from(registers_special),
-            memory: mem_empty![],
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
             &mut cpu,
             vec![
-                (0, 0x0c10a0e3),
-                (4, 0x0a20a0e3),
-                (8, 0x0e30a0e3),
-                (0xc, 0x004093e5),
-                (0x10, 0x225191e7)
+                (0, 0x0f10a0e3),
+                (4, 0x0120e0e1),
+                (8, 0x0230f0e1),
+                (0xc, 0x0040f0e3),
             ],
-
         );
     }
 
     #[test]
-    fn or01() {
-        let cpu = emulate("tests/or01");
+    fn nv01() {
+        // ARMv4 never executes instructions with the 0b1111 condition
+        let options = EmulateOptions {
+            architecture: Architecture::ARMv4,
+            ..EmulateOptions::default()
+        };
+        let cpu = emulate_with("tests/nv01", &options);
         assert!(cpu.is_ok());
         let mut cpu = cpu.unwrap();
-        let registers_special = vec![(2, 171), (PC, 16)];
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![(1, 1), (3, 3), (PC, 24), (CPSR, 0)];
 
         let mut expected = CpuState {
-            registers: reg_from(registers_special),
-            memory: mem_empty![],
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
             &mut cpu,
             vec![
-                (0, 0x0010a0e3),
-                (4, 0xab2081e3)
+                (0, 0x0110a0e3),
+                (4, 0x0220a0f3),
+                (8, 0x00f0d1f5),
+                (0xc, 0x0330a0e3),
             ],
-
         );
     }
 
     #[test]
-    fn or02() {
-        let cpu = emulate("tests/or02");
+    fn exc01() {
+        let cpu = emulate("tests/exc01");
         assert!(cpu.is_ok());
         let mut cpu = cpu.unwrap();
-        let registers_special = vec![(1, 15), (2, 171), (3, 175), (PC, 20)];
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (0, 0x20000),
+            (1, 1),
+            (2, 2),
+            (3, 3),
+            (5, 5),
+            (6, 6),
+            (7, 52),
+            (8, 0x20000010),
+            (9, 66),
+            (10, 76),
+            (11, 104),
+            (12, 0x20004),
+            (PC, 92),
+            (CPSR, 0x20000010),
+        ];
 
         let mut expected = CpuState {
-            registers: reg_from(registers_special),
-            memory: mem_empty![],
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
             &mut cpu,
             vec![
-                (0, 0x0f10a0e3),
-                (4, 0xab20a0e3),
-                (8, 0x023081e1)
+                (0, 0x060000ea),
+                (4, 0x130000ea),
+                (8, 0x180000ea),
+                (0xc, 0x210000ea),
+                (0x10, 0x1b0000ea),
+                (0x14, 0x0000a0e1),
+                (0x18, 0x0000a0e1),
+                (0x1c, 0x0000a0e1),
+                (0x20, 0x1000a0e3),
+                (0x24, 0x020280e3),
+                (0x28, 0x00f029e1),
+                (0x2c, 0x0110a0e3),
+                (0x30, 0x0220a0f3),
+                (0x34, 0x0220a0e3),
+                (0x38, 0x420000ef),
+                (0x3c, 0x0330a0e3),
+                (0x40, 0x0208a0e3),
+                (0x44, 0x004090e5),
+                (0x48, 0x0550a0e3),
+                (0x4c, 0x00f0a0e1),
+                (0x50, 0x0660a0e3),
+                (0x58, 0x0e70a0e1),
+                (0x5c, 0x00804fe1),
+                (0x60, 0x0fb0a0e1),
+                (0x64, 0x01daa0e3),
+                (0x68, 0x00402de9),
+                (0x6c, 0x0080fde8),
+                (0x70, 0x04901ee5),
+                (0x74, 0xff94c9e3),
+                (0x78, 0x02daa0e3),
+                (0x7c, 0x00402de9),
+                (0x80, 0x0080fde8),
+                (0x84, 0x0ea0a0e1),
+                (0x88, 0x04e04ee2),
+                (0x8c, 0x03daa0e3),
+                (0x90, 0x00402de9),
+                (0x94, 0x0080fde8),
+                (0x98, 0x0ec0a0e1),
+                (0x9c, 0x50e0a0e3),
+                (0xa0, 0x03daa0e3),
+                (0xa4, 0x00402de9),
+                (0xa8, 0x0080fde8),
+                (0xffc, 0x34000000),
+                (0x1ffc, 0x3c000000),
+                (0x2ffc, 0x50000000),
             ],
-
         );
     }
 
     #[test]
-    fn rsb01() {
-        let cpu = emulate("tests/rsb01");
+    fn exc01_high_vectors() {
+        let options = EmulateOptions {
+            high_vectors: true,
+            ..EmulateOptions::default()
+        };
+        let cpu = emulate_with("tests/exc01", &options).unwrap();
+        // The handlers run from the copy of memory at the top of the address space
+        assert_eq!(cpu.registers[11], 0xffff_0068);
+        assert_eq!(cpu.registers[7], 52);
+        assert_eq!(cpu.pc(), 92);
+        assert_eq!(cpu.banks.spsr(Mode::UND), 0x2000_0010);
+        assert_eq!(cpu.banks.spsr(Mode::ABT), 0x2000_0010);
+    }
+
+    #[test]
+    fn cp01() {
+        let cpu = emulate("tests/cp01");
         assert!(cpu.is_ok());
         let mut cpu = cpu.unwrap();
-        let registers_special = vec![(1, 2), (2, 0xffffffff), (PC, 16)];
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (0, 0x410fb767),
+            (1, 0x52078),
+            (2, 0x4000),
+            (3, 0x4000),
+            (4, 4),
+            (5, 56),
+            (7, 7),
+            (8, 0xffff004c),
+            (PC, 68),
+            (CPSR, 0x40000000),
+        ];
 
         let mut expected = CpuState {
-            registers: reg_from(registers_special),
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x000000ea),
+                (4, 0x0d0000ea),
+                (8, 0x100f10ee),
+                (0xc, 0x101f11ee),
+                (0x10, 0x021a81e3),
+                (0x14, 0x101f01ee),
+                (0x18, 0x0129a0e3),
+                (0x1c, 0x102f02ee),
+                (0x20, 0x103f12ee),
+                (0x24, 0x170f07ee),
+                (0x28, 0x0040a0e3),
+                (0x2c, 0x7eff17ee),
+                (0x30, 0x0440a003),
+                (0x34, 0x106e10ee),
+                (0x38, 0x0770a0e3),
+                (0x40, 0x0e50a0e1),
+                (0x44, 0x0f80a0e1),
+                (0x48, 0x0ef0b0e1),
+            ],
+        );
+        assert!(cpu.high_vectors());
+    }
+
+    #[test]
+    fn thumb01() {
+        let cpu = emulate("tests/thumb01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (0, 18),
+            (1, 40),
+            (2, 50),
+            (3, 16),
+            (4, 129),
+            (5, 0xfffffffb),
+            (6, 0x78563412),
+            (7, 55),
+            (8, 5),
+            (9, 9),
+            (10, 10),
+            (13, 0x1000),
+            (14, 0x41),
+            (PC, 20),
+            (CPSR, 0x60000000),
+        ];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x01daa0e3),
+                (4, 0x030000fa),
+                (8, 0x0990a0e3),
+                (0x10, 0x0aa0a0e3),
+                (0x14, 0x1eff2fe1),
+                (0x18, 0x00b50a20),
+                (0x1c, 0x81000a18),
+                (0x20, 0xd31f0324),
+                (0x24, 0x5c434542),
+                (0x28, 0x6d10a846),
+                (0x2c, 0x8044064e),
+                (0x30, 0x00273f18),
+                (0x34, 0x0138fcd1),
+                (0x38, 0x00f003f8),
+                (0x3c, 0x034b9847),
+                (0x40, 0x00bd36ba),
+                (0x44, 0xf0b27047),
+                (0x48, 0x78563412),
+                (0x4c, 0x10000000),
+                (0xffc, 0x08000000),
+            ],
+        );
+    }
+
+    #[test]
+    fn thumb02() {
+        let (mut cpu, exit_status) = run("tests/thumb02", &EmulateOptions::default()).unwrap();
+        // The Thumb semihosting SWI exited with ADP_Stopped_ApplicationExit
+        assert_eq!(exit_status, Some(0));
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (1, 0x20026),
+            (2, 2),
+            (9, 66),
+            (10, 32),
+            (11, 52),
+            (13, 0x1000),
+            (14, 20),
+            (PC, 62),
+            (CPSR, 32),
+        ];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x010000ea),
+                (4, 0x0000a0e1),
+                (8, 0x020000ea),
+                (0xc, 0x01daa0e3),
+                (0x10, 0x060000fa),
+                (0x18, 0x02905ee5),
+                (0x1c, 0x00a04fe1),
+                (0x20, 0x0eb0a0e1),
+                (0x24, 0x02daa0e3),
+                (0x28, 0x00402de9),
+                (0x2c, 0x0080fde8),
+                (0x30, 0x012142df),
+                (0x34, 0x02221820),
+                (0x38, 0x0149abdf),
+                (0x3c, 0x0323c046),
+                (0x40, 0x26000200),
+                (0x1ffc, 0x34000000),
+            ],
+        );
+    }
+
+    #[test]
+    fn pc01() {
+        let cpu = emulate("tests/pc01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            // mov r0, pc and add r1, pc, #4 at 0xc and 0x10 read the PC as 8 ahead
+            (0, 20),
+            (1, 28),
+            (2, 68),
+            // With a register specified shift it is 12 ahead
+            (4, 40),
+            (5, 5),
+            (7, 9),
+            (8, 60),
+            (9, 36),
+            (10, 10),
+            // The SWI handler set the flags, movs pc, lr restored the CPSR
+            (11, 0xf0000093),
+            (PC, 80),
+            (CPSR, 0),
+        ];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x010000ea),
+                (4, 0x0000a0e1),
+                (8, 0x0f0000ea),
+                (0xc, 0x0f00a0e1),
+                (0x10, 0x04108fe2),
+                (0x14, 0x0030a0e3),
+                (0x18, 0x1f93a0e1),
+                (0x1c, 0x13438fe0),
+                (0x20, 0x017ca0e3),
+                (0x24, 0x00f087e5),
+                (0x28, 0x008087e9),
+                (0x2c, 0x00f08fe2),
+                (0x30, 0xff50a0e3),
+                (0x34, 0x0550a0e3),
+                (0x38, 0x100000ef),
+                (0x3c, 0x00600fe1),
+                (0x40, 0x20f09fe5),
+                (0x44, 0x0aa0a0e3),
+                (0x4c, 0x0e80a0e1),
+                (0x50, 0x0ff228e3),
+                (0x54, 0x00b00fe1),
+                (0x58, 0x0ef0b0e1),
+                (0x5c, 0x0927014a),
+                (0x60, 0x1047c046),
+                (0x64, 0x44000000),
+                (0x68, 0x5d000000),
+                (0x100, 0x2c000000),
+                (0x104, 0x30000000),
+            ],
+        );
+    }
+
+    #[test]
+    fn dsp01() {
+        let cpu = emulate("tests/dsp01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (0, 0x7fff0000),
+            (1, 0x7fff0000),
+            (2, 0x7fffffff),
+            (3, 1),
+            (4, 256),
+            (6, 0x7fff0000),
+            (8, 0xfefe0000),
+            (9, 0xff0100),
+            (10, 0xff7f),
+            (11, 0x7fff),
+            (PC, 68),
+            (CPSR, 0x8040000),
+        ];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x7f14a0e3),
+                (4, 0xff1881e3),
+                (8, 0x512001e1),
+                (0xc, 0x123f6fe1),
+                (0x10, 0x014ca0e3),
+                (0x14, 0x925f84e1),
+                (0x18, 0x9f6f94e1),
+                (0x1c, 0x915f84e1),
+                (0x20, 0xd060c4e1),
+                (0x24, 0x918f51e6),
+                (0x28, 0xb49f81e6),
+                (0x2c, 0x31afbfe6),
+                (0x30, 0x51b2afe6),
+                (0x34, 0xe1036ce1),
+                (0x38, 0x930004e1),
+                (0x100, 0x01000000),
+            ],
+        );
+    }
+
+    #[test]
+    fn nv02() {
+        let cpu = emulate("tests/nv02");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![(1, 1), (3, 3), (PC, 20), (CPSR, 0)];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(&mut cpu, vec![(0, 0x0110a0e3), (4, 0x00f0d1f5), (8, 0x0330a0e3)]);
+    }
+
+    #[test]
+    fn opt_add05() {
+        let cpu = emulate("tests/opt_add05");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let registers_special = vec![(1, 4), (2, 2), (3, 6), (4, 10), (5, 9), (PC, 28)];
+
+        let mut expected = CpuState {
+            registers: reg_from(registers_special),
+            memory: mem_empty![],
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x0410a0e3),
+                (4, 0x0220a0e3),
+                (8, 0x023081e0),
+                (0xc, 0x44083e2),
+                (0x10, 0x335244e0)
+            ],
+
+        );
+    }
+
+    #[test]
+    fn opt_ldr10() {
+        let cpu = emulate("tests/opt_ldr10");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let registers_special = vec![(1, 12), (2, 10), (3, 14), (4, 0x5122e593), (5, 0x5122e593), (PC, 28)];
+
+        let mut expected = CpuState {
+            registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x0c10a0e3),
+                (4, 0x0a20a0e3),
+                (8, 0x0e30a0e3),
+                (0xc, 0x004093e5),
+                (0x10, 0x225191e7)
+            ],
+
+        );
+    }
+
+    #[test]
+    fn or01() {
+        let cpu = emulate("tests/or01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let registers_special = vec![(2, 171), (PC, 16)];
+
+        let mut expected = CpuState {
+            registers: reg_from(registers_special),
+            memory: mem_empty![],
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x0010a0e3),
+                (4, 0xab2081e3)
+            ],
+
+        );
+    }
+
+    #[test]
+    fn or02() {
+        let cpu = emulate("tests/or02");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let registers_special = vec![(1, 15), (2, 171), (3, 175), (PC, 20)];
+
+        let mut expected = CpuState {
+            registers: reg_from(registers_special),
+            memory: mem_empty![],
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x0f10a0e3),
+                (4, 0xab20a0e3),
+                (8, 0x023081e1)
+            ],
+
+        );
+    }
+
+    #[test]
+    fn rsb01() {
+        let cpu = emulate("tests/rsb01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let registers_special = vec![(1, 2), (2, 0xffffffff), (PC, 16)];
+
+        let mut expected = CpuState {
+            registers: reg_from(registers_special),
+            memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1002,6 +1966,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1024,6 +1989,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1036,6 +2002,116 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rsc01() {
+        let cpu = emulate("tests/rsc01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![(1, 3), (2, 0xfffffffd), (3, 6), (PC, 20), (CPSR, 0x80000000)];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(&mut cpu, vec![(0, 0x0310a0e3), (4, 0x002071e2), (8, 0x0a30e1e2)]);
+    }
+
+    #[test]
+    fn sbc01() {
+        let cpu = emulate("tests/sbc01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![(1, 5), (2, 3), (4, 4), (PC, 28), (CPSR, 0x60000000)];
+
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x0510a0e3),
+                (4, 0x060051e3),
+                (8, 0x0120c1e2),
+                (0xc, 0x053051e2),
+                (0x10, 0x0140c1e2),
+            ],
+        );
+    }
+
+    #[test]
+    fn semi01() {
+        // Writes a file through semihosting, fails to open one outside
+        // the host directory and exits with status 3
+        let dir = std::env::temp_dir().join(format!("arm_semi01_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let options = EmulateOptions {
+            host_directory: Some(dir.clone()),
+            ..EmulateOptions::default()
+        };
+        let (mut cpu, exit_status) = run("tests/semi01", &options).unwrap();
+        let written = std::fs::read_to_string(dir.join("out.txt"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(exit_status, Some(3));
+        assert_eq!(written.unwrap(), "data");
+
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![(1, 0xc0), (4, 1), (6, 0xffffffff), (PC, 96), (CPSR, 0)];
+        let mut expected = CpuState {
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(&mut cpu, vec![(0xa4, 0x01000000), (0xb0, 0x01000000)]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn sandboxes_semihosting_files() {
+        // Without a host directory the program can't open out.txt
+        let (cpu, exit_status) = run("tests/semi01", &EmulateOptions::default()).unwrap();
+        assert_eq!(exit_status, Some(3));
+        assert_eq!(cpu.registers[4], 0xffff_ffff);
+
+        // Nor through a link leading out of the host directory
+        let dir = std::env::temp_dir().join(format!("arm_sandbox_{}", std::process::id()));
+        let outside = dir.with_extension("txt");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&outside, "outside").unwrap();
+        std::os::unix::fs::symlink(&outside, dir.join("out.txt")).unwrap();
+        let options = EmulateOptions {
+            host_directory: Some(dir.clone()),
+            ..EmulateOptions::default()
+        };
+        let (cpu, _) = run("tests/semi01", &options).unwrap();
+        let written = std::fs::read_to_string(&outside);
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&outside).unwrap();
+        assert_eq!(cpu.registers[4], 0xffff_ffff);
+        assert_eq!(written.unwrap(), "outside");
+    }
+
+    #[test]
+    fn semihosts_through_the_high_memory_mirror() {
+        // SYS_GET_CMDLINE with its parameter block and buffer at the high vectors
+        let mut cpu = CpuState::default();
+        cpu.write_word(0xffff_0100, 0xffff_0200).unwrap();
+        cpu.write_word(0xffff_0104, 16).unwrap();
+        cpu.registers[0] = 0x15;
+        cpu.registers[1] = 0xffff_0100;
+        Semihosting::new(None, "prog").call(&mut cpu);
+        assert_eq!(cpu.registers[0], 0);
+        assert_eq!(&cpu.memory[0x200..0x205], b"prog\0");
+        assert_eq!(cpu.read_word(0x104), Ok(4));
+    }
+
     #[test]
     fn str01() {
         let cpu = emulate("tests/str01");
@@ -1046,6 +2122,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1070,6 +2147,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1094,6 +2172,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1122,6 +2201,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1146,6 +2226,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1168,6 +2249,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1190,6 +2272,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1203,21 +2286,68 @@ mod tests {
     }
 
     #[test]
-    fn tst01() {
-        let cpu = emulate("tests/tst01");
+    fn push01() {
+        let cpu = emulate("tests/push01");
         assert!(cpu.is_ok());
         let mut cpu = cpu.unwrap();
-        let registers_special = vec![(1, 1), (2, 2), (4, 4), (PC, 32), (CPSR, 0x40000000)];
+        let expected_mem: Vec<u8> = vec![0; 65536];
+        let special_registers = vec![
+            (1, 2),
+            (2, 3),
+            (3, 1),
+            (4, 2),
+            (5, 3),
+            (7, 7),
+            (13, 256),
+            (14, 36),
+            (PC, 48),
+            (CPSR, 0),
+        ];
 
         let mut expected = CpuState {
-            registers: reg_from(registers_special),
-            memory: mem_empty![],
+            registers: reg_from(special_registers),
+            memory: expected_mem.into_boxed_slice(),
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
             &mut cpu,
             vec![
-                (0, 0x0110a0e3),
+                (0, 0x01dca0e3),
+                (4, 0x0100a0e3),
+                (8, 0x0210a0e3),
+                (0xc, 0x0320a0e3),
+                (0x10, 0x24e0a0e3),
+                (0x14, 0x07402de9),
+                (0x18, 0x0000a0e3),
+                (0x1c, 0x3880bde8),
+                (0x20, 0x0960a0e3),
+                (0x24, 0x0770a0e3),
+                (0xf0, 0x01000000),
+                (0xf4, 0x02000000),
+                (0xf8, 0x03000000),
+                (0xfc, 0x24000000),
+            ],
+        );
+    }
+
+    #[test]
+    fn tst01() {
+        let cpu = emulate("tests/tst01");
+        assert!(cpu.is_ok());
+        let mut cpu = cpu.unwrap();
+        let registers_special = vec![(1, 1), (2, 2), (4, 4), (PC, 32), (CPSR, 0x40000000)];
+
+        let mut expected = CpuState {
+            registers: reg_from(registers_special),
+            memory: mem_empty![],
+            ..CpuState::default()
+        };
+        registers_eq(&mut cpu, &mut expected);
+        memory_eq(
+            &mut cpu,
+            vec![
+                (0, 0x0110a0e3),
                 (4, 0x0220a0e3),
                 (8, 0x020011e1),
                 (0xc, 0x0000000a),
@@ -1238,6 +2368,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1264,6 +2395,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1290,6 +2422,7 @@ mod tests {
         let mut expected = CpuState {
             registers: reg_from(registers_special),
             memory: mem_empty![],
+            ..CpuState::default()
         };
         registers_eq(&mut cpu, &mut expected);
         memory_eq(
@@ -1306,3 +2439,1835 @@ mod tests {
         );
     }
 }
+
+#[cfg(test)]
+mod assembler_tests {
+    use std::fs;
+
+    use crate::assembler::asm_utilities::*;
+    use crate::assembler::symbol_table::{SymbolTable, Value};
+    use crate::assembler::two_pass_assembler::{
+        assemble, assemble_file, assemble_source, AssembleOptions,
+    };
+    use crate::elf;
+    use crate::emulator::pipeline_executor::emulate;
+    use crate::emulator::symbol_map::SymbolMap;
+
+    /// Assembles a snippet and returns its words
+    fn assemble_words(source: &str) -> Vec<u32> {
+        let bytes = assemble_source(source).expect("source should assemble");
+        bytes
+            .chunks(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect()
+    }
+
+    /// Asserts that the snippet fails to assemble with a single error on the given line
+    fn assert_error_on_line(source: &str, line: usize) {
+        match assemble_source(source) {
+            Ok(_) => panic!("Expected `{}` to fail assembling", source),
+            Err(errors) => {
+                assert_eq!(errors.len(), 1, "Unexpected errors: {:?}", errors);
+                assert_eq!(errors[0].location.line, line, "Unexpected error: {}", errors[0]);
+            }
+        }
+    }
+
+    /// Assembles a snippet that should fail and renders its errors
+    fn rendered_errors(source: &str) -> Vec<String> {
+        let errors = assemble_source(source).expect_err("source should not assemble");
+        errors.iter().map(AsmError::to_string).collect()
+    }
+
+    #[test]
+    fn assembles_every_test_program() {
+        let mut checked = 0;
+        for entry in fs::read_dir("tests").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "s") {
+                continue;
+            }
+            let source = fs::read_to_string(&path).unwrap();
+            let expected = fs::read(path.with_extension("")).unwrap();
+            let got = assemble_source(&source)
+                .unwrap_or_else(|errors| panic!("{}: {}", path.display(), render_errors(&errors)));
+            assert!(got == expected, "Binary mismatch for {}", path.display());
+            checked += 1;
+        }
+        assert!(checked >= 60, "Expected to check the test programs, only found {}", checked);
+    }
+
+    #[test]
+    fn splits_mnemonics() {
+        let bls = split_mnemonic("bls").unwrap();
+        assert_eq!((bls.base, bls.cond), ("b", 9));
+        let bleq = split_mnemonic("bleq").unwrap();
+        assert_eq!((bleq.base, bleq.cond), ("bl", 0));
+        let addeqs = split_mnemonic("addeqs").unwrap();
+        assert_eq!((addeqs.base, addeqs.cond, addeqs.suffix), ("add", 0, "s"));
+        let ldrbne = split_mnemonic("LDRBNE").unwrap();
+        assert_eq!((ldrbne.base, ldrbne.cond, ldrbne.suffix), ("ldr", 1, "b"));
+        assert!(split_mnemonic("cmps").is_err());
+    }
+
+    #[test]
+    fn encodes_rotated_immediates() {
+        assert_eq!(encode_rotated_immediate(0xFF), Some(0xFF));
+        assert_eq!(encode_rotated_immediate(0x0300_0000), Some(0x403));
+        assert_eq!(encode_rotated_immediate(0xF000_000F), Some(0x2FF));
+        assert_eq!(encode_rotated_immediate(0x101), None);
+    }
+
+    #[test]
+    fn encodes_instruction_forms() {
+        let words = assemble_words(
+            "start: mvn r0, #0\n\
+             mov r0, #-1\n\
+             cmp r1, #-4\n\
+             ldr r0, [r1, #4]!\n\
+             strb r2, [r3], -r4, lsl #2\n\
+             mov r5, r6, rrx\n\
+             lsr r1, r2, #32\n\
+             bl start\n",
+        );
+        assert_eq!(
+            words,
+            vec![
+                0xe3e0_0000,
+                0xe3e0_0000,
+                0xe371_0004,
+                0xe5b1_0004,
+                0xe643_2104,
+                0xe1a0_5066,
+                0xe1a0_1022,
+                0xebff_fff7,
+            ]
+        );
+    }
+
+    #[test]
+    fn encodes_halfword_transfers() {
+        let words = assemble_words(
+            "ldrh r0, [r1, #-255]\n\
+             strhne r2, [r3], r4\n\
+             ldrsb r5, [r6, -r7]!\n\
+             ldreqsh r8, value\n\
+             value: .word 0\n",
+        );
+        assert_eq!(
+            words,
+            vec![0xe151_0fbf, 0x1083_20b4, 0xe136_50d7, 0x015f_80f4, 0]
+        );
+        assert_error_on_line("ldrh r0, [r1, #256]\n", 1);
+        assert_error_on_line("ldrsb r0, [r1, r2, lsl #1]\n", 1);
+        assert_error_on_line("strsh r0, [r1]\n", 1);
+        assert_error_on_line("ldrsh r0, =1\n", 1);
+    }
+
+    #[test]
+    fn encodes_dsp_and_media_instructions() {
+        let words = assemble_words(
+            "clz r0, r1\n\
+             qdsub r0, r1, r2\n\
+             smulwt r0, r1, r2\n\
+             smlalbt r0, r1, r2, r3\n\
+             ldrd r0, [r2, #8]\n\
+             strd r0, r1, [r2], -r3\n\
+             uxth r0, r1, ror #16\n\
+             qadd16 r0, r1, r2\n\
+             saddsubx r0, r1, r2\n\
+             ssat r0, #8, r1, asr #2\n\
+             usat16 r0, #15, r1\n\
+             revsh r0, r1\n\
+             strex r0, r2, [r1]\n\
+             swpb r0, r1, [r2]\n",
+        );
+        assert_eq!(
+            words,
+            vec![
+                0xe16f_0f11,
+                0xe162_0051,
+                0xe120_02e1,
+                0xe141_03c2,
+                0xe1c2_00d8,
+                0xe002_00f3,
+                0xe6ff_0871,
+                0xe621_0f12,
+                0xe611_0f32,
+                0xe6a7_0151,
+                0xe6ef_0f31,
+                0xe6ff_0fb1,
+                0xe181_0f92,
+                0xe142_0091,
+            ]
+        );
+        assert_error_on_line("ldrd r1, [r2]\n", 1);
+        assert_error_on_line("ldrd r0, r2, [r2]\n", 1);
+        assert_error_on_line("ssat r0, #0, r1\n", 1);
+        assert_error_on_line("usat r0, #4, r1, ror #2\n", 1);
+        assert_error_on_line("sxtb r0, r1, ror #4\n", 1);
+        assert_error_on_line("smul r0, r1, r2\n", 1);
+    }
+
+    #[test]
+    fn encodes_branch_exchanges() {
+        let words = assemble_words("bx lr\nblxne r3\nblx 16\nblx 18\n");
+        assert_eq!(words, vec![0xe12f_ff1e, 0x112f_ff33, 0xfa00_0000, 0xfbff_ffff]);
+        assert_error_on_line("bx 4\n", 1);
+        assert_error_on_line("blxeq 8\n", 1);
+        assert_error_on_line("blx 7\n", 1);
+    }
+
+    #[test]
+    fn encodes_psr_transfers() {
+        let words = assemble_words(
+            "mrs r1, cpsr\n\
+             mrsne r2, spsr\n\
+             msr cpsr_c, r0\n\
+             msr spsr_fsxc, #0x10\n\
+             msr cpsr, r3\n\
+             msr cpsr_flg, #0xf0000000\n",
+        );
+        assert_eq!(
+            words,
+            vec![0xe10f_1000, 0x114f_2000, 0xe121_f000, 0xe36f_f010, 0xe129_f003, 0xe328_f20f]
+        );
+        assert_error_on_line("msr cpsr_cc, r0\n", 1);
+        assert_error_on_line("msr apsr_c, r0\n", 1);
+        assert_error_on_line("mrs r0, cpsr_c\n", 1);
+        assert_error_on_line("msr cpsr_f, #0x101\n", 1);
+    }
+
+    #[test]
+    fn encodes_software_interrupts() {
+        let words = assemble_words("swi 0x123456\nsvcne #0xab\n");
+        assert_eq!(words, vec![0xef12_3456, 0x1f00_00ab]);
+        assert_error_on_line("swi 0x1000000\n", 1);
+        assert_error_on_line("swi r0\n", 1);
+    }
+
+    #[test]
+    fn encodes_block_transfers() {
+        let words = assemble_words(
+            "push {r0-r3, lr}\n\
+             pop {r4, pc}\n\
+             ldmfa r0, {r1}\n\
+             stmedne r1!, {r2-r4}^\n\
+             ldmib r2, {r0, sp}\n",
+        );
+        assert_eq!(
+            words,
+            vec![0xe92d_400f, 0xe8bd_8010, 0xe810_0002, 0x1861_001c, 0xe992_2001]
+        );
+        assert_error_on_line("ldm r0, {}\n", 1);
+        assert_error_on_line("push {r3-r1}\n", 1);
+        assert_error_on_line("ldmia r0, r1\n", 1);
+    }
+
+    #[test]
+    fn places_literal_pools() {
+        let words = assemble_words(
+            "ldr r0, =0x20200000\n\
+             ldr r1, =0xFF000000\n\
+             ldr r2, =0x20200000\n\
+             ldreq r3, =0x12345678\n\
+             b skip\n\
+             .ltorg\n\
+             skip: ldr r4, =0x12345678\n\
+             andeq r0, r0, r0\n",
+        );
+        assert_eq!(
+            words,
+            vec![
+                // Repeated constants share their pool entry
+                0xe59f_000c,
+                0xe3a0_14ff,
+                0xe59f_2004,
+                0x059f_3004,
+                0xea00_0001,
+                0x2020_0000,
+                0x1234_5678,
+                // A new pool after .ltorg, placed at the end of the program
+                0xe59f_4000,
+                0x0000_0000,
+                0x1234_5678,
+            ]
+        );
+
+        // Spacing doesn't matter when sharing entries, but it does inside character constants
+        let words = assemble_words(
+            "ldr r0, =label + 4\n\
+             ldr r1, =label+4\n\
+             ldr r2, =0x12345600+' '\n\
+             label: andeq r0, r0, r0\n",
+        );
+        assert_eq!(words[..2], [0xe59f_0008, 0xe59f_1004]);
+        assert_eq!(words[2..], [0xe59f_2004, 0, 0x10, 0x1234_5620]);
+    }
+
+    #[test]
+    fn rejects_literal_pools_out_of_reach() {
+        let mut source = String::from("ldr r0, =0x12345678\n");
+        source.push_str(&"mov r1, r1\n".repeat(1100));
+        assert_error_on_line(&source, 1);
+        assert_error_on_line("strb r0, =0x12345678\n", 1);
+    }
+
+    #[test]
+    fn lays_out_data_directives() {
+        let bytes = assemble_source(
+            ".equ BASE, 0x100\n\
+             .set COUNT, 3\n\
+             ldr r0, =table\n\
+             ldr r1, value\n\
+             b end\n\
+             table: .word 1, -2, BASE\n\
+             value: .hword 0xbeef, 0xdead\n\
+             msg: .asciz \"hi\\n\", \"\\x41\\101\"\n\
+             .align 2\n\
+             buf: .space COUNT, 0xaa\n\
+             .balign 4\n\
+             end: mov r2, #COUNT\n\
+             .fill 2, 2, 0x1234\n\
+             .org 0x40\n\
+             .byte 0xff, -1\n",
+        )
+        .unwrap();
+
+        let mut expected: Vec<u8> = Vec::new();
+        for word in &[0xe59f_003c_u32, 0xe59f_100c, 0xea00_0006, 1, 0xffff_fffe, 0x100] {
+            expected.extend_from_slice(&word.to_le_bytes());
+        }
+        expected.extend_from_slice(&[0xef, 0xbe, 0xad, 0xde]);
+        expected.extend_from_slice(b"hi\n\0AA\0\0");
+        expected.extend_from_slice(&[0xaa, 0xaa, 0xaa, 0]);
+        expected.extend_from_slice(&0xe3a0_2003_u32.to_le_bytes());
+        expected.extend_from_slice(&[0x34, 0x12, 0x34, 0x12]);
+        expected.resize(0x40, 0);
+        // The literal pool for `=table` goes after the data, word aligned
+        expected.extend_from_slice(&[0xff, 0xff, 0, 0, 0x0c, 0, 0, 0]);
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        assert_error_on_line("mov r0, #1\nmov r0, #0x101\n", 2);
+        assert_error_on_line("mov r16, #1\n", 1);
+        assert_error_on_line("b nowhere\n", 1);
+        assert_error_on_line("loop:\nloop:\n", 2);
+        assert_error_on_line("mov r0, #1\nfrobnicate r0\n", 2);
+        assert_error_on_line(".byte 1\nmov r0, #1\n", 2);
+        assert_error_on_line(".org 8\n.org 4\n", 2);
+        assert_error_on_line(".equ A, 1\n.equ A, 2\n", 2);
+        assert_error_on_line(".ascii \"\\q\"\n", 1);
+    }
+
+    #[test]
+    fn reports_every_error_with_a_caret() {
+        let errors = rendered_errors(
+            "start: mov r0, #0x101\n\
+             \tadd r16, r1, r2\n\
+             b loop2\n\
+             mov r1, #1\n\
+             ldr r2, =missing\n",
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "<source>:1:16: error: immediate #0x101 cannot be encoded as rotated 8-bit, \
+                 use ldr =\nstart: mov r0, #0x101\n               ^",
+                "<source>:2:6: error: register r16 does not exist\n\tadd r16, r1, r2\n\t    ^",
+                "<source>:3:3: error: undefined label `loop2`\nb loop2\n  ^",
+                "<source>:5:10: error: undefined label `missing`\nldr r2, =missing\n         ^",
+            ]
+        );
+
+        // Errors about values point at the operand
+        let errors = rendered_errors("mov r0, #1 << 40\nldr r1, ='ab'\n");
+        assert_eq!(
+            errors,
+            vec![
+                "<source>:1:10: error: `1 << 40` is 1099511627776, which does not fit in 32 bits\n\
+                 mov r0, #1 << 40\n         ^",
+                "<source>:2:10: error: invalid character constant `'ab`\nldr r1, ='ab'\n         ^",
+            ]
+        );
+
+        // The literal pool comes last, its errors are still reported in line order
+        let errors = assemble_source("ldr r0, =missing\nmov r0, #0x101\n").unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|error| error.location.line).collect();
+        assert_eq!(lines, vec![1, 2]);
+
+        let errors = rendered_errors(".org 0x4000000\nb 0\n");
+        assert_eq!(
+            errors,
+            vec!["<source>:2:1: error: branch target 0x0 out of ±32MB range\nb 0\n^"]
+        );
+        let errors = assemble_source(".if 1\n").unwrap_err();
+        assert_eq!(errors[0].to_string(), "<source>:1: error: `.if` is missing its `.endif`");
+        assert!(render_errors(&errors).ends_with("\n1 error found"));
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let mut symbols = SymbolTable::new();
+        symbols.set("BASE", Value::absolute(0x100));
+        let cases: Vec<(&str, i64)> = vec![
+            ("1 + 2 * 3", 7),
+            ("(1 + 2) * 3", 9),
+            ("1 << 9", 512),
+            ("10 - 4 - 3", 3),
+            ("~0 & 0xff", 255),
+            ("-3 + 5", 2),
+            ("7 / 2 + 7 % 3", 4),
+            ("1 < 2 && 3 == 3", 1),
+            ("6 | 1 ^ 3", 6),
+            ("1 + 2 << 3", 24),
+            ("!BASE || 0", 0),
+            ("'a' + 1", 98),
+            ("BASE + 4*3", 0x10c),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(evaluate(expression, &symbols), Ok(expected), "{}", expression);
+        }
+        for expression in &["1 / 0", "(1 + 2", "1 +", "missing", "1 << 64", "1 2", ")"] {
+            assert!(evaluate(expression, &symbols).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn assembles_expression_operands() {
+        let words = assemble_words(
+            ".equ GPIO_BASE, 0x20200000\n\
+             .equ SIZE, end - start\n\
+             start: mov r0, #1 << 9\n\
+             ldr r1, =GPIO_BASE + 4*3\n\
+             mov r2, #(end - start)\n\
+             ldr r3, =table+8\n\
+             mov r4, #SIZE\n\
+             table: .word end - ., . - start\n\
+             end:\n",
+        );
+        assert_eq!(
+            words,
+            vec![
+                0xe3a0_0c02,
+                0xe59f_1010,
+                0xe3a0_201c,
+                0xe59f_300c,
+                0xe3a0_401c,
+                8,
+                20,
+                0x2020_000c,
+                28,
+            ]
+        );
+    }
+
+    #[test]
+    fn places_data_after_text() {
+        let words = assemble_words(
+            ".text\n\
+             start: ldr r0, =value\n\
+             mov r2, #0\n\
+             .data\n\
+             value: .word 42\n\
+             .section .text\n\
+             mov r3, #(value & 0xff)\n",
+        );
+        assert_eq!(
+            words,
+            vec![0xe59f_0004, 0xe3a0_2000, 0xe3a0_3010, 0x10, 42]
+        );
+
+        let errors = rendered_errors("start: mov r0, #1\n.data\nvalue: .word value - start\n");
+        assert_eq!(
+            errors,
+            vec!["<source>:3:8: error: cannot subtract an address in `.text` from one in `.data`\n\
+                  value: .word value - start\n       ^"]
+        );
+        assert_error_on_line(".data\nvalue: .word 1\n.text\n.space value\n", 4);
+        assert_error_on_line(".section .bss\n", 1);
+    }
+
+    #[test]
+    fn expands_macros() {
+        let words = assemble_words(
+            ".macro clear reg, value=0\n\
+             mov \\reg, #\\value\n\
+             .endm\n\
+             .macro countdown reg\n\
+             loop\\@: subs \\reg, \\reg, #1\n\
+             bne loop\\@\n\
+             .endm\n\
+             start: clear r1\n\
+             clear value=7, reg=r2\n\
+             countdown r1\n\
+             countdown r2\n\
+             b start\n",
+        );
+        assert_eq!(
+            words,
+            vec![
+                0xe3a0_1000,
+                0xe3a0_2007,
+                0xe251_1001,
+                0x1aff_fffd,
+                0xe252_2001,
+                0x1aff_fffd,
+                0xeaff_fff8,
+            ]
+        );
+
+        assert_error_on_line(".macro twice\nmov r0, #1\n", 1);
+        assert_error_on_line(".macro pair a, b\n.endm\npair r0\n", 3);
+        assert_error_on_line(".macro loop\nloop\n.endm\nloop\n", 4);
+    }
+
+    #[test]
+    fn assembles_conditionally() {
+        let words = assemble_words(
+            ".equ DEBUG, 1\n\
+             .equ LEVEL, 0\n\
+             .if DEBUG\n\
+             mov r0, #1\n\
+             .if LEVEL\n\
+             mov r0, #2\n\
+             .else\n\
+             mov r0, #3\n\
+             .endif\n\
+             .else\n\
+             mov r0, #4\n\
+             .endif\n\
+             .ifdef TRACE\n\
+             mov r1, #1\n\
+             .endif\n\
+             .ifndef TRACE\n\
+             mov r1, #2\n\
+             .endif\n",
+        );
+        assert_eq!(words, vec![0xe3a0_0001, 0xe3a0_0003, 0xe3a0_1002]);
+
+        assert_error_on_line("mov r0, #1\n.if 1\nmov r0, #2\n", 2);
+        assert_error_on_line(".else\n", 1);
+        assert_error_on_line(".if MISSING\n.endif\n", 1);
+    }
+
+    #[test]
+    fn includes_files() {
+        let dir = std::env::temp_dir().join(format!("arm_includes_{}", std::process::id()));
+        let lib = dir.join("lib");
+        fs::create_dir_all(&lib).unwrap();
+        fs::write(lib.join("defs.s"), ".equ VALUE, 42\n.macro load reg\nmov \\reg, #VALUE\n.endm\n")
+            .unwrap();
+        fs::write(dir.join("main.s"), ".include \"defs.s\"\nload r3\n").unwrap();
+        fs::write(dir.join("a.s"), "mov r0, #1\n.include \"b.s\"\n").unwrap();
+        fs::write(dir.join("b.s"), ".include \"a.s\"\n").unwrap();
+
+        let assembly = assemble_file(&dir.join("main.s"), &[lib]).unwrap();
+        assert_eq!(assembly.binary, 0xe3a0_302a_u32.to_le_bytes());
+
+        let errors = assemble_file(&dir.join("a.s"), &[]).unwrap_err();
+        assert_eq!(errors[0].location.line, 1);
+        assert!(errors[0].message.starts_with("include cycle"), "{}", errors[0]);
+        let errors = assemble_file(&dir.join("main.s"), &[]).unwrap_err();
+        assert!(errors[0].to_string().contains("main.s:1"), "{}", errors[0]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_listing_and_symbol_map() {
+        let dir = std::env::temp_dir().join(format!("arm_listing_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("prog.s");
+        fs::write(
+            &path,
+            ".macro inc reg\n\
+             add \\reg, \\reg, #1\n\
+             .endm\n\
+             start: mov r0, #0\n\
+             loop: inc r0\n\
+             ldr r1, =0x12345678\n\
+             .byte 1, 2, 3, 4, 5\n\
+             end:\n",
+        )
+        .unwrap();
+
+        let assembly = assemble_file(&path, &[]).unwrap();
+        let file = path.display().to_string();
+        let listing: Vec<&str> = assembly.listing.lines().collect();
+        let expected = vec![
+            format!("00000000  e3a00000   {}:4 start: mov r0, #0", file),
+            format!("00000004           {}:5 loop:", file),
+            format!("00000004  e2800001 + {}:5 add r0, r0, #1", file),
+            format!("00000008  e59f1004   {}:6 ldr r1, =0x12345678", file),
+            format!("0000000c  01020304   {}:7 .byte 1, 2, 3, 4, 5", file),
+            String::from("00000010  05"),
+            format!("00000011           {}:8 end:", file),
+            format!("00000014  12345678   {}:6     .word 0x12345678 @ literal pool", file),
+        ];
+        for (got, expected) in listing.iter().zip(&expected) {
+            // Short file names are padded to line the source up
+            let got: Vec<&str> = got.split_whitespace().collect();
+            let expected: Vec<&str> = expected.split_whitespace().collect();
+            assert_eq!(got, expected);
+        }
+        assert_eq!(listing.len(), expected.len());
+        assert_eq!(assembly.symbol_map, "00000000 start\n00000004 loop\n00000011 end\n");
+
+        let symbols = SymbolMap::parse(&assembly.symbol_map).unwrap();
+        assert_eq!(symbols.describe(0x4).as_deref(), Some("loop"));
+        assert_eq!(symbols.describe(0xc).as_deref(), Some("loop+0x8"));
+        assert_eq!(symbols.annotate(0x20), "0x00000020 <end+0xf>");
+        assert_eq!(SymbolMap::default().annotate(0x20), "0x00000020");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_and_loads_elf_executables() {
+        let dir = std::env::temp_dir().join(format!("arm_elf_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("prog.s");
+        let out = dir.join("prog.elf");
+        fs::write(
+            &source,
+            ".text\n\
+             skipped: mov r1, #7\n\
+             _start: mov r0, #1\n\
+             ldr r2, =value\n\
+             ldr r3, [r2]\n\
+             .word 0\n\
+             .data\n\
+             value: .word 42\n",
+        )
+        .unwrap();
+        let options = AssembleOptions {
+            elf: true,
+            ..AssembleOptions::default()
+        };
+        assemble(source.to_str().unwrap(), out.to_str().unwrap(), &options).unwrap();
+
+        let bytes = fs::read(&out).unwrap();
+        let executable = elf::read_executable(&bytes).unwrap();
+        assert_eq!(executable.entry, 4);
+        let segments: Vec<(u32, usize)> = executable
+            .segments
+            .iter()
+            .map(|segment| (segment.address, segment.data.len()))
+            .collect();
+        assert_eq!(segments, vec![(0, 0x18), (0x18, 4)]);
+        let names: Vec<&str> = executable.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["skipped", "_start", "value"]);
+
+        // Execution starts at `_start`, so r1 is never written
+        let cpu = emulate(out.to_str().unwrap()).unwrap();
+        assert_eq!(&cpu.registers[..4], &[1, 0, 0x18, 42]);
+        let symbols = SymbolMap::find_for(out.to_str().unwrap()).unwrap();
+        assert_eq!(symbols.describe(0x1c).as_deref(), Some("value+0x4"));
+
+        assert!(elf::read_executable(&bytes[..60]).is_err());
+        fs::write(&out, &bytes[..60]).unwrap();
+        assert!(emulate(out.to_str().unwrap()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod disassembler_tests {
+    use std::fs;
+
+    use crate::assembler::two_pass_assembler::assemble_source;
+    use crate::emulator::disassembler::*;
+
+    #[test]
+    fn renders_instructions() {
+        let cases: Vec<(u32, &str)> = vec![
+            (0x1aff_fffa, "bne 0x10"),
+            (0xeb00_0000, "bl 0x28"),
+            (0x0000_0000, "andeq r0, r0, r0"),
+            (0xe3a0_0403, "mov r0, #0x3000000"),
+            (0xe1b0_5066, "movs r5, r6, rrx"),
+            (0xe0445233, "sub r5, r4, r3, lsr r2"),
+            (0x01a0_1022, "moveq r1, r2, lsr #32"),
+            (0xe371_0004, "cmn r1, #4"),
+            (0xe033_4291, "mlas r3, r1, r2, r4"),
+            (0xe5b1_0004, "ldr r0, [r1, #4]!"),
+            (0xe491_2005, "ldr r2, [r1], #5"),
+            (0xe643_2104, "strb r2, [r3], -r4, lsl #2"),
+            (0xe513_3004, "ldr r3, [r3, #-4]"),
+            (0xe59f_0008, "ldr r0, [pc, #8] @ 0x30"),
+            (0xf000_0000, ".word 0xf0000000"),
+            (0xe100_0090, "swp r0, r0, [r0]"),
+            (0xe16f_0f11, "clz r0, r1"),
+            (0xe120_0172, "bkpt #18"),
+            (0xe141_03c2, "smlalbt r0, r1, r2, r3"),
+            (0xe162_40d8, "ldrd r4, r5, [r2, #-8]!"),
+            (0xe1c2_10d8, ".word 0xe1c210d8"),
+            (0xe6b1_0c72, "sxtah r0, r1, r2, ror #24"),
+            (0xe661_0ff2, "uqsub8 r0, r1, r2"),
+            (0xe6a0_0051, "ssat r0, #1, r1, asr #32"),
+            (0xe181_0f92, "strex r0, r2, [r1]"),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(disassemble_instr(code, 0x20), expected, "code 0x{:08x}", code);
+        }
+    }
+
+    #[test]
+    fn prints_memory_layout() {
+        let lines = disassemble_binary(&[0x01, 0x10, 0xa0, 0xe3, 0xfa, 0xff, 0xff, 0x1a]);
+        assert_eq!(
+            lines,
+            vec![
+                "0x00000000: 0x0110a0e3    mov r1, #1",
+                "0x00000004: 0xfaffff1a    bne 0xfffffff4",
+            ]
+        );
+    }
+
+    #[test]
+    fn reassembles_every_test_program() {
+        for entry in fs::read_dir("tests").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some() || !path.is_file() {
+                continue;
+            }
+            let binary = fs::read(&path).unwrap();
+            for (ind, word) in binary.chunks(4).enumerate() {
+                let address = ind * 4;
+                let code = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                let text = disassemble_instr(code, address as u32);
+                let source = format!(".org {}\n{}\n", address, text);
+                let bytes = assemble_source(&source)
+                    .unwrap_or_else(|errors| {
+                        panic!("`{}` from {}: {:?}", text, path.display(), errors)
+                    });
+                assert_eq!(
+                    &bytes[address..address + 4],
+                    word,
+                    "`{}` from {} does not reassemble",
+                    text,
+                    path.display()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod alu_tests {
+    use crate::emulator::alu::{self, AluResult};
+
+    /// The value and the C and V flags of a result
+    fn flags(result: AluResult) -> (u32, Option<bool>, Option<bool>) {
+        (result.value, result.carry, result.overflow)
+    }
+
+    #[test]
+    fn adds_with_carry_and_overflow() {
+        assert_eq!(flags(alu::add(1, 2, false)), (3, Some(false), Some(false)));
+        assert_eq!(flags(alu::add(1, 2, true)), (4, Some(false), Some(false)));
+        assert_eq!(flags(alu::add(u32::MAX, 1, false)), (0, Some(true), Some(false)));
+        assert_eq!(flags(alu::add(0x7fff_ffff, 1, false)), (0x8000_0000, Some(false), Some(true)));
+        assert_eq!(flags(alu::add(0x8000_0000, 0x8000_0000, false)), (0, Some(true), Some(true)));
+        assert_eq!(flags(alu::add(u32::MAX, 0, true)), (0, Some(true), Some(false)));
+    }
+
+    #[test]
+    fn subtracts_with_borrow() {
+        assert_eq!(flags(alu::subtract(5, 3, true)), (2, Some(true), Some(false)));
+        assert_eq!(flags(alu::subtract(3, 5, true)), (u32::MAX - 1, Some(false), Some(false)));
+        assert_eq!(flags(alu::subtract(5, 5, true)), (0, Some(true), Some(false)));
+        assert_eq!(flags(alu::subtract(5, 3, false)), (1, Some(true), Some(false)));
+        assert_eq!(
+            flags(alu::subtract(0x8000_0000, 1, true)),
+            (0x7fff_ffff, Some(true), Some(true))
+        );
+        assert_eq!(flags(alu::subtract(0, 0, false)), (u32::MAX, Some(false), Some(false)));
+        assert_eq!(flags(alu::reverse_subtract(3, 5, true)), (2, Some(true), Some(false)));
+        assert_eq!(flags(alu::reverse_subtract(3, 5, false)), (1, Some(true), Some(false)));
+    }
+
+    #[test]
+    fn keeps_flags_logical_and_multiply_do_not_produce() {
+        assert_eq!(flags(alu::logical(0, None)), (0, None, None));
+        assert_eq!(flags(alu::logical(4, Some(true))), (4, Some(true), None));
+        assert_eq!(flags(alu::multiply(0)), (0, None, None));
+    }
+}
+
+#[cfg(test)]
+mod barrel_shifter_tests {
+    use crate::emulator::barrel_shifter::*;
+
+    const OPS: [ShiftOp; 4] = [ShiftOp::LSL, ShiftOp::LSR, ShiftOp::ASR, ShiftOp::ROR];
+
+    /// Operands with interesting top and bottom bits
+    const OPERANDS: [u32; 8] = [
+        0,
+        1,
+        0x8000_0000,
+        0xffff_ffff,
+        0x7fff_ffff,
+        0x1234_5678,
+        0x8765_4321,
+        0xa5a5_a5a5,
+    ];
+
+    /// Reference shift: `amount` shifts by one bit, each producing a carry out.
+    /// Shifting by 0 leaves the carry unchanged
+    fn reference(operand: u32, amount: u32, op: ShiftOp) -> ShiftResult {
+        let mut value = operand;
+        let mut carry = None;
+        for _ in 0..amount {
+            match op {
+                ShiftOp::LSL => {
+                    carry = Some(value >> 31 == 1);
+                    value <<= 1;
+                }
+                ShiftOp::LSR => {
+                    carry = Some(value & 1 == 1);
+                    value >>= 1;
+                }
+                ShiftOp::ASR => {
+                    carry = Some(value & 1 == 1);
+                    value = (value >> 1) | (value & 0x8000_0000);
+                }
+                ShiftOp::ROR => {
+                    value = value.rotate_right(1);
+                    carry = Some(value >> 31 == 1);
+                }
+            }
+        }
+        (value, carry)
+    }
+
+    #[test]
+    fn shifts_by_register() {
+        for &op in &OPS {
+            for &operand in &OPERANDS {
+                for amount in 0..=255 {
+                    assert_eq!(
+                        shift_by_register(operand, amount, op),
+                        reference(operand, amount, op),
+                        "{:?} 0x{:x} by {}",
+                        op,
+                        operand,
+                        amount
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shifts_by_immediate() {
+        for &op in &OPS {
+            for &operand in &OPERANDS {
+                for carry in [false, true] {
+                    for amount in 0..32 {
+                        let expected = match (op, amount) {
+                            // LSR #32 and ASR #32
+                            (ShiftOp::LSR, 0) | (ShiftOp::ASR, 0) => reference(operand, 32, op),
+                            // RRX
+                            (ShiftOp::ROR, 0) => {
+                                ((carry as u32) << 31 | operand >> 1, Some(operand & 1 == 1))
+                            }
+                            _ => reference(operand, amount, op),
+                        };
+                        assert_eq!(
+                            shift_by_immediate(operand, amount, op, carry),
+                            expected,
+                            "{:?} 0x{:x} by #{} with carry {}",
+                            op,
+                            operand,
+                            amount,
+                            carry
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotates_immediates() {
+        assert_eq!(rotated_immediate(0xff), (0xff, None));
+        assert_eq!(rotated_immediate(0x4ff), (0xff00_0000, Some(true)));
+        assert_eq!(rotated_immediate(0x102), (0x8000_0000, Some(true)));
+        assert_eq!(rotated_immediate(0xf01), (4, Some(false)));
+    }
+}
+
+#[cfg(test)]
+mod modes_tests {
+    use crate::emulator::modes::{Mode, RegisterBanks};
+
+    #[test]
+    fn banks_registers_per_mode() {
+        let mut banks = RegisterBanks::default();
+        let mut registers = [0; 17];
+        registers[8] = 8;
+        registers[13] = 13;
+        registers[14] = 14;
+
+        banks.switch(&mut registers, Mode::SYS, Mode::FIQ);
+        assert_eq!(registers[8..15], [0; 7]);
+        registers[8] = 0x88;
+        registers[13] = 0x13;
+
+        // IRQ mode has its own r13 and r14 but the user r8-r12
+        banks.switch(&mut registers, Mode::FIQ, Mode::IRQ);
+        assert_eq!(registers[8..15], [8, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(banks.user_register(Mode::IRQ, 13), Some(13));
+        assert_eq!(banks.user_register(Mode::IRQ, 8), None);
+
+        banks.switch(&mut registers, Mode::IRQ, Mode::FIQ);
+        assert_eq!(registers[8..15], [0x88, 0, 0, 0, 0, 0x13, 0]);
+        assert_eq!(banks.user_register(Mode::FIQ, 8), Some(8));
+
+        banks.switch(&mut registers, Mode::FIQ, Mode::USR);
+        assert_eq!(registers[8..15], [8, 0, 0, 0, 0, 13, 14]);
+    }
+
+    #[test]
+    fn reads_modes_from_psrs() {
+        assert_eq!(Mode::from_psr(0xd3), Mode::SVC);
+        assert_eq!(Mode::from_psr(0x8000_0010), Mode::USR);
+        // The reset value of the emulator's CPSR acts as system mode
+        assert_eq!(Mode::from_psr(0), Mode::SYS);
+        assert!(!Mode::USR.is_privileged());
+        assert!(!Mode::SYS.has_spsr() && Mode::UND.has_spsr());
+    }
+}
+
+#[cfg(test)]
+mod exceptions_tests {
+    use crate::emulator::branch_instr::execute_branch_exchange_instr;
+    use crate::emulator::em_utilities::*;
+    use crate::emulator::exceptions::{interrupt, take_exception, Exception};
+    use crate::emulator::modes::Mode;
+    use crate::emulator::pipeline_executor::{decode_instruction, start_pipeline};
+    use crate::emulator::semihosting::Semihosting;
+
+    #[test]
+    fn enters_exception_modes() {
+        let mut cpu = CpuState::default();
+        cpu.registers[PC] = 0x100;
+        cpu.set_cpsr(0x6000_0010);
+        let mut pipe = Pipe::init(&mut cpu);
+        take_exception(&mut cpu, &mut pipe, Exception::DataAbort, 0x80);
+        assert_eq!(cpu.mode(), Mode::ABT);
+        assert_eq!(cpu.cpsr(), 0x6000_0197);
+        assert_eq!(cpu.spsr(), Some(0x6000_0010));
+        assert_eq!(cpu.registers[LR], 0x88);
+        // The vector is in the pipe, the PC is past it
+        assert_eq!(cpu.pc(), 0x14);
+
+        take_exception(&mut cpu, &mut pipe, Exception::FIQ, 0x10);
+        assert_eq!(cpu.cpsr(), 0x6000_01d1);
+        assert_eq!(cpu.spsr(), Some(0x6000_0197));
+        assert_eq!(cpu.pc(), 0x20);
+    }
+
+    #[test]
+    fn masks_interrupts() {
+        let mut cpu = CpuState::default();
+        cpu.set_cpsr(0x10);
+        let mut pipe = Pipe::init(&mut cpu);
+        assert!(interrupt(&mut cpu, &mut pipe, Exception::IRQ));
+        assert_eq!(cpu.mode(), Mode::IRQ);
+        // The fetched instruction at 0 is the next one, so the handler returns to it
+        assert_eq!(cpu.registers[LR], 4);
+        assert!(!interrupt(&mut cpu, &mut pipe, Exception::IRQ));
+        assert!(interrupt(&mut cpu, &mut pipe, Exception::FIQ));
+        assert!(!interrupt(&mut cpu, &mut pipe, Exception::FIQ));
+        assert_eq!(cpu.mode(), Mode::FIQ);
+    }
+
+    #[test]
+    fn takes_pending_interrupts_between_instructions() {
+        // mov r0, #1 then mov r1, #2, with an IRQ line asserted
+        let program = |cpsr| {
+            let mut cpu = CpuState {
+                irq_line: true,
+                ..Default::default()
+            };
+            cpu.write_word(0, 0xe3a0_0001).unwrap();
+            cpu.write_word(4, 0xe3a0_1002).unwrap();
+            cpu.set_cpsr(cpsr);
+            let mut host = Semihosting::new(None, "interrupts");
+            start_pipeline(&mut cpu, &mut host, None);
+            cpu
+        };
+
+        // The IRQ is taken before the first instruction, the empty handler ends the program
+        let cpu = program(0x10);
+        assert_eq!(cpu.mode(), Mode::IRQ);
+        assert_eq!(cpu.registers[0], 0);
+        assert_eq!(cpu.registers[LR], 4);
+        assert_eq!(cpu.spsr(), Some(0x10));
+
+        // With the I bit set the program runs to its end
+        let cpu = program(0x90);
+        assert_eq!(cpu.mode(), Mode::USR);
+        assert_eq!((cpu.registers[0], cpu.registers[1]), (1, 2));
+    }
+
+    #[test]
+    fn has_no_blx_before_armv5() {
+        let mut cpu = CpuState {
+            architecture: Architecture::ARMv4,
+            ..Default::default()
+        };
+        cpu.registers[1] = 0x40;
+        let mut pipe = Pipe::init(&mut cpu);
+        // blx r1 is undefined, bx r1 isn't
+        let blx = decode_instruction(0xe12f_ff31);
+        let result = execute_branch_exchange_instr(&blx, &mut cpu, &mut pipe);
+        assert_eq!(result, Err(Exception::UndefinedInstruction));
+        assert_eq!(cpu.registers[LR], 0);
+        let bx = decode_instruction(0xe12f_ff11);
+        assert_eq!(execute_branch_exchange_instr(&bx, &mut cpu, &mut pipe), Ok(true));
+        // The target is in the pipe, the PC is past it
+        assert_eq!(cpu.pc(), 0x44);
+    }
+}
+
+#[cfg(test)]
+mod thumb_tests {
+    use crate::emulator::em_utilities::*;
+    use crate::emulator::exceptions::Exception;
+    use crate::emulator::semihosting::Semihosting;
+    use crate::emulator::thumb_instr::execute_thumb_instr;
+
+    /// Executes a Thumb instruction as if it were at `address`
+    fn execute(cpu: &mut CpuState, address: u32, code: u32) -> Result<bool, Exception> {
+        let mut host = Semihosting::new(None, "thumb");
+        cpu.set_thumb_state(true);
+        cpu.registers[PC] = address + 4;
+        let instr = Instruction {
+            code,
+            instruction_type: InstructionType::THUMB,
+        };
+        execute_thumb_instr(&instr, cpu, &mut host)
+    }
+
+    #[test]
+    fn shifts_set_the_carry() {
+        let mut cpu = CpuState::default();
+        cpu.registers[1] = 0x8000_0001;
+        // lsls r0, r1, #1
+        assert_eq!(execute(&mut cpu, 0, 0x0048), Ok(false));
+        assert_eq!(cpu.registers[0], 2);
+        assert!(cpu.get_flag(Flag::C) && !cpu.get_flag(Flag::Z));
+        // lsrs r0, r1, #32 is encoded with a 0 shift
+        assert_eq!(execute(&mut cpu, 0, 0x0808), Ok(false));
+        assert_eq!(cpu.registers[0], 0);
+        assert!(cpu.get_flag(Flag::C) && cpu.get_flag(Flag::Z));
+    }
+
+    #[test]
+    fn branches_with_link() {
+        let mut cpu = CpuState::default();
+        // bl 0x100 from 0x200: the prefix adds the high part of the offset to the PC
+        assert_eq!(execute(&mut cpu, 0x200, 0xf7ff), Ok(false));
+        assert_eq!(execute(&mut cpu, 0x202, 0xff7e), Ok(true));
+        assert_eq!(cpu.pc(), 0x100);
+        assert_eq!(cpu.registers[LR], 0x205);
+        // beq isn't taken without Z
+        assert_eq!(execute(&mut cpu, 0x100, 0xd0fe), Ok(false));
+        // blx 0x400 from 0x300 lands word aligned in ARM state
+        assert_eq!(execute(&mut cpu, 0x300, 0xf000), Ok(false));
+        assert_eq!(execute(&mut cpu, 0x302, 0xe87e), Ok(true));
+        assert_eq!(cpu.pc(), 0x400);
+        assert!(!cpu.in_thumb_state());
+    }
+
+    #[test]
+    fn raises_exceptions() {
+        let mut cpu = CpuState::default();
+        // udf, svc #1, bkpt and setend be
+        assert_eq!(execute(&mut cpu, 0, 0xde00), Err(Exception::UndefinedInstruction));
+        assert_eq!(execute(&mut cpu, 0, 0xdf01), Err(Exception::SoftwareInterrupt));
+        assert_eq!(execute(&mut cpu, 0, 0xbe00), Err(Exception::PrefetchAbort));
+        assert_eq!(execute(&mut cpu, 0, 0xb658), Err(Exception::UndefinedInstruction));
+        // ldr r0, [r1] outside memory
+        cpu.registers[1] = 0x10_0000;
+        assert_eq!(execute(&mut cpu, 0, 0x6808), Err(Exception::DataAbort));
+    }
+
+    #[test]
+    fn follows_the_architecture() {
+        let mut cpu = CpuState {
+            architecture: Architecture::ARMv5,
+            ..Default::default()
+        };
+        // sxth, rev, cpsid i and setend le came with ARMv6
+        for code in [0xb208, 0xba08, 0xb672, 0xb650] {
+            assert_eq!(execute(&mut cpu, 0, code), Err(Exception::UndefinedInstruction));
+        }
+        assert_eq!(execute(&mut cpu, 0, 0xbe00), Err(Exception::PrefetchAbort));
+
+        // bkpt and blx came with ARMv5
+        cpu.architecture = Architecture::ARMv4;
+        assert_eq!(execute(&mut cpu, 0, 0xbe00), Err(Exception::UndefinedInstruction));
+        assert_eq!(execute(&mut cpu, 0x302, 0xe87e), Err(Exception::UndefinedInstruction));
+        // So did blx r1, bx r1 still runs
+        cpu.registers[1] = 0x201;
+        assert_eq!(execute(&mut cpu, 0, 0x4788), Err(Exception::UndefinedInstruction));
+        assert_eq!(cpu.registers[LR], 0);
+        assert_eq!(execute(&mut cpu, 0, 0x4708), Ok(true));
+        assert_eq!(cpu.registers[PC], 0x200);
+        // pop {pc} of an ARM address doesn't leave Thumb state
+        cpu.registers[13] = 0x1000;
+        cpu.write_word(0x1000, 0x400).unwrap();
+        assert_eq!(execute(&mut cpu, 0, 0xbd00), Ok(true));
+        assert_eq!(cpu.registers[PC], 0x400);
+        assert!(cpu.in_thumb_state());
+    }
+}
+
+#[cfg(test)]
+mod coprocessor_tests {
+    use crate::emulator::coprocessor::{Coprocessor, CoprocessorInstr};
+    use crate::emulator::coprocessor_instr::execute_coprocessor_instr;
+    use crate::emulator::em_utilities::*;
+    use crate::emulator::exceptions::Exception;
+    use crate::emulator::system_control::*;
+
+    /// A coprocessor with 16 registers, which CDP adds together
+    #[derive(Debug, Default)]
+    struct Adder {
+        registers: [u32; 16],
+    }
+
+    impl Coprocessor for Adder {
+        fn data_operation(&mut self, instr: &CoprocessorInstr) -> Result<(), Exception> {
+            let sum = self.registers[instr.crn as usize] + self.registers[instr.crm as usize];
+            self.registers[instr.crd as usize] = sum;
+            Ok(())
+        }
+
+        fn write_register(
+            &mut self,
+            instr: &CoprocessorInstr,
+            value: u32,
+        ) -> Result<(), Exception> {
+            self.registers[instr.crn as usize] = value;
+            Ok(())
+        }
+
+        fn read_register(&mut self, instr: &CoprocessorInstr) -> Result<u32, Exception> {
+            Ok(self.registers[instr.crn as usize])
+        }
+
+        fn transfer_length(&self, instr: &CoprocessorInstr) -> Result<usize, Exception> {
+            Ok(if instr.long { 2 } else { 1 })
+        }
+
+        fn load(&mut self, instr: &CoprocessorInstr, words: &[u32]) -> Result<(), Exception> {
+            let crd = instr.crd as usize;
+            self.registers[crd..crd + words.len()].copy_from_slice(words);
+            Ok(())
+        }
+
+        fn store(&mut self, instr: &CoprocessorInstr) -> Result<Vec<u32>, Exception> {
+            let crd = instr.crd as usize;
+            let length = self.transfer_length(instr)?;
+            Ok(self.registers[crd..crd + length].to_vec())
+        }
+    }
+
+    /// Executes a coprocessor instruction, also used by the VFP tests
+    pub(super) fn execute(cpu: &mut CpuState, code: u32) -> Result<bool, Exception> {
+        let instr = Instruction {
+            code,
+            instruction_type: InstructionType::COPROCESSOR,
+        };
+        execute_coprocessor_instr(&instr, cpu)
+    }
+
+    #[test]
+    fn passes_instructions_to_the_coprocessor() {
+        let mut cpu = CpuState::default();
+        cpu.coprocessors.attach(6, Box::new(Adder::default()));
+        cpu.registers[0] = 40;
+        cpu.registers[1] = 2;
+        // mcr p6, 0, r0, c1, c0, 0 and mcr p6, 0, r1, c2, c0, 0
+        assert_eq!(execute(&mut cpu, 0xee010610), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xee021610), Ok(false));
+        // cdp p6, 0, c3, c1, c2, 0 then mrc p6, 0, r2, c3, c0, 0
+        assert_eq!(execute(&mut cpu, 0xee013602), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xee132610), Ok(false));
+        assert_eq!(cpu.registers[2], 42);
+
+        // stcl p6, c2, [r3], #8 stores c2 and c3, then ldc p6, c5, [r3, #-4]! loads c3
+        cpu.registers[3] = 0x100;
+        assert_eq!(execute(&mut cpu, 0xece32602), Ok(false));
+        assert_eq!(cpu.read_word(0x100), Ok(2));
+        assert_eq!(cpu.read_word(0x104), Ok(42));
+        assert_eq!(cpu.registers[3], 0x108);
+        assert_eq!(execute(&mut cpu, 0xed335601), Ok(false));
+        assert_eq!(cpu.registers[3], 0x104);
+        // mrc p6, 0, r4, c5, c0, 0
+        assert_eq!(execute(&mut cpu, 0xee154610), Ok(false));
+        assert_eq!(cpu.registers[4], 42);
+    }
+
+    #[test]
+    fn raises_undefined_instructions() {
+        let mut cpu = CpuState::default();
+        // mrc p6, 0, r0, c0, c0, 0 with nothing in slot 6
+        assert_eq!(execute(&mut cpu, 0xee100610), Err(Exception::UndefinedInstruction));
+        // The adder has no double register transfers: mcrr p6, 0, r0, r1, c0
+        cpu.coprocessors.attach(6, Box::new(Adder::default()));
+        assert_eq!(execute(&mut cpu, 0xec410600), Err(Exception::UndefinedInstruction));
+        // ldc p6, c0, [r0, #4]! outside memory aborts and leaves the base alone
+        cpu.registers[0] = 0x10_0000;
+        assert_eq!(execute(&mut cpu, 0xedb00601), Err(Exception::DataAbort));
+        assert_eq!(cpu.registers[0], 0x10_0000);
+        // CP15 has no registers in c4: mrc p15, 0, r0, c4, c0, 0
+        assert_eq!(execute(&mut cpu, 0xee140f10), Err(Exception::UndefinedInstruction));
+    }
+
+    #[test]
+    fn reads_the_system_control_registers() {
+        let mut cpu = CpuState::default();
+        let cp15 = SystemControl::new(Architecture::ARMv5, true);
+        cpu.coprocessors.attach(SYSTEM_CONTROL, Box::new(cp15));
+        assert!(cpu.high_vectors());
+        // mrc p15, 0, r0, c0, c0, 0 reads the ID of an ARM926EJ-S
+        assert_eq!(execute(&mut cpu, 0xee100f10), Ok(false));
+        assert_eq!(cpu.registers[0], 0x4106_9265);
+        // TTBR1 only exists from ARMv6 on: mrc p15, 0, r0, c2, c0, 1
+        assert_eq!(execute(&mut cpu, 0xee120f30), Err(Exception::UndefinedInstruction));
+        // mcr p15, 0, r1, c1, c0, 0 clearing V moves the vectors back down
+        cpu.registers[1] = 0x0005_0078;
+        assert_eq!(execute(&mut cpu, 0xee011f10), Ok(false));
+        assert!(!cpu.high_vectors());
+    }
+
+    #[test]
+    fn keeps_the_control_register_bits() {
+        let mut cpu = CpuState::default();
+        // mcr p15, 0, r1, c1, c0, 0 then mrc p15, 0, r0, c1, c0, 0
+        let bits = MMU_BIT | ALIGNMENT_BIT | DATA_CACHE_BIT | INSTRUCTION_CACHE_BIT;
+        cpu.registers[1] = bits;
+        assert_eq!(execute(&mut cpu, 0xee011f10), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xee110f10), Ok(false));
+        // The bits that should be one stay set
+        assert_eq!(cpu.registers[0], 0x0005_0078 | bits);
+        assert!(!cpu.high_vectors());
+    }
+}
+
+#[cfg(test)]
+mod float_tests {
+    use crate::emulator::float::{self, Environment, Format, Rounding, DOUBLE, SINGLE};
+
+    const ROUNDINGS: [Rounding; 4] = [
+        Rounding::Nearest,
+        Rounding::PlusInfinity,
+        Rounding::MinusInfinity,
+        Rounding::Zero,
+    ];
+
+    fn environment(rounding: Rounding) -> Environment {
+        Environment {
+            rounding,
+            flush_to_zero: false,
+            default_nan: false,
+            flags: 0,
+        }
+    }
+
+    /// A xorshift generator, so every run checks the same numbers
+    struct Numbers(u64);
+
+    impl Numbers {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// Any single precision number, NaNs and denormals included
+        fn single(&mut self) -> f32 {
+            f32::from_bits(self.next() as u32)
+        }
+
+        fn double(&mut self) -> f64 {
+            f64::from_bits(self.next())
+        }
+
+        /// A double whose sums, products and quotients neither overflow nor underflow
+        fn moderate_double(&mut self) -> f64 {
+            let bits = self.next();
+            let exponent = 1023 - 200 + (bits >> 52) % 400;
+            f64::from_bits(bits & 0x800f_ffff_ffff_ffff | exponent << 52)
+        }
+    }
+
+    /// Rounds a result from the one nearest to it, given whether the exact result
+    /// is above (`Greater`) or below it
+    fn round_from_nearest<T: Copy>(
+        nearest: T,
+        exact: std::cmp::Ordering,
+        rounding: Rounding,
+        positive: bool,
+        next_up: fn(T) -> T,
+        next_down: fn(T) -> T,
+    ) -> T {
+        use std::cmp::Ordering::*;
+        match (rounding, exact) {
+            (Rounding::PlusInfinity, Greater) => next_up(nearest),
+            (Rounding::MinusInfinity, Less) => next_down(nearest),
+            (Rounding::Zero, Greater) if !positive => next_up(nearest),
+            (Rounding::Zero, Less) if positive => next_down(nearest),
+            _ => nearest,
+        }
+    }
+
+    /// Rounds an exact result, held in a double, to single precision
+    fn round_single(exact: f64, rounding: Rounding) -> f32 {
+        let nearest = exact as f32;
+        let error = exact.partial_cmp(&(nearest as f64)).unwrap();
+        round_from_nearest(nearest, error, rounding, nearest > 0.0, f32::next_up, f32::next_down)
+    }
+
+    /// Checks an operation on the bits of two numbers against the host's result.
+    /// NaN results only have to be NaNs, since hosts differ in which NaN they give
+    fn check<T: std::fmt::Debug>(format: Format, ours: u64, host: u64, operands: T) {
+        if format.is_nan(host) {
+            assert!(format.is_nan(ours), "{:?} gave 0x{:x}", operands, ours);
+        } else {
+            assert_eq!(ours, host, "{:?}: 0x{:x} instead of 0x{:x}", operands, ours, host);
+        }
+    }
+
+    #[test]
+    fn rounds_to_nearest_like_the_host() {
+        let mut numbers = Numbers(0x2545_f491_4f6c_dd1d);
+        for _ in 0..20000 {
+            let (x, y) = (numbers.single(), numbers.single());
+            let (a, b) = (x.to_bits() as u64, y.to_bits() as u64);
+            let env = &mut environment(Rounding::Nearest);
+            check(SINGLE, float::add(SINGLE, a, b, env), (x + y).to_bits() as u64, (x, y));
+            check(SINGLE, float::subtract(SINGLE, a, b, env), (x - y).to_bits() as u64, (x, y));
+            check(SINGLE, float::multiply(SINGLE, a, b, env), (x * y).to_bits() as u64, (x, y));
+            check(SINGLE, float::divide(SINGLE, a, b, env), (x / y).to_bits() as u64, (x, y));
+            check(SINGLE, float::square_root(SINGLE, a, env), x.sqrt().to_bits() as u64, x);
+
+            let (x, y) = (numbers.double(), numbers.double());
+            let (a, b) = (x.to_bits(), y.to_bits());
+            check(DOUBLE, float::add(DOUBLE, a, b, env), (x + y).to_bits(), (x, y));
+            check(DOUBLE, float::subtract(DOUBLE, a, b, env), (x - y).to_bits(), (x, y));
+            check(DOUBLE, float::multiply(DOUBLE, a, b, env), (x * y).to_bits(), (x, y));
+            check(DOUBLE, float::divide(DOUBLE, a, b, env), (x / y).to_bits(), (x, y));
+            check(DOUBLE, float::square_root(DOUBLE, a, env), x.sqrt().to_bits(), x);
+        }
+    }
+
+    #[test]
+    fn rounds_single_precision_in_every_mode() {
+        let mut numbers = Numbers(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..20000 {
+            // Sums are only exact in double precision for close exponents
+            let x = numbers.single();
+            let y = f32::from_bits(numbers.next() as u32 & 0x807f_ffff | 0x3f80_0000);
+            let x = if x.is_finite() && x.abs() < 1e6 && x.abs() > 1e-6 { x } else { 3.0 };
+            let (a, b) = (x.to_bits() as u64, y.to_bits() as u64);
+            for &rounding in &ROUNDINGS {
+                let env = &mut environment(rounding);
+                let sum = round_single(x as f64 + y as f64, rounding);
+                check(SINGLE, float::add(SINGLE, a, b, env), sum.to_bits() as u64, (x, y));
+                let product = round_single(x as f64 * y as f64, rounding);
+                let ours = float::multiply(SINGLE, a, b, env);
+                check(SINGLE, ours, product.to_bits() as u64, (x, y));
+                let inexact = sum as f64 != x as f64 + y as f64
+                    || product as f64 != x as f64 * y as f64;
+                assert_eq!(env.flags & float::INEXACT != 0, inexact);
+            }
+        }
+    }
+
+    #[test]
+    fn rounds_double_precision_in_every_mode() {
+        let mut numbers = Numbers(0xd1b5_4a32_d192_ed03);
+        for _ in 0..20000 {
+            let (x, y) = (numbers.moderate_double(), numbers.moderate_double());
+            let (a, b) = (x.to_bits(), y.to_bits());
+            // Which side of the nearest result the exact one is on, found exactly
+            let sum = x + y;
+            let sum_error = (x - (sum - (sum - x))) + (y - (sum - x));
+            let product = x * y;
+            let quotient = x / y;
+            let root = x.abs().sqrt();
+            let results = [
+                (sum, sum_error),
+                (product, x.mul_add(y, -product)),
+                (quotient, (-quotient).mul_add(y, x) * y.signum()),
+                (root, (-root).mul_add(root, x.abs())),
+            ];
+            for &rounding in &ROUNDINGS {
+                let env = &mut environment(rounding);
+                let ours = [
+                    float::add(DOUBLE, a, b, env),
+                    float::multiply(DOUBLE, a, b, env),
+                    float::divide(DOUBLE, a, b, env),
+                    float::square_root(DOUBLE, a & !(1 << 63), env),
+                ];
+                for (&ours, &(nearest, error)) in ours.iter().zip(results.iter()) {
+                    let error = error.partial_cmp(&0.0).unwrap();
+                    let expected = round_from_nearest(
+                        nearest,
+                        error,
+                        rounding,
+                        nearest > 0.0,
+                        f64::next_up,
+                        f64::next_down,
+                    );
+                    check(DOUBLE, ours, expected.to_bits(), (x, y, rounding));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn raises_exceptions() {
+        let env = &mut environment(Rounding::Nearest);
+        let (max, one, zero) = (f32::MAX.to_bits() as u64, 1f32.to_bits() as u64, 0);
+        assert_eq!(float::multiply(SINGLE, max, max, env), f32::INFINITY.to_bits() as u64);
+        assert_eq!(env.flags, float::OVERFLOW | float::INEXACT);
+
+        // Overflowing towards zero gives the largest number instead
+        let env = &mut environment(Rounding::Zero);
+        assert_eq!(float::add(SINGLE, max, max, env), max);
+        assert_eq!(env.flags, float::OVERFLOW | float::INEXACT);
+
+        let env = &mut environment(Rounding::Nearest);
+        assert_eq!(float::divide(SINGLE, one, zero, env), f32::INFINITY.to_bits() as u64);
+        assert_eq!(env.flags, float::DIVISION_BY_ZERO);
+
+        let env = &mut environment(Rounding::Nearest);
+        let infinity = f64::INFINITY.to_bits();
+        let difference = float::subtract(DOUBLE, infinity, infinity, env);
+        assert_eq!(difference, DOUBLE.default_nan());
+        assert_eq!(float::square_root(DOUBLE, (-1f64).to_bits(), env), DOUBLE.default_nan());
+        assert_eq!(env.flags, float::INVALID_OPERATION);
+
+        // A tiny inexact result underflows, an exact one doesn't
+        let env = &mut environment(Rounding::Nearest);
+        let tiny = f32::MIN_POSITIVE.to_bits() as u64;
+        let third = (1.0f32 / 3.0).to_bits() as u64;
+        float::multiply(SINGLE, tiny, (0.5f32).to_bits() as u64, env);
+        assert_eq!(env.flags, 0);
+        float::multiply(SINGLE, tiny, third, env);
+        assert_eq!(env.flags, float::UNDERFLOW | float::INEXACT);
+
+        // Flushing denormals to zero
+        let env = &mut Environment {
+            flush_to_zero: true,
+            ..environment(Rounding::Nearest)
+        };
+        assert_eq!(float::add(SINGLE, 1, 1, env), 0);
+        assert_eq!(env.flags, float::INPUT_DENORMAL);
+        assert_eq!(float::multiply(SINGLE, tiny, third, env), 0);
+        assert_eq!(env.flags, float::INPUT_DENORMAL | float::UNDERFLOW);
+
+        // Signalling NaNs are made quiet, unless the default NaN is used instead
+        let env = &mut environment(Rounding::Nearest);
+        assert_eq!(float::add(SINGLE, 0x7f80_0001, one, env), 0x7fc0_0001);
+        assert_eq!(env.flags, float::INVALID_OPERATION);
+        let env = &mut Environment {
+            default_nan: true,
+            ..environment(Rounding::Nearest)
+        };
+        assert_eq!(float::add(SINGLE, 0xffc0_0001, one, env), 0x7fc0_0000);
+        assert_eq!(env.flags, 0);
+    }
+
+    #[test]
+    fn converts_like_the_host() {
+        let mut numbers = Numbers(0x1234_5678_9abc_def1);
+        let env = &mut environment(Rounding::Nearest);
+        for _ in 0..20000 {
+            let x = numbers.double();
+            let narrowed = float::convert(DOUBLE, SINGLE, x.to_bits(), env);
+            check(SINGLE, narrowed, (x as f32).to_bits() as u64, x);
+            let y = numbers.single();
+            let widened = float::convert(SINGLE, DOUBLE, y.to_bits() as u64, env);
+            check(DOUBLE, widened, (y as f64).to_bits(), y);
+
+            // Casts to integers round towards zero and saturate
+            let signed = float::to_integer(DOUBLE, x.to_bits(), true, Rounding::Zero, env);
+            assert_eq!(signed, x as i32 as u32, "{}", x);
+            let y_bits = y.to_bits() as u64;
+            let unsigned = float::to_integer(SINGLE, y_bits, false, Rounding::Zero, env);
+            assert_eq!(unsigned, y as u32, "{}", y);
+
+            let integer = numbers.next() as u32;
+            let single = float::from_integer(SINGLE, integer, true, env);
+            assert_eq!(single, (integer as i32 as f32).to_bits() as u64);
+            let double = float::from_integer(DOUBLE, integer, false, env);
+            assert_eq!(double, (integer as f64).to_bits());
+        }
+
+        let env = &mut environment(Rounding::Nearest);
+        // 2.5 and -1.5 are halfway between integers
+        let (positive, negative) = ((2.5f64).to_bits(), (-1.5f64).to_bits());
+        let signed = |bits, rounding, env: &mut Environment| {
+            float::to_integer(DOUBLE, bits, true, rounding, env) as i32
+        };
+        assert_eq!(signed(positive, Rounding::Nearest, env), 2);
+        assert_eq!(signed(negative, Rounding::Nearest, env), -2);
+        assert_eq!(signed(positive, Rounding::PlusInfinity, env), 3);
+        assert_eq!(signed(negative, Rounding::MinusInfinity, env), -2);
+        assert_eq!(env.flags, float::INEXACT);
+        assert_eq!(float::to_integer(DOUBLE, negative, false, Rounding::Zero, env), 0);
+        assert_eq!(env.flags, float::INEXACT | float::INVALID_OPERATION);
+    }
+
+    #[test]
+    fn compares_like_the_host() {
+        let mut numbers = Numbers(0x0f0f_1234_abcd_0001);
+        let env = &mut environment(Rounding::Nearest);
+        for _ in 0..20000 {
+            let (x, y) = (numbers.single(), numbers.single());
+            let expected = match x.partial_cmp(&y) {
+                Some(std::cmp::Ordering::Less) => float::LESS_THAN,
+                Some(std::cmp::Ordering::Equal) => float::EQUAL,
+                Some(std::cmp::Ordering::Greater) => float::GREATER_THAN,
+                None => float::UNORDERED,
+            };
+            let (a, b) = (x.to_bits() as u64, y.to_bits() as u64);
+            let flags = float::compare(SINGLE, a, b, false, env);
+            assert_eq!(flags, expected, "{} and {}", x, y);
+        }
+        // Quiet NaNs only signal when asked to
+        let env = &mut environment(Rounding::Nearest);
+        let nan = f64::NAN.to_bits();
+        assert_eq!(float::compare(DOUBLE, nan, 0, false, env), float::UNORDERED);
+        assert_eq!(env.flags, 0);
+        assert_eq!(float::compare(DOUBLE, nan, 0, true, env), float::UNORDERED);
+        assert_eq!(env.flags, float::INVALID_OPERATION);
+        let negative_zero = (-0f64).to_bits();
+        assert_eq!(float::compare(DOUBLE, negative_zero, 0, true, env), float::EQUAL);
+    }
+}
+
+#[cfg(test)]
+mod vfp_tests {
+    use super::coprocessor_tests::execute;
+    use crate::emulator::coprocessor::Coprocessors;
+    use crate::emulator::em_utilities::*;
+    use crate::emulator::exceptions::Exception;
+
+    /// A CPU with VFP enabled and the FPSCR set to `fpscr`
+    fn enabled(fpscr: u32) -> CpuState {
+        let mut cpu = CpuState::default();
+        cpu.registers[0] = 1 << 30;
+        // fmxr fpexc, r0 then fmxr fpscr, r0
+        assert_eq!(execute(&mut cpu, 0xeee80a10), Ok(false));
+        cpu.registers[0] = fpscr;
+        assert_eq!(execute(&mut cpu, 0xeee10a10), Ok(false));
+        cpu
+    }
+
+    /// Moves a single precision register into r3 with fmrs r3, s<n>
+    fn single(cpu: &mut CpuState, n: u32) -> f32 {
+        assert_eq!(execute(cpu, 0xee103a10 | (n >> 1) << 16 | (n & 1) << 7), Ok(false));
+        f32::from_bits(cpu.registers[3])
+    }
+
+    #[test]
+    fn is_disabled_out_of_reset() {
+        let mut cpu = CpuState::default();
+        // fadds s0, s1, s2 and fmrx r0, fpscr are undefined, fmrx r0, fpsid isn't
+        assert_eq!(execute(&mut cpu, 0xee300a81), Err(Exception::UndefinedInstruction));
+        assert_eq!(execute(&mut cpu, 0xeef10a10), Err(Exception::UndefinedInstruction));
+        assert_eq!(execute(&mut cpu, 0xeef00a10), Ok(false));
+        assert_eq!(cpu.registers[0], 0x4101_20b5);
+        // Only ARMv6 cores have VFP
+        cpu.coprocessors = Coprocessors::built_in(Architecture::ARMv5, false);
+        assert_eq!(execute(&mut cpu, 0xeef00a10), Err(Exception::UndefinedInstruction));
+    }
+
+    #[test]
+    fn computes_in_both_precisions() {
+        let mut cpu = enabled(0);
+        cpu.registers[1] = 1.5f32.to_bits();
+        cpu.registers[2] = 2.25f32.to_bits();
+        // fmsr s1, r1, fmsr s2, r2 then fadds s0, s1, s2
+        assert_eq!(execute(&mut cpu, 0xee001a90), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xee012a10), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xee300a81), Ok(false));
+        assert_eq!(single(&mut cpu, 0), 3.75);
+        // fmacs s0, s1, s2 and fdivs s0, s1, s2
+        assert_eq!(execute(&mut cpu, 0xee000a81), Ok(false));
+        assert_eq!(single(&mut cpu, 0), 3.75 + 1.5 * 2.25);
+        assert_eq!(execute(&mut cpu, 0xee800a81), Ok(false));
+        assert_eq!(single(&mut cpu, 0), 1.5 / 2.25);
+
+        // fmdrr d1, r4, r5, fmuld d2, d1, d1 and fmrrd r6, r7, d2
+        let third = 1.0f64 / 3.0;
+        cpu.registers[4] = third.to_bits() as u32;
+        cpu.registers[5] = (third.to_bits() >> 32) as u32;
+        assert_eq!(execute(&mut cpu, 0xec454b11), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xee212b01), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xec576b12), Ok(false));
+        let square = cpu.registers[6] as u64 | (cpu.registers[7] as u64) << 32;
+        assert_eq!(f64::from_bits(square), third * third);
+        // fcvtsd s2, d2, then fsqrtd d0, d1 and fcvtsd s2, d0
+        assert_eq!(execute(&mut cpu, 0xeeb71bc2), Ok(false));
+        assert_eq!(single(&mut cpu, 2), (third * third) as f32);
+        assert_eq!(execute(&mut cpu, 0xeeb10bc1), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xeeb71bc0), Ok(false));
+        assert_eq!(single(&mut cpu, 2), third.sqrt() as f32);
+        // Rounding made all of them inexact
+        assert_eq!(execute(&mut cpu, 0xeef10a10), Ok(false));
+        assert_eq!(cpu.registers[0], 0x10);
+    }
+
+    #[test]
+    fn converts_with_the_rounding_mode() {
+        // Rounding towards minus infinity
+        let mut cpu = enabled(0b10 << 22);
+        let (low, high) = ((-2.5f64).to_bits() as u32, ((-2.5f64).to_bits() >> 32) as u32);
+        cpu.registers[4] = low;
+        cpu.registers[5] = high;
+        assert_eq!(execute(&mut cpu, 0xec454b11), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xee212b01), Ok(false));
+        // ftosizd s0, d2 always rounds towards zero, ftosid s0, d2 uses the FPSCR
+        assert_eq!(execute(&mut cpu, 0xeebd0bc2), Ok(false));
+        assert_eq!(single(&mut cpu, 0).to_bits(), 6);
+        assert_eq!(execute(&mut cpu, 0xeebd0b42), Ok(false));
+        assert_eq!(single(&mut cpu, 0).to_bits(), 6);
+        // fnmuld d0, d2, d2 then ftosid s0, d0: -39.0625 rounds down to -40
+        assert_eq!(execute(&mut cpu, 0xee220b42), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xeebd0b40), Ok(false));
+        assert_eq!(single(&mut cpu, 0).to_bits() as i32, -40);
+        // fsitos s1, s0
+        assert_eq!(execute(&mut cpu, 0xeef80ac0), Ok(false));
+        assert_eq!(single(&mut cpu, 1), -40.0);
+    }
+
+    #[test]
+    fn compares_into_the_cpsr() {
+        let mut cpu = enabled(0);
+        cpu.registers[1] = (-1.0f32).to_bits();
+        // fmsr s0, r1, fcmpezs s0, then fmstat
+        assert_eq!(execute(&mut cpu, 0xee001a10), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xeeb50ac0), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xeef1fa10), Ok(false));
+        assert!(cpu.get_flag(Flag::N) && !cpu.get_flag(Flag::Z) && !cpu.get_flag(Flag::C));
+
+        // A NaN is unordered, and fcmpe raises invalid operation for it
+        cpu.registers[1] = f32::NAN.to_bits();
+        assert_eq!(execute(&mut cpu, 0xee001a10), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xeeb50ac0), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xeef1fa10), Ok(false));
+        assert!(cpu.get_flag(Flag::C) && cpu.get_flag(Flag::V) && !cpu.get_flag(Flag::N));
+        assert_eq!(execute(&mut cpu, 0xeef10a10), Ok(false));
+        assert_eq!(cpu.registers[0], 0x3000_0001);
+    }
+
+    #[test]
+    fn runs_short_vectors() {
+        // Vectors of 4 with a stride of 1
+        let mut cpu = enabled(0b011 << 16);
+        let vectors = (0..4).map(|i| (8 + i, i as f32));
+        for (register, value) in vectors.chain((0..4).map(|i| (16 + i, 10.0))) {
+            cpu.registers[1] = value.to_bits();
+            let (field, low) = (register >> 1, register & 1);
+            assert_eq!(execute(&mut cpu, 0xee001a10 | field << 16 | low << 7), Ok(false));
+        }
+        // fadds s8, s8, s16 adds each element, fadds s8, s8, s0 adds s0 to each
+        assert_eq!(execute(&mut cpu, 0xee344a08), Ok(false));
+        cpu.registers[1] = 0.5f32.to_bits();
+        assert_eq!(execute(&mut cpu, 0xee001a10), Ok(false));
+        assert_eq!(execute(&mut cpu, 0xee344a00), Ok(false));
+        for i in 0..4 {
+            assert_eq!(single(&mut cpu, 8 + i), i as f32 + 10.5);
+        }
+        // Destinations in the first bank are scalar: fadds s0, s1, s2 only writes s0
+        assert_eq!(execute(&mut cpu, 0xee300a81), Ok(false));
+        assert_eq!(single(&mut cpu, 1), 0.0);
+    }
+
+    #[test]
+    fn loads_and_stores() {
+        let mut cpu = enabled(0);
+        for i in 0..4 {
+            cpu.write_word(0x100 + 4 * i, (i as f32 + 1.0).to_bits()).unwrap();
+        }
+        cpu.registers[8] = 0x100;
+        // fldmias r8, {s8-s11}, then fldd d1, [r8, #8] and flds s3, [r8, #-4]
+        assert_eq!(execute(&mut cpu, 0xec984a04), Ok(false));
+        assert_eq!(single(&mut cpu, 11), 4.0);
+        assert_eq!(execute(&mut cpu, 0xed981b02), Ok(false));
+        assert_eq!(single(&mut cpu, 3), 4.0);
+        assert_eq!(execute(&mut cpu, 0xed581a01), Ok(false));
+        assert_eq!(single(&mut cpu, 3), 0.0);
+        // fstmdbd r8!, {d0-d2}
+        assert_eq!(execute(&mut cpu, 0xed280b06), Ok(false));
+        assert_eq!(cpu.registers[8], 0x100 - 24);
+        assert_eq!(cpu.read_word(0xf0), Ok(3f32.to_bits()));
+        assert_eq!(cpu.read_word(0xf4), Ok(0));
+    }
+}
+
+#[cfg(test)]
+mod extension_tests {
+    use crate::emulator::em_utilities::*;
+    use crate::emulator::exceptions::Exception;
+    use crate::emulator::halfword_data_transfer_instr::execute_halfword_data_instr;
+    use crate::emulator::media_instr::execute_media_instr;
+    use crate::emulator::miscellaneous_instr::execute_miscellaneous_instr;
+    use crate::emulator::pipeline_executor::decode_instruction;
+    use crate::emulator::synchronization_instr::execute_synchronization_instr;
+
+    /// Decodes and executes an instruction from the ARMv5TE and ARMv6 extensions
+    fn execute(cpu: &mut CpuState, code: u32) -> Result<bool, Exception> {
+        let instr = decode_instruction(code);
+        match instr.instruction_type {
+            InstructionType::MISCELLANEOUS => execute_miscellaneous_instr(&instr, cpu),
+            InstructionType::MEDIA => execute_media_instr(&instr, cpu),
+            InstructionType::SYNCHRONIZATION => execute_synchronization_instr(&instr, cpu),
+            InstructionType::HALFWORD_DATA_TRANSFER => execute_halfword_data_instr(&instr, cpu),
+            other => panic!("0x{:08x} decoded as {:?}", code, other),
+        }
+    }
+
+    #[test]
+    fn saturates_into_the_q_flag() {
+        let mut cpu = CpuState::default();
+        cpu.registers[1] = 0x0001_0000;
+        // clz r0, r1 doesn't touch Q
+        assert_eq!(execute(&mut cpu, 0xe16f_0f11), Ok(false));
+        assert_eq!(cpu.registers[0], 15);
+        assert!(!cpu.get_flag(Flag::Q));
+
+        // qadd r0, r1, r2
+        cpu.registers[1] = 0x7fff_ffff;
+        cpu.registers[2] = 1;
+        assert_eq!(execute(&mut cpu, 0xe102_0051), Ok(false));
+        assert_eq!(cpu.registers[0], 0x7fff_ffff);
+        assert!(cpu.get_flag(Flag::Q));
+        // qdsub r0, r1, r2 saturates the doubling, Q is sticky
+        cpu.set_CPSR_flag(Flag::Q, false);
+        cpu.registers[1] = 0;
+        cpu.registers[2] = 0x4000_0000;
+        assert_eq!(execute(&mut cpu, 0xe162_0051), Ok(false));
+        assert_eq!(cpu.registers[0], 0x8000_0001);
+        assert_eq!(cpu.cpsr() & 1 << 27, 1 << 27);
+
+        // ssat r0, #8, r1, asr #2
+        cpu.set_CPSR_flag(Flag::Q, false);
+        cpu.registers[1] = 0x1000;
+        assert_eq!(execute(&mut cpu, 0xe6a7_0151), Ok(false));
+        assert_eq!(cpu.registers[0], 127);
+        assert!(cpu.get_flag(Flag::Q));
+    }
+
+    #[test]
+    fn multiplies_halfwords() {
+        let mut cpu = CpuState::default();
+        // smlabb r0, r1, r2, r3
+        cpu.registers[1] = 0xffff;
+        cpu.registers[2] = 0x0003_0002;
+        cpu.registers[3] = 10;
+        assert_eq!(execute(&mut cpu, 0xe100_3281), Ok(false));
+        assert_eq!(cpu.registers[0], 8);
+        // smlalbt r0, r1, r2, r3 accumulates into r1:r0
+        cpu.registers[0] = 5;
+        cpu.registers[1] = 0;
+        cpu.registers[2] = 0xfffe;
+        cpu.registers[3] = 0x0003_0000;
+        assert_eq!(execute(&mut cpu, 0xe141_03c2), Ok(false));
+        assert_eq!((cpu.registers[0], cpu.registers[1]), (0xffff_ffff, 0xffff_ffff));
+    }
+
+    #[test]
+    fn selects_bytes_with_the_ge_flags() {
+        let mut cpu = CpuState::default();
+        // uadd8 r0, r1, r2 carries out of bytes 1 and 3
+        cpu.registers[1] = 0x8001_ff10;
+        cpu.registers[2] = 0x8001_0210;
+        assert_eq!(execute(&mut cpu, 0xe651_0f92), Ok(false));
+        assert_eq!(cpu.registers[0], 0x0002_0120);
+        assert_eq!(cpu.ge_flags(), 0b1010);
+        // sel r3, r4, r5
+        cpu.registers[4] = 0xaaaa_aaaa;
+        cpu.registers[5] = 0x5555_5555;
+        assert_eq!(execute(&mut cpu, 0xe684_3fb5), Ok(false));
+        assert_eq!(cpu.registers[3], 0xaa55_aa55);
+
+        // rev r0, r1, revsh r0, r1 and sxtb r0, r1, ror #8
+        cpu.registers[1] = 0x1234_80ff;
+        assert_eq!(execute(&mut cpu, 0xe6bf_0f31), Ok(false));
+        assert_eq!(cpu.registers[0], 0xff80_3412);
+        assert_eq!(execute(&mut cpu, 0xe6ff_0fb1), Ok(false));
+        assert_eq!(cpu.registers[0], 0xffff_ff80);
+        assert_eq!(execute(&mut cpu, 0xe6af_0471), Ok(false));
+        assert_eq!(cpu.registers[0], 0xffff_ff80);
+    }
+
+    #[test]
+    fn stores_exclusively_while_the_monitor_is_tagged() {
+        let mut cpu = CpuState::default();
+        cpu.registers[1] = 0x100;
+        cpu.write_word(0x100, 7).unwrap();
+        // ldrex r0, [r1] then strex r0, r2, [r1] twice
+        assert_eq!(execute(&mut cpu, 0xe191_0f9f), Ok(false));
+        assert_eq!(cpu.registers[0], 7);
+        cpu.registers[2] = 9;
+        assert_eq!(execute(&mut cpu, 0xe181_0f92), Ok(false));
+        assert_eq!(cpu.registers[0], 0);
+        cpu.registers[2] = 11;
+        assert_eq!(execute(&mut cpu, 0xe181_0f92), Ok(false));
+        assert_eq!(cpu.registers[0], 1);
+        assert_eq!(cpu.read_word(0x100), Ok(9));
+
+        // swp r0, r1, [r2]
+        cpu.registers[2] = 0x100;
+        assert_eq!(execute(&mut cpu, 0xe102_0091), Ok(false));
+        assert_eq!(cpu.registers[0], 9);
+        assert_eq!(cpu.read_word(0x100), Ok(0x100));
+    }
+
+    #[test]
+    fn transfers_doublewords() {
+        let mut cpu = CpuState::default();
+        cpu.registers[2] = 0x100;
+        cpu.write_word(0x108, 1).unwrap();
+        cpu.write_word(0x10c, 2).unwrap();
+        // ldrd r0, r1, [r2, #8]
+        assert_eq!(execute(&mut cpu, 0xe1c2_00d8), Ok(false));
+        assert_eq!((cpu.registers[0], cpu.registers[1]), (1, 2));
+        // ldrd r1, [r2, #8] needs an even register
+        assert_eq!(execute(&mut cpu, 0xe1c2_10d8), Err(Exception::UndefinedInstruction));
+    }
+
+    #[test]
+    fn needs_the_architecture() {
+        let mut cpu = CpuState {
+            architecture: Architecture::ARMv5,
+            ..Default::default()
+        };
+        // rev r0, r1 and ldrex r0, [r1] are ARMv6, clz r0, r1 is ARMv5
+        assert_eq!(execute(&mut cpu, 0xe6bf_0f31), Err(Exception::UndefinedInstruction));
+        assert_eq!(execute(&mut cpu, 0xe191_0f9f), Err(Exception::UndefinedInstruction));
+        assert_eq!(execute(&mut cpu, 0xe16f_0f11), Ok(false));
+        // bkpt #0 too, which aborts the prefetch as in Thumb state
+        assert_eq!(execute(&mut cpu, 0xe120_0070), Err(Exception::PrefetchAbort));
+        cpu.architecture = Architecture::ARMv4;
+        assert_eq!(execute(&mut cpu, 0xe16f_0f11), Err(Exception::UndefinedInstruction));
+        assert_eq!(execute(&mut cpu, 0xe120_0070), Err(Exception::UndefinedInstruction));
+        assert_eq!(execute(&mut cpu, 0xe1c2_00d8), Err(Exception::UndefinedInstruction));
+        // swp r0, r1, [r2] is there from the start
+        assert_eq!(execute(&mut cpu, 0xe102_0091), Ok(false));
+    }
+}
@@ -0,0 +1,3 @@
+mvn r1,#0
+adds r2,r1,#1
+adc r3,r1,#2
@@ -0,0 +1,3 @@
+mov r1,#0xff
+bic r2,r1,#0xf0
+bics r3,r1,r1
@@ -0,0 +1,27 @@
+    mov sp,#0x400
+    mov r0,#5
+    bl double
+    bl twice
+    mov r5,r0
+    bl load_return
+    mov r8,#double
+    blx r8
+    b end
+double:
+    add r0,r0,r0
+    mov pc,lr
+add_three:
+    add r0,r0,#3
+    bx lr
+twice:
+    push {lr}
+    bl add_three
+    bl add_three
+    pop {pc}
+load_return:
+    str lr,[sp,#-4]!
+    mov r6,#1
+    ldr pc,[sp],#4
+end:
+    mov r7,#7
+    andeq r0,r0,r0
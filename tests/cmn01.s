@@ -0,0 +1,5 @@
+mvn r1,#0x80000000
+cmn r1,#1
+movlt r2,#1
+movge r3,#1
+mov r4,#2
@@ -0,0 +1,11 @@
+mov r0,#0
+cmp r0,#1
+movmi r1,#1
+movpl r2,#1
+movcc r3,#1
+movcs r4,#1
+movls r5,#1
+movhi r6,#1
+movvc r7,#1
+movvs r8,#1
+mov r9,#1
@@ -0,0 +1,13 @@
+mov r0,#2
+cmp r0,#1
+movhi r1,#1
+movls r2,#1
+movhs r3,#1
+movlo r4,#1
+mvn r5,#0x80000000
+adds r6,r5,r5
+movvs r7,#1
+movvc r8,#1
+movlt r9,#1
+movge r10,#1
+mov r11,#1
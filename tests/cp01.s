@@ -0,0 +1,21 @@
+b start
+b undefined
+start:
+mrc p15,0,r0,c0,c0,0
+mrc p15,0,r1,c1,c0,0
+orr r1,r1,#0x2000
+mcr p15,0,r1,c1,c0,0
+mov r2,#0x4000
+mcr p15,0,r2,c2,c0,0
+mrc p15,0,r3,c2,c0,0
+mcr p15,0,r0,c7,c7,0
+mov r4,#0
+mrc p15,0,pc,c7,c14,3
+moveq r4,#4
+mrc p14,0,r6,c0,c0,0
+mov r7,#7
+andeq r0,r0,r0
+undefined:
+mov r5,lr
+mov r8,pc
+movs pc,lr
@@ -0,0 +1,16 @@
+mov r1,#0x7f000000
+orr r1,r1,#0xff0000
+qadd r2,r1,r1
+clz r3,r2
+mov r4,#0x100
+strex r5,r2,[r4]
+ldrex r6,[r4]
+strex r5,r1,[r4]
+ldrd r6,r7,[r4]
+uadd8 r8,r1,r1
+sel r9,r1,r4
+rev r10,r1
+ssat r11,#16,r1,asr #4
+smultt r12,r1,r3
+swp r0,r3,[r4]
+andeq r0,r0,r0
@@ -0,0 +1,50 @@
+b start
+b undefined
+b software_interrupt
+b prefetch_abort
+b data_abort
+mov r0,r0
+mov r0,r0
+mov r0,r0
+start:
+mov r0,#0x10
+orr r0,r0,#0x20000000
+msr cpsr_fc,r0
+mov r1,#1
+.word 0xf3a02002 @ movnv r2, #2, undefined from ARMv5 on
+mov r2,#2
+swi 0x42
+mov r3,#3
+mov r0,#0x20000
+ldr r4,[r0]
+mov r5,#5
+mov pc,r0
+resume:
+mov r6,#6
+andeq r0,r0,r0
+undefined:
+mov r7,lr
+mrs r8,spsr
+mov r11,pc
+ldr sp,=0x1000
+stmfd sp!,{lr}
+ldmfd sp!,{pc}^
+software_interrupt:
+ldr r9,[lr,#-4]
+bic r9,r9,#0xff000000
+ldr sp,=0x2000
+stmfd sp!,{lr}
+ldmfd sp!,{pc}^
+data_abort:
+mov r10,lr
+sub lr,lr,#4
+ldr sp,=0x3000
+stmfd sp!,{lr}
+ldmfd sp!,{pc}^
+prefetch_abort:
+mov r12,lr
+ldr lr,=resume
+ldr sp,=0x3000
+stmfd sp!,{lr}
+ldmfd sp!,{pc}^
+.ltorg
@@ -0,0 +1,7 @@
+mov r0,#0
+cmp r0,#0
+movs r1,#1
+mov r2,#3
+muls r3,r0,r2
+ands r4,r2,r2,lsl #0
+mov r5,#5
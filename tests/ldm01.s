@@ -0,0 +1,12 @@
+mov r0,#0x200
+mov r1,#1
+mov r2,#2
+mov r3,#3
+stmia r0!,{r1-r3}
+stmib r0,{r1,r2}
+ldmdb r0!,{r4-r6}
+mov r7,#0x218
+stmda r7,{r1-r3}
+ldmfd r0,{r8,r9}
+ldmia r0!,{r0,r10}
+andeq r0,r0,r0
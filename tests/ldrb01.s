@@ -0,0 +1,10 @@
+ldr r0,=0x87654321
+mov r1,#0x100
+str r0,[r1]
+ldrb r2,[r1]
+ldrb r3,[r1,#3]
+mov r4,#0xff
+strb r4,[r1,#1]!
+ldr r5,[r1,#-1]
+ldrb r6,[r1],#1
+andeq r0,r0,r0
@@ -0,0 +1,14 @@
+ldr r0,=0x87654321
+mov r1,#0x100
+str r0,[r1]
+ldrh r2,[r1]
+ldrsh r3,[r1,#2]
+ldrsb r4,[r1,#3]
+ldrsb r5,[r1]
+mov r6,#2
+ldrh r7,[r1,r6]
+strh r0,[r1,#4]!
+ldrh r8,[r1],#-4
+strh r0,[r1,-r6]
+ldr r9,[r1,#-4]
+andeq r0,r0,r0
@@ -0,0 +1,24 @@
+mov r0,#0xd2
+msr cpsr_c,r0
+mov sp,#0x100
+mov lr,#1
+msr spsr_fsxc,#0x10
+mrs r1,spsr
+msr cpsr_c,#0xd3
+mov sp,#0x200
+mov r8,#8
+msr cpsr_c,#0xd1
+mov r8,#0x88
+mov sp,#0x300
+msr cpsr_c,#0xd2
+mov r2,sp
+mov r3,lr
+mov r4,r8
+msr cpsr_c,#0x10
+mov sp,#0x400
+mrs r5,cpsr
+mov r6,#0xd3
+orr r6,r6,#0xf0000000
+msr cpsr_fc,r6
+mrs r7,cpsr
+andeq r0,r0,r0
@@ -0,0 +1,12 @@
+mvn r0,#0
+mov r1,#2
+umull r2,r3,r0,r1
+smull r4,r5,r0,r1
+mov r6,#1
+mov r7,#0
+umlal r6,r7,r0,r0
+smlals r4,r5,r1,r1
+mul r8,r0,r0
+mla r9,r0,r1,r0
+smulls r10,r11,r0,r1
+andeq r0,r0,r0
@@ -0,0 +1,4 @@
+mov r1,#0x0f
+mvn r2,r1
+mvns r3,r2
+mvns r4,#0
@@ -0,0 +1,4 @@
+mov r1,#1
+.word 0xf3a02002 @ movnv r2, #2
+.word 0xf5d1f000 @ pld [r1]
+mov r3,#3
@@ -0,0 +1,3 @@
+mov r1,#1
+.word 0xf5d1f000 @ pld [r1]
+mov r3,#3
@@ -0,0 +1,34 @@
+b start
+mov r0,r0
+b software_interrupt
+start:
+mov r0,pc
+add r1,pc,#4
+mov r3,#0
+mov r9,pc,lsl r3
+add r4,pc,r3,lsl r3
+mov r7,#0x100
+str pc,[r7]
+stmib r7,{pc}
+add pc,pc,#0
+mov r5,#0xff
+mov r5,#5
+swi 0x10
+mrs r6,cpsr
+ldr pc,=thumb_code+1
+arm_code:
+mov r10,#10
+andeq r0,r0,r0
+software_interrupt:
+mov r8,lr
+msr cpsr_f,#0xf0000000
+mrs r11,cpsr
+movs pc,lr
+thumb_code:
+.hword 0x2709 @ movs r7, #9
+.hword 0x4a01 @ ldr r2, arm_code_address
+.hword 0x4710 @ bx r2
+.hword 0x46c0 @ nop
+arm_code_address:
+.word arm_code
+.ltorg
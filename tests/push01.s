@@ -0,0 +1,11 @@
+mov sp,#0x100
+mov r0,#1
+mov r1,#2
+mov r2,#3
+mov lr,#36
+push {r0-r2,lr}
+mov r0,#0
+pop {r3-r5,pc}
+mov r6,#9
+mov r7,#7
+andeq r0,r0,r0
@@ -0,0 +1,3 @@
+mov r1,#3
+rsbs r2,r1,#0
+rsc r3,r1,#10
@@ -0,0 +1,5 @@
+mov r1,#5
+cmp r1,#6
+sbc r2,r1,#1
+subs r3,r1,#5
+sbc r4,r1,#1
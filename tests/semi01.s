@@ -0,0 +1,45 @@
+    ldr r1,=hello
+    mov r0,#4
+    swi 0x123456
+    ldr r1,=open_block
+    mov r0,#1
+    swi 0x123456
+    mov r4,r0
+    ldr r1,=write_block
+    str r4,[r1]
+    mov r0,#5
+    swi 0x123456
+    mov r5,r0
+    ldr r1,=close_block
+    str r4,[r1]
+    mov r0,#2
+    swi 0x123456
+    ldr r1,=escape_block
+    mov r0,#1
+    swi 0x123456
+    mov r6,r0
+    ldr r1,=exit_block
+    mov r0,#0x20
+    swi 0x123456
+    mov r7,#1
+    andeq r0,r0,r0
+    .ltorg
+hello:
+    .asciz "hello\n"
+name:
+    .ascii "out.txt"
+escape:
+    .ascii "../out.txt"
+data:
+    .ascii "data"
+    .align 2
+open_block:
+    .word name, 4, 7
+write_block:
+    .word 0, data, 4
+close_block:
+    .word 0
+escape_block:
+    .word escape, 4, 10
+exit_block:
+    .word 0x20026, 3
@@ -0,0 +1,38 @@
+mov sp,#0x1000
+blx thumb_code
+mov r9,#9
+andeq r0,r0,r0
+arm_func:
+mov r10,#10
+bx lr
+thumb_code:
+.hword 0xb500 @ push {lr}
+.hword 0x200a @ movs r0, #10
+.hword 0x0081 @ lsls r1, r0, #2
+.hword 0x180a @ adds r2, r1, r0
+.hword 0x1fd3 @ subs r3, r2, #7
+.hword 0x2403 @ movs r4, #3
+.hword 0x435c @ muls r4, r3, r4
+.hword 0x4245 @ negs r5, r0
+.hword 0x106d @ asrs r5, r5, #1
+.hword 0x46a8 @ mov r8, r5
+.hword 0x4480 @ add r8, r0
+.hword 0x4e06 @ ldr r6, literal
+.hword 0x2700 @ movs r7, #0
+loop:
+.hword 0x183f @ adds r7, r7, r0
+.hword 0x3801 @ subs r0, #1
+.hword 0xd1fc @ bne loop
+.hword 0xf000 @ bl thumb_sub
+.hword 0xf803
+.hword 0x4b03 @ ldr r3, arm_func_address
+.hword 0x4798 @ blx r3
+.hword 0xbd00 @ pop {pc}
+thumb_sub:
+.hword 0xba36 @ rev r6, r6
+.hword 0xb2f0 @ uxtb r0, r6
+.hword 0x4770 @ bx lr
+literal:
+.word 0x12345678
+arm_func_address:
+.word arm_func
@@ -0,0 +1,25 @@
+b start
+mov r0,r0
+b software_interrupt
+start:
+mov sp,#0x1000
+blx thumb_code
+andeq r0,r0,r0
+software_interrupt:
+ldrb r9,[lr,#-2]
+mrs r10,spsr
+mov r11,lr
+mov sp,#0x2000
+stmfd sp!,{lr}
+ldmfd sp!,{pc}^
+thumb_code:
+.hword 0x2101 @ movs r1, #1
+.hword 0xdf42 @ svc #66
+.hword 0x2202 @ movs r2, #2
+.hword 0x2018 @ movs r0, #0x18
+.hword 0x4901 @ ldr r1, exit_reason
+.hword 0xdfab @ svc #0xab
+.hword 0x2303 @ movs r3, #3
+.hword 0x46c0 @ nop
+exit_reason:
+.word 0x20026